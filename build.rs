@@ -0,0 +1,136 @@
+use std::env;
+use std::fmt::Write as _;
+use std::fs;
+use std::path::PathBuf;
+
+fn main() {
+    if env::var_os("CARGO_FEATURE_STATIC_MAPPING").is_none() {
+        return;
+    }
+
+    println!("cargo:rerun-if-env-changed=STATIC_MAPPING_FILE");
+
+    let file = env::var("STATIC_MAPPING_FILE")
+        .expect("The 'static-mapping' feature requires STATIC_MAPPING_FILE to be set");
+    println!("cargo:rerun-if-changed={}", file);
+
+    let content = fs::read_to_string(&file)
+        .unwrap_or_else(|err| panic!("Failed to read mapping file {}: {}", file, err));
+
+    let mut fields = Vec::new();
+    let mut tags = Vec::new();
+    let mut types = Vec::new();
+
+    for line in content.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (key, value) = match line.split_once('=') {
+            Some((key, value)) => (key.trim(), value.trim()),
+            None => panic!("Invalid mapping line: {}", line),
+        };
+
+        if let Some(field) = key.strip_prefix("TYPE_FIELD_") {
+            types.push((field.to_lowercase(), value.to_string()));
+        } else if let Some(field) = key.strip_prefix("FIELD_") {
+            fields.push((field.to_lowercase(), value.to_string()));
+        } else if let Some(tag) = key.strip_prefix("TAG_") {
+            tags.push((tag.to_lowercase(), value.to_string()));
+        } else {
+            panic!("Unknown mapping key: {}", key);
+        }
+    }
+
+    let mut out = String::new();
+    let mut selectors = String::new();
+
+    let mut write_paths = |name: &str, entries: &[(String, String)], with_types: bool| {
+        writeln!(out, "pub const {}: &[StaticPath] = &[", name).unwrap();
+        for (i, (key, path)) in entries.iter().enumerate() {
+            let r#type = if with_types {
+                types
+                    .iter()
+                    .find(|(field, _)| field == key)
+                    .map(|(_, t)| t.as_str())
+                    .unwrap_or("")
+            } else {
+                ""
+            };
+            let function = format!("select_{}_{}", name.to_lowercase(), i);
+            writeln!(
+                out,
+                "    StaticPath {{ name: {:?}, path: {:?}, r#type: {:?}, select: {} }},",
+                key, path, r#type, function
+            )
+            .unwrap();
+            writeln!(
+                selectors,
+                "fn {}(json: &Value) -> Vec<&Value> {{\n    Some(json){}.into_iter().collect()\n}}\n",
+                function,
+                accessors(path)
+            )
+            .unwrap();
+        }
+        writeln!(out, "];\n").unwrap();
+    };
+
+    write_paths("FIELDS", &fields, true);
+    write_paths("TAGS", &tags, false);
+
+    out.push_str(&selectors);
+
+    let dest = PathBuf::from(env::var("OUT_DIR").unwrap()).join("static_mapping.rs");
+    fs::write(dest, out).expect("Failed to write generated mapping");
+}
+
+/// Translate a simple JSON path (`$.a.b[0]['c']`) into a chain of `serde_json` accessors.
+fn accessors(path: &str) -> String {
+    let rest = path
+        .strip_prefix('$')
+        .unwrap_or_else(|| panic!("JSON path must start with '$': {}", path));
+    let mut chars = rest.chars().peekable();
+    let mut result = String::new();
+
+    while let Some(c) = chars.next() {
+        let segment = match c {
+            '.' => {
+                let mut key = String::new();
+                while let Some(&c) = chars.peek() {
+                    if c == '.' || c == '[' {
+                        break;
+                    }
+                    key.push(c);
+                    chars.next();
+                }
+                if key.is_empty() || key == "*" {
+                    panic!("Unsupported JSON path for static mapping: {}", path);
+                }
+                format!("{:?}", key)
+            }
+            '[' => {
+                let mut inner = String::new();
+                for c in chars.by_ref() {
+                    if c == ']' {
+                        break;
+                    }
+                    inner.push(c);
+                }
+                let inner = inner.trim();
+                if let Ok(index) = inner.parse::<usize>() {
+                    index.to_string()
+                } else if inner.len() >= 2
+                    && ((inner.starts_with('\'') && inner.ends_with('\''))
+                        || (inner.starts_with('"') && inner.ends_with('"')))
+                {
+                    format!("{:?}", &inner[1..inner.len() - 1])
+                } else {
+                    panic!("Unsupported JSON path for static mapping: {}", path);
+                }
+            }
+            _ => panic!("Unsupported JSON path for static mapping: {}", path),
+        };
+        result.push_str(&format!(".and_then(|v| v.get({}))", segment));
+    }
+
+    result
+}
@@ -0,0 +1,392 @@
+use crate::auth::Authenticated;
+use crate::batch::Flushed;
+use crate::config::{Mapping, Path, Processor};
+use crate::denylist;
+use crate::effective;
+use crate::event;
+use crate::handler::{self, Admission, Mapped, Mapper};
+use crate::influx;
+use crate::line::Point;
+use crate::series::{Pending, SeriesKey};
+use actix_web::{web, HttpRequest, HttpResponse};
+use chrono::{DateTime, Utc};
+use cloudevents::binding::actix::HttpRequestDeserializer;
+use cloudevents::message::MessageDeserializer;
+use cloudevents::{Event, EventBuilder, EventBuilderV10};
+use influxdb::{Type, WriteQuery};
+use serde::Serialize;
+use serde_json::Value;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::scope("/admin")
+            .route("/validate", web::post().to(validate))
+            .route("/preview-series", web::post().to(preview_series))
+            .route("/flush", web::post().to(flush))
+            .route("/config", web::get().to(effective::config))
+            .route("/denylist", web::get().to(denylist::list))
+            .route("/denylist/{device:.+}", web::put().to(denylist::add))
+            .route("/denylist/{device:.+}", web::delete().to(denylist::remove)),
+    );
+}
+
+/// Write the points of open transactions, and the aggregated and batched points right away,
+/// instead of waiting for the last event, their window or the batch interval.
+async fn flush(_: Authenticated, processor: web::Data<Processor>) -> HttpResponse {
+    let mut flushed = Flushed::default();
+    if let Some(transactions) = &processor.transactions {
+        flushed.add(transactions.drain(&processor).await);
+    }
+    if let Some(aggregator) = &processor.aggregator {
+        flushed.add(aggregator.drain().await);
+    }
+    if let Some(batcher) = &processor.batcher {
+        flushed.add(batcher.drain().await);
+    }
+    log::info!(
+        "Flushed on request, {} points written, {} failed",
+        flushed.written,
+        flushed.failed
+    );
+    HttpResponse::Ok().json(flushed)
+}
+
+/// What the current mapping makes of a sample event.
+#[derive(Debug, Default, Serialize)]
+struct Report {
+    /// Processing stopped early, with this error.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    filtered: bool,
+    measurements: Vec<MeasurementReport>,
+}
+
+#[derive(Debug, Serialize)]
+struct MeasurementReport {
+    table: String,
+    retention: Option<String>,
+    fields: BTreeMap<String, PathReport>,
+    tags: BTreeMap<String, PathReport>,
+    computed: BTreeMap<String, Option<f64>>,
+    /// The point which would be written, `None` if there are no values or it failed.
+    line: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    /// The series of the point, for previewing them.
+    #[serde(skip)]
+    series: Option<SeriesKey>,
+}
+
+#[derive(Debug, Default, Serialize)]
+struct PathReport {
+    path: String,
+    expected: &'static str,
+    /// The condition (`WHEN_FIELD_`) held, if there is one.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    condition: Option<bool>,
+    matched: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    r#type: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    value: Option<String>,
+    /// The value was out of range, and dropped.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    dropped: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+/// Run a CloudEvent, or a plain JSON payload, through the mapping without writing anything.
+async fn validate(
+    _: Authenticated,
+    req: HttpRequest,
+    body: web::Bytes,
+    processor: web::Data<Processor>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let event = sample_event(&req, body)?;
+    let profile = handler::profile(&req, &processor);
+    let mut report = Report::default();
+    if let Err(err) = run(&event, profile.as_deref(), &processor, &mut report).await {
+        report.error = Some(err.to_string());
+    }
+
+    Ok(HttpResponse::Ok().json(report))
+}
+
+/// The series a sample event would be written to.
+#[derive(Debug, Serialize)]
+struct SeriesPreview {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    filtered: bool,
+    series: Vec<PreviewedSeries>,
+    /// Estimate of the distinct series written since the start.
+    observed_series: u64,
+}
+
+#[derive(Debug, Serialize)]
+struct PreviewedSeries {
+    key: String,
+    measurement: String,
+    tags: BTreeMap<String, String>,
+}
+
+/// Preview the series keys of a sample event, for planning the cardinality.
+async fn preview_series(
+    _: Authenticated,
+    req: HttpRequest,
+    body: web::Bytes,
+    processor: web::Data<Processor>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let event = sample_event(&req, body)?;
+    let profile = handler::profile(&req, &processor);
+    let mut report = Report::default();
+    if let Err(err) = run(&event, profile.as_deref(), &processor, &mut report).await {
+        report.error = Some(err.to_string());
+    }
+
+    let mut preview = SeriesPreview {
+        error: report.error,
+        filtered: report.filtered,
+        series: Vec::new(),
+        observed_series: processor.series.estimate(),
+    };
+    for measurement in report.measurements {
+        match (measurement.series, measurement.error) {
+            (Some(series), _) => preview.series.push(PreviewedSeries {
+                key: series.to_string(),
+                measurement: series.measurement,
+                tags: series.tags,
+            }),
+            // the first failing mapping stops the event
+            (None, Some(err)) if preview.error.is_none() => preview.error = Some(err),
+            _ => {}
+        }
+    }
+
+    Ok(HttpResponse::Ok().json(preview))
+}
+
+/// A CloudEvent, or a plain JSON payload wrapped into one.
+fn sample_event(req: &HttpRequest, body: web::Bytes) -> Result<Event, actix_web::Error> {
+    if event::is_cloud_event(req) {
+        HttpRequestDeserializer::new(req, body)
+            .into_event()
+            .map_err(actix_web::error::ErrorBadRequest)
+    } else {
+        let json: Value =
+            serde_json::from_slice(&body).map_err(actix_web::error::ErrorBadRequest)?;
+        EventBuilderV10::new()
+            .id("validate")
+            .source("validate")
+            .ty("validate")
+            .data("application/json", json)
+            .build()
+            .map_err(actix_web::error::ErrorBadRequest)
+    }
+}
+
+/// Map an event like the handler does, up to where the points would be written.
+async fn run(
+    event: &Event,
+    profile: Option<&str>,
+    processor: &Processor,
+    report: &mut Report,
+) -> anyhow::Result<()> {
+    let quarantine = match handler::admit(event, processor)? {
+        Admission::Admitted { quarantine } => quarantine,
+        Admission::Skipped => {
+            report.filtered = true;
+            return Ok(());
+        }
+    };
+
+    match handler::map(event, profile, quarantine, true, processor).await? {
+        Mapped::Skipped => report.filtered = true,
+        Mapped::Metrics { queries, .. } => {
+            for (table, query) in queries {
+                report.measurements.push(metric(table, &query));
+            }
+        }
+        Mapped::Records { mapper, records } => {
+            for (json, time) in &records {
+                for mapping in mapper.mappings() {
+                    report
+                        .measurements
+                        .push(measurement(processor, &mapper, mapping, json, *time));
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// A Telegraf metric, which isn't mapped with paths.
+fn metric(table: String, query: &WriteQuery) -> MeasurementReport {
+    let mut report = MeasurementReport {
+        table,
+        retention: None,
+        fields: BTreeMap::new(),
+        tags: BTreeMap::new(),
+        computed: BTreeMap::new(),
+        line: None,
+        error: None,
+        series: None,
+    };
+    let point = influx::line(query)
+        .map_err(anyhow::Error::from)
+        .and_then(|line| Ok((Point::from_query(query)?, line)));
+    match point {
+        Ok((point, line)) => {
+            report.series = Some(SeriesKey::new(
+                &point.measurement,
+                point.tags.iter().map(|(k, v)| (k, v)),
+            ));
+            report.line = Some(line);
+        }
+        Err(err) => report.error = Some(err.to_string()),
+    }
+    report
+}
+
+fn measurement(
+    processor: &Processor,
+    mapper: &Mapper,
+    mapping: &Mapping,
+    json: &Value,
+    time: DateTime<Utc>,
+) -> MeasurementReport {
+    let mut values = HashMap::new();
+    let fields = inspect(&mapping.fields, json, Some(&mut values));
+    let tags = inspect(&mapping.tags, mapper.event_json(), None);
+    let computed = mapping
+        .computed
+        .iter()
+        .map(|(field, expression)| (field.clone(), expression.eval(&values)))
+        .collect();
+
+    let mut report = MeasurementReport {
+        table: String::new(),
+        retention: mapping.retention.clone(),
+        fields,
+        tags,
+        computed,
+        line: None,
+        error: None,
+        series: None,
+    };
+
+    // what would be remembered of the series is thrown away again
+
+    let pending = RefCell::new(Pending::default());
+    let point = mapper
+        .point(mapping, json, time, &pending)
+        .map_err(anyhow::Error::from)
+        .and_then(|point| match point {
+            Some(point) => Ok(Some((influx::line(&point.query)?, point))),
+            None => Ok(None),
+        });
+
+    match point {
+        Ok(Some((line, point))) => {
+            report.table = point.table;
+            report.line = Some(line);
+            report.series = Some(point.series);
+        }
+        Ok(None) => {}
+        Err(err) => report.error = Some(err.to_string()),
+    }
+    if report.table.is_empty() {
+        report.table = mapper
+            .table(mapping, time)
+            .unwrap_or_else(|_| processor.table(mapping, time));
+    }
+
+    report
+}
+
+/// Report on each path on its own, so that one failing doesn't hide the others.
+fn inspect(
+    paths: &HashMap<String, Path>,
+    json: &Value,
+    mut values: Option<&mut HashMap<String, f64>>,
+) -> BTreeMap<String, PathReport> {
+    paths
+        .iter()
+        .map(|(name, path)| {
+            let mut report = PathReport {
+                path: path.path.clone(),
+                expected: path.r#type.name(),
+                ..Default::default()
+            };
+
+            if let Some(condition) = &path.condition {
+                match condition.holds(json) {
+                    Ok(holds) => report.condition = Some(holds),
+                    Err(err) => report.error = Some(err.to_string()),
+                }
+            }
+            if report.error.is_some() || report.condition == Some(false) {
+                return (name.clone(), report);
+            }
+
+            let sel = match path.selector.select(json) {
+                Ok(sel) => sel,
+                Err(err) => {
+                    report.error = Some(err.to_string());
+                    return (name.clone(), report);
+                }
+            };
+            report.matched = sel.len();
+
+            let value = match sel.as_slice() {
+                [] => None,
+                [v] => Some(path.value(v)),
+                [..] => path.join(&sel).or_else(|| {
+                    report.error = Some("Selector found more than one value".to_string());
+                    None
+                }),
+            };
+
+            if let Some(value) = value {
+                match value {
+                    Ok(None) => report.dropped = true,
+                    Ok(Some(value)) => {
+                        if let Some(values) = values.as_mut() {
+                            if let Some(v) = numeric(&value) {
+                                values.insert(name.clone(), v);
+                            }
+                        }
+                        report.r#type = Some(type_name(&value));
+                        report.value = Some(value.to_string());
+                    }
+                    Err(err) => report.error = Some(err.to_string()),
+                }
+            }
+
+            (name.clone(), report)
+        })
+        .collect()
+}
+
+fn numeric(value: &Type) -> Option<f64> {
+    match value {
+        Type::Float(v) => Some(*v),
+        Type::SignedInteger(v) => Some(*v as f64),
+        Type::UnsignedInteger(v) => Some(*v as f64),
+        _ => None,
+    }
+}
+
+fn type_name(value: &Type) -> &'static str {
+    match value {
+        Type::Boolean(_) => "boolean",
+        Type::Float(_) => "float",
+        Type::SignedInteger(_) => "integer",
+        Type::UnsignedInteger(_) => "unsigned",
+        Type::Text(_) => "string",
+    }
+}
@@ -0,0 +1,691 @@
+use crate::batch::Flushed;
+use crate::config::Precision;
+use crate::error::ServiceError;
+use crate::line::Point;
+use crate::metrics::POINTS_TOO_LATE;
+use crate::recent::RecentErrors;
+use crate::series::Commit;
+use crate::sink::Sink;
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use envconfig::Envconfig;
+use influxdb::{InfluxDbWriteable, Type, WriteQuery};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+
+#[derive(Envconfig, Clone, Debug)]
+pub struct AggregateConfig {
+    /// Length of the windows points are aggregated over, disabled when zero.
+    #[envconfig(from = "AGGREGATE_WINDOW_S", default = "0")]
+    pub window_s: i64,
+    /// Comma separated aggregates of numeric fields, of `min`, `max`, `mean`, `sum`, `count`
+    /// and `last`.
+    #[envconfig(from = "AGGREGATE_FUNCTIONS", default = "min,max,mean,last")]
+    pub functions: String,
+    /// Time given to late points, before the aggregates of a window are written.
+    #[envconfig(from = "AGGREGATE_GRACE_S", default = "5")]
+    pub grace_s: i64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Function {
+    Min,
+    Max,
+    Mean,
+    Sum,
+    Count,
+    Last,
+}
+
+impl FromStr for Function {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "min" => Ok(Function::Min),
+            "max" => Ok(Function::Max),
+            "mean" => Ok(Function::Mean),
+            "sum" => Ok(Function::Sum),
+            "count" => Ok(Function::Count),
+            "last" => Ok(Function::Last),
+            _ => anyhow::bail!("Unknown aggregate function: {}", s),
+        }
+    }
+}
+
+impl Function {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Function::Min => "min",
+            Function::Max => "max",
+            Function::Mean => "mean",
+            Function::Sum => "sum",
+            Function::Count => "count",
+            Function::Last => "last",
+        }
+    }
+}
+
+/// How points are aggregated, before the writer is started.
+#[derive(Clone, Debug)]
+pub struct Aggregation {
+    window: Duration,
+    grace: Duration,
+    functions: Vec<Function>,
+}
+
+impl Aggregation {
+    pub fn from_config(config: AggregateConfig) -> anyhow::Result<Option<Self>> {
+        if config.window_s <= 0 {
+            return Ok(None);
+        }
+        let functions = config
+            .functions
+            .split(',')
+            .map(str::trim)
+            .filter(|function| !function.is_empty())
+            .map(str::parse)
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        if functions.is_empty() {
+            anyhow::bail!("Missing aggregate functions");
+        }
+
+        Ok(Some(Self {
+            window: Duration::seconds(config.window_s),
+            grace: Duration::seconds(config.grace_s.max(0)),
+            functions,
+        }))
+    }
+}
+
+/// Series a point belongs to, and the start of its window.
+type Key = (Option<String>, String, Vec<(String, String)>, DateTime<Utc>);
+
+/// Aggregates of a field, in a window.
+#[derive(Debug, Serialize, Deserialize)]
+pub enum Aggregate {
+    Numeric {
+        min: f64,
+        max: f64,
+        sum: f64,
+        count: i64,
+        #[serde(with = "crate::checkpoint::field_value")]
+        last: Type,
+    },
+    Other(#[serde(with = "crate::checkpoint::field_value")] Type),
+}
+
+/// The aggregates of a series in a window, and what is pending of the events they are of.
+#[derive(Debug, Default)]
+struct Bucket {
+    fields: BTreeMap<String, Aggregate>,
+    commits: Vec<Commit>,
+}
+
+/// The aggregates of a series in a window, as persisted in a checkpoint.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct WindowEntry {
+    pub retention: Option<String>,
+    pub measurement: String,
+    pub tags: Vec<(String, String)>,
+    pub start: DateTime<Utc>,
+    pub fields: BTreeMap<String, Aggregate>,
+}
+
+/// Buffers points per series and window, writing aggregates of their fields instead.
+///
+/// Numeric fields get a field for each function, like `temperature_mean`, others only their
+/// last value, as `<field>_last`. The aggregates are written with the start of the window as
+/// their time, once the window and the grace period after it have passed. Points arriving
+/// later than that are dropped, and counted, as their aggregates would replace those written
+/// before. Windows which fail to be written are kept, and written again with the next ones.
+#[derive(Clone, Debug)]
+pub struct Aggregator {
+    aggregation: Arc<Aggregation>,
+    precision: Precision,
+    sink: Arc<dyn Sink>,
+    errors: RecentErrors,
+    buckets: Arc<Mutex<HashMap<Key, Bucket>>>,
+}
+
+impl Aggregator {
+    /// Start writing the aggregates on the current arbiter.
+    pub fn start(
+        aggregation: Aggregation,
+        precision: Precision,
+        sink: Arc<dyn Sink>,
+        errors: RecentErrors,
+    ) -> Self {
+        let aggregator = Self {
+            aggregation: Arc::new(aggregation),
+            precision,
+            sink,
+            errors,
+            buckets: Default::default(),
+        };
+
+        actix_rt::spawn({
+            let aggregator = aggregator.clone();
+            async move {
+                let mut interval = actix_rt::time::interval(std::time::Duration::from_secs(1));
+                loop {
+                    interval.tick().await;
+                    let closed =
+                        Utc::now() - aggregator.aggregation.window - aggregator.aggregation.grace;
+                    aggregator.write(|start| *start <= closed).await;
+                }
+            }
+        });
+
+        aggregator
+    }
+
+    /// Add the points to their windows, committing what is pending of their event once the
+    /// windows were written.
+    pub fn push(
+        &self,
+        queries: &[(Option<String>, WriteQuery)],
+        commit: Commit,
+    ) -> Result<(), ServiceError> {
+        let points = queries
+            .iter()
+            .map(|(retention, query)| Ok((retention, Point::from_query(query)?)))
+            .collect::<anyhow::Result<Vec<_>>>()
+            .map_err(|err| ServiceError::WriteError {
+                details: err.to_string(),
+            })?;
+
+        let window = self
+            .aggregation
+            .window
+            .num_nanoseconds()
+            .unwrap_or(i64::MAX);
+        let closed = Utc::now() - self.aggregation.window - self.aggregation.grace;
+        let mut buckets = self.buckets.lock().unwrap();
+        for (retention, point) in points {
+            let time = point.time.unwrap_or_else(Utc::now);
+            let nanos = time.timestamp_nanos_opt().unwrap_or_default();
+            let start = Utc.timestamp_nanos(nanos - nanos.rem_euclid(window));
+            if start <= closed {
+                log::debug!(
+                    "Dropping late point of {}, its window was written",
+                    point.measurement
+                );
+                POINTS_TOO_LATE
+                    .with_label_values(&[&point.measurement])
+                    .inc();
+                commit.fail();
+                continue;
+            }
+
+            let mut tags = point.tags;
+            tags.sort();
+            let bucket = buckets
+                .entry((retention.clone(), point.measurement, tags, start))
+                .or_default();
+            for (field, value) in point.fields {
+                add(&mut bucket.fields, field, value);
+            }
+            if !bucket.commits.last().is_some_and(|last| last.is(&commit)) {
+                bucket.commits.push(commit.clone());
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Write the aggregates of all windows, open or not.
+    pub async fn drain(&self) -> Flushed {
+        self.write(|_| true).await
+    }
+
+    async fn write<F>(&self, closed: F) -> Flushed
+    where
+        F: Fn(&DateTime<Utc>) -> bool,
+    {
+        let buckets = {
+            let mut buckets = self.buckets.lock().unwrap();
+            let keys = buckets
+                .keys()
+                .filter(|(_, _, _, start)| closed(start))
+                .cloned()
+                .collect::<Vec<_>>();
+            keys.into_iter()
+                .filter_map(|key| buckets.remove_entry(&key))
+                .collect::<Vec<_>>()
+        };
+        if buckets.is_empty() {
+            return Flushed::default();
+        }
+
+        let queries = buckets
+            .iter()
+            .map(|((retention, measurement, tags, start), bucket)| {
+                let mut query = self.precision.timestamp(*start).into_query(measurement);
+                for (tag, value) in tags {
+                    query = query.add_tag(tag, value.clone());
+                }
+                for (field, aggregate) in &bucket.fields {
+                    for (name, value) in self.values(field, aggregate) {
+                        query = query.add_field(name, value);
+                    }
+                }
+                (retention.clone(), query)
+            })
+            .collect::<Vec<_>>();
+
+        log::debug!("Writing {} aggregated points", queries.len());
+
+        match self.sink.write(&queries).await {
+            Ok(_) => Flushed {
+                written: queries.len(),
+                failed: 0,
+            },
+            Err(err) => {
+                log::warn!(
+                    "Failed to write {} aggregated points, keeping them for the next attempt: {}",
+                    queries.len(),
+                    err
+                );
+                self.errors.record(None, "WriteError", &err);
+                self.requeue(buckets);
+                Flushed {
+                    written: 0,
+                    failed: queries.len(),
+                }
+            }
+        }
+    }
+
+    /// The windows which haven't been written yet.
+    pub fn snapshot(&self) -> Vec<WindowEntry> {
+        let buckets = std::mem::take(&mut *self.buckets.lock().unwrap());
+        buckets
+            .into_iter()
+            .map(
+                |((retention, measurement, tags, start), bucket)| WindowEntry {
+                    retention,
+                    measurement,
+                    tags,
+                    start,
+                    fields: bucket.fields,
+                },
+            )
+            .collect()
+    }
+
+    /// Windows of a checkpoint, those which closed meanwhile are written with the next ones.
+    pub fn restore(&self, entries: Vec<WindowEntry>) {
+        self.requeue(
+            entries
+                .into_iter()
+                .map(|entry| {
+                    let key = (entry.retention, entry.measurement, entry.tags, entry.start);
+                    let bucket = Bucket {
+                        fields: entry.fields,
+                        commits: Vec::new(),
+                    };
+                    (key, bucket)
+                })
+                .collect(),
+        );
+    }
+
+    /// Put back windows which failed to be written, merging what arrived for them meanwhile.
+    fn requeue(&self, failed: Vec<(Key, Bucket)>) {
+        let mut buckets = self.buckets.lock().unwrap();
+        for (key, failed) in failed {
+            let bucket = buckets.entry(key).or_default();
+            for (field, aggregate) in failed.fields {
+                let aggregate = match bucket.fields.remove(&field) {
+                    Some(newer) => merge(aggregate, newer),
+                    None => aggregate,
+                };
+                bucket.fields.insert(field, aggregate);
+            }
+            bucket.commits.extend(failed.commits);
+        }
+    }
+
+    fn values(&self, field: &str, aggregate: &Aggregate) -> Vec<(String, Type)> {
+        let name = |function: Function| format!("{}_{}", field, function.name());
+        match aggregate {
+            Aggregate::Numeric {
+                min,
+                max,
+                sum,
+                count,
+                last,
+            } => self
+                .aggregation
+                .functions
+                .iter()
+                .map(|function| {
+                    let value = match function {
+                        Function::Min => Type::Float(*min),
+                        Function::Max => Type::Float(*max),
+                        Function::Mean => Type::Float(sum / *count as f64),
+                        Function::Sum => Type::Float(*sum),
+                        Function::Count => Type::SignedInteger(*count),
+                        Function::Last => last.clone(),
+                    };
+                    (name(*function), value)
+                })
+                .collect(),
+            Aggregate::Other(last) => vec![(name(Function::Last), last.clone())],
+        }
+    }
+}
+
+fn add(bucket: &mut BTreeMap<String, Aggregate>, field: String, value: Type) {
+    let number = match value {
+        Type::Float(v) => Some(v),
+        Type::SignedInteger(v) => Some(v as f64),
+        Type::UnsignedInteger(v) => Some(v as f64),
+        _ => None,
+    };
+
+    let aggregate = match (bucket.remove(&field), number) {
+        (
+            Some(Aggregate::Numeric {
+                min,
+                max,
+                sum,
+                count,
+                ..
+            }),
+            Some(number),
+        ) => Aggregate::Numeric {
+            min: min.min(number),
+            max: max.max(number),
+            sum: sum + number,
+            count: count + 1,
+            last: value,
+        },
+        // the field changed its type, only the last value is left
+        (Some(_), _) => Aggregate::Other(value),
+        (None, Some(number)) => Aggregate::Numeric {
+            min: number,
+            max: number,
+            sum: number,
+            count: 1,
+            last: value,
+        },
+        (None, None) => Aggregate::Other(value),
+    };
+    bucket.insert(field, aggregate);
+}
+
+/// Aggregates of the same field and window, `newer` having the last value.
+fn merge(older: Aggregate, newer: Aggregate) -> Aggregate {
+    match (older, newer) {
+        (
+            Aggregate::Numeric {
+                min,
+                max,
+                sum,
+                count,
+                ..
+            },
+            Aggregate::Numeric {
+                min: newer_min,
+                max: newer_max,
+                sum: newer_sum,
+                count: newer_count,
+                last,
+            },
+        ) => Aggregate::Numeric {
+            min: min.min(newer_min),
+            max: max.max(newer_max),
+            sum: sum + newer_sum,
+            count: count + newer_count,
+            last,
+        },
+        // like a field changing its type, only the last value is left
+        (_, Aggregate::Numeric { last, .. }) | (_, Aggregate::Other(last)) => {
+            Aggregate::Other(last)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::recent::RecentErrorsConfig;
+    use futures::future::LocalBoxFuture;
+    use futures::FutureExt;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Records the points written, failing the first `failures` writes.
+    #[derive(Debug, Default)]
+    struct TestSink {
+        points: Mutex<Vec<String>>,
+        failures: AtomicUsize,
+    }
+
+    impl Sink for TestSink {
+        fn write<'a>(
+            &'a self,
+            points: &'a [(Option<String>, WriteQuery)],
+        ) -> LocalBoxFuture<'a, Result<(), influxdb::Error>> {
+            let failed = self
+                .failures
+                .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1))
+                .is_ok();
+            let result = match failed {
+                true => Err(influxdb::Error::ConnectionError {
+                    error: "unavailable".into(),
+                }),
+                false => {
+                    let mut written = self.points.lock().unwrap();
+                    for (_, query) in points {
+                        written.push(crate::influx::line(query).unwrap());
+                    }
+                    Ok(())
+                }
+            };
+            futures::future::ready(result).boxed_local()
+        }
+    }
+
+    const WINDOW_S: i64 = 3600;
+
+    fn aggregation(functions: &str) -> Aggregation {
+        Aggregation::from_config(AggregateConfig {
+            window_s: WINDOW_S,
+            functions: functions.into(),
+            grace_s: 5,
+        })
+        .unwrap()
+        .unwrap()
+    }
+
+    fn start(functions: &str, sink: &Arc<TestSink>) -> Aggregator {
+        let errors = RecentErrors::from_config(RecentErrorsConfig { size: 10 });
+        Aggregator::start(
+            aggregation(functions),
+            Precision::Seconds,
+            sink.clone(),
+            errors,
+        )
+    }
+
+    /// Start of the current window, in seconds.
+    fn window() -> i64 {
+        let now = Utc::now().timestamp();
+        now - now.rem_euclid(WINDOW_S)
+    }
+
+    fn point(
+        time: i64,
+        tags: &[(&str, &str)],
+        value: impl Into<Type>,
+    ) -> (Option<String>, WriteQuery) {
+        let mut query = influxdb::Timestamp::Seconds(time as u128).into_query("m");
+        for (tag, value) in tags {
+            query = query.add_tag(*tag, *value);
+        }
+        (None, query.add_field("v", value.into()))
+    }
+
+    fn written(sink: &TestSink) -> Vec<String> {
+        let mut written = sink.points.lock().unwrap().clone();
+        written.sort();
+        written
+    }
+
+    #[test]
+    fn test_config() {
+        let config = |window_s, functions: &str| AggregateConfig {
+            window_s,
+            functions: functions.into(),
+            grace_s: 5,
+        };
+        assert!(Aggregation::from_config(config(0, "min"))
+            .unwrap()
+            .is_none());
+        let aggregation = Aggregation::from_config(config(60, " Sum, count ,"))
+            .unwrap()
+            .unwrap();
+        assert_eq!(aggregation.functions, vec![Function::Sum, Function::Count]);
+        assert!(Aggregation::from_config(config(60, "median")).is_err());
+        assert!(Aggregation::from_config(config(60, " , ")).is_err());
+    }
+
+    #[test]
+    fn test_aggregate() {
+        actix_rt::System::new("test").block_on(async {
+            let sink = Arc::new(TestSink::default());
+            let aggregator = start("min,max,mean,sum,count,last", &sink);
+            let start = window();
+            aggregator
+                .push(
+                    &[
+                        point(start + 1, &[("room", "a")], 1i64),
+                        point(start + 2, &[("room", "a")], 3.5),
+                        point(start + 3, &[("room", "b")], 2i64),
+                    ],
+                    Commit::default(),
+                )
+                .unwrap();
+            aggregator
+                .push(
+                    &[point(start + 4, &[("room", "a")], 4.5)],
+                    Commit::default(),
+                )
+                .unwrap();
+
+            let flushed = aggregator.drain().await;
+            assert_eq!((flushed.written, flushed.failed), (2, 0));
+            assert_eq!(
+                written(&sink),
+                vec![
+                    format!(
+                        "m,room=a v_min=1,v_max=4.5,v_mean=3,v_sum=9,v_count=3i,v_last=4.5 {}",
+                        start
+                    ),
+                    format!(
+                        "m,room=b v_min=2,v_max=2,v_mean=2,v_sum=2,v_count=1i,v_last=2i {}",
+                        start
+                    ),
+                ]
+            );
+            assert_eq!(aggregator.drain().await.written, 0);
+        });
+    }
+
+    #[test]
+    fn test_windows() {
+        actix_rt::System::new("test").block_on(async {
+            let sink = Arc::new(TestSink::default());
+            let aggregator = start("max", &sink);
+            let start = window();
+            // tags in any order, are of the same series
+            let points = [
+                point(start, &[("a", "1"), ("b", "2")], 1i64),
+                point(start + 1, &[("b", "2"), ("a", "1")], 2i64),
+                point(start + WINDOW_S, &[("a", "1"), ("b", "2")], 3i64),
+                point(start + 2, &[("a", "1"), ("b", "2")], "text"),
+            ];
+            aggregator.push(&points, Commit::default()).unwrap();
+
+            aggregator.drain().await;
+            assert_eq!(
+                written(&sink),
+                vec![
+                    // the field changed its type
+                    format!("m,a=1,b=2 v_last=\"text\" {}", start),
+                    format!("m,a=1,b=2 v_max=3 {}", start + WINDOW_S),
+                ]
+            );
+        });
+    }
+
+    #[test]
+    fn test_late() {
+        actix_rt::System::new("test").block_on(async {
+            let sink = Arc::new(TestSink::default());
+            let aggregator = start("max", &sink);
+            // the window and its grace period passed
+            let late = window() - WINDOW_S - 10;
+            aggregator
+                .push(&[point(late, &[], 1i64)], Commit::default())
+                .unwrap();
+            assert_eq!(aggregator.drain().await.written, 0);
+            assert!(written(&sink).is_empty());
+        });
+    }
+
+    #[test]
+    fn test_requeue() {
+        actix_rt::System::new("test").block_on(async {
+            let sink = Arc::new(TestSink::default());
+            sink.failures.store(1, Ordering::SeqCst);
+            let aggregator = start("max,count", &sink);
+            let start = window();
+            aggregator
+                .push(&[point(start, &[], 1i64)], Commit::default())
+                .unwrap();
+
+            let flushed = aggregator.drain().await;
+            assert_eq!((flushed.written, flushed.failed), (0, 1));
+
+            // merged with what arrived meanwhile
+            aggregator
+                .push(&[point(start + 1, &[], 5i64)], Commit::default())
+                .unwrap();
+            let flushed = aggregator.drain().await;
+            assert_eq!((flushed.written, flushed.failed), (1, 0));
+            assert_eq!(
+                written(&sink),
+                vec![format!("m v_max=5,v_count=2i {}", start)]
+            );
+        });
+    }
+
+    #[test]
+    fn test_snapshot() {
+        actix_rt::System::new("test").block_on(async {
+            let sink = Arc::new(TestSink::default());
+            let aggregator = start("sum", &sink);
+            let start = window();
+            aggregator
+                .push(&[point(start, &[], 2i64)], Commit::default())
+                .unwrap();
+
+            let entries = aggregator.snapshot();
+            assert_eq!(entries.len(), 1);
+            assert_eq!(aggregator.drain().await.written, 0);
+
+            let entries = serde_json::from_value(serde_json::to_value(entries).unwrap()).unwrap();
+            let restored = self::start("sum", &sink);
+            restored.restore(entries);
+            restored
+                .push(&[point(start + 1, &[], 3i64)], Commit::default())
+                .unwrap();
+            restored.drain().await;
+            assert_eq!(written(&sink), vec![format!("m v_sum=5 {}", start)]);
+        });
+    }
+}
@@ -0,0 +1,63 @@
+use crate::metrics::{EVENTS_OUT_OF_ORDER, EVENT_AGE};
+use chrono::{DateTime, Utc};
+use cloudevents::{AttributesReader, Event};
+use envconfig::Envconfig;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+#[derive(Envconfig, Clone, Debug)]
+pub struct ArrivalConfig {
+    /// Number of devices (event sources) to track the order of events for.
+    #[envconfig(from = "ARRIVAL_MAX_DEVICES", default = "10000")]
+    pub max_devices: usize,
+}
+
+/// Records how old events are when they arrive, and whether they arrive in order.
+#[derive(Clone, Debug)]
+pub struct Arrivals {
+    max_devices: usize,
+    last: Arc<Mutex<HashMap<String, DateTime<Utc>>>>,
+}
+
+impl Arrivals {
+    pub fn from_config(config: ArrivalConfig) -> Self {
+        Self {
+            max_devices: config.max_devices,
+            last: Default::default(),
+        }
+    }
+
+    pub fn observe(&self, event: &Event) {
+        let time = match event.time() {
+            Some(time) => *time,
+            None => return,
+        };
+
+        // clocks running ahead end up in the lowest bucket
+        let age = (Utc::now() - time).to_std().unwrap_or_default();
+        EVENT_AGE.observe(age.as_secs_f64());
+
+        if self.max_devices == 0 {
+            return;
+        }
+
+        let mut last = self.last.lock().unwrap();
+        let device = event.source().to_string();
+        if !last.contains_key(&device) && last.len() >= self.max_devices {
+            // make room, we don't track which one is the oldest
+            if let Some(evict) = last.keys().next().cloned() {
+                last.remove(&evict);
+            }
+        }
+
+        match last.get_mut(&device) {
+            Some(previous) if time < *previous => {
+                EVENTS_OUT_OF_ORDER.with_label_values(&[event.ty()]).inc();
+            }
+            Some(previous) => *previous = time,
+            None => {
+                last.insert(device, time);
+            }
+        }
+    }
+}
@@ -0,0 +1,252 @@
+use crate::error::ServiceError;
+use actix_web::dev::Payload;
+use actix_web::http::header;
+use actix_web::{web, FromRequest, HttpRequest};
+use envconfig::Envconfig;
+use futures::future::{ready, Ready};
+
+#[derive(Envconfig, Clone, Debug)]
+pub struct AuthConfig {
+    #[envconfig(from = "AUTH_TOKEN")]
+    pub token: Option<String>,
+    #[envconfig(from = "AUTH_BASIC_USERNAME")]
+    pub basic_username: Option<String>,
+    #[envconfig(from = "AUTH_BASIC_PASSWORD")]
+    pub basic_password: Option<String>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Authenticator {
+    token: Option<String>,
+    basic: Option<(String, String)>,
+}
+
+impl Authenticator {
+    pub fn from_config(config: AuthConfig) -> anyhow::Result<Self> {
+        let basic = match (config.basic_username, config.basic_password) {
+            (Some(username), Some(password)) => Some((username, password)),
+            (None, None) => None,
+            _ => anyhow::bail!("AUTH_BASIC_USERNAME and AUTH_BASIC_PASSWORD must be set together"),
+        };
+
+        Ok(Self {
+            token: config.token,
+            basic,
+        })
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.token.is_some() || self.basic.is_some()
+    }
+
+    pub fn authenticate(&self, req: &HttpRequest) -> Result<(), ServiceError> {
+        if !self.is_enabled() {
+            return Ok(());
+        }
+
+        let value = req
+            .headers()
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .ok_or_else(|| self.unauthorized("Missing credentials"))?;
+
+        let authenticated = match value.split_once(' ') {
+            Some((scheme, token)) if scheme.eq_ignore_ascii_case("bearer") => self
+                .token
+                .as_ref()
+                .is_some_and(|expected| constant_time_eq(expected, token.trim())),
+            Some((scheme, credentials)) if scheme.eq_ignore_ascii_case("basic") => {
+                self.basic.as_ref().is_some_and(|(username, password)| {
+                    base64::decode(credentials.trim())
+                        .ok()
+                        .and_then(|decoded| String::from_utf8(decoded).ok())
+                        .and_then(|decoded| {
+                            decoded
+                                .split_once(':')
+                                .map(|(u, p)| (u.to_string(), p.to_string()))
+                        })
+                        .is_some_and(|(u, p)| {
+                            // evaluate both to not leak which part was wrong
+                            constant_time_eq(username, &u) & constant_time_eq(password, &p)
+                        })
+                })
+            }
+            _ => false,
+        };
+
+        if authenticated {
+            Ok(())
+        } else {
+            Err(self.unauthorized("Invalid credentials"))
+        }
+    }
+
+    fn unauthorized(&self, details: &str) -> ServiceError {
+        let challenge = if self.basic.is_some() {
+            r#"Basic realm="drogue-influxdb-function""#
+        } else {
+            "Bearer"
+        };
+        ServiceError::Unauthorized {
+            details: details.to_string(),
+            challenge: challenge.to_string(),
+        }
+    }
+}
+
+fn constant_time_eq(expected: &str, provided: &str) -> bool {
+    let expected = expected.as_bytes();
+    let provided = provided.as_bytes();
+
+    if expected.len() != provided.len() {
+        return false;
+    }
+
+    expected
+        .iter()
+        .zip(provided)
+        .fold(0u8, |acc, (a, b)| acc | (a ^ b))
+        == 0
+}
+
+/// Extractor rejecting requests which don't pass the configured [`Authenticator`].
+///
+/// Put it first in the handler's arguments, so that the payload isn't read for
+/// unauthenticated requests.
+pub struct Authenticated;
+
+impl FromRequest for Authenticated {
+    type Config = ();
+    type Error = ServiceError;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let result = match req.app_data::<web::Data<Authenticator>>() {
+            Some(authenticator) => authenticator.authenticate(req).map(|_| Authenticated),
+            // fail closed, the authenticator is registered for every listener
+            None => Err(ServiceError::Unauthorized {
+                details: "No authenticator configured".to_string(),
+                challenge: "Bearer".to_string(),
+            }),
+        };
+        ready(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+
+    fn authenticator(token: Option<&str>, basic: Option<(&str, &str)>) -> Authenticator {
+        Authenticator::from_config(AuthConfig {
+            token: token.map(String::from),
+            basic_username: basic.map(|(u, _)| u.to_string()),
+            basic_password: basic.map(|(_, p)| p.to_string()),
+        })
+        .unwrap()
+    }
+
+    fn request(authorization: Option<&str>) -> HttpRequest {
+        let request = TestRequest::default();
+        match authorization {
+            Some(value) => request.header(header::AUTHORIZATION, value),
+            None => request,
+        }
+        .to_http_request()
+    }
+
+    fn details(result: Result<(), ServiceError>) -> String {
+        match result {
+            Err(ServiceError::Unauthorized { details, .. }) => details,
+            other => panic!("Unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_disabled() {
+        let authenticator = authenticator(None, None);
+        assert!(!authenticator.is_enabled());
+        assert!(authenticator.authenticate(&request(None)).is_ok());
+    }
+
+    #[test]
+    fn test_bearer() {
+        let authenticator = authenticator(Some("s3cret"), None);
+        assert!(authenticator
+            .authenticate(&request(Some("Bearer s3cret")))
+            .is_ok());
+        assert!(authenticator
+            .authenticate(&request(Some("bearer  s3cret ")))
+            .is_ok());
+        assert_eq!(
+            details(authenticator.authenticate(&request(None))),
+            "Missing credentials"
+        );
+        assert_eq!(
+            details(authenticator.authenticate(&request(Some("Bearer s3cre")))),
+            "Invalid credentials"
+        );
+        assert_eq!(
+            details(authenticator.authenticate(&request(Some("s3cret")))),
+            "Invalid credentials"
+        );
+        // basic credentials aren't accepted without a username and password
+        assert!(authenticator
+            .authenticate(&request(Some("Basic dTpz")))
+            .is_err());
+    }
+
+    #[test]
+    fn test_basic() {
+        let authenticator = authenticator(None, Some(("user", "pa:ss")));
+        let basic = |credentials: &str| format!("Basic {}", base64::encode(credentials));
+        assert!(authenticator
+            .authenticate(&request(Some(&basic("user:pa:ss"))))
+            .is_ok());
+        assert!(authenticator
+            .authenticate(&request(Some(&basic("user:pa"))))
+            .is_err());
+        assert!(authenticator
+            .authenticate(&request(Some(&basic("other:pa:ss"))))
+            .is_err());
+        assert!(authenticator
+            .authenticate(&request(Some("Basic not-base64")))
+            .is_err());
+        assert!(authenticator
+            .authenticate(&request(Some("Bearer user")))
+            .is_err());
+
+        match authenticator.authenticate(&request(None)) {
+            Err(ServiceError::Unauthorized { challenge, .. }) => {
+                assert!(challenge.starts_with("Basic"))
+            }
+            other => panic!("Unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_incomplete_basic() {
+        assert!(Authenticator::from_config(AuthConfig {
+            token: None,
+            basic_username: Some("user".into()),
+            basic_password: None,
+        })
+        .is_err());
+    }
+
+    #[test]
+    fn test_extractor() {
+        let (request, mut payload) = TestRequest::default().to_http_parts();
+        let result =
+            futures::executor::block_on(Authenticated::from_request(&request, &mut payload));
+        assert!(result.is_err());
+
+        let (request, mut payload) = TestRequest::default()
+            .app_data(web::Data::new(authenticator(None, None)))
+            .to_http_parts();
+        let result =
+            futures::executor::block_on(Authenticated::from_request(&request, &mut payload));
+        assert!(result.is_ok());
+    }
+}
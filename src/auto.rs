@@ -0,0 +1,87 @@
+use crate::filter;
+use envconfig::Envconfig;
+use influxdb::Type;
+use serde_json::Value;
+
+#[derive(Envconfig, Clone, Debug)]
+pub struct AutoFieldsConfig {
+    /// Write everything in the payload as fields of the default measurement.
+    #[envconfig(from = "AUTO_FIELDS", default = "false")]
+    pub enabled: bool,
+    /// Comma separated globs of flattened keys, like `sensors.*`, all if empty.
+    #[envconfig(from = "AUTO_FIELDS_INCLUDE", default = "")]
+    pub include: String,
+    #[envconfig(from = "AUTO_FIELDS_EXCLUDE", default = "")]
+    pub exclude: String,
+    #[envconfig(from = "AUTO_FIELDS_SEPARATOR", default = ".")]
+    pub separator: String,
+}
+
+/// Flattens the payload into fields, joining the keys of nested objects and array indices.
+///
+/// Types are inferred like for fields without `TYPE_FIELD_`, `null`s are left out. Fields
+/// which are mapped explicitly take precedence.
+#[derive(Clone, Debug)]
+pub struct AutoFields {
+    include: Vec<String>,
+    exclude: Vec<String>,
+    separator: String,
+}
+
+impl AutoFields {
+    pub fn from_config(config: AutoFieldsConfig) -> Option<Self> {
+        if !config.enabled {
+            return None;
+        }
+        Some(Self {
+            include: filter::split(Some(config.include)),
+            exclude: filter::split(Some(config.exclude)),
+            separator: config.separator,
+        })
+    }
+
+    pub fn fields(&self, json: &Value) -> Vec<(String, Type)> {
+        let mut fields = Vec::new();
+        self.flatten(String::new(), json, &mut fields);
+        fields
+    }
+
+    fn flatten(&self, key: String, value: &Value, fields: &mut Vec<(String, Type)>) {
+        let child = |name: &str| match key.is_empty() {
+            true => name.to_string(),
+            false => format!("{}{}{}", key, self.separator, name),
+        };
+
+        let value = match value {
+            Value::Object(object) => {
+                for (name, value) in object {
+                    self.flatten(child(name), value, fields);
+                }
+                return;
+            }
+            Value::Array(array) => {
+                for (index, value) in array.iter().enumerate() {
+                    self.flatten(child(&index.to_string()), value, fields);
+                }
+                return;
+            }
+            Value::Null => return,
+            Value::Bool(b) => Type::Boolean(*b),
+            Value::String(s) => Type::Text(s.clone()),
+            Value::Number(n) => match n.as_f64() {
+                Some(n) => Type::Float(n),
+                None => return,
+            },
+        };
+
+        if key.is_empty() || !self.accept(&key) {
+            return;
+        }
+        fields.push((key, value));
+    }
+
+    fn accept(&self, key: &str) -> bool {
+        let any = |patterns: &[String]| patterns.iter().any(|pattern| filter::glob(pattern, key));
+        (self.include.is_empty() || any(&self.include)) && !any(&self.exclude)
+    }
+}
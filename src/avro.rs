@@ -0,0 +1,701 @@
+use crate::error::ServiceError;
+use crate::filter;
+use cloudevents::event::Data;
+use envconfig::Envconfig;
+use serde_json::{Map, Number, Value};
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+#[derive(Envconfig, Clone)]
+pub struct AvroConfig {
+    /// Confluent compatible schema registry, like `http://registry:8081`, disabled if unset.
+    #[envconfig(from = "SCHEMA_REGISTRY_URL")]
+    pub url: Option<String>,
+    #[envconfig(from = "SCHEMA_REGISTRY_USERNAME")]
+    pub username: Option<String>,
+    #[envconfig(from = "SCHEMA_REGISTRY_PASSWORD")]
+    pub password: Option<String>,
+    #[envconfig(from = "SCHEMA_REGISTRY_TIMEOUT_S", default = "10")]
+    pub timeout_s: u64,
+    /// Comma separated content types of Avro payloads, a trailing `*` matches a prefix.
+    #[envconfig(
+        from = "AVRO_CONTENT_TYPES",
+        default = "application/avro,avro/binary,application/vnd.apache.avro+binary"
+    )]
+    pub content_types: String,
+}
+
+impl fmt::Debug for AvroConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AvroConfig")
+            .field("url", &self.url)
+            .field("username", &self.username)
+            .field("timeout_s", &self.timeout_s)
+            .field("content_types", &self.content_types)
+            .finish()
+    }
+}
+
+/// Decodes Avro payloads in the Confluent wire format, with the schema from the registry.
+///
+/// Payloads start with a zero byte and the ID of the writer schema, as four bytes in big
+/// endian. Schemas are fetched once and kept, they don't change for an ID. Records and maps
+/// become objects, unions their value, enums their symbol and bytes base64 strings. Decimals
+/// become numbers.
+#[derive(Clone)]
+pub struct Avro {
+    url: String,
+    username: Option<String>,
+    password: Option<String>,
+    content_types: Vec<String>,
+    client: reqwest::Client,
+    schemas: Arc<Mutex<HashMap<u32, Arc<Schemas>>>>,
+}
+
+impl fmt::Debug for Avro {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Avro")
+            .field("url", &self.url)
+            .field("content_types", &self.content_types)
+            .finish()
+    }
+}
+
+impl Avro {
+    pub fn from_config(config: AvroConfig) -> anyhow::Result<Option<Self>> {
+        let url = match config.url {
+            Some(url) => url.trim_end_matches('/').to_string(),
+            None => return Ok(None),
+        };
+        reqwest::Url::parse(&url)
+            .map_err(|err| anyhow::anyhow!("Invalid SCHEMA_REGISTRY_URL: {}", err))?;
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(config.timeout_s))
+            .build()?;
+
+        Ok(Some(Self {
+            url,
+            username: config.username,
+            password: config.password,
+            content_types: filter::split(Some(config.content_types)),
+            client,
+            schemas: Default::default(),
+        }))
+    }
+
+    pub fn is_avro(&self, content_type: Option<&str>) -> bool {
+        let mime = content_type
+            .and_then(|content_type| content_type.split(';').next())
+            .unwrap_or_default()
+            .trim()
+            .to_lowercase();
+        !mime.is_empty() && filter::matches(&self.content_types, &mime)
+    }
+
+    pub async fn decode(&self, data: Option<&Data>) -> Result<Value, ServiceError> {
+        let data = match data {
+            Some(Data::Binary(b)) => b.as_slice(),
+            Some(Data::String(s)) => s.as_bytes(),
+            _ => return Err(error("Missing binary payload")),
+        };
+        let id = match data {
+            [0, a, b, c, d, ..] => u32::from_be_bytes([*a, *b, *c, *d]),
+            _ => return Err(error("Missing magic byte and schema ID")),
+        };
+
+        let schemas = self.schemas(id).await?;
+        let mut decoder = Decoder {
+            data: &data[5..],
+            schemas: &schemas,
+            depth: 0,
+        };
+        let value = decoder.value(&schemas.root)?;
+        if !decoder.data.is_empty() {
+            return Err(error("Trailing data"));
+        }
+        Ok(value)
+    }
+
+    async fn schemas(&self, id: u32) -> Result<Arc<Schemas>, ServiceError> {
+        if let Some(schemas) = self.schemas.lock().unwrap().get(&id) {
+            return Ok(schemas.clone());
+        }
+
+        let unavailable = |err: reqwest::Error| ServiceError::SchemaLookupFailed {
+            details: format!("Schema {}: {}", id, err),
+        };
+        let mut request = self.client.get(&format!("{}/schemas/ids/{}", self.url, id));
+        if let Some(username) = &self.username {
+            request = request.basic_auth(username, self.password.as_ref());
+        }
+        let response = request.send().await.map_err(unavailable)?;
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            return Err(error(format!("Unknown schema: {}", id)));
+        }
+        let body = response
+            .error_for_status()
+            .map_err(unavailable)?
+            .bytes()
+            .await
+            .map_err(unavailable)?;
+        let body: Value = serde_json::from_slice(&body)
+            .map_err(|err| error(format!("Invalid response for schema {}: {}", id, err)))?;
+
+        let schema = body
+            .get("schema")
+            .and_then(Value::as_str)
+            .ok_or_else(|| error(format!("Registry didn't return schema {}", id)))?;
+        let schema = serde_json::from_str(schema)
+            .map_err(|err| error(format!("Invalid schema {}: {}", id, err)))?;
+        let schemas = Arc::new(
+            Schemas::parse(&schema)
+                .map_err(|err| error(format!("Invalid schema {}: {}", id, err)))?,
+        );
+
+        self.schemas.lock().unwrap().insert(id, schemas.clone());
+        Ok(schemas)
+    }
+}
+
+#[derive(Debug)]
+enum Schema {
+    Null,
+    Boolean,
+    Int,
+    Long,
+    Float,
+    Double,
+    Bytes,
+    String,
+    Record(Vec<(String, Schema)>),
+    Enum(Vec<String>),
+    Array(Box<Schema>),
+    Map(Box<Schema>),
+    Union(Vec<Schema>),
+    Fixed(usize),
+    Decimal {
+        scale: u32,
+        size: Option<usize>,
+    },
+    /// A named type, defined before.
+    Named(String),
+}
+
+/// A schema, with the named types it defines.
+#[derive(Debug)]
+struct Schemas {
+    root: Schema,
+    named: HashMap<String, Schema>,
+}
+
+impl Schemas {
+    fn parse(schema: &Value) -> anyhow::Result<Self> {
+        let mut named = HashMap::new();
+        let root = parse(schema, "", &mut named)?;
+        Ok(Self { root, named })
+    }
+}
+
+fn parse(
+    schema: &Value,
+    namespace: &str,
+    named: &mut HashMap<String, Schema>,
+) -> anyhow::Result<Schema> {
+    let object = match schema {
+        Value::String(name) => {
+            return Ok(primitive(name).unwrap_or_else(|| Schema::Named(full_name(name, namespace))))
+        }
+        Value::Array(schemas) => {
+            return schemas
+                .iter()
+                .map(|schema| parse(schema, namespace, named))
+                .collect::<anyhow::Result<_>>()
+                .map(Schema::Union)
+        }
+        Value::Object(object) => object,
+        _ => anyhow::bail!("Invalid schema: {}", schema),
+    };
+
+    let r#type = object
+        .get("type")
+        .ok_or_else(|| anyhow::anyhow!("Missing type: {}", schema))?;
+    let r#type = match r#type {
+        Value::String(r#type) => r#type.as_str(),
+        // a type wrapped in an object, possibly with attributes of its own
+        r#type => return parse(r#type, namespace, named),
+    };
+
+    if object.get("logicalType").and_then(Value::as_str) == Some("decimal") {
+        let scale = object.get("scale").and_then(Value::as_u64).unwrap_or(0) as u32;
+        match r#type {
+            "bytes" => return Ok(Schema::Decimal { scale, size: None }),
+            "fixed" => {
+                let size = size(object)?;
+                let name = name(object, namespace)?;
+                named.insert(
+                    name.clone(),
+                    Schema::Decimal {
+                        scale,
+                        size: Some(size),
+                    },
+                );
+                return Ok(Schema::Named(name));
+            }
+            _ => {}
+        }
+    }
+
+    let schema = match r#type {
+        "record" | "error" => {
+            let name = name(object, namespace)?;
+            let namespace = name.rsplit_once('.').map_or("", |(namespace, _)| namespace);
+            let fields = object
+                .get("fields")
+                .and_then(Value::as_array)
+                .ok_or_else(|| anyhow::anyhow!("Missing fields of {}", name))?
+                .iter()
+                .map(|field| {
+                    let field_name = field
+                        .get("name")
+                        .and_then(Value::as_str)
+                        .ok_or_else(|| anyhow::anyhow!("Missing name of a field of {}", name))?;
+                    let r#type = field.get("type").ok_or_else(|| {
+                        anyhow::anyhow!("Missing type of {}.{}", name, field_name)
+                    })?;
+                    Ok((field_name.to_string(), parse(r#type, namespace, named)?))
+                })
+                .collect::<anyhow::Result<_>>()?;
+            named.insert(name.clone(), Schema::Record(fields));
+            Schema::Named(name)
+        }
+        "enum" => {
+            let name = name(object, namespace)?;
+            let symbols = object
+                .get("symbols")
+                .and_then(Value::as_array)
+                .ok_or_else(|| anyhow::anyhow!("Missing symbols of {}", name))?
+                .iter()
+                .map(|symbol| symbol.as_str().unwrap_or_default().to_string())
+                .collect();
+            named.insert(name.clone(), Schema::Enum(symbols));
+            Schema::Named(name)
+        }
+        "fixed" => {
+            let name = name(object, namespace)?;
+            named.insert(name.clone(), Schema::Fixed(size(object)?));
+            Schema::Named(name)
+        }
+        "array" => {
+            let items = object
+                .get("items")
+                .ok_or_else(|| anyhow::anyhow!("Missing items of an array"))?;
+            Schema::Array(Box::new(parse(items, namespace, named)?))
+        }
+        "map" => {
+            let values = object
+                .get("values")
+                .ok_or_else(|| anyhow::anyhow!("Missing values of a map"))?;
+            Schema::Map(Box::new(parse(values, namespace, named)?))
+        }
+        r#type => primitive(r#type).unwrap_or_else(|| Schema::Named(full_name(r#type, namespace))),
+    };
+    Ok(schema)
+}
+
+fn primitive(name: &str) -> Option<Schema> {
+    Some(match name {
+        "null" => Schema::Null,
+        "boolean" => Schema::Boolean,
+        "int" => Schema::Int,
+        "long" => Schema::Long,
+        "float" => Schema::Float,
+        "double" => Schema::Double,
+        "bytes" => Schema::Bytes,
+        "string" => Schema::String,
+        _ => return None,
+    })
+}
+
+fn name(object: &Map<String, Value>, namespace: &str) -> anyhow::Result<String> {
+    let name = object
+        .get("name")
+        .and_then(Value::as_str)
+        .ok_or_else(|| anyhow::anyhow!("Missing name of a named type"))?;
+    let namespace = object
+        .get("namespace")
+        .and_then(Value::as_str)
+        .unwrap_or(namespace);
+    Ok(full_name(name, namespace))
+}
+
+fn full_name(name: &str, namespace: &str) -> String {
+    match name.contains('.') || namespace.is_empty() {
+        true => name.to_string(),
+        false => format!("{}.{}", namespace, name),
+    }
+}
+
+fn size(object: &Map<String, Value>) -> anyhow::Result<usize> {
+    object
+        .get("size")
+        .and_then(Value::as_u64)
+        .map(|size| size as usize)
+        .ok_or_else(|| anyhow::anyhow!("Missing size of a fixed type"))
+}
+
+/// Nesting of records, arrays, maps and unions, beyond which the payload is rejected.
+const MAX_DEPTH: usize = 64;
+
+struct Decoder<'a> {
+    data: &'a [u8],
+    schemas: &'a Schemas,
+    depth: usize,
+}
+
+impl<'a> Decoder<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], ServiceError> {
+        if self.data.len() < n {
+            return Err(error("Truncated payload"));
+        }
+        let (taken, rest) = self.data.split_at(n);
+        self.data = rest;
+        Ok(taken)
+    }
+
+    /// A zig-zag encoded variable length integer.
+    fn long(&mut self) -> Result<i64, ServiceError> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.take(1)?[0];
+            value |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok((value >> 1) as i64 ^ -((value & 1) as i64));
+            }
+        }
+        Err(error("Invalid variable length integer"))
+    }
+
+    fn length(&mut self) -> Result<usize, ServiceError> {
+        match self.long()? {
+            // each element takes at least a byte, anything longer is truncated
+            n if n < 0 || n as u64 > self.data.len() as u64 => Err(error("Invalid length")),
+            n => Ok(n as usize),
+        }
+    }
+
+    fn bytes(&mut self) -> Result<&'a [u8], ServiceError> {
+        let n = self.length()?;
+        self.take(n)
+    }
+
+    /// The items of an array or map, in blocks with their count, ended by an empty one.
+    fn blocks<F>(&mut self, mut item: F) -> Result<(), ServiceError>
+    where
+        F: FnMut(&mut Self) -> Result<(), ServiceError>,
+    {
+        loop {
+            let count = match self.long()? {
+                0 => return Ok(()),
+                // followed by the size of the block in bytes
+                count if count < 0 => {
+                    self.long()?;
+                    count.unsigned_abs()
+                }
+                count => count as u64,
+            };
+            if count > self.data.len() as u64 {
+                return Err(error("Invalid block count"));
+            }
+            for _ in 0..count {
+                item(self)?;
+            }
+        }
+    }
+
+    fn value(&mut self, schema: &'a Schema) -> Result<Value, ServiceError> {
+        if self.depth > MAX_DEPTH {
+            return Err(error("Nested too deep"));
+        }
+        let float = |value: f64| Number::from_f64(value).map_or(Value::Null, Value::Number);
+
+        Ok(match schema {
+            Schema::Null => Value::Null,
+            Schema::Boolean => Value::Bool(self.take(1)?[0] != 0),
+            Schema::Int | Schema::Long => Value::from(self.long()?),
+            Schema::Float => {
+                let mut bytes = [0; 4];
+                bytes.copy_from_slice(self.take(4)?);
+                float(f32::from_le_bytes(bytes) as f64)
+            }
+            Schema::Double => {
+                let mut bytes = [0; 8];
+                bytes.copy_from_slice(self.take(8)?);
+                float(f64::from_le_bytes(bytes))
+            }
+            Schema::Bytes => Value::String(base64::encode(self.bytes()?)),
+            Schema::String => std::str::from_utf8(self.bytes()?)
+                .map(|s| Value::String(s.to_string()))
+                .map_err(|err| error(err.to_string()))?,
+            Schema::Fixed(size) => Value::String(base64::encode(self.take(*size)?)),
+            Schema::Decimal { scale, size } => {
+                let bytes = match size {
+                    Some(size) => self.take(*size)?,
+                    None => self.bytes()?,
+                };
+                if bytes.len() > 16 {
+                    return Err(error("Decimal too large"));
+                }
+                // big endian two's complement, sign extended
+                let unscaled = bytes.iter().fold(
+                    if bytes.first().is_some_and(|b| b & 0x80 != 0) {
+                        -1i128
+                    } else {
+                        0
+                    },
+                    |acc, b| acc << 8 | *b as i128,
+                );
+                float(unscaled as f64 / 10f64.powi(*scale as i32))
+            }
+            Schema::Enum(symbols) => {
+                let index = self.long()?;
+                let symbol = usize::try_from(index)
+                    .ok()
+                    .and_then(|index| symbols.get(index))
+                    .ok_or_else(|| error(format!("Invalid enum index: {}", index)))?;
+                Value::String(symbol.clone())
+            }
+            Schema::Union(schemas) => {
+                let index = self.long()?;
+                let schema = usize::try_from(index)
+                    .ok()
+                    .and_then(|index| schemas.get(index))
+                    .ok_or_else(|| error(format!("Invalid union index: {}", index)))?;
+                self.nested(schema)?
+            }
+            Schema::Record(fields) => {
+                let mut object = Map::new();
+                for (name, schema) in fields {
+                    let value = self.nested(schema)?;
+                    object.insert(name.clone(), value);
+                }
+                Value::Object(object)
+            }
+            Schema::Array(items) => {
+                let mut array = Vec::new();
+                self.blocks(|decoder| {
+                    array.push(decoder.nested(items)?);
+                    Ok(())
+                })?;
+                Value::Array(array)
+            }
+            Schema::Map(values) => {
+                let mut object = Map::new();
+                self.blocks(|decoder| {
+                    let key = std::str::from_utf8(decoder.bytes()?)
+                        .map_err(|err| error(err.to_string()))?
+                        .to_string();
+                    object.insert(key, decoder.nested(values)?);
+                    Ok(())
+                })?;
+                Value::Object(object)
+            }
+            Schema::Named(name) => {
+                let schemas = self.schemas;
+                let schema = schemas
+                    .named
+                    .get(name)
+                    .ok_or_else(|| error(format!("Unknown type: {}", name)))?;
+                self.nested(schema)?
+            }
+        })
+    }
+
+    fn nested(&mut self, schema: &'a Schema) -> Result<Value, ServiceError> {
+        self.depth += 1;
+        let value = self.value(schema);
+        self.depth -= 1;
+        value
+    }
+}
+
+fn error(details: impl Into<String>) -> ServiceError {
+    ServiceError::PayloadParseError {
+        details: format!("Invalid Avro payload: {}", details.into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn schemas(schema: Value) -> Schemas {
+        Schemas::parse(&schema).unwrap()
+    }
+
+    fn decode(schemas: &Schemas, data: &[u8]) -> Result<Value, ServiceError> {
+        let mut decoder = Decoder {
+            data,
+            schemas,
+            depth: 0,
+        };
+        decoder.value(&schemas.root)
+    }
+
+    fn details(result: Result<Value, ServiceError>) -> String {
+        match result {
+            Err(ServiceError::PayloadParseError { details }) => details,
+            other => panic!("Unexpected result: {:?}", other),
+        }
+    }
+
+    fn reading() -> Schemas {
+        schemas(json!({
+            "type": "record",
+            "name": "Reading",
+            "namespace": "iot",
+            "fields": [
+                {"name": "device", "type": "string"},
+                {"name": "temp", "type": "double"},
+                {"name": "count", "type": "long"},
+                {"name": "state", "type": {"type": "enum", "name": "State", "symbols": ["ON", "OFF"]}},
+                {"name": "note", "type": ["null", "string"]},
+                {"name": "tags", "type": {"type": "map", "values": "int"}},
+                {"name": "values", "type": {"type": "array", "items": "float"}},
+                {"name": "price", "type": {"type": "bytes", "logicalType": "decimal", "scale": 2}},
+            ]
+        }))
+    }
+
+    #[test]
+    fn test_decode() {
+        let mut data = vec![0x04, b'a', b'b'];
+        data.extend_from_slice(&21.5f64.to_le_bytes());
+        data.extend_from_slice(&[0x03]); // -2
+        data.extend_from_slice(&[0x02]); // OFF
+        data.extend_from_slice(&[0x00]); // null
+        data.extend_from_slice(&[0x02, 0x02, b'x', 0x54, 0x00]); // {"x": 42}
+        data.extend_from_slice(&[0x03, 0x09]); // a block of -2 items, with its size
+        data.extend_from_slice(&1.5f32.to_le_bytes());
+        data.extend_from_slice(&2.0f32.to_le_bytes());
+        data.extend_from_slice(&[0x00]);
+        data.extend_from_slice(&[0x04, 0x04, 0xd2]); // 12.34
+
+        assert_eq!(
+            decode(&reading(), &data).unwrap(),
+            json!({
+                "device": "ab",
+                "temp": 21.5,
+                "count": -2,
+                "state": "OFF",
+                "note": null,
+                "tags": {"x": 42},
+                "values": [1.5, 2.0],
+                "price": 12.34,
+            })
+        );
+    }
+
+    #[test]
+    fn test_named_types() {
+        let schemas = schemas(json!({
+            "type": "record",
+            "name": "Node",
+            "fields": [
+                {"name": "id", "type": {"type": "fixed", "name": "Id", "size": 2}},
+                {"name": "next", "type": ["null", "Node"]},
+            ]
+        }));
+        let data = [0x01, 0x02, 0x02, 0x03, 0x04, 0x00];
+        assert_eq!(
+            decode(&schemas, &data).unwrap(),
+            json!({"id": "AQI=", "next": {"id": "AwQ=", "next": null}})
+        );
+    }
+
+    #[test]
+    fn test_truncated() {
+        let schemas = reading();
+        assert!(details(decode(&schemas, &[0x04, b'a'])).contains("Invalid length"));
+        assert!(details(decode(&schemas, &[0x04, b'a', b'b', 0x00])).contains("Truncated"));
+        assert!(details(decode(&schemas, &[0x80])).contains("Truncated"));
+    }
+
+    #[test]
+    fn test_depth() {
+        let schemas = schemas(json!({
+            "type": "record",
+            "name": "Node",
+            "fields": [{"name": "next", "type": ["null", "Node"]}]
+        }));
+        // every node nests at least once, in the union of its next field
+        let mut data = vec![0x02; MAX_DEPTH];
+        data.push(0x00);
+        assert!(details(decode(&schemas, &data)).contains("Nested too deep"));
+
+        let mut data = vec![0x02; 8];
+        data.push(0x00);
+        assert!(decode(&schemas, &data).is_ok());
+    }
+
+    #[test]
+    fn test_size_limits() {
+        let array = schemas(json!({"type": "array", "items": "null"}));
+        assert!(details(decode(&array, &[0x80, 0x01])).contains("Invalid block count"));
+        let string = schemas(json!("string"));
+        assert!(details(decode(&string, &[0x01])).contains("Invalid length"));
+        let decimal = schemas(json!({"type": "bytes", "logicalType": "decimal"}));
+        let mut data = vec![0x22];
+        data.extend_from_slice(&[0; 17]);
+        assert!(details(decode(&decimal, &data)).contains("Decimal too large"));
+    }
+
+    #[test]
+    fn test_malformed() {
+        let reading = reading();
+        let mut data = vec![0x02, 0xff];
+        assert!(details(decode(&reading, &data)).contains("invalid utf-8"));
+
+        data = vec![0x00];
+        data.extend_from_slice(&0f64.to_le_bytes());
+        data.extend_from_slice(&[0x00, 0x04]);
+        assert!(details(decode(&reading, &data)).contains("Invalid enum index: 2"));
+
+        let long = schemas(json!("long"));
+        assert!(details(decode(&long, &[0xff; 10])).contains("Invalid variable length"));
+
+        assert!(Schemas::parse(&json!({"type": "record", "name": "R"})).is_err());
+        assert!(Schemas::parse(&json!({"name": "R"})).is_err());
+        assert!(Schemas::parse(&json!(1)).is_err());
+    }
+
+    #[test]
+    fn test_wire_format() {
+        let avro = Avro::from_config(AvroConfig {
+            url: Some("http://localhost:8081/".into()),
+            username: None,
+            password: None,
+            timeout_s: 1,
+            content_types: "application/avro,avro/*".into(),
+        })
+        .unwrap()
+        .unwrap();
+        assert!(avro.is_avro(Some("application/avro; charset=binary")));
+        assert!(avro.is_avro(Some("avro/binary")));
+        assert!(!avro.is_avro(Some("application/json")));
+
+        avro.schemas
+            .lock()
+            .unwrap()
+            .insert(7, Arc::new(schemas(json!("long"))));
+        let decode = |data: &[u8]| {
+            futures::executor::block_on(avro.decode(Some(&Data::Binary(data.to_vec()))))
+        };
+        assert_eq!(decode(&[0, 0, 0, 0, 7, 0x54]).unwrap(), json!(42));
+        assert!(details(decode(&[0, 0, 0, 0, 7, 0x54, 0x00])).contains("Trailing data"));
+        assert!(details(decode(&[1, 0, 0])).contains("Missing magic byte"));
+    }
+}
@@ -0,0 +1,927 @@
+use crate::line::Point;
+use crate::metrics::{EVENT_LATENCY, WRITE_QUEUE_DEPTH, WRITE_QUEUE_DROPPED};
+use crate::recent::RecentErrors;
+use crate::redact::Secrets;
+use crate::series::Commit;
+use crate::sink::Sink;
+use actix_rt::time::Instant;
+use chrono::{DateTime, Utc};
+use envconfig::Envconfig;
+use futures::channel::{mpsc, oneshot};
+use futures::future::LocalBoxFuture;
+use futures::stream::FuturesUnordered;
+use futures::{FutureExt, StreamExt};
+use influxdb::WriteQuery;
+use serde::Serialize;
+use std::collections::{HashSet, VecDeque};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+#[derive(Envconfig, Clone, Debug)]
+pub struct BatchConfig {
+    /// Maximum time a point is buffered, batching is disabled when zero.
+    #[envconfig(from = "BATCH_INTERVAL_MS", default = "0")]
+    pub interval_ms: u64,
+    #[envconfig(from = "BATCH_SIZE", default = "5000")]
+    pub size: usize,
+    /// Flush early when no new points arrived for this long.
+    #[envconfig(from = "BATCH_IDLE_MS", default = "200")]
+    pub idle_ms: u64,
+    /// Maximum number of points waiting to be written, unbounded when zero.
+    #[envconfig(from = "BATCH_QUEUE_SIZE", default = "0")]
+    pub queue_size: usize,
+    /// What happens to points once the queue is full.
+    #[envconfig(from = "BATCH_QUEUE_POLICY", default = "reject")]
+    pub queue_policy: QueuePolicy,
+    /// Batches which are written at the same time.
+    #[envconfig(from = "BATCH_CONCURRENCY", default = "1")]
+    pub concurrency: usize,
+    /// Keep writing the points of a series in order, while writing batches concurrently.
+    #[envconfig(from = "BATCH_PRESERVE_ORDER", default = "false")]
+    pub preserve_order: bool,
+    /// Times the points of a batch which failed to be written are queued again, before they
+    /// are dropped.
+    #[envconfig(from = "BATCH_MAX_RETRIES", default = "3")]
+    pub max_retries: u32,
+    /// Time to wait before writing again after a batch failed, doubling with each attempt.
+    #[envconfig(from = "BATCH_RETRY_BACKOFF_MS", default = "1000")]
+    pub retry_backoff_ms: u64,
+}
+
+impl BatchConfig {
+    pub fn is_enabled(&self) -> bool {
+        self.interval_ms > 0
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum QueuePolicy {
+    /// Make room by dropping the points which have waited longest.
+    DropOldest,
+    /// Drop new points, while still accepting their events.
+    DropNewest,
+    /// Reject events, so that they are delivered again later.
+    Reject,
+}
+
+impl FromStr for QueuePolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "drop-oldest" => Ok(QueuePolicy::DropOldest),
+            "drop-newest" => Ok(QueuePolicy::DropNewest),
+            "reject" => Ok(QueuePolicy::Reject),
+            _ => anyhow::bail!("Unknown queue policy: {}", s),
+        }
+    }
+}
+
+impl QueuePolicy {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            QueuePolicy::DropOldest => "drop-oldest",
+            QueuePolicy::DropNewest => "drop-newest",
+            QueuePolicy::Reject => "reject",
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Message {
+    /// Points were queued, since the writer last looked.
+    Queued,
+    Flush(oneshot::Sender<Flushed>),
+}
+
+/// Points written by a flush, and those which failed to be, and were dropped.
+#[derive(Debug, Default, Serialize)]
+pub struct Flushed {
+    pub written: usize,
+    pub failed: usize,
+}
+
+impl Flushed {
+    pub fn add(&mut self, other: Flushed) {
+        self.written += other.written;
+        self.failed += other.failed;
+    }
+}
+
+#[derive(Debug)]
+struct Entry {
+    query: WriteQuery,
+    retention: Option<String>,
+    time: Option<DateTime<Utc>>,
+    /// Only known when preserving the order of series.
+    series: Option<String>,
+    commit: Commit,
+    /// Of the event, to mask in write errors.
+    secrets: Option<Arc<Secrets>>,
+    /// Failed writes of the point.
+    attempts: u32,
+}
+
+/// Points waiting to be written, shared with the writer.
+#[derive(Debug, Default)]
+struct Queue {
+    entries: Mutex<VecDeque<Entry>>,
+    /// Whether the writer was told about the latest points already.
+    notified: AtomicBool,
+}
+
+impl Queue {
+    fn len(&self) -> usize {
+        self.entries.lock().unwrap().len()
+    }
+
+    /// Take up to `n` points from the front.
+    fn take(&self, n: usize) -> Vec<Entry> {
+        let mut entries = self.entries.lock().unwrap();
+        let n = n.min(entries.len());
+        let taken = entries.drain(..n).collect();
+        WRITE_QUEUE_DEPTH.set(entries.len() as i64);
+        taken
+    }
+
+    /// Put points which failed to be written back to the front, whatever the capacity.
+    fn requeue(&self, failed: Vec<Entry>) {
+        let mut entries = self.entries.lock().unwrap();
+        for entry in failed.into_iter().rev() {
+            entries.push_front(entry);
+        }
+        WRITE_QUEUE_DEPTH.set(entries.len() as i64);
+    }
+
+    /// Take up to `n` points, leaving those of the `busy` series where they are.
+    fn take_except(&self, n: usize, busy: &HashSet<String>) -> Vec<Entry> {
+        let mut entries = self.entries.lock().unwrap();
+        let mut taken = Vec::new();
+        let mut left = VecDeque::with_capacity(entries.len());
+        for entry in entries.drain(..) {
+            let is_busy = entry
+                .series
+                .as_ref()
+                .is_some_and(|series| busy.contains(series));
+            match taken.len() < n && !is_busy {
+                true => taken.push(entry),
+                false => left.push_back(entry),
+            }
+        }
+        *entries = left;
+        WRITE_QUEUE_DEPTH.set(entries.len() as i64);
+        taken
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Batcher {
+    tx: mpsc::UnboundedSender<Message>,
+    queue: Arc<Queue>,
+    capacity: usize,
+    policy: QueuePolicy,
+    ordered: bool,
+}
+
+impl Batcher {
+    /// Start the writer task on the current arbiter.
+    pub fn start(config: BatchConfig, sink: Arc<dyn Sink>, errors: RecentErrors) -> Self {
+        let (tx, rx) = mpsc::unbounded();
+        let queue = Arc::new(Queue::default());
+        let capacity = config.queue_size;
+        let policy = config.queue_policy;
+        let ordered = config.preserve_order && config.concurrency > 1;
+        actix_rt::spawn(run(config, sink, rx, queue.clone(), errors));
+        Self {
+            tx,
+            queue,
+            capacity,
+            policy,
+            ordered,
+        }
+    }
+
+    /// Write everything which is buffered right now.
+    pub async fn drain(&self) -> Flushed {
+        let (tx, rx) = oneshot::channel();
+        if self.tx.unbounded_send(Message::Flush(tx)).is_err() {
+            return Flushed::default();
+        }
+        rx.await.unwrap_or_default()
+    }
+
+    /// Number of points waiting to be written.
+    pub fn queued(&self) -> usize {
+        self.queue.len()
+    }
+
+    /// Whether the queue is bounded, and full.
+    pub fn is_full(&self) -> bool {
+        self.capacity > 0 && self.queued() >= self.capacity
+    }
+
+    /// Queue the points of an event, returns `false` if they are rejected because the queue is
+    /// full, or the writer isn't running anymore.
+    ///
+    /// The points are queued all together or none of them, so that an event which is delivered
+    /// again isn't partly written twice. Those of an event which takes more than the whole queue
+    /// are only taken into an empty one. What is pending of the event is committed once all of
+    /// them were written.
+    pub fn push(
+        &self,
+        queries: Vec<(Option<String>, WriteQuery)>,
+        time: Option<DateTime<Utc>>,
+        commit: Commit,
+        secrets: &Secrets,
+    ) -> bool {
+        if self.tx.is_closed() {
+            return false;
+        }
+        let secrets = match secrets.is_empty() {
+            true => None,
+            false => Some(Arc::new(secrets.clone())),
+        };
+        let entries: Vec<_> = queries
+            .into_iter()
+            .map(|(retention, query)| {
+                let series = match self.ordered {
+                    true => series(&query, retention.as_deref()),
+                    false => None,
+                };
+                Entry {
+                    query,
+                    retention,
+                    time,
+                    series,
+                    commit: commit.clone(),
+                    secrets: secrets.clone(),
+                    attempts: 0,
+                }
+            })
+            .collect();
+
+        {
+            let mut queued = self.queue.entries.lock().unwrap();
+            let n = entries.len();
+            let room = match self.capacity {
+                0 => n,
+                capacity => capacity.max(n).saturating_sub(queued.len()),
+            };
+            if room < n {
+                let dropped = match self.policy {
+                    QueuePolicy::Reject => n,
+                    _ => n - room,
+                };
+                WRITE_QUEUE_DROPPED
+                    .with_label_values(&[self.policy.as_str()])
+                    .inc_by(dropped as u64);
+            }
+            let keep = match self.policy {
+                _ if room >= n => n,
+                QueuePolicy::DropOldest => {
+                    queued
+                        .drain(..n - room)
+                        .for_each(|entry| entry.commit.fail());
+                    n
+                }
+                QueuePolicy::DropNewest => {
+                    commit.fail();
+                    room
+                }
+                QueuePolicy::Reject => return false,
+            };
+            queued.extend(entries.into_iter().take(keep));
+            WRITE_QUEUE_DEPTH.set(queued.len() as i64);
+        }
+
+        if !self.queue.notified.swap(true, Ordering::AcqRel) {
+            return self.tx.unbounded_send(Message::Queued).is_ok();
+        }
+        true
+    }
+}
+
+/// The series of a point, as far as the order of its points matters.
+fn series(query: &WriteQuery, retention: Option<&str>) -> Option<String> {
+    let point = match Point::from_query(query) {
+        Ok(point) => point,
+        Err(err) => {
+            log::debug!("Failed to find the series of a point: {}", err);
+            return None;
+        }
+    };
+    let mut tags = point.tags;
+    tags.sort();
+    let mut series = format!("{}/{}", retention.unwrap_or_default(), point.measurement);
+    for (tag, value) in tags {
+        series.push_str(&format!(",{}={}", tag, value));
+    }
+    Some(series)
+}
+
+async fn run(
+    config: BatchConfig,
+    sink: Arc<dyn Sink>,
+    mut rx: mpsc::UnboundedReceiver<Message>,
+    queue: Arc<Queue>,
+    errors: RecentErrors,
+) {
+    let interval = Duration::from_millis(config.interval_ms);
+    let idle = Duration::from_millis(config.idle_ms);
+    let mut writer = Writer {
+        sink,
+        errors,
+        size: config.size.max(1),
+        concurrency: config.concurrency.max(1),
+        ordered: config.preserve_order && config.concurrency > 1,
+        in_flight: FuturesUnordered::new(),
+        busy: HashSet::new(),
+        max_retries: config.max_retries,
+        backoff: Duration::from_millis(config.retry_backoff_ms),
+        retry_at: None,
+    };
+
+    let mut deadline = None;
+    // the interval passed, or no points arrived for a while
+    let mut due = false;
+
+    loop {
+        while writer.in_flight.len() < writer.concurrency && writer.backoff().is_none() {
+            let queued = queue.len();
+            if queued == 0 || (queued < writer.size && !due) {
+                break;
+            }
+            if !writer.start(&queue) {
+                // all queued points are of series which are being written
+                break;
+            }
+            deadline = None;
+        }
+        if queue.len() == 0 {
+            due = false;
+        }
+
+        // after a failed write, what is queued waits for the backoff, instead of the interval
+        let backoff = writer.backoff();
+        let waiting = queue.len() > 0 && (!due || backoff.is_some());
+        let wait = match (waiting, backoff) {
+            (true, Some(backoff)) => backoff,
+            (true, None) => {
+                let until = *deadline.get_or_insert_with(|| Instant::now() + interval);
+                idle.min(until.saturating_duration_since(Instant::now()))
+            }
+            (false, _) => Duration::default(),
+        };
+
+        let next = futures::select_biased! {
+            (series, _, failed) = writer.in_flight.select_next_some() => {
+                writer.done(series, failed, &queue);
+                continue;
+            }
+            next = rx.next() => next,
+            _ = delay(waiting, wait).fuse() => {
+                due = true;
+                continue;
+            }
+        };
+
+        match next {
+            Some(Message::Queued) => queue.notified.store(false, Ordering::Release),
+            Some(Message::Flush(done)) => {
+                let flushed = writer.drain(&queue).await;
+                deadline = None;
+                due = false;
+                let _ = done.send(flushed);
+            }
+            None => {
+                writer.drain(&queue).await;
+                break;
+            }
+        }
+    }
+}
+
+async fn delay(waiting: bool, wait: Duration) {
+    match waiting {
+        true => actix_rt::time::delay_for(wait).await,
+        false => futures::future::pending().await,
+    }
+}
+
+/// Series of the points of a write, what was written, and the points to retry.
+type Written = (Vec<String>, Flushed, Vec<Entry>);
+
+/// Writes batches of the queued points, up to `concurrency` at a time.
+///
+/// With `ordered`, points of a series which is being written wait for that write to finish,
+/// so that they are written in the order they were queued in. Points of a batch which failed to
+/// be written are queued again, in front of the others, and writing pauses for a backoff.
+struct Writer {
+    sink: Arc<dyn Sink>,
+    errors: RecentErrors,
+    size: usize,
+    concurrency: usize,
+    ordered: bool,
+    in_flight: FuturesUnordered<LocalBoxFuture<'static, Written>>,
+    busy: HashSet<String>,
+    max_retries: u32,
+    backoff: Duration,
+    /// Until when writing pauses, after a failed write.
+    retry_at: Option<Instant>,
+}
+
+impl Writer {
+    /// Start writing the next batch, returns `false` if nothing could be taken.
+    fn start(&mut self, queue: &Queue) -> bool {
+        let entries = match self.ordered {
+            true => queue.take_except(self.size, &self.busy),
+            false => queue.take(self.size),
+        };
+        if entries.is_empty() {
+            return false;
+        }
+
+        let series = match self.ordered {
+            true => entries
+                .iter()
+                .filter_map(|entry| entry.series.clone())
+                .collect(),
+            false => Vec::new(),
+        };
+        self.busy.extend(series.iter().cloned());
+
+        let sink = self.sink.clone();
+        let errors = self.errors.clone();
+        let max_retries = self.max_retries;
+        self.in_flight.push(
+            async move {
+                let (flushed, failed) = flush(&*sink, entries, max_retries, &errors).await;
+                (series, flushed, failed)
+            }
+            .boxed_local(),
+        );
+        true
+    }
+
+    fn done(&mut self, series: Vec<String>, failed: Vec<Entry>, queue: &Queue) {
+        for series in series {
+            self.busy.remove(&series);
+        }
+
+        if let Some(attempts) = failed.iter().map(|entry| entry.attempts).max() {
+            let backoff = self.backoff * 2u32.pow(attempts.clamp(1, 7) - 1);
+            self.retry_at = Some(Instant::now() + backoff);
+            queue.requeue(failed);
+        }
+    }
+
+    /// Time left until writing again, after a failed write.
+    fn backoff(&self) -> Option<Duration> {
+        self.retry_at
+            .map(|retry_at| retry_at.saturating_duration_since(Instant::now()))
+            .filter(|left| *left > Duration::default())
+    }
+
+    /// Write everything which is queued, and wait for what is being written.
+    async fn drain(&mut self, queue: &Queue) -> Flushed {
+        let mut flushed = Flushed::default();
+        loop {
+            while self.in_flight.len() < self.concurrency
+                && self.backoff().is_none()
+                && self.start(queue)
+            {}
+            match self.in_flight.next().await {
+                Some((series, written, failed)) => {
+                    flushed.add(written);
+                    self.done(series, failed, queue);
+                }
+                None => match self.backoff() {
+                    Some(backoff) if queue.len() > 0 => actix_rt::time::delay_for(backoff).await,
+                    _ => break,
+                },
+            }
+        }
+        flushed
+    }
+}
+
+/// Write a batch of points, returns the failed ones which are retried.
+async fn flush(
+    sink: &dyn Sink,
+    entries: Vec<Entry>,
+    max_retries: u32,
+    errors: &RecentErrors,
+) -> (Flushed, Vec<Entry>) {
+    let queries: Vec<_> = entries
+        .iter()
+        .map(|entry| (entry.retention.clone(), entry.query.clone()))
+        .collect();
+
+    log::debug!("Flushing {} points", queries.len());
+
+    let err = match sink.write(&queries).await {
+        Ok(_) => {
+            let now = Utc::now();
+            for time in entries.iter().filter_map(|entry| entry.time) {
+                let latency = (now - time).to_std().unwrap_or_default();
+                EVENT_LATENCY.observe(latency.as_secs_f64());
+            }
+            let flushed = Flushed {
+                written: queries.len(),
+                failed: 0,
+            };
+            return (flushed, Vec::new());
+        }
+        Err(err) => err,
+    };
+
+    let mut secrets = Secrets::default();
+    for entry_secrets in entries.iter().filter_map(|entry| entry.secrets.as_deref()) {
+        secrets.extend(entry_secrets.clone());
+    }
+    let err = secrets.mask(&err.to_string());
+    errors.record(None, "WriteError", &err);
+
+    let (retried, dropped): (Vec<_>, Vec<_>) = entries
+        .into_iter()
+        .map(|mut entry| {
+            entry.attempts += 1;
+            entry
+        })
+        .partition(|entry| entry.attempts <= max_retries);
+    match dropped.len() {
+        0 => log::warn!(
+            "Failed to write batch of {} points, retrying: {}",
+            queries.len(),
+            err
+        ),
+        n => log::warn!(
+            "Failed to write batch of {} points, dropping {} retried {} times: {}",
+            queries.len(),
+            n,
+            max_retries,
+            err
+        ),
+    }
+    for entry in &dropped {
+        entry.commit.fail();
+    }
+
+    let flushed = Flushed {
+        written: 0,
+        failed: dropped.len(),
+    };
+    (flushed, retried)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::recent::RecentErrorsConfig;
+    use crate::redact::Redactor;
+    use influxdb::{InfluxDbWriteable, Timestamp};
+    use std::sync::atomic::AtomicUsize;
+
+    /// Records the points written, failing the first `failures` writes.
+    #[derive(Debug, Default)]
+    struct TestSink {
+        points: Mutex<Vec<String>>,
+        failures: AtomicUsize,
+    }
+
+    impl Sink for TestSink {
+        fn write<'a>(
+            &'a self,
+            points: &'a [(Option<String>, WriteQuery)],
+        ) -> LocalBoxFuture<'a, Result<(), influxdb::Error>> {
+            let failed = self
+                .failures
+                .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1))
+                .is_ok();
+            let result = match failed {
+                true => Err(influxdb::Error::ConnectionError {
+                    error: "db.internal unavailable".into(),
+                }),
+                false => {
+                    let mut written = self.points.lock().unwrap();
+                    for (_, query) in points {
+                        written.push(crate::influx::line(query).unwrap());
+                    }
+                    Ok(())
+                }
+            };
+            futures::future::ready(result).boxed_local()
+        }
+    }
+
+    fn config(queue_size: usize, queue_policy: QueuePolicy) -> BatchConfig {
+        BatchConfig {
+            interval_ms: 60_000,
+            size: 1000,
+            idle_ms: 60_000,
+            queue_size,
+            queue_policy,
+            concurrency: 1,
+            preserve_order: false,
+            max_retries: 1,
+            retry_backoff_ms: 10,
+        }
+    }
+
+    fn start(config: BatchConfig, sink: &Arc<TestSink>) -> Batcher {
+        start_with(
+            config,
+            sink,
+            RecentErrors::from_config(RecentErrorsConfig { size: 10 }),
+        )
+    }
+
+    fn start_with(config: BatchConfig, sink: &Arc<TestSink>, errors: RecentErrors) -> Batcher {
+        Batcher::start(config, sink.clone(), errors)
+    }
+
+    fn points(values: std::ops::Range<i64>) -> Vec<(Option<String>, WriteQuery)> {
+        values
+            .map(|value| {
+                let query = Timestamp::Seconds(1).into_query("m").add_field("v", value);
+                (None, query)
+            })
+            .collect()
+    }
+
+    fn written(sink: &TestSink) -> Vec<String> {
+        sink.points.lock().unwrap().clone()
+    }
+
+    #[test]
+    fn test_push() {
+        actix_rt::System::new("test").block_on(async {
+            let sink = Arc::new(TestSink::default());
+            let batcher = start(config(0, QueuePolicy::Reject), &sink);
+            assert!(batcher.push(points(0..2), None, Commit::default(), &Secrets::default()));
+            assert!(batcher.push(points(2..3), None, Commit::default(), &Secrets::default()));
+            assert_eq!(batcher.queued(), 3);
+
+            let flushed = batcher.drain().await;
+            assert_eq!((flushed.written, flushed.failed), (3, 0));
+            assert_eq!(batcher.queued(), 0);
+            assert_eq!(written(&sink), vec!["m v=0i 1", "m v=1i 1", "m v=2i 1"]);
+        });
+    }
+
+    #[test]
+    fn test_reject() {
+        actix_rt::System::new("test").block_on(async {
+            let sink = Arc::new(TestSink::default());
+            let batcher = start(config(3, QueuePolicy::Reject), &sink);
+            assert!(batcher.push(points(0..2), None, Commit::default(), &Secrets::default()));
+            // all points of an event, or none
+            assert!(!batcher.push(points(2..4), None, Commit::default(), &Secrets::default()));
+            assert_eq!(batcher.queued(), 2);
+            assert!(batcher.push(points(4..5), None, Commit::default(), &Secrets::default()));
+            assert!(batcher.is_full());
+
+            batcher.drain().await;
+            assert_eq!(written(&sink), vec!["m v=0i 1", "m v=1i 1", "m v=4i 1"]);
+
+            // more than fits the whole queue, only into an empty one
+            assert!(batcher.push(points(0..5), None, Commit::default(), &Secrets::default()));
+            assert!(!batcher.push(points(5..6), None, Commit::default(), &Secrets::default()));
+            assert_eq!(batcher.queued(), 5);
+        });
+    }
+
+    #[test]
+    fn test_drop() {
+        actix_rt::System::new("test").block_on(async {
+            let sink = Arc::new(TestSink::default());
+            let batcher = start(config(3, QueuePolicy::DropOldest), &sink);
+            assert!(batcher.push(points(0..2), None, Commit::default(), &Secrets::default()));
+            assert!(batcher.push(points(2..4), None, Commit::default(), &Secrets::default()));
+            batcher.drain().await;
+            assert_eq!(written(&sink), vec!["m v=1i 1", "m v=2i 1", "m v=3i 1"]);
+
+            let sink = Arc::new(TestSink::default());
+            let batcher = start(config(3, QueuePolicy::DropNewest), &sink);
+            assert!(batcher.push(points(0..2), None, Commit::default(), &Secrets::default()));
+            assert!(batcher.push(points(2..4), None, Commit::default(), &Secrets::default()));
+            batcher.drain().await;
+            assert_eq!(written(&sink), vec!["m v=0i 1", "m v=1i 1", "m v=2i 1"]);
+        });
+    }
+
+    #[test]
+    fn test_retry() {
+        actix_rt::System::new("test").block_on(async {
+            let sink = Arc::new(TestSink::default());
+            sink.failures.store(1, Ordering::SeqCst);
+            let batcher = start(config(0, QueuePolicy::Reject), &sink);
+            assert!(batcher.push(points(0..2), None, Commit::default(), &Secrets::default()));
+
+            let flushed = batcher.drain().await;
+            assert_eq!((flushed.written, flushed.failed), (2, 0));
+            assert_eq!(written(&sink), vec!["m v=0i 1", "m v=1i 1"]);
+        });
+    }
+
+    #[test]
+    fn test_retries_exhausted() {
+        actix_rt::System::new("test").block_on(async {
+            let sink = Arc::new(TestSink::default());
+            sink.failures.store(2, Ordering::SeqCst);
+            let errors = RecentErrors::from_config(RecentErrorsConfig { size: 10 });
+            let batcher = start_with(config(0, QueuePolicy::Reject), &sink, errors.clone());
+            let secrets = Redactor::from_paths("$.host")
+                .unwrap()
+                .secrets(&[serde_json::json!({"host": "db.internal"})]);
+            assert!(batcher.push(points(0..2), None, Commit::default(), &secrets));
+
+            let flushed = batcher.drain().await;
+            assert_eq!((flushed.written, flushed.failed), (0, 2));
+            assert_eq!(batcher.queued(), 0);
+            assert!(written(&sink).is_empty());
+
+            let errors = errors.list();
+            assert_eq!(errors.len(), 2);
+            assert!(errors.iter().all(|error| error.class == "WriteError"
+                && error.message.contains("*** unavailable")
+                && !error.message.contains("db.internal")));
+        });
+    }
+
+    #[test]
+    fn test_size() {
+        actix_rt::System::new("test").block_on(async {
+            let sink = Arc::new(TestSink::default());
+            let batcher = start(
+                BatchConfig {
+                    size: 2,
+                    ..config(0, QueuePolicy::Reject)
+                },
+                &sink,
+            );
+            assert!(batcher.push(points(0..3), None, Commit::default(), &Secrets::default()));
+
+            // a full batch is written right away, the rest waits for the interval
+            actix_rt::time::delay_for(Duration::from_millis(50)).await;
+            assert_eq!(written(&sink), vec!["m v=0i 1", "m v=1i 1"]);
+            assert_eq!(batcher.queued(), 1);
+        });
+    }
+
+    #[test]
+    fn test_idle() {
+        actix_rt::System::new("test").block_on(async {
+            let sink = Arc::new(TestSink::default());
+            let batcher = start(
+                BatchConfig {
+                    idle_ms: 20,
+                    ..config(0, QueuePolicy::Reject)
+                },
+                &sink,
+            );
+            assert!(batcher.push(points(0..1), None, Commit::default(), &Secrets::default()));
+            assert!(written(&sink).is_empty());
+
+            actix_rt::time::delay_for(Duration::from_millis(100)).await;
+            assert_eq!(written(&sink), vec!["m v=0i 1"]);
+            assert_eq!(batcher.queued(), 0);
+        });
+    }
+
+    #[test]
+    fn test_interval() {
+        actix_rt::System::new("test").block_on(async {
+            let sink = Arc::new(TestSink::default());
+            let batcher = start(
+                BatchConfig {
+                    interval_ms: 50,
+                    ..config(0, QueuePolicy::Reject)
+                },
+                &sink,
+            );
+            // arriving more often than the idle time, written with the interval
+            for value in 0..4 {
+                assert!(batcher.push(
+                    points(value..value + 1),
+                    None,
+                    Commit::default(),
+                    &Secrets::default()
+                ));
+                actix_rt::time::delay_for(Duration::from_millis(20)).await;
+            }
+            actix_rt::time::delay_for(Duration::from_millis(100)).await;
+            assert_eq!(written(&sink).len(), 4);
+        });
+    }
+
+    fn entry(series: Option<&str>, value: i64) -> Entry {
+        let (retention, query) = points(value..value + 1).remove(0);
+        Entry {
+            query,
+            retention,
+            time: None,
+            series: series.map(Into::into),
+            commit: Commit::default(),
+            secrets: None,
+            attempts: 0,
+        }
+    }
+
+    #[test]
+    fn test_series() {
+        let query = Timestamp::Seconds(1)
+            .into_query("m")
+            .add_tag("b", "2")
+            .add_tag("a", "1")
+            .add_field("v", 1);
+        assert_eq!(series(&query, Some("week")).unwrap(), "week/m,a=1,b=2");
+        assert_eq!(series(&query, None).unwrap(), "/m,a=1,b=2");
+    }
+
+    #[test]
+    fn test_take_except() {
+        let queue = Queue::default();
+        queue.requeue(vec![
+            entry(Some("a"), 0),
+            entry(Some("b"), 1),
+            entry(None, 2),
+            entry(Some("a"), 3),
+            entry(Some("c"), 4),
+        ]);
+
+        let busy = vec!["a".to_string()].into_iter().collect();
+        let taken = queue.take_except(2, &busy);
+        let lines = |entries: &[Entry]| {
+            entries
+                .iter()
+                .map(|entry| crate::influx::line(&entry.query).unwrap())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(lines(&taken), vec!["m v=1i 1", "m v=2i 1"]);
+        // the others keep their order
+        let left = queue.take(10);
+        assert_eq!(lines(&left), vec!["m v=0i 1", "m v=3i 1", "m v=4i 1"]);
+    }
+
+    #[test]
+    fn test_concurrency() {
+        actix_rt::System::new("test").block_on(async {
+            let sink = Arc::new(TestSink::default());
+            let batcher = start(
+                BatchConfig {
+                    size: 1,
+                    concurrency: 4,
+                    preserve_order: true,
+                    ..config(0, QueuePolicy::Reject)
+                },
+                &sink,
+            );
+            assert!(batcher.ordered);
+            let series = |room: &str, value: i64| {
+                let query = Timestamp::Seconds(1)
+                    .into_query("m")
+                    .add_tag("room", room.to_string())
+                    .add_field("v", value);
+                (None, query)
+            };
+            let points = vec![
+                series("a", 0),
+                series("a", 1),
+                series("b", 2),
+                series("a", 3),
+            ];
+            assert!(batcher.push(points, None, Commit::default(), &Secrets::default()));
+
+            // batches of a single point are written as they come
+            batcher.drain().await;
+            assert_eq!(written(&sink).len(), 4);
+            let written: Vec<_> = written(&sink)
+                .into_iter()
+                .filter(|line| line.starts_with("m,room=a"))
+                .collect();
+            assert_eq!(
+                written,
+                vec!["m,room=a v=0i 1", "m,room=a v=1i 1", "m,room=a v=3i 1"]
+            );
+        });
+    }
+
+    #[test]
+    fn test_unordered() {
+        actix_rt::System::new("test").block_on(async {
+            let sink = Arc::new(TestSink::default());
+            // keeping the order only matters with several writes at a time
+            let batcher = start(
+                BatchConfig {
+                    preserve_order: true,
+                    ..config(0, QueuePolicy::Reject)
+                },
+                &sink,
+            );
+            assert!(!batcher.ordered);
+        });
+    }
+}
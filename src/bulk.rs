@@ -0,0 +1,180 @@
+use crate::auth::Authenticated;
+use crate::config::Processor;
+use crate::error::ServiceError;
+use crate::event::{read_body, EventConfig};
+use crate::handler::{self, HandleParams};
+use crate::shutdown::Accepting;
+use actix_web::body::Body;
+use actix_web::dev::{Decompress, Payload};
+use actix_web::error::{ErrorBadRequest, ErrorUnsupportedMediaType};
+use actix_web::http::{header, StatusCode};
+use actix_web::{web, FromRequest, HttpRequest, HttpResponse};
+use cloudevents::{AttributesReader, Event};
+use futures::future::LocalBoxFuture;
+use futures::FutureExt;
+use serde::Serialize;
+use serde_json::Value;
+
+/// Structured mode events of a batch, each of which may fail to parse on its own.
+pub struct IncomingBatch(pub Vec<Result<Event, String>>);
+
+impl FromRequest for IncomingBatch {
+    type Config = ();
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let limit = EventConfig::get(req).batch_limit;
+        let content_type = req
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default()
+            .to_string();
+        let payload = Decompress::from_headers(payload.take(), req.headers());
+
+        async move {
+            let body = read_body(payload, limit).await?;
+            let records: Vec<serde_json::Result<Value>> =
+                match content_type.split(';').next().map(str::trim) {
+                    // a JSON array of events
+                    Some("application/cloudevents-batch+json") => {
+                        serde_json::from_slice::<Vec<Value>>(&body)
+                            .map_err(ErrorBadRequest)?
+                            .into_iter()
+                            .map(Ok)
+                            .collect()
+                    }
+                    // one event per line, bad lines are reported like bad events
+                    Some("application/x-ndjson") => String::from_utf8_lossy(&body)
+                        .lines()
+                        .filter(|line| !line.trim().is_empty())
+                        .map(serde_json::from_str)
+                        .collect(),
+                    _ => {
+                        return Err(ErrorUnsupportedMediaType(
+                            "Expected application/cloudevents-batch+json or application/x-ndjson",
+                        ))
+                    }
+                };
+
+            Ok(IncomingBatch(
+                records
+                    .into_iter()
+                    .map(|record| {
+                        record
+                            .and_then(serde_json::from_value)
+                            .map_err(|err| err.to_string())
+                    })
+                    .collect(),
+            ))
+        }
+        .boxed_local()
+    }
+}
+
+/// Outcome of each event, in the spirit of the bulk API of Elasticsearch.
+#[derive(Debug, Serialize)]
+struct BulkResponse {
+    /// Some event failed, its item has the details.
+    errors: bool,
+    items: Vec<Item>,
+}
+
+#[derive(Debug, Serialize)]
+struct Item {
+    index: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    id: Option<String>,
+    status: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    message: Option<String>,
+}
+
+/// Process a batch of events one by one, so that senders can retry only the failed ones.
+///
+/// Responds with `200` if all events succeeded, `207` otherwise.
+pub async fn handle(
+    _: Accepting,
+    _: Authenticated,
+    IncomingBatch(events): IncomingBatch,
+    req: HttpRequest,
+    processor: web::Data<Processor>,
+) -> HttpResponse {
+    let profile = handler::profile(&req, &processor);
+    let mut items = Vec::with_capacity(events.len());
+    for (index, event) in events.into_iter().enumerate() {
+        let event = match event {
+            Ok(event) => event,
+            Err(err) => {
+                items.push(Item {
+                    index,
+                    id: None,
+                    status: StatusCode::BAD_REQUEST.as_u16(),
+                    error: Some("InvalidEvent".into()),
+                    message: Some(err),
+                });
+                continue;
+            }
+        };
+
+        let id = Some(event.id().to_string());
+        let params = HandleParams {
+            profile: profile.clone(),
+            ..Default::default()
+        };
+        let item = match handler::handle_event(event, params, &processor).await {
+            Ok(response) if is_success(response.status()) => Item {
+                index,
+                id,
+                status: response.status().as_u16(),
+                error: None,
+                message: None,
+            },
+            Ok(response) => Item {
+                index,
+                id,
+                status: response.status().as_u16(),
+                error: Some(
+                    match response.status() {
+                        StatusCode::MULTI_STATUS => "PartialWrite",
+                        _ => "WriteError",
+                    }
+                    .into(),
+                ),
+                message: match response.body().as_ref() {
+                    Some(Body::Bytes(body)) => Some(String::from_utf8_lossy(body).into_owned()),
+                    _ => None,
+                },
+            },
+            Err(err) => Item {
+                index,
+                id,
+                status: err.as_response_error().error_response().status().as_u16(),
+                error: Some(
+                    err.as_error::<ServiceError>()
+                        .map(ServiceError::class)
+                        .unwrap_or("InternalError")
+                        .into(),
+                ),
+                message: Some(err.to_string()),
+            },
+        };
+        items.push(item);
+    }
+
+    let errors = items.iter().any(|item| item.error.is_some());
+    let status = if errors {
+        StatusCode::MULTI_STATUS
+    } else {
+        StatusCode::OK
+    };
+    HttpResponse::build(status).json(BulkResponse { errors, items })
+}
+
+/// Partially written events need to be retried too.
+fn is_success(status: StatusCode) -> bool {
+    status.is_success() && status != StatusCode::MULTI_STATUS
+}
@@ -0,0 +1,278 @@
+use crate::error::ServiceError;
+use serde_json::{Map, Number, Value};
+use std::convert::TryFrom;
+
+/// Decode a CBOR item, as far as it can be represented in JSON.
+///
+/// Byte strings become base64 strings, map keys which aren't strings their text, and tags
+/// are dropped, leaving their content. Undefined becomes `null`.
+pub fn decode(data: &[u8]) -> Result<Value, ServiceError> {
+    let mut decoder = Decoder { data, depth: 0 };
+    let value = decoder.item()?;
+    if !decoder.data.is_empty() {
+        return Err(error("Trailing data"));
+    }
+    Ok(value)
+}
+
+/// Nesting of arrays, maps and tags, beyond which the payload is rejected.
+const MAX_DEPTH: usize = 64;
+
+struct Decoder<'a> {
+    data: &'a [u8],
+    depth: usize,
+}
+
+impl<'a> Decoder<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], ServiceError> {
+        if self.data.len() < n {
+            return Err(error("Truncated item"));
+        }
+        let (taken, rest) = self.data.split_at(n);
+        self.data = rest;
+        Ok(taken)
+    }
+
+    /// The argument of an item, `None` for indefinite lengths.
+    fn argument(&mut self, info: u8) -> Result<Option<u64>, ServiceError> {
+        let size = match info {
+            0..=23 => return Ok(Some(info as u64)),
+            24 => 1,
+            25 => 2,
+            26 => 4,
+            27 => 8,
+            31 => return Ok(None),
+            _ => return Err(error(format!("Invalid additional information: {}", info))),
+        };
+        Ok(Some(
+            self.take(size)?
+                .iter()
+                .fold(0, |acc, b| acc << 8 | *b as u64),
+        ))
+    }
+
+    fn length(&mut self, info: u8) -> Result<Option<usize>, ServiceError> {
+        match self.argument(info)? {
+            // each element takes at least a byte, anything longer is truncated
+            Some(n) if n > self.data.len() as u64 => Err(error("Truncated item")),
+            n => Ok(n.map(|n| n as usize)),
+        }
+    }
+
+    fn item(&mut self) -> Result<Value, ServiceError> {
+        let initial = self.take(1)?[0];
+        let (major, info) = (initial >> 5, initial & 0x1f);
+
+        if self.depth > MAX_DEPTH {
+            return Err(error("Nested too deep"));
+        }
+
+        match major {
+            0 => Ok(Value::Number(self.definite(info)?.into())),
+            1 => {
+                let n = -1 - self.definite(info)? as i128;
+                Ok(i64::try_from(n).map_or_else(|_| Value::from(n as f64), Value::from))
+            }
+            2 => Ok(Value::String(base64::encode(self.bytes(major, info)?))),
+            3 => String::from_utf8(self.bytes(major, info)?)
+                .map(Value::String)
+                .map_err(|err| error(err.to_string())),
+            4 => {
+                self.depth += 1;
+                let mut array = Vec::new();
+                match self.length(info)? {
+                    Some(n) => {
+                        for _ in 0..n {
+                            array.push(self.item()?);
+                        }
+                    }
+                    None => {
+                        while !self.at_break()? {
+                            array.push(self.item()?);
+                        }
+                    }
+                }
+                self.depth -= 1;
+                Ok(Value::Array(array))
+            }
+            5 => {
+                self.depth += 1;
+                let mut map = Map::new();
+                match self.length(info)? {
+                    Some(n) => {
+                        for _ in 0..n {
+                            let (key, value) = self.entry()?;
+                            map.insert(key, value);
+                        }
+                    }
+                    None => {
+                        while !self.at_break()? {
+                            let (key, value) = self.entry()?;
+                            map.insert(key, value);
+                        }
+                    }
+                }
+                self.depth -= 1;
+                Ok(Value::Object(map))
+            }
+            6 => {
+                self.definite(info)?;
+                self.depth += 1;
+                let value = self.item();
+                self.depth -= 1;
+                value
+            }
+            _ => self.simple(info),
+        }
+    }
+
+    fn definite(&mut self, info: u8) -> Result<u64, ServiceError> {
+        self.argument(info)?
+            .ok_or_else(|| error("Unexpected indefinite length"))
+    }
+
+    /// Byte or text strings, joining the chunks of indefinite ones.
+    fn bytes(&mut self, major: u8, info: u8) -> Result<Vec<u8>, ServiceError> {
+        match self.length(info)? {
+            Some(n) => Ok(self.take(n)?.to_vec()),
+            None => {
+                let mut bytes = Vec::new();
+                while !self.at_break()? {
+                    let initial = self.take(1)?[0];
+                    if initial >> 5 != major {
+                        return Err(error("Invalid chunk of an indefinite string"));
+                    }
+                    let n = self
+                        .length(initial & 0x1f)?
+                        .ok_or_else(|| error("Nested indefinite string"))?;
+                    bytes.extend_from_slice(self.take(n)?);
+                }
+                Ok(bytes)
+            }
+        }
+    }
+
+    fn entry(&mut self) -> Result<(String, Value), ServiceError> {
+        let key = match self.item()? {
+            Value::String(key) => key,
+            key => key.to_string(),
+        };
+        Ok((key, self.item()?))
+    }
+
+    /// Consume the break ending an indefinite length item, if it's next.
+    fn at_break(&mut self) -> Result<bool, ServiceError> {
+        match self.data.first() {
+            Some(0xff) => {
+                self.data = &self.data[1..];
+                Ok(true)
+            }
+            Some(_) => Ok(false),
+            None => Err(error("Missing break")),
+        }
+    }
+
+    fn simple(&mut self, info: u8) -> Result<Value, ServiceError> {
+        let float = |value: f64| Ok(Number::from_f64(value).map_or(Value::Null, Value::Number));
+        match info {
+            20 => Ok(Value::Bool(false)),
+            21 => Ok(Value::Bool(true)),
+            22 | 23 => Ok(Value::Null),
+            25 => {
+                let mut bytes = [0; 2];
+                bytes.copy_from_slice(self.take(2)?);
+                float(half(u16::from_be_bytes(bytes)))
+            }
+            26 => {
+                let mut bytes = [0; 4];
+                bytes.copy_from_slice(self.take(4)?);
+                float(f32::from_be_bytes(bytes) as f64)
+            }
+            27 => {
+                let mut bytes = [0; 8];
+                bytes.copy_from_slice(self.take(8)?);
+                float(f64::from_be_bytes(bytes))
+            }
+            _ => Err(error(format!("Unsupported simple value: {}", info))),
+        }
+    }
+}
+
+/// IEEE 754 half precision.
+fn half(bits: u16) -> f64 {
+    let sign = if bits & 0x8000 != 0 { -1.0 } else { 1.0 };
+    let exponent = (bits >> 10) & 0x1f;
+    let mantissa = (bits & 0x3ff) as f64;
+    sign * match exponent {
+        0 => mantissa * 2f64.powi(-24),
+        31 if mantissa == 0.0 => f64::INFINITY,
+        31 => f64::NAN,
+        exponent => (1.0 + mantissa / 1024.0) * 2f64.powi(exponent as i32 - 15),
+    }
+}
+
+fn error<S: Into<String>>(details: S) -> ServiceError {
+    ServiceError::PayloadParseError {
+        details: format!("Invalid CBOR payload: {}", details.into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn details(result: Result<Value, ServiceError>) -> String {
+        match result {
+            Err(ServiceError::PayloadParseError { details }) => details,
+            other => panic!("Unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_decode() {
+        // {"temp": 21.5, "ok": true, "n": [1, -2]}
+        let data = [
+            0xa3, 0x64, b't', b'e', b'm', b'p', 0xf9, 0x4d, 0x60, 0x62, b'o', b'k', 0xf5, 0x61,
+            b'n', 0x82, 0x01, 0x21,
+        ];
+        assert_eq!(
+            decode(&data).unwrap(),
+            json!({"temp": 21.5, "ok": true, "n": [1, -2]})
+        );
+    }
+
+    #[test]
+    fn test_indefinite_and_tags() {
+        // tag 1 of [_ "a", h'01'], with an indefinite text string
+        let data = [0xc1, 0x9f, 0x7f, 0x61, b'a', 0xff, 0x41, 0x01, 0xff];
+        assert_eq!(decode(&data).unwrap(), json!(["a", "AQ=="]));
+    }
+
+    #[test]
+    fn test_truncated() {
+        assert!(details(decode(&[0x82, 0x01])).contains("Truncated item"));
+        assert!(details(decode(&[0x64, b'a'])).contains("Truncated item"));
+        assert!(details(decode(&[0x9f, 0x01])).contains("Missing break"));
+        assert!(details(decode(&[])).contains("Truncated item"));
+    }
+
+    #[test]
+    fn test_depth() {
+        let mut data = vec![0x81; MAX_DEPTH + 2];
+        data.push(0x01);
+        assert!(details(decode(&data)).contains("Nested too deep"));
+
+        let mut data = vec![0x81; MAX_DEPTH];
+        data.push(0x01);
+        assert!(decode(&data).is_ok());
+    }
+
+    #[test]
+    fn test_malformed() {
+        assert!(details(decode(&[0x01, 0x02])).contains("Trailing data"));
+        assert!(details(decode(&[0x1c])).contains("Invalid additional information"));
+        assert!(details(decode(&[0x62, 0xff, 0xfe])).contains("invalid utf-8"));
+        assert!(details(decode(&[0x5f, 0x61, b'a', 0xff])).contains("Invalid chunk"));
+    }
+}
@@ -0,0 +1,148 @@
+use crate::aggregate::WindowEntry;
+use crate::config::Processor;
+use crate::dedup::DedupEntry;
+use crate::rate::CounterEntry;
+use crate::sticky::StickyEntry;
+use crate::transaction::TransactionEntry;
+use crate::unchanged::UnchangedEntry;
+use envconfig::Envconfig;
+use serde::{Deserialize, Serialize};
+use std::fs;
+
+#[derive(Envconfig, Clone, Debug)]
+pub struct CheckpointConfig {
+    /// File to persist in-memory state to on shutdown, and restore it from on start.
+    #[envconfig(from = "CHECKPOINT_FILE")]
+    pub file: Option<String>,
+}
+
+/// In-memory state, which should survive a restart.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Checkpoint {
+    #[serde(default)]
+    pub sticky: Vec<StickyEntry>,
+    /// Windows of the aggregation, which haven't been written yet.
+    #[serde(default)]
+    pub windows: Vec<WindowEntry>,
+    /// Previous values of counters.
+    #[serde(default)]
+    pub counters: Vec<CounterEntry>,
+    /// Tracked values of the last points written.
+    #[serde(default)]
+    pub unchanged: Vec<UnchangedEntry>,
+    /// Events already written, to drop redeliveries of.
+    #[serde(default)]
+    pub dedup: Vec<DedupEntry>,
+    /// Transactions, whose last event didn't arrive yet.
+    #[serde(default)]
+    pub transactions: Vec<TransactionEntry>,
+}
+
+pub fn restore(config: &CheckpointConfig, processor: &Processor) -> anyhow::Result<()> {
+    let file = match &config.file {
+        Some(file) => file,
+        None => return Ok(()),
+    };
+
+    let checkpoint: Checkpoint = match fs::read(file) {
+        Ok(data) => serde_json::from_slice(&data)?,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(err) => return Err(err.into()),
+    };
+
+    log::info!("Restoring state from {}", file);
+
+    if let Some(sticky) = &processor.sticky {
+        sticky.restore(checkpoint.sticky);
+    }
+    if let Some(aggregator) = &processor.aggregator {
+        aggregator.restore(checkpoint.windows);
+    }
+    processor.rates.restore(checkpoint.counters);
+    processor.unchanged.restore(checkpoint.unchanged);
+    if let Some(dedup) = &processor.dedup {
+        dedup.restore(checkpoint.dedup);
+    }
+    if let Some(transactions) = &processor.transactions {
+        transactions.restore(checkpoint.transactions, processor.precision);
+    }
+
+    Ok(())
+}
+
+pub fn save(config: &CheckpointConfig, processor: &Processor) -> anyhow::Result<()> {
+    let file = match &config.file {
+        Some(file) => file,
+        None => return Ok(()),
+    };
+
+    let checkpoint = Checkpoint {
+        sticky: processor
+            .sticky
+            .as_ref()
+            .map(|sticky| sticky.snapshot())
+            .unwrap_or_default(),
+        windows: processor
+            .aggregator
+            .as_ref()
+            .map(|aggregator| aggregator.snapshot())
+            .unwrap_or_default(),
+        counters: processor.rates.snapshot(),
+        unchanged: processor.unchanged.snapshot(),
+        dedup: processor
+            .dedup
+            .as_ref()
+            .map(|dedup| dedup.snapshot())
+            .unwrap_or_default(),
+        transactions: processor
+            .transactions
+            .as_ref()
+            .map(|transactions| transactions.snapshot())
+            .unwrap_or_default(),
+    };
+
+    log::info!("Saving state to {}", file);
+
+    // don't leave a broken checkpoint behind, when being killed while writing
+    let tmp = format!("{}.tmp", file);
+    fs::write(&tmp, serde_json::to_vec(&checkpoint)?)?;
+    fs::rename(&tmp, file)?;
+
+    Ok(())
+}
+
+/// Field values, which `influxdb::Type` can't be serialized as itself.
+pub mod field_value {
+    use influxdb::Type;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    #[derive(Serialize, Deserialize)]
+    enum Value {
+        Boolean(bool),
+        Float(f64),
+        SignedInteger(i64),
+        UnsignedInteger(u64),
+        Text(String),
+    }
+
+    pub fn serialize<S: Serializer>(value: &Type, serializer: S) -> Result<S::Ok, S::Error> {
+        match value.clone() {
+            Type::Boolean(v) => Value::Boolean(v),
+            Type::Float(v) => Value::Float(v),
+            Type::SignedInteger(v) => Value::SignedInteger(v),
+            Type::UnsignedInteger(v) => Value::UnsignedInteger(v),
+            Type::Text(v) => Value::Text(v),
+        }
+        .serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Type, D::Error> {
+        Ok(match Value::deserialize(deserializer)? {
+            Value::Boolean(v) => Type::Boolean(v),
+            Value::Float(v) => Type::Float(v),
+            Value::SignedInteger(v) => Type::SignedInteger(v),
+            Value::UnsignedInteger(v) => Type::UnsignedInteger(v),
+            Value::Text(v) => Type::Text(v),
+        })
+    }
+}
@@ -0,0 +1,83 @@
+use crate::sink::Sink;
+use envconfig::Envconfig;
+use influxdb::WriteQuery;
+use serde::Serialize;
+use std::time::{Duration, Instant};
+
+#[derive(Envconfig, Clone, Debug)]
+pub struct ChunkConfig {
+    /// Maximum number of points of one event per write, disabled when zero.
+    #[envconfig(from = "WRITE_CHUNK_SIZE", default = "0")]
+    pub size: usize,
+    /// Time for writing all chunks of one event, chunks left when it runs out fail.
+    #[envconfig(from = "WRITE_DEADLINE_MS")]
+    pub deadline_ms: Option<u64>,
+}
+
+/// Splits the points of a single event into several, sequential, writes.
+#[derive(Clone, Debug)]
+pub struct Chunker {
+    size: usize,
+    deadline: Option<Duration>,
+}
+
+/// What happened to the points of an event.
+#[derive(Debug, Default, Serialize)]
+pub struct ChunkResult {
+    pub written: usize,
+    pub failed: usize,
+    pub errors: Vec<String>,
+}
+
+impl Chunker {
+    pub fn from_config(config: ChunkConfig) -> Option<Self> {
+        if config.size == 0 {
+            return None;
+        }
+
+        Some(Self {
+            size: config.size,
+            deadline: config.deadline_ms.map(Duration::from_millis),
+        })
+    }
+
+    pub async fn write(
+        &self,
+        sink: &dyn Sink,
+        points: &[(Option<String>, WriteQuery)],
+    ) -> ChunkResult {
+        let start = Instant::now();
+        let mut result = ChunkResult::default();
+
+        for chunk in points.chunks(self.size) {
+            let remaining = self
+                .deadline
+                .map(|deadline| deadline.checked_sub(start.elapsed()).unwrap_or_default());
+
+            let outcome = match remaining {
+                Some(remaining) if remaining.is_zero() => Err(deadline_exceeded().to_string()),
+                Some(remaining) => actix_rt::time::timeout(remaining, sink.write(chunk))
+                    .await
+                    .unwrap_or_else(|_| Err(deadline_exceeded()))
+                    .map_err(|err| err.to_string()),
+                None => sink.write(chunk).await.map_err(|err| err.to_string()),
+            };
+
+            match outcome {
+                Ok(()) => result.written += chunk.len(),
+                Err(err) => {
+                    result.failed += chunk.len();
+                    result.errors.push(err);
+                }
+            }
+        }
+
+        result
+    }
+}
+
+fn deadline_exceeded() -> influxdb::Error {
+    influxdb::Error::ConnectionError {
+        error: "Write deadline exceeded".to_string(),
+    }
+}
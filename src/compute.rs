@@ -0,0 +1,240 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::iter::Peekable;
+use std::str::Chars;
+
+/// Arithmetic expression over extracted field values, like `voltage * current`.
+///
+/// Supports numbers, field names, `+ - * /` and parentheses.
+#[derive(Clone, Debug)]
+pub enum Expression {
+    Number(f64),
+    Field(String),
+    Negate(Box<Expression>),
+    Binary(Box<Expression>, Operator, Box<Expression>),
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum Operator {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+}
+
+impl Expression {
+    pub fn parse(expression: &str) -> anyhow::Result<Self> {
+        let mut parser = Parser {
+            chars: expression.chars().peekable(),
+        };
+        let result = parser.expression()?;
+        match parser.next_token()? {
+            None => Ok(result),
+            Some(token) => anyhow::bail!("Unexpected token in expression: {:?}", token),
+        }
+    }
+
+    /// Evaluate with the given field values, `None` if a field is missing or the
+    /// result is not a finite number.
+    pub fn eval(&self, values: &HashMap<String, f64>) -> Option<f64> {
+        let result = match self {
+            Expression::Number(n) => *n,
+            Expression::Field(name) => *values.get(name)?,
+            Expression::Negate(e) => -e.eval(values)?,
+            Expression::Binary(l, op, r) => {
+                let (l, r) = (l.eval(values)?, r.eval(values)?);
+                match op {
+                    Operator::Add => l + r,
+                    Operator::Subtract => l - r,
+                    Operator::Multiply => l * r,
+                    Operator::Divide => l / r,
+                }
+            }
+        };
+
+        if result.is_finite() {
+            Some(result)
+        } else {
+            None
+        }
+    }
+}
+
+impl fmt::Display for Expression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Expression::Number(n) => write!(f, "{}", n),
+            Expression::Field(name) => write!(f, "{}", name),
+            Expression::Negate(e) => write!(f, "-{}", e),
+            Expression::Binary(l, op, r) => {
+                let op = match op {
+                    Operator::Add => '+',
+                    Operator::Subtract => '-',
+                    Operator::Multiply => '*',
+                    Operator::Divide => '/',
+                };
+                write!(f, "({} {} {})", l, op, r)
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Token {
+    Number(f64),
+    Field(String),
+    Operator(char),
+    Open,
+    Close,
+}
+
+struct Parser<'a> {
+    chars: Peekable<Chars<'a>>,
+}
+
+impl Parser<'_> {
+    // expression := term (('+' | '-') term)*
+    fn expression(&mut self) -> anyhow::Result<Expression> {
+        let mut result = self.term()?;
+        while let Some(op) = self.operator(&['+', '-']) {
+            let right = self.term()?;
+            result = Expression::Binary(Box::new(result), op, Box::new(right));
+        }
+        Ok(result)
+    }
+
+    // term := factor (('*' | '/') factor)*
+    fn term(&mut self) -> anyhow::Result<Expression> {
+        let mut result = self.factor()?;
+        while let Some(op) = self.operator(&['*', '/']) {
+            let right = self.factor()?;
+            result = Expression::Binary(Box::new(result), op, Box::new(right));
+        }
+        Ok(result)
+    }
+
+    // factor := number | field | '-' factor | '(' expression ')'
+    fn factor(&mut self) -> anyhow::Result<Expression> {
+        match self.next_token()? {
+            Some(Token::Number(n)) => Ok(Expression::Number(n)),
+            Some(Token::Field(name)) => Ok(Expression::Field(name)),
+            Some(Token::Operator('-')) => Ok(Expression::Negate(Box::new(self.factor()?))),
+            Some(Token::Open) => {
+                let result = self.expression()?;
+                match self.next_token()? {
+                    Some(Token::Close) => Ok(result),
+                    _ => anyhow::bail!("Missing closing parenthesis in expression"),
+                }
+            }
+            Some(token) => anyhow::bail!("Unexpected token in expression: {:?}", token),
+            None => anyhow::bail!("Unexpected end of expression"),
+        }
+    }
+
+    fn operator(&mut self, accepted: &[char]) -> Option<Operator> {
+        self.skip_whitespace();
+        let op = match self.chars.peek()? {
+            c if !accepted.contains(c) => return None,
+            '+' => Operator::Add,
+            '-' => Operator::Subtract,
+            '*' => Operator::Multiply,
+            _ => Operator::Divide,
+        };
+        self.chars.next();
+        Some(op)
+    }
+
+    fn next_token(&mut self) -> anyhow::Result<Option<Token>> {
+        self.skip_whitespace();
+        let c = match self.chars.next() {
+            Some(c) => c,
+            None => return Ok(None),
+        };
+
+        let token = match c {
+            '(' => Token::Open,
+            ')' => Token::Close,
+            '+' | '-' | '*' | '/' => Token::Operator(c),
+            c if c.is_ascii_digit() || c == '.' => {
+                let number = self.take_while(c, |c| c.is_ascii_digit() || c == '.');
+                Token::Number(
+                    number
+                        .parse()
+                        .map_err(|_| anyhow::anyhow!("Invalid number in expression: {}", number))?,
+                )
+            }
+            c if c.is_alphabetic() || c == '_' => {
+                let name = self.take_while(c, |c| c.is_alphanumeric() || c == '_');
+                // field names are lowercased, like in the mapping
+                Token::Field(name.to_lowercase())
+            }
+            c => anyhow::bail!("Unexpected character in expression: {}", c),
+        };
+
+        Ok(Some(token))
+    }
+
+    fn take_while(&mut self, first: char, f: fn(char) -> bool) -> String {
+        let mut result = first.to_string();
+        while let Some(&c) = self.chars.peek() {
+            if !f(c) {
+                break;
+            }
+            result.push(c);
+            self.chars.next();
+        }
+        result
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.chars.peek().is_some_and(|c| c.is_whitespace()) {
+            self.chars.next();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn values(values: &[(&str, f64)]) -> HashMap<String, f64> {
+        values
+            .iter()
+            .map(|(name, value)| (name.to_string(), *value))
+            .collect()
+    }
+
+    #[test]
+    fn test_parse() {
+        let expression = Expression::parse("Voltage * current + 1").unwrap();
+        assert_eq!(expression.to_string(), "((voltage * current) + 1)");
+
+        let expression = Expression::parse("-(a - b) / 2").unwrap();
+        assert_eq!(expression.to_string(), "(-(a - b) / 2)");
+    }
+
+    #[test]
+    fn test_invalid() {
+        assert!(Expression::parse("").is_err());
+        assert!(Expression::parse("a +").is_err());
+        assert!(Expression::parse("(a + b").is_err());
+        assert!(Expression::parse("a b").is_err());
+        assert!(Expression::parse("a % b").is_err());
+        assert!(Expression::parse("1.2.3").is_err());
+    }
+
+    #[test]
+    fn test_eval() {
+        let values = values(&[("voltage", 230.0), ("current", 2.0)]);
+        let eval = |expression: &str| Expression::parse(expression).unwrap().eval(&values);
+
+        assert_eq!(eval("voltage * current"), Some(460.0));
+        assert_eq!(eval("1 + 2 * 3"), Some(7.0));
+        assert_eq!(eval("(1 + 2) * 3"), Some(9.0));
+        assert_eq!(eval("-current - 1"), Some(-3.0));
+        assert_eq!(eval("10 - 4 - 3"), Some(3.0));
+        // missing fields, and results which aren't numbers
+        assert_eq!(eval("power * 2"), None);
+        assert_eq!(eval("current / 0"), None);
+    }
+}
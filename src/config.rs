@@ -1,86 +1,647 @@
 use actix_web::web;
 
+use std::borrow::Cow;
 use std::convert::{TryFrom, TryInto};
 use std::env::VarError;
+use std::str::FromStr;
+use std::sync::Arc;
 
+use crate::aggregate::{Aggregation, Aggregator};
+use crate::arrival::Arrivals;
+use crate::auto::AutoFields;
+use crate::avro::Avro;
+use crate::batch::{BatchConfig, Batcher};
+use crate::chunk::Chunker;
+use crate::compute::Expression;
+use crate::convert::{self, Custom};
+use crate::csv::Csv;
+use crate::dedup::Deduplicator;
+use crate::denylist::Denylist;
+use crate::encrypt::Encryption;
 use crate::error::ServiceError;
+use crate::event::EventConfig;
+use crate::extract::Extraction;
+use crate::filter::{self, Condition, Filter};
+use crate::geohash::Geohash;
+use crate::hash::Hashing;
+use crate::inflight::InFlight;
+use crate::influx::{Consistency, Flavor, InfluxClient};
+use crate::inner::InnerPayload;
+use crate::jq::JqTransform;
+use crate::link::LinkMetrics;
+use crate::names::Names;
+use crate::preset::Preset;
+use crate::problems::{built, Problems};
+use crate::profile::Profile;
+use crate::questdb::QuestDbConfig;
+use crate::range::Range;
+use crate::rate::{RateKind, Rates};
+use crate::recent::RecentErrors;
+use crate::records::Records;
+use crate::redact::Redactor;
+use crate::registry::Registry;
+use crate::reply::Reply;
+use crate::schema::{self, SchemaConfig};
+use crate::script::Transformer;
+use crate::series::SeriesEstimate;
+use crate::shed::Shedder;
+use crate::sink::{Sink, SinkKinds, SinkPolicy};
+use crate::skew::SkewWindow;
+use crate::stats::Stats;
+use crate::sticky::StickyTags;
+use crate::stream::Streaming;
+use crate::tagformat::TagFormat;
+use crate::tags::TagGuard;
+use crate::telegraf::Telegraf;
+use crate::timescale::PgConfig;
+use crate::timestamp::PayloadTime;
+use crate::transaction::Transactions;
+use crate::udp::Protocol;
+use crate::unchanged::Unchanged;
+use crate::valuemap::ValueMap;
+use crate::wasm::WasmDecoder;
+use chrono::format::{Item, StrftimeItems};
+use chrono::{DateTime, Utc};
 use envconfig::Envconfig;
-use influxdb::{Client, Type};
+use influxdb::{Timestamp, Type};
 use serde_json::Value;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 
 // cfg.service(web::resource("/test")
 //     .route(web::get().to(|| HttpResponse::Ok()))
 //     .route(web::head().to(|| HttpResponse::MethodNotAllowed()))
 // );
-pub fn config(cfg: &mut web::ServiceConfig) {
+pub fn config(cfg: &mut web::ServiceConfig, service: Option<&Service>) {
     log::info!("Configuring service");
 
-    match init() {
-        Ok((processor, max_json_payload_size)) => {
-            cfg.data(processor.clone())
-                .data(web::JsonConfig::default().limit(max_json_payload_size));
+    if let Some(service) = service {
+        cfg.app_data(service.processor.clone())
+            .data(web::JsonConfig::default().limit(service.max_json_payload_size))
+            .app_data(
+                EventConfig::default()
+                    .limit(service.max_json_payload_size)
+                    .batch_limit(service.max_batch_payload_size),
+            );
+    }
+}
+
+/// State of the service, shared by all workers.
+#[derive(Clone)]
+pub struct Service {
+    pub processor: web::Data<Processor>,
+    pub max_json_payload_size: usize,
+    pub max_batch_payload_size: usize,
+}
+
+pub fn init() -> anyhow::Result<Service> {
+    // collect what is wrong, instead of stopping at the first problem
+
+    let mut problems = Problems::default();
+
+    let influx = problems.env::<InfluxDb>();
+    let client = influx
+        .as_ref()
+        .and_then(|influx| problems.check(InfluxClient::new(influx)));
+    let config = problems.env::<Config>();
+    let batch = problems.env::<BatchConfig>();
+    let shedder = problems.build(|config| Ok(Shedder::from_config(config)));
+    let redactor = config
+        .as_ref()
+        .and_then(|config| problems.check(Redactor::from_paths(&config.redact_paths)));
+    let filter = problems.build(Filter::from_config);
+    let transformer = problems.build(Transformer::from_config);
+    let jq = problems.build(JqTransform::from_config);
+    let payload_time = problems.build(PayloadTime::from_config);
+    let stats = problems.build(Stats::from_config);
+    let skew = problems.build(|config| Ok(SkewWindow::from_config(config)));
+    let reply = problems.build(|config| Ok(Reply::from_config(config)));
+    let decoder = problems.build(WasmDecoder::from_config);
+    let sticky = problems.build(StickyTags::from_config);
+    let transactions = problems.build(Transactions::from_config);
+    let recent_errors = problems.build(|config| Ok(RecentErrors::from_config(config)));
+    let arrivals = problems.build(|config| Ok(Arrivals::from_config(config)));
+    let dedup = problems.build(|config| Ok(Deduplicator::from_config(config)));
+    let profile = problems.build(|config| Ok(Profile::from_config(config)));
+    let tag_guard = problems.build(TagGuard::from_config);
+    let schema_config = problems.env::<SchemaConfig>();
+    let link_metrics = config.as_ref().and_then(|config| {
+        let preset = config.payload_preset;
+        problems.build(|link| LinkMetrics::from_config(link, preset))
+    });
+    let telegraf = config.as_ref().and_then(|config| {
+        let (preset, prefix) = (config.payload_preset, &config.measurement_prefix);
+        problems.build(|telegraf| Ok(Telegraf::from_config(telegraf, preset, prefix)))
+    });
+    let chunker = problems.build(|config| Ok(Chunker::from_config(config)));
+    let records = problems.build(Records::from_config);
+    let csv = problems.build(Csv::from_config);
+    let inner = problems.build(InnerPayload::from_config);
+    let rates = problems.build(|config| Ok(Rates::from_config(config)));
+    let unchanged = problems.build(|config| Ok(Unchanged::from_config(config)));
+    let aggregation = problems.build(Aggregation::from_config);
+    let avro = problems.build(Avro::from_config);
+    let streaming = problems.build(Streaming::from_config);
+    let in_flight = problems.build(|config| Ok(InFlight::from_config(config)));
+    let registry = problems.build(Registry::from_config);
+    let denylist = problems.build(|config| Ok(Denylist::from_config(config)));
+    let names = registry
+        .as_ref()
+        .and_then(|registry| problems.build(|config| Names::from_config(config, registry.clone())));
+    let pg = problems.env::<PgConfig>();
+    let questdb = problems.env::<QuestDbConfig>();
+    let sink = match (&influx, &config, &client, &pg, &questdb) {
+        (Some(influx), Some(config), Some(client), Some(pg), Some(questdb)) => problems.check_key(
+            Some("SINK"),
+            config
+                .sink
+                .create(config.sink_policy, influx, client, pg, questdb),
+        ),
+        _ => None,
+    };
+
+    // report mapping problems even without a valid InfluxDB configuration
+    let table = influx
+        .as_ref()
+        .map(|influx| influx.table.clone())
+        .unwrap_or_default();
+    let prefix = config
+        .as_ref()
+        .map(|config| config.measurement_prefix.as_str())
+        .unwrap_or_default();
+    let auto_fields = problems.build(|config| Ok(AutoFields::from_config(config)));
+    let mappings = mappings(table, prefix, auto_fields.flatten(), &mut problems);
+
+    // conflicting options
+
+    if let (Some(shedder), Some(batch)) = (&shedder, &batch) {
+        if shedder.is_some() && !batch.is_enabled() {
+            problems.add(None, "Load shedding requires batching to be enabled");
         }
-        Err(err) => {
-            log::error!("Error configuring service {:}", err);
+    }
+
+    // batches and aggregation windows are written after the events, without holding a slot
+    if let (Some(Some(_)), Some(batch)) = (&in_flight, &batch) {
+        if batch.is_enabled() {
+            problems.add(Some("MAX_IN_FLIGHT"), "Can't be used with batching");
+        }
+    }
+    if let (Some(Some(_)), Some(Some(_))) = (&in_flight, &aggregation) {
+        problems.add(Some("MAX_IN_FLIGHT"), "Can't be used with aggregation");
+    }
+
+    if let Some(suffix) = config.as_ref().and_then(|c| c.table_suffix.as_ref()) {
+        if StrftimeItems::new(suffix).any(|item| item == Item::Error) {
+            problems.add(
+                Some("TABLE_SUFFIX"),
+                format!("Invalid time format: {}", suffix),
+            );
         }
     }
-}
 
-fn init() -> anyhow::Result<(Processor, usize)> {
-    env_logger::init();
+    // every component which failed to build left a problem, there are none from here on
+    problems.into_result()?;
+    let client = built(client)?;
+    let config = built(config)?;
+    let batch = built(batch)?;
+    let shedder = built(shedder)?;
+    let redactor = built(redactor)?;
+    let filter = built(filter)?;
+    let transformer = built(transformer)?;
+    let decoder = built(decoder)?;
+    let sticky = built(sticky)?;
+    let transactions = built(transactions)?;
+    let recent_errors = built(recent_errors)?;
+    let arrivals = built(arrivals)?;
+    let dedup = built(dedup)?;
+    let profile = built(profile)?;
+    let tag_guard = built(tag_guard)?;
+    let mut schema_config = built(schema_config)?;
+    let link_metrics = built(link_metrics)?;
+    let sink = built(sink)?;
+    let chunker = built(chunker)?;
+    let records = built(records)?;
+    let in_flight = built(in_flight)?;
+    let registry = built(registry)?;
+    let mut denylist = built(denylist)?;
+    let names = built(names)?;
+    let jq = built(jq)?;
+    let payload_time = built(payload_time)?;
+    let stats = built(stats)?;
+    let skew = built(skew)?;
+    let telegraf = built(telegraf)?;
+    let reply = built(reply)?;
+    let csv = built(csv)?;
+    let inner = built(inner)?;
+    let rates = built(rates)?;
+    let unchanged = built(unchanged)?;
+    let aggregation = built(aggregation)?;
+    let avro = built(avro)?;
+    let streaming = built(streaming)?;
 
-    let influx = InfluxDb::init_from_env()?;
-    let client = Client::new(influx.uri, influx.db).with_auth(influx.user, influx.password);
+    // everything is valid, start up
 
-    let config = Config::init_from_env()?;
     let max_json_payload_size = config.max_json_payload_size;
+    let max_batch_payload_size = config.max_batch_payload_size;
+
+    if config.dry_run {
+        log::warn!("Dry run, nothing will be written to InfluxDB");
+    }
+
+    log::info!("Writing to {:?} ({:?})", config.sink.0, config.sink_policy);
+
+    let batcher = if batch.is_enabled() && !config.dry_run {
+        log::info!("Batching writes - {:?}", batch);
+        Some(Batcher::start(batch, sink.clone(), recent_errors.clone()))
+    } else {
+        None
+    };
+
+    let aggregator = match aggregation {
+        Some(aggregation) if !config.dry_run => {
+            log::info!("Aggregating points - {:?}", aggregation);
+            Some(Aggregator::start(
+                aggregation,
+                config.timestamp_precision,
+                sink.clone(),
+                recent_errors.clone(),
+            ))
+        }
+        _ => None,
+    };
+
+    if config.simd_json && !cfg!(feature = "simd") {
+        log::warn!("SIMD JSON parsing requested, but the 'simd' feature is not enabled");
+    }
+
+    if let Some(registry) = &registry {
+        log::info!("Accepting known devices only - {:?}", registry);
+        registry.start();
+    }
+
+    if let Some(stats) = stats {
+        log::info!("Sending stats - {:?}", stats);
+        stats.start();
+    }
+
+    denylist.measurement = format!("{}{}", config.measurement_prefix, denylist.measurement);
+
+    let processor = Processor {
+        client,
+        sink,
+        mappings,
+        simd_json: config.simd_json,
+        payload_format: config.payload_format,
+        payload_preset: config.payload_preset,
+        latency_field: config.latency_field,
+        sink_kinds: config.sink.clone(),
+        sink_policy: config.sink_policy,
+        table_suffix: config.table_suffix,
+        measurement_prefix: config.measurement_prefix.clone(),
+        precision: config.timestamp_precision,
+        verbose_response: config.verbose_response,
+        mapping_profile_header: config.mapping_profile_header,
+        dry_run: config.dry_run,
+        batcher,
+        aggregator,
+        redactor,
+        filter,
+        transformer,
+        jq,
+        payload_time,
+        skew,
+        telegraf,
+        reply,
+        decoder,
+        sticky,
+        shedder,
+        transactions,
+        recent_errors,
+        arrivals,
+        dedup,
+        profile,
+        tag_guard,
+        link_metrics,
+        chunker,
+        records,
+        csv,
+        inner,
+        avro,
+        rates,
+        unchanged,
+        series: Default::default(),
+        streaming,
+        in_flight,
+        registry,
+        denylist,
+        names,
+    };
+
+    schema_config.measurement =
+        format!("{}{}", config.measurement_prefix, schema_config.measurement);
+    if !processor.dry_run {
+        schema::start(schema_config, processor.sink.clone(), &processor.mappings);
+    }
+
+    let processor = web::Data::new(processor);
+    if let Some(transactions) = &processor.transactions {
+        transactions.start(processor.clone());
+    }
+
+    Ok(Service {
+        processor,
+        max_json_payload_size,
+        max_batch_payload_size,
+    })
+}
+
+/// Read the mappings from the environment, recording the problems of each entry.
+///
+/// All measurement names get the `prefix`.
+fn mappings(
+    table: String,
+    prefix: &str,
+    auto: Option<AutoFields>,
+    problems: &mut Problems,
+) -> Vec<Mapping> {
+    let mut default = Mapping::new(format!("{}{}", prefix, table));
+    default.auto = auto;
+    let mut measurements = BTreeMap::new();
+    let mut profiles = BTreeMap::new();
 
-    let mut fields = HashMap::new();
-    let mut tags = HashMap::new();
+    #[cfg(feature = "static-mapping")]
+    problems.check_key(
+        Some("STATIC_MAPPING_FILE"),
+        add_static_mapping(&mut default),
+    );
 
     for (key, value) in std::env::vars() {
+        let result = if let Some(rest) = key.strip_prefix("MEASUREMENT_") {
+            // MEASUREMENT_<NAME>_<KEY>
+            match split_name(rest, &[MEASUREMENT_KEYS]) {
+                Some((name, key)) => {
+                    let mapping = measurements.entry(name.to_string()).or_insert_with(|| {
+                        Mapping::new(format!("{}{}", prefix, name.to_lowercase()))
+                    });
+                    let prefix = format!("MEASUREMENT_{}", &rest[..rest.len() - key.len()]);
+                    mapping.add(&prefix, key, value)
+                }
+                None => Ok(()),
+            }
+        } else if let Some(rest) = key.strip_prefix("PROFILE_") {
+            // like measurements, with keys of their own
+            match split_name(rest, &[MEASUREMENT_KEYS, PROFILE_KEYS]) {
+                Some((name, key)) => {
+                    let mapping = profiles.entry(name.to_string()).or_insert_with(|| Mapping {
+                        profile: Some(name.to_lowercase()),
+                        ..Mapping::new(format!("{}{}", prefix, name.to_lowercase()))
+                    });
+                    match key {
+                        "MATCH_TYPE" => {
+                            mapping.event_types = Some(filter::split(Some(value)));
+                            Ok(())
+                        }
+                        "MEASUREMENT" => {
+                            mapping.table = format!("{}{}", prefix, value);
+                            Ok(())
+                        }
+                        key => {
+                            let prefix = format!("PROFILE_{}", &rest[..rest.len() - key.len()]);
+                            mapping.add(&prefix, key, value)
+                        }
+                    }
+                }
+                None => Ok(()),
+            }
+        } else {
+            default.add("", &key, value)
+        };
+        problems.check_key(Some(&key), result);
+    }
+
+    for (name, profile) in &profiles {
+        // profiles without event types are only used when selected by the header
+        if profile.event_types.as_ref().is_some_and(Vec::is_empty) {
+            problems.check_key(
+                Some(&format!("PROFILE_{}_MATCH_TYPE", name)),
+                Err::<(), _>(anyhow::anyhow!("Missing event types of profile {}", name)),
+            );
+        }
+    }
+
+    std::iter::once(default)
+        .chain(measurements.into_values())
+        .chain(profiles.into_values())
+        .flat_map(Mapping::split_by_retention)
+        .collect()
+}
+
+/// Keys of a profile, besides those of a mapping.
+/// Split the `<NAME>_<KEY>` of a measurement or profile variable.
+///
+/// The name ends at the first known key, unless it is followed by a double underscore and a key,
+/// like `MEASUREMENT_HASH_TAG__FIELD_X`, for names which contain a key themselves.
+fn split_name<'a>(rest: &'a str, keys: &[&[&str]]) -> Option<(&'a str, &'a str)> {
+    let keys = || keys.iter().flat_map(|keys| keys.iter());
+
+    if let Some((split, _)) = rest
+        .match_indices("__")
+        .find(|(split, _)| keys().any(|key| rest[split + 2..].starts_with(&key[1..])))
+    {
+        return Some((&rest[..split], &rest[split + 2..]));
+    }
+
+    let split = keys().filter_map(|key| rest.find(key)).min()?;
+    Some((&rest[..split], &rest[split + 1..]))
+}
+
+const PROFILE_KEYS: &[&str] = &["_MATCH_TYPE", "_MEASUREMENT"];
+
+/// Keys of a mapping, including options which are looked up by [`Mapping::add`].
+const MEASUREMENT_KEYS: &[&str] = &[
+    "_FIELD_",
+    "_TAG_",
+    "_TYPE_FIELD_",
+    "_ENCRYPT_FIELD_",
+    "_COMPUTE_FIELD_",
+    "_HASH_TAG_",
+    "_WHEN_FIELD_",
+    "_RETENTION_FIELD_",
+    "_JOIN_TAG_",
+    "_MAP_FIELD_",
+    "_REGEX_FIELD_",
+    "_REGEX_TAG_",
+    "_MIN_FIELD_",
+    "_MAX_FIELD_",
+    "_RANGE_POLICY_FIELD_",
+    "_GEOHASH_TAG_",
+    "_GEOHASH_PRECISION_TAG_",
+    "_RATE_FIELD_",
+    "_DEDUP_FIELD_",
+    "_FORMAT_TAG_",
+    "_CASE_TAG_",
+    "_PREFIX_TAG_",
+    "_SUFFIX_TAG_",
+];
+
+#[cfg(feature = "static-mapping")]
+fn add_static_mapping(mapping: &mut Mapping) -> anyhow::Result<()> {
+    use crate::static_mapping;
+
+    for field in static_mapping::FIELDS {
+        log::debug!("Adding static field - {} -> {}", field.name, field.path);
+        mapping.fields.insert(
+            field.name.to_string(),
+            Path::field(
+                "",
+                &field.name.to_uppercase(),
+                field.path.to_string(),
+                Selector::Static(field.select),
+                field.r#type.to_string().try_into()?,
+            )?,
+        );
+    }
+    for tag in static_mapping::TAGS {
+        log::debug!("Adding static tag - {} -> {}", tag.name, tag.path);
+        mapping.tags.insert(
+            tag.name.to_string(),
+            Path::tag(
+                "",
+                &tag.name.to_uppercase(),
+                tag.path.to_string(),
+                Selector::Static(tag.select),
+            )?,
+        );
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, Clone)]
+pub struct Mapping {
+    pub table: String,
+    pub fields: HashMap<String, Path>,
+    pub tags: HashMap<String, Path>,
+    pub computed: HashMap<String, Expression>,
+    /// Tags derived from a position in the payload.
+    pub geohashes: HashMap<String, Geohash>,
+    /// Retention policy to write to, the database's default if not set.
+    pub retention: Option<String>,
+    /// Fields flattened from the payload, in addition to the mapped ones.
+    pub auto: Option<AutoFields>,
+    /// Event types (globs) of a profile, which replaces the other mappings for them.
+    pub event_types: Option<Vec<String>>,
+    /// Name of the profile, in lower case, which can also be selected by a header.
+    pub profile: Option<String>,
+}
+
+impl Mapping {
+    fn new(table: String) -> Self {
+        Self {
+            table,
+            fields: HashMap::new(),
+            tags: HashMap::new(),
+            computed: HashMap::new(),
+            geohashes: HashMap::new(),
+            retention: None,
+            auto: None,
+            event_types: None,
+            profile: None,
+        }
+    }
+
+    fn is_for(&self, ty: &str) -> bool {
+        self.event_types
+            .iter()
+            .flatten()
+            .any(|pattern| filter::glob(pattern, ty))
+    }
+
+    /// Split off fields with a retention policy into mappings of their own.
+    ///
+    /// Tags are shared, computed fields stay with the database's default.
+    fn split_by_retention(mut self) -> Vec<Self> {
+        let mut split: BTreeMap<String, Mapping> = BTreeMap::new();
+
+        for (name, path) in std::mem::take(&mut self.fields) {
+            match &path.retention {
+                Some(retention) => {
+                    split
+                        .entry(retention.clone())
+                        .or_insert_with(|| Mapping {
+                            table: self.table.clone(),
+                            fields: HashMap::new(),
+                            tags: self.tags.clone(),
+                            computed: HashMap::new(),
+                            geohashes: self.geohashes.clone(),
+                            retention: Some(retention.clone()),
+                            auto: None,
+                            event_types: self.event_types.clone(),
+                            profile: self.profile.clone(),
+                        })
+                        .fields
+                        .insert(name, path);
+                }
+                None => {
+                    self.fields.insert(name, path);
+                }
+            }
+        }
+
+        std::iter::once(self).chain(split.into_values()).collect()
+    }
+
+    /// Add a `FIELD_`, `TAG_`, `COMPUTE_FIELD_` or `GEOHASH_TAG_` entry, `prefix` is the prefix of
+    /// `key` in the environment.
+    fn add(&mut self, prefix: &str, key: &str, value: String) -> anyhow::Result<()> {
         if let Some(field) = key.strip_prefix("FIELD_") {
-            log::debug!("Adding field - {} -> {}", field, value);
+            log::debug!("Adding field - {} -> {} ({})", field, value, self.table);
             let compiled = jsonpath_lib::Compiled::compile(&value)
                 .map_err(|err| anyhow::anyhow!("Failed to parse JSON path: {}", err))?;
 
             // find expected type for the field
-            let expected_type = std::env::var(format!("TYPE_FIELD_{}", field)).try_into()?;
-            fields.insert(
+            let expected_type =
+                std::env::var(format!("{}TYPE_FIELD_{}", prefix, field)).try_into()?;
+            self.fields.insert(
                 field.to_lowercase(),
-                Path {
-                    path: value,
-                    compiled,
-                    r#type: expected_type,
-                },
+                Path::field(
+                    prefix,
+                    field,
+                    value,
+                    Selector::JsonPath(compiled),
+                    expected_type,
+                )?,
             );
         } else if let Some(tag) = key.strip_prefix("TAG_") {
-            log::debug!("Adding tag - {} -> {}", tag, value);
+            log::debug!("Adding tag - {} -> {} ({})", tag, value, self.table);
             let compiled = jsonpath_lib::Compiled::compile(&value)
                 .map_err(|err| anyhow::anyhow!("Failed to parse JSON path: {}", err))?;
-            tags.insert(
+            self.tags.insert(
                 tag.to_lowercase(),
-                Path {
-                    path: value,
-                    compiled,
-                    r#type: ExpectedType::None,
-                },
+                Path::tag(prefix, tag, value, Selector::JsonPath(compiled))?,
+            );
+        } else if let Some(field) = key.strip_prefix("COMPUTE_FIELD_") {
+            log::debug!(
+                "Adding computed field - {} -> {} ({})",
+                field,
+                value,
+                self.table
             );
+            let expression = Expression::parse(&value)
+                .map_err(|err| anyhow::anyhow!("Failed to parse expression {}: {}", value, err))?;
+            self.computed.insert(field.to_lowercase(), expression);
+        } else if let Some(tag) = key.strip_prefix("GEOHASH_TAG_") {
+            log::debug!("Adding geohash tag - {} -> {} ({})", tag, value, self.table);
+            let precision = optional_var(&format!("{}GEOHASH_PRECISION_TAG_{}", prefix, tag))?;
+            self.geohashes
+                .insert(tag.to_lowercase(), Geohash::from_spec(&value, precision)?);
         }
-    }
 
-    let processor = Processor {
-        client,
-        table: influx.table,
-        fields,
-        tags,
-    };
-    Ok((processor, max_json_payload_size))
+        Ok(())
+    }
 }
 
 #[derive(Envconfig, Clone, Debug)]
-struct InfluxDb {
+pub struct InfluxDb {
     #[envconfig(from = "INFLUXDB_URI")]
     pub uri: String,
     #[envconfig(from = "INFLUXDB_DATABASE")]
@@ -91,21 +652,310 @@ struct InfluxDb {
     pub password: String,
     #[envconfig(from = "INFLUXDB_TABLE")]
     pub table: String,
+    /// Retention policy to write to, unless the mapping or field has its own.
+    #[envconfig(from = "INFLUXDB_RETENTION_POLICY")]
+    pub retention_policy: Option<String>,
+    /// Write consistency of InfluxDB Enterprise clusters: any, one, quorum or all.
+    #[envconfig(from = "INFLUXDB_CONSISTENCY")]
+    pub consistency: Option<Consistency>,
+    #[envconfig(from = "INFLUXDB_CA_CERT")]
+    pub ca_cert: Option<String>,
+    #[envconfig(from = "INFLUXDB_CLIENT_CERT")]
+    pub client_cert: Option<String>,
+    /// PKCS#8 encoded, matching INFLUXDB_CLIENT_CERT
+    #[envconfig(from = "INFLUXDB_CLIENT_KEY")]
+    pub client_key: Option<String>,
+    #[envconfig(from = "INFLUXDB_INSECURE_SKIP_VERIFY", default = "false")]
+    pub insecure_skip_verify: bool,
+    /// Standby instance, written to while this one is unreachable.
+    #[envconfig(from = "INFLUXDB_URI_FALLBACK")]
+    pub fallback_uri: Option<String>,
+    /// Consecutive writes failing to connect before switching to the standby.
+    #[envconfig(from = "INFLUXDB_FAILOVER_AFTER", default = "3")]
+    pub failover_after: u32,
+    /// Time between checks whether the primary is back, while writing to the standby.
+    #[envconfig(from = "INFLUXDB_FAILBACK_INTERVAL_S", default = "30")]
+    pub failback_interval_s: u64,
+    /// `influxdb1`, `influxdb2` or `victoriametrics`, which differ in the API written to, how
+    /// credentials are passed and how errors are reported.
+    #[envconfig(from = "SINK_FLAVOR", default = "influxdb1")]
+    pub flavor: Flavor,
+    /// Organization of the bucket, with `SINK_FLAVOR=influxdb2`.
+    #[envconfig(from = "INFLUXDB_ORG")]
+    pub org: Option<String>,
+    /// Second instance for the `influxdb-mirror` sink.
+    #[envconfig(from = "INFLUXDB_MIRROR_URI")]
+    pub mirror_uri: Option<String>,
+    /// `http`, or `udp` for sending points to a UDP listener, without waiting for InfluxDB.
+    #[envconfig(from = "INFLUXDB_PROTOCOL", default = "http")]
+    pub protocol: Protocol,
+    /// Host of the UDP listener, the host of `INFLUXDB_URI` if not set.
+    #[envconfig(from = "INFLUXDB_UDP_HOST")]
+    pub udp_host: Option<String>,
+    #[envconfig(from = "INFLUXDB_UDP_PORT", default = "8089")]
+    pub udp_port: u16,
+    /// Maximum size of a datagram, lines are packed into.
+    #[envconfig(from = "INFLUXDB_UDP_PAYLOAD_SIZE", default = "1400")]
+    pub udp_payload_size: usize,
+    /// Time a request may take overall, including connecting, 0 to wait forever.
+    #[envconfig(from = "INFLUXDB_TIMEOUT_MS", default = "10000")]
+    pub timeout_ms: u64,
+    #[envconfig(from = "INFLUXDB_CONNECT_TIMEOUT_MS", default = "0")]
+    pub connect_timeout_ms: u64,
+    /// TCP keep-alive interval of the connections, 0 to disable it.
+    #[envconfig(from = "INFLUXDB_KEEP_ALIVE_S", default = "60")]
+    pub keep_alive_s: u64,
+    /// Idle connections kept open for reuse, unlimited if unset.
+    #[envconfig(from = "INFLUXDB_POOL_MAX_IDLE")]
+    pub pool_max_idle: Option<usize>,
+    /// Time an idle connection is kept open, 0 to keep it forever.
+    #[envconfig(from = "INFLUXDB_POOL_IDLE_TIMEOUT_S", default = "90")]
+    pub pool_idle_timeout_s: u64,
 }
 
 #[derive(Envconfig, Clone, Debug)]
 struct Config {
     #[envconfig(from = "MAX_JSON_PAYLOAD_SIZE", default = "65536")]
     pub max_json_payload_size: usize,
-    #[envconfig(from = "BIND_ADDR", default = "127.0.0.1:8080")]
-    pub bind_addr: String,
+    /// Limit of a whole batch, posted to `/batch`.
+    #[envconfig(from = "MAX_BATCH_PAYLOAD_SIZE", default = "1048576")]
+    pub max_batch_payload_size: usize,
+    #[envconfig(from = "SIMD_JSON", default = "false")]
+    pub simd_json: bool,
+    #[envconfig(from = "LATENCY_FIELD")]
+    pub latency_field: Option<String>,
+    /// Comma separated JSON paths of the payload, masked in logs, errors and quarantined points.
+    #[envconfig(from = "REDACT_PATHS", default = "")]
+    pub redact_paths: String,
+    #[envconfig(from = "PAYLOAD_FORMAT", default = "json")]
+    pub payload_format: PayloadFormat,
+    #[envconfig(from = "PAYLOAD_PRESET")]
+    pub payload_preset: Option<Preset>,
+    #[envconfig(from = "TIMESTAMP_PRECISION", default = "ns")]
+    pub timestamp_precision: Precision,
+    /// Run the full pipeline, but only log and respond with the points, never writing them.
+    #[envconfig(from = "DRY_RUN", default = "false")]
+    pub dry_run: bool,
+    /// Respond with what was written, instead of an empty body.
+    #[envconfig(from = "VERBOSE_RESPONSE", default = "false")]
+    pub verbose_response: bool,
+    /// Time based suffix of measurement names, like `_%Y_%m`, using the point's timestamp.
+    #[envconfig(from = "TABLE_SUFFIX")]
+    pub table_suffix: Option<String>,
+    /// Prefix of all measurement names, to share a database between deployments.
+    #[envconfig(from = "MEASUREMENT_PREFIX", default = "")]
+    pub measurement_prefix: String,
+    /// Comma separated, each point is written to all of them.
+    #[envconfig(from = "SINK", default = "influxdb")]
+    pub sink: SinkKinds,
+    #[envconfig(from = "SINK_POLICY", default = "fail-fast")]
+    pub sink_policy: SinkPolicy,
+    /// Header naming the profile to map an event with, instead of matching its type.
+    #[envconfig(from = "MAPPING_PROFILE_HEADER", default = "X-Mapping-Profile")]
+    pub mapping_profile_header: String,
 }
 
 #[derive(Debug, Clone)]
 pub struct Path {
+    /// Name of the field or tag, for reporting.
+    pub name: String,
     pub path: String,
-    pub compiled: jsonpath_lib::Compiled,
+    pub selector: Selector,
     pub r#type: ExpectedType,
+    pub encryption: Option<Encryption>,
+    pub hashing: Option<Hashing>,
+    /// Only write the value while the condition holds.
+    pub condition: Option<Condition>,
+    pub retention: Option<String>,
+    /// Separator to join multiple values with, instead of rejecting them.
+    pub join: Option<String>,
+    /// Translation of the values, before converting them to the expected type.
+    pub map: Option<ValueMap>,
+    /// Applied to selected strings, before translating them.
+    pub extraction: Option<Extraction>,
+    /// Bounds of the converted value.
+    pub range: Option<Range>,
+    /// Write the increase of a counter, instead of its value.
+    pub rate: Option<RateKind>,
+    /// Skip points which don't change the value, see [`Unchanged`].
+    pub dedup: bool,
+    /// Text the value of a tag is written as.
+    pub format: Option<TagFormat>,
+}
+
+impl Path {
+    /// Create a field path, picking up the per-field options from the environment.
+    fn field(
+        prefix: &str,
+        field: &str,
+        path: String,
+        selector: Selector,
+        r#type: ExpectedType,
+    ) -> anyhow::Result<Self> {
+        let encryption = optional_var(&format!("{}ENCRYPT_FIELD_{}", prefix, field))?
+            .map(|spec| Encryption::from_spec(&spec))
+            .transpose()?;
+        let retention = optional_var(&format!("{}RETENTION_FIELD_{}", prefix, field))?;
+        let condition = optional_var(&format!("{}WHEN_FIELD_{}", prefix, field))?
+            .map(|condition| Condition::parse(&condition))
+            .transpose()?;
+        let map = optional_var(&format!("{}MAP_FIELD_{}", prefix, field))?
+            .map(|spec| ValueMap::from_spec(&spec))
+            .transpose()?;
+        let extraction = optional_var(&format!("{}REGEX_FIELD_{}", prefix, field))?
+            .map(|spec| Extraction::from_spec(&spec))
+            .transpose()?;
+        let range = Range::from_spec(
+            optional_var(&format!("{}MIN_FIELD_{}", prefix, field))?,
+            optional_var(&format!("{}MAX_FIELD_{}", prefix, field))?,
+            optional_var(&format!("{}RANGE_POLICY_FIELD_{}", prefix, field))?,
+        )?;
+        let rate = optional_var(&format!("{}RATE_FIELD_{}", prefix, field))?
+            .map(|rate| rate.parse())
+            .transpose()?;
+        let dedup = optional_var(&format!("{}DEDUP_FIELD_{}", prefix, field))?
+            .map(|dedup| {
+                dedup
+                    .parse()
+                    .map_err(|_| anyhow::anyhow!("Expected true or false: {}", dedup))
+            })
+            .transpose()?
+            .unwrap_or_default();
+
+        Ok(Self {
+            name: field.to_lowercase(),
+            path,
+            selector,
+            r#type,
+            encryption,
+            hashing: None,
+            condition,
+            retention,
+            join: None,
+            map,
+            extraction,
+            range,
+            rate,
+            dedup,
+            format: None,
+        })
+    }
+
+    /// Create a tag path, picking up the per-tag options from the environment.
+    fn tag(prefix: &str, tag: &str, path: String, selector: Selector) -> anyhow::Result<Self> {
+        let hashing = optional_var(&format!("{}HASH_TAG_{}", prefix, tag))?
+            .map(|spec| Hashing::from_spec(&spec))
+            .transpose()?;
+        let join = optional_var(&format!("{}JOIN_TAG_{}", prefix, tag))?;
+        let extraction = optional_var(&format!("{}REGEX_TAG_{}", prefix, tag))?
+            .map(|spec| Extraction::from_spec(&spec))
+            .transpose()?;
+        let format = TagFormat::from_spec(
+            optional_var(&format!("{}FORMAT_TAG_{}", prefix, tag))?,
+            optional_var(&format!("{}CASE_TAG_{}", prefix, tag))?,
+            optional_var(&format!("{}PREFIX_TAG_{}", prefix, tag))?,
+            optional_var(&format!("{}SUFFIX_TAG_{}", prefix, tag))?,
+        )?;
+
+        Ok(Self {
+            name: tag.to_lowercase(),
+            path,
+            selector,
+            r#type: ExpectedType::None,
+            encryption: None,
+            hashing,
+            condition: None,
+            retention: None,
+            join,
+            map: None,
+            extraction,
+            range: None,
+            rate: None,
+            dedup: false,
+            format,
+        })
+    }
+
+    /// Convert a selected value into what gets written, `None` if it's dropped.
+    pub fn value(&self, value: &Value) -> Result<Option<Type>, ServiceError> {
+        let value = match &self.extraction {
+            Some(extraction) => {
+                extraction.apply(value, matches!(self.r#type, ExpectedType::Text))?
+            }
+            None => Cow::Borrowed(value),
+        };
+        let value = match self.map.as_ref().and_then(|map| map.translate(&value)) {
+            Some(translated) => Cow::Borrowed(translated),
+            None => value,
+        };
+        let mut value = self.r#type.convert(&value, self)?;
+
+        if let Some(range) = &self.range {
+            value = match range.apply(&self.path, value)? {
+                Some(value) => value,
+                None => return Ok(None),
+            };
+        }
+
+        if let Some(format) = &self.format {
+            value = format.apply(&value);
+        }
+
+        if let Some(hashing) = &self.hashing {
+            value = hashing.hash(&value);
+        }
+
+        match &self.encryption {
+            Some(encryption) => encryption.encrypt(&value).map(Some),
+            None => Ok(Some(value)),
+        }
+    }
+
+    /// Join multiple selected values into one, `None` if the path doesn't allow that.
+    pub fn join(&self, values: &[&Value]) -> Option<Result<Option<Type>, ServiceError>> {
+        let separator = self.join.as_ref()?;
+        let joined = values
+            .iter()
+            .map(|value| match value {
+                Value::String(s) => s.clone(),
+                value => value.to_string(),
+            })
+            .collect::<Vec<_>>()
+            .join(separator);
+        Some(self.value(&Value::String(joined)))
+    }
+}
+
+fn optional_var(name: &str) -> anyhow::Result<Option<String>> {
+    match std::env::var(name) {
+        Ok(value) => Ok(Some(value)),
+        Err(VarError::NotPresent) => Ok(None),
+        Err(err) => Err(err.into()),
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum Selector {
+    JsonPath(jsonpath_lib::Compiled),
+    // generated at build time, see build.rs
+    #[cfg(feature = "static-mapping")]
+    Static(fn(&Value) -> Vec<&Value>),
+}
+
+impl Selector {
+    pub fn select<'a>(&self, json: &'a Value) -> Result<Vec<&'a Value>, ServiceError> {
+        match self {
+            Selector::JsonPath(compiled) => {
+                compiled
+                    .select(json)
+                    .map_err(|err| ServiceError::SelectorError {
+                        details: err.to_string(),
+                    })
+            }
+            #[cfg(feature = "static-mapping")]
+            Selector::Static(select) => Ok(select(json)),
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -116,61 +966,100 @@ pub enum ExpectedType {
     UnsignedInteger,
     Text,
     None,
+    /// Registered by the application, see [`convert::register`].
+    Custom(Custom),
 }
 
 impl ExpectedType {
-    fn accept(&self, value: Option<Type>) -> Result<Type, ServiceError> {
-        value.ok_or_else(|| ServiceError::PayloadParseError {
-            details: format!(""),
-        })
+    /// The built-in type of a (lowercase) name.
+    pub fn builtin(name: &str) -> Option<Self> {
+        match name {
+            "bool" | "boolean" => Some(ExpectedType::Boolean),
+            "float" | "number" => Some(ExpectedType::Float),
+            "int" | "integer" => Some(ExpectedType::SignedInteger),
+            "uint" | "unsigned" => Some(ExpectedType::UnsignedInteger),
+            "string" | "text" => Some(ExpectedType::Text),
+            "" | "none" => Some(ExpectedType::None),
+            _ => None,
+        }
     }
 
-    pub fn convert(&self, value: &Value, path: &Path) -> Result<Type, ServiceError> {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ExpectedType::Boolean => "boolean",
+            ExpectedType::Float => "float",
+            ExpectedType::SignedInteger => "integer",
+            ExpectedType::UnsignedInteger => "unsigned",
+            ExpectedType::Text => "string",
+            ExpectedType::None => "none",
+            ExpectedType::Custom(custom) => custom.name,
+        }
+    }
+
+    /// What's accepted, as reported when converting fails.
+    fn expectation(&self) -> &'static str {
         match self {
-            ExpectedType::Boolean => self.accept(value.as_bool().map(Type::Boolean)),
-            ExpectedType::Text => {
-                self.accept(value.as_str().map(ToString::to_string).map(Type::Text))
+            ExpectedType::None => "string, boolean or number",
+            ExpectedType::Custom(custom) => custom.expectation(),
+            r#type => r#type.name(),
+        }
+    }
+
+    fn error(&self, value: &Value, path: &Path) -> ServiceError {
+        let mut value = value.to_string();
+        if value.len() > MAX_REPORTED_VALUE {
+            let mut end = MAX_REPORTED_VALUE;
+            while !value.is_char_boundary(end) {
+                end -= 1;
             }
-            ExpectedType::UnsignedInteger => self.accept(value.as_u64().map(Type::UnsignedInteger)),
-            ExpectedType::SignedInteger => self.accept(value.as_i64().map(Type::SignedInteger)),
-            ExpectedType::Float => self.accept(value.as_f64().map(Type::Float)),
+            value.truncate(end);
+            value.push_str("...");
+        }
+        ServiceError::ConversionError {
+            field: path.name.clone(),
+            path: path.path.clone(),
+            value,
+            expected: self.expectation(),
+        }
+    }
+
+    pub fn convert(&self, value: &Value, path: &Path) -> Result<Type, ServiceError> {
+        let converted = match self {
+            ExpectedType::Boolean => value.as_bool().map(Type::Boolean),
+            ExpectedType::Text => value.as_str().map(ToString::to_string).map(Type::Text),
+            ExpectedType::UnsignedInteger => value.as_u64().map(Type::UnsignedInteger),
+            ExpectedType::SignedInteger => value.as_i64().map(Type::SignedInteger),
+            ExpectedType::Float => value.as_f64().map(Type::Float),
             ExpectedType::None => match value {
-                Value::String(s) => Ok(Type::Text(s.clone())),
-                Value::Bool(b) => Ok(Type::Boolean(*b)),
+                Value::String(s) => Some(Type::Text(s.clone())),
+                Value::Bool(b) => Some(Type::Boolean(*b)),
                 Value::Number(n) => n
                     .as_f64()
                     .map(Type::Float)
                     .or_else(|| n.as_i64().map(Type::SignedInteger))
-                    .or_else(|| n.as_u64().map(Type::UnsignedInteger))
-                    .ok_or_else(|| ServiceError::PayloadParseError {
-                        details: format!(
-                            "Unknown numeric type - path: {}, value: {:?}",
-                            path.path, n
-                        ),
-                    }),
-                _ => Err(ServiceError::PayloadParseError {
-                    details: format!(
-                        "Invalid value type selected - path: {}, value: {:?}",
-                        path.path, value
-                    ),
-                }),
+                    .or_else(|| n.as_u64().map(Type::UnsignedInteger)),
+                _ => None,
             },
-        }
+            ExpectedType::Custom(custom) => custom.convert(value),
+        };
+        converted.ok_or_else(|| self.error(value, path))
     }
 }
 
+/// Length of the JSON of a value which failed to convert, before it gets cut off in the error.
+const MAX_REPORTED_VALUE: usize = 128;
+
 impl TryFrom<String> for ExpectedType {
     type Error = anyhow::Error;
 
     fn try_from(value: String) -> Result<Self, Self::Error> {
-        match value.to_lowercase().as_str() {
-            "bool" | "boolean" => Ok(ExpectedType::Boolean),
-            "float" | "number" => Ok(ExpectedType::Float),
-            "int" | "integer" => Ok(ExpectedType::SignedInteger),
-            "uint" | "unsigned" => Ok(ExpectedType::UnsignedInteger),
-            "string" | "text" => Ok(ExpectedType::Text),
-            "" | "none" => Ok(ExpectedType::None),
-            _ => anyhow::bail!("Unknown type: {}", value),
+        let name = value.to_lowercase();
+        match ExpectedType::builtin(&name) {
+            Some(r#type) => Ok(r#type),
+            None => match convert::lookup(&name) {
+                Some(custom) => Ok(ExpectedType::Custom(custom)),
+                None => anyhow::bail!("Unknown type: {}", value),
+            },
         }
     }
 }
@@ -189,10 +1078,209 @@ impl TryFrom<Result<String, VarError>> for ExpectedType {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PayloadFormat {
+    Json,
+    CayenneLpp,
+}
+
+impl FromStr for PayloadFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "json" => Ok(PayloadFormat::Json),
+            "cayenne-lpp" | "lpp" => Ok(PayloadFormat::CayenneLpp),
+            _ => anyhow::bail!("Unknown payload format: {}", s),
+        }
+    }
+}
+
+/// Precision of the timestamps written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Precision {
+    Nanoseconds,
+    Microseconds,
+    Milliseconds,
+    Seconds,
+}
+
+impl FromStr for Precision {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "ns" => Ok(Precision::Nanoseconds),
+            "us" | "u" => Ok(Precision::Microseconds),
+            "ms" => Ok(Precision::Milliseconds),
+            "s" => Ok(Precision::Seconds),
+            _ => anyhow::bail!("Unknown timestamp precision: {}", s),
+        }
+    }
+}
+
+impl Precision {
+    /// Truncate the time to this precision.
+    pub fn timestamp(&self, time: DateTime<Utc>) -> Timestamp {
+        match self {
+            Precision::Nanoseconds => Timestamp::from(time),
+            Precision::Microseconds => Timestamp::Microseconds(
+                (time.timestamp() * 1_000_000 + time.timestamp_subsec_micros() as i64) as u128,
+            ),
+            Precision::Milliseconds => Timestamp::Milliseconds(time.timestamp_millis() as u128),
+            Precision::Seconds => Timestamp::Seconds(time.timestamp() as u128),
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Processor {
-    pub client: Client,
-    pub table: String,
-    pub fields: HashMap<String, Path>,
-    pub tags: HashMap<String, Path>,
+    /// For what is specific to InfluxDB, points go to the sink.
+    pub client: InfluxClient,
+    pub sink: Arc<dyn Sink>,
+    pub sink_kinds: SinkKinds,
+    pub sink_policy: SinkPolicy,
+    pub mappings: Vec<Mapping>,
+    pub simd_json: bool,
+    pub payload_format: PayloadFormat,
+    pub payload_preset: Option<Preset>,
+    pub latency_field: Option<String>,
+    pub table_suffix: Option<String>,
+    /// Of measurements which aren't mapped, like those of line protocol writes.
+    pub measurement_prefix: String,
+    pub precision: Precision,
+    pub verbose_response: bool,
+    pub mapping_profile_header: String,
+    pub dry_run: bool,
+    pub batcher: Option<Batcher>,
+    pub aggregator: Option<Aggregator>,
+    pub redactor: Redactor,
+    pub filter: Filter,
+    pub transformer: Option<Transformer>,
+    pub jq: Option<JqTransform>,
+    pub payload_time: Option<PayloadTime>,
+    pub skew: Option<SkewWindow>,
+    pub telegraf: Option<Telegraf>,
+    pub reply: Option<Reply>,
+    pub decoder: Option<WasmDecoder>,
+    pub sticky: Option<StickyTags>,
+    pub shedder: Option<Shedder>,
+    pub transactions: Option<Transactions>,
+    pub recent_errors: RecentErrors,
+    pub arrivals: Arrivals,
+    pub dedup: Option<Deduplicator>,
+    pub profile: Profile,
+    pub tag_guard: TagGuard,
+    pub link_metrics: Option<LinkMetrics>,
+    /// Splits the writes of events with many points.
+    pub chunker: Option<Chunker>,
+    pub records: Option<Records>,
+    pub csv: Csv,
+    pub inner: Option<InnerPayload>,
+    pub avro: Option<Avro>,
+    pub rates: Rates,
+    pub unchanged: Unchanged,
+    /// Distinct series written, as far as they are estimated.
+    pub series: SeriesEstimate,
+    pub streaming: Option<Streaming>,
+    pub in_flight: Option<InFlight>,
+    pub registry: Option<Registry>,
+    pub denylist: Denylist,
+    pub names: Option<Names>,
+}
+
+impl Processor {
+    /// The mappings of the profiles for an event type, or the others if there is none.
+    ///
+    /// A profile selected by name, like with the `X-Mapping-Profile` header, is used whatever
+    /// the type of the event.
+    pub fn mappings_for(
+        &self,
+        ty: &str,
+        profile: Option<&str>,
+    ) -> Result<Vec<&Mapping>, ServiceError> {
+        if let Some(profile) = profile {
+            let profile = profile.to_lowercase();
+            let mappings = self
+                .mappings
+                .iter()
+                .filter(|mapping| mapping.profile.as_ref() == Some(&profile))
+                .collect::<Vec<_>>();
+            if mappings.is_empty() {
+                return Err(ServiceError::UnknownProfile { details: profile });
+            }
+            return Ok(mappings);
+        }
+
+        let profiled = self.mappings.iter().any(|mapping| mapping.is_for(ty));
+        Ok(self
+            .mappings
+            .iter()
+            .filter(|mapping| match profiled {
+                true => mapping.is_for(ty),
+                false => mapping.profile.is_none(),
+            })
+            .collect())
+    }
+
+    /// Name of the measurement for a point at `time`.
+    pub fn table(&self, mapping: &Mapping, time: DateTime<Utc>) -> String {
+        self.measurement(&mapping.table, time)
+    }
+
+    /// Add the suffix to the name of a measurement.
+    pub fn measurement(&self, table: &str, time: DateTime<Utc>) -> String {
+        match &self.table_suffix {
+            Some(suffix) => format!("{}{}", table, time.format(suffix)),
+            None => table.to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_name() {
+        let measurement = |rest| split_name(rest, &[MEASUREMENT_KEYS]);
+        assert_eq!(measurement("ENV_FIELD_TEMP"), Some(("ENV", "FIELD_TEMP")));
+        assert_eq!(
+            measurement("ENV_TYPE_FIELD_TEMP"),
+            Some(("ENV", "TYPE_FIELD_TEMP"))
+        );
+        assert_eq!(
+            measurement("ENV_FIELD_MY_TAG_X"),
+            Some(("ENV", "FIELD_MY_TAG_X"))
+        );
+        assert_eq!(
+            measurement("ROOM_TEMP_HASH_TAG_ID"),
+            Some(("ROOM_TEMP", "HASH_TAG_ID"))
+        );
+        assert_eq!(measurement("ENV_VALUE"), None);
+    }
+
+    #[test]
+    fn test_split_name_with_keys() {
+        let measurement = |rest| split_name(rest, &[MEASUREMENT_KEYS]);
+        // the name would end at _TAG_ otherwise
+        assert_eq!(
+            measurement("HASH_TAG__FIELD_X"),
+            Some(("HASH_TAG", "FIELD_X"))
+        );
+        assert_eq!(
+            measurement("MY_FIELD_DATA__TYPE_FIELD_X"),
+            Some(("MY_FIELD_DATA", "TYPE_FIELD_X"))
+        );
+        // not followed by a key, part of the name or key
+        assert_eq!(measurement("ENV__X_FIELD_Y"), Some(("ENV__X", "FIELD_Y")));
+        assert_eq!(measurement("ENV_FIELD_A__B"), Some(("ENV", "FIELD_A__B")));
+
+        let profile = |rest| split_name(rest, &[MEASUREMENT_KEYS, PROFILE_KEYS]);
+        assert_eq!(profile("A_MATCH_TYPE"), Some(("A", "MATCH_TYPE")));
+        assert_eq!(
+            profile("A_TAG__MEASUREMENT"),
+            Some(("A_TAG", "MEASUREMENT"))
+        );
+    }
 }
@@ -0,0 +1,92 @@
+use crate::auth::Authenticated;
+use crate::config::Processor;
+use actix_web::{web, HttpResponse};
+use envconfig::Envconfig;
+use serde::Serialize;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+#[derive(Envconfig, Clone, Debug)]
+pub struct ControlConfig {
+    /// Value of the `Retry-After` header, while paused.
+    #[envconfig(from = "CONTROL_RETRY_AFTER_S", default = "30")]
+    pub retry_after_s: u64,
+}
+
+/// Lets operators pause ingestion, for example during InfluxDB maintenance.
+#[derive(Clone, Debug)]
+pub struct Control {
+    paused: Arc<AtomicBool>,
+    pub retry_after_s: u64,
+}
+
+#[derive(Serialize)]
+struct Status {
+    paused: bool,
+}
+
+impl Control {
+    pub fn from_config(config: ControlConfig) -> Self {
+        Self {
+            paused: Default::default(),
+            retry_after_s: config.retry_after_s,
+        }
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::Relaxed)
+    }
+
+    fn set_paused(&self, paused: bool) -> HttpResponse {
+        self.paused.store(paused, Ordering::Relaxed);
+        log::info!("Ingestion {}", if paused { "paused" } else { "resumed" });
+        self.status()
+    }
+
+    fn status(&self) -> HttpResponse {
+        HttpResponse::Ok().json(Status {
+            paused: self.is_paused(),
+        })
+    }
+}
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::scope("/control")
+            .route("", web::get().to(status))
+            .route("/pause", web::post().to(pause))
+            .route("/resume", web::post().to(resume))
+            .route("/drain", web::post().to(drain)),
+    );
+}
+
+async fn status(_: Authenticated, control: web::Data<Control>) -> HttpResponse {
+    control.status()
+}
+
+async fn pause(_: Authenticated, control: web::Data<Control>) -> HttpResponse {
+    control.set_paused(true)
+}
+
+async fn resume(_: Authenticated, control: web::Data<Control>) -> HttpResponse {
+    control.set_paused(false)
+}
+
+/// Pause, and write everything which is buffered.
+async fn drain(
+    _: Authenticated,
+    control: web::Data<Control>,
+    processor: web::Data<Processor>,
+) -> HttpResponse {
+    let response = control.set_paused(true);
+    if let Some(transactions) = &processor.transactions {
+        transactions.drain(&processor).await;
+    }
+    if let Some(aggregator) = &processor.aggregator {
+        aggregator.drain().await;
+    }
+    if let Some(batcher) = &processor.batcher {
+        batcher.drain().await;
+    }
+    response
+}
@@ -0,0 +1,85 @@
+use influxdb::Type;
+use lazy_static::lazy_static;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, RwLock};
+
+/// Converts selected values into what gets written, for types beyond the built-in ones.
+pub trait Converter: Send + Sync {
+    /// The value to write, `None` if it can't be converted.
+    fn convert(&self, value: &Value) -> Option<Type>;
+
+    /// What's accepted, as reported when converting fails.
+    fn expectation(&self) -> &'static str {
+        "convertible value"
+    }
+}
+
+impl<F> Converter for F
+where
+    F: Fn(&Value) -> Option<Type> + Send + Sync,
+{
+    fn convert(&self, value: &Value) -> Option<Type> {
+        self(value)
+    }
+}
+
+/// A registered converter, with its name.
+#[derive(Clone)]
+pub struct Custom {
+    pub name: &'static str,
+    converter: Arc<dyn Converter>,
+}
+
+impl fmt::Debug for Custom {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Custom").field(&self.name).finish()
+    }
+}
+
+impl Custom {
+    pub fn convert(&self, value: &Value) -> Option<Type> {
+        self.converter.convert(value)
+    }
+
+    pub fn expectation(&self) -> &'static str {
+        self.converter.expectation()
+    }
+}
+
+lazy_static! {
+    static ref CONVERTERS: RwLock<HashMap<String, Custom>> = Default::default();
+}
+
+/// Register a converter, so that fields can use it with `TYPE_FIELD_<NAME>=<name>`, like
+/// `celsius_from_decikelvin`.
+///
+/// This has to happen before the configuration is read. Names are case insensitive and can't
+/// be those of the built-in types, registering one again replaces its converter.
+pub fn register<C>(name: &str, converter: C) -> anyhow::Result<()>
+where
+    C: Converter + 'static,
+{
+    let name = name.to_lowercase();
+    if crate::config::ExpectedType::builtin(&name).is_some() {
+        anyhow::bail!("Can't replace the built-in type: {}", name);
+    }
+
+    let mut converters = CONVERTERS.write().unwrap();
+    let custom = Custom {
+        // only registered once in a while, at startup
+        name: match converters.get(&name) {
+            Some(custom) => custom.name,
+            None => Box::leak(name.clone().into_boxed_str()),
+        },
+        converter: Arc::new(converter),
+    };
+    converters.insert(name, custom);
+    Ok(())
+}
+
+/// The converter registered with a (lowercase) name.
+pub(crate) fn lookup(name: &str) -> Option<Custom> {
+    CONVERTERS.read().unwrap().get(name).cloned()
+}
@@ -0,0 +1,283 @@
+use crate::error::ServiceError;
+use crate::records::{self, TimeUnit};
+use chrono::{DateTime, Utc};
+use envconfig::Envconfig;
+use serde_json::{Map, Number, Value};
+
+#[derive(Envconfig, Clone, Debug)]
+pub struct CsvConfig {
+    /// Comma separated names of the columns, for CSV without a header row.
+    #[envconfig(from = "CSV_COLUMNS")]
+    pub columns: Option<String>,
+    /// Column with the time of each row, the time of the event if unset.
+    #[envconfig(from = "CSV_TIMESTAMP_COLUMN")]
+    pub timestamp_column: Option<String>,
+    /// Unit of numeric timestamps, counted since the epoch.
+    #[envconfig(from = "CSV_TIMESTAMP_UNIT", default = "s")]
+    pub timestamp_unit: TimeUnit,
+    #[envconfig(from = "CSV_DELIMITER", default = ",")]
+    pub delimiter: char,
+}
+
+/// Reads `text/csv` payloads, each row becoming a point of its own.
+///
+/// Rows are turned into objects, keyed by the name of the column, so that fields are selected
+/// like `$.temperature`. Numbers and booleans are recognized, empty values are left out.
+#[derive(Clone, Debug)]
+pub struct Csv {
+    columns: Option<Vec<String>>,
+    timestamp_column: Option<String>,
+    timestamp_unit: TimeUnit,
+    delimiter: char,
+}
+
+impl Csv {
+    pub fn from_config(config: CsvConfig) -> anyhow::Result<Self> {
+        if config.delimiter == '"' || config.delimiter == '\n' {
+            anyhow::bail!("Invalid CSV_DELIMITER: {:?}", config.delimiter);
+        }
+        let columns = config.columns.map(|columns| {
+            columns
+                .split(',')
+                .map(|column| column.trim().to_string())
+                .collect()
+        });
+
+        Ok(Self {
+            columns,
+            timestamp_column: config.timestamp_column,
+            timestamp_unit: config.timestamp_unit,
+            delimiter: config.delimiter,
+        })
+    }
+
+    /// Parse the payload into an array of rows.
+    pub fn decode(&self, data: &[u8]) -> Result<Value, ServiceError> {
+        let text = std::str::from_utf8(data).map_err(|err| error(err.to_string()))?;
+        let mut rows = parse(text, self.delimiter)?.into_iter();
+
+        let columns = match &self.columns {
+            Some(columns) => columns.clone(),
+            None => rows
+                .next()
+                .ok_or_else(|| error("Missing header row"))?
+                .into_iter()
+                .map(|column| column.trim().to_string())
+                .collect(),
+        };
+
+        rows.enumerate()
+            .map(|(index, row)| {
+                if row.len() > columns.len() {
+                    return Err(error(format!(
+                        "Row {} has {} values, but there are {} columns",
+                        index + 1,
+                        row.len(),
+                        columns.len()
+                    )));
+                }
+                let row = columns
+                    .iter()
+                    .zip(row)
+                    .filter(|(_, value)| !value.is_empty())
+                    .map(|(column, value)| (column.clone(), infer(value)))
+                    .collect::<Map<_, _>>();
+                Ok(Value::Object(row))
+            })
+            .collect::<Result<_, _>>()
+            .map(Value::Array)
+    }
+
+    /// The rows of a decoded payload, with their time.
+    pub fn split(
+        &self,
+        json: Value,
+        time: DateTime<Utc>,
+    ) -> Result<Vec<(Value, DateTime<Utc>)>, ServiceError> {
+        let rows = match json {
+            Value::Array(rows) => rows,
+            json => return Ok(vec![(json, time)]),
+        };
+
+        rows.into_iter()
+            .map(|mut row| {
+                let timestamp = match (&self.timestamp_column, row.as_object_mut()) {
+                    (Some(column), Some(row)) => row.remove(column),
+                    _ => None,
+                };
+                let time = match timestamp {
+                    Some(value) => records::base_time(&value, self.timestamp_unit)
+                        .ok_or_else(|| error(format!("Invalid timestamp: {}", value)))?,
+                    None => time,
+                };
+                Ok((row, time))
+            })
+            .collect()
+    }
+}
+
+pub fn is_csv(content_type: Option<&str>) -> bool {
+    content_type
+        .and_then(|content_type| content_type.split(';').next())
+        .map(|mime| mime.trim().eq_ignore_ascii_case("text/csv"))
+        .unwrap_or_default()
+}
+
+/// Split into rows of values, unquoting them, skipping empty lines.
+fn parse(text: &str, delimiter: char) -> Result<Vec<Vec<String>>, ServiceError> {
+    let mut rows = Vec::new();
+    let mut row = Vec::new();
+    let mut value = String::new();
+    let mut quoted = false;
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted => {
+                if chars.peek() == Some(&'"') {
+                    chars.next();
+                    value.push('"');
+                } else {
+                    quoted = false;
+                }
+            }
+            '"' if value.is_empty() => quoted = true,
+            '\r' if !quoted && chars.peek() == Some(&'\n') => {}
+            '\n' if !quoted => {
+                row.push(std::mem::take(&mut value));
+                if row.iter().any(|value| !value.is_empty()) {
+                    rows.push(std::mem::take(&mut row));
+                } else {
+                    row.clear();
+                }
+            }
+            c if c == delimiter && !quoted => row.push(std::mem::take(&mut value)),
+            c => value.push(c),
+        }
+    }
+
+    if quoted {
+        return Err(error("Unterminated quoted value"));
+    }
+    row.push(value);
+    if row.iter().any(|value| !value.is_empty()) {
+        rows.push(row);
+    }
+
+    Ok(rows)
+}
+
+/// The value of a text, recognizing numbers and booleans.
+pub fn infer(value: String) -> Value {
+    match value.as_str() {
+        "true" => return Value::Bool(true),
+        "false" => return Value::Bool(false),
+        _ => {}
+    }
+    if let Ok(n) = value.parse::<i64>() {
+        return Value::Number(n.into());
+    }
+    // `inf` and `NaN` stay strings, they aren't numbers in JSON
+    match value.parse::<f64>().ok().and_then(Number::from_f64) {
+        Some(n) => Value::Number(n),
+        None => Value::String(value),
+    }
+}
+
+fn error(details: impl Into<String>) -> ServiceError {
+    ServiceError::PayloadParseError {
+        details: format!("Invalid CSV: {}", details.into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use serde_json::json;
+
+    fn csv(columns: Option<&str>, timestamp_column: Option<&str>) -> Csv {
+        Csv::from_config(CsvConfig {
+            columns: columns.map(ToString::to_string),
+            timestamp_column: timestamp_column.map(ToString::to_string),
+            timestamp_unit: TimeUnit::Seconds,
+            delimiter: ',',
+        })
+        .unwrap()
+    }
+
+    fn details<T: std::fmt::Debug>(result: Result<T, ServiceError>) -> String {
+        match result {
+            Err(ServiceError::PayloadParseError { details }) => details,
+            other => panic!("Unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_decode() {
+        let data = b"time,temp,ok,note\r\n1,21.5,true,\"a, \"\"b\"\"\"\n\n2,,false,x\n";
+        assert_eq!(
+            csv(None, None).decode(data).unwrap(),
+            json!([
+                {"time": 1, "temp": 21.5, "ok": true, "note": "a, \"b\""},
+                {"time": 2, "ok": false, "note": "x"},
+            ])
+        );
+
+        let data = b"1,2";
+        assert_eq!(
+            csv(Some("a, b"), None).decode(data).unwrap(),
+            json!([{"a": 1, "b": 2}])
+        );
+    }
+
+    #[test]
+    fn test_split() {
+        let now = Utc::now();
+        let rows = csv(None, Some("time"))
+            .split(json!([{"time": 1, "temp": 2}, {"temp": 3}]), now)
+            .unwrap();
+        assert_eq!(
+            rows,
+            vec![
+                (json!({"temp": 2}), Utc.timestamp_opt(1, 0).unwrap()),
+                (json!({"temp": 3}), now),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_truncated() {
+        assert!(details(csv(None, None).decode(b"a,b\n1,\"2")).contains("Unterminated quoted"));
+        assert!(details(csv(None, None).decode(b"")).contains("Missing header row"));
+    }
+
+    #[test]
+    fn test_size() {
+        assert!(details(csv(None, None).decode(b"a,b\n1,2,3"))
+            .contains("Row 1 has 3 values, but there are 2 columns"));
+    }
+
+    #[test]
+    fn test_malformed() {
+        assert!(details(csv(None, None).decode(&[0x61, 0xff])).contains("invalid utf-8"));
+        let rows = csv(None, Some("time")).split(json!([{"time": "never"}]), Utc::now());
+        assert!(details(rows).contains("Invalid timestamp"));
+        assert!(Csv::from_config(CsvConfig {
+            columns: None,
+            timestamp_column: None,
+            timestamp_unit: TimeUnit::Seconds,
+            delimiter: '"',
+        })
+        .is_err());
+    }
+
+    #[test]
+    fn test_infer() {
+        assert_eq!(infer("1".into()), json!(1));
+        assert_eq!(infer("1.5".into()), json!(1.5));
+        assert_eq!(infer("true".into()), json!(true));
+        assert_eq!(infer("NaN".into()), json!("NaN"));
+        assert_eq!(infer("x".into()), json!("x"));
+    }
+}
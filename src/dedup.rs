@@ -0,0 +1,243 @@
+use chrono::{DateTime, Utc};
+use cloudevents::{AttributesReader, Event};
+use envconfig::Envconfig;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+#[derive(Envconfig, Clone, Debug)]
+pub struct DedupConfig {
+    /// Number of events to remember, disabled when zero.
+    #[envconfig(from = "DEDUP_SIZE", default = "0")]
+    pub size: usize,
+    #[envconfig(from = "DEDUP_TTL_S", default = "300")]
+    pub ttl_s: u64,
+}
+
+type Key = (String, String);
+
+/// A written event, as persisted in a checkpoint.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DedupEntry {
+    pub source: String,
+    pub id: String,
+    pub time: DateTime<Utc>,
+}
+
+#[derive(Debug, Default)]
+struct Seen {
+    times: HashMap<Key, Instant>,
+    order: VecDeque<Key>,
+    /// Events being processed, not remembered yet.
+    claimed: HashSet<Key>,
+}
+
+/// Remembers the `(source, id)` of written events, to drop redeliveries.
+#[derive(Clone, Debug)]
+pub struct Deduplicator {
+    size: usize,
+    ttl: Duration,
+    seen: Arc<Mutex<Seen>>,
+}
+
+impl Deduplicator {
+    pub fn from_config(config: DedupConfig) -> Option<Self> {
+        if config.size == 0 {
+            return None;
+        }
+
+        Some(Self {
+            size: config.size,
+            ttl: Duration::from_secs(config.ttl_s),
+            seen: Default::default(),
+        })
+    }
+
+    /// Claim an event for processing, false if it was seen or is being processed already.
+    ///
+    /// Must be followed by [`Self::record`] or [`Self::release`].
+    pub fn claim(&self, event: &Event) -> bool {
+        let mut seen = self.seen.lock().unwrap();
+        let key = key(event);
+        let duplicate = seen.claimed.contains(&key)
+            || seen
+                .times
+                .get(&key)
+                .is_some_and(|time| time.elapsed() < self.ttl);
+        if !duplicate {
+            seen.claimed.insert(key);
+        }
+        !duplicate
+    }
+
+    /// Forget a claimed event, when processing failed, to let a retry through.
+    pub fn release(&self, event: &Event) {
+        self.seen.lock().unwrap().claimed.remove(&key(event));
+    }
+
+    /// Remember a claimed event, once it was processed.
+    pub fn record(&self, event: &Event) {
+        let mut seen = self.seen.lock().unwrap();
+        let Seen {
+            times,
+            order,
+            claimed,
+        } = &mut *seen;
+        let key = key(event);
+        claimed.remove(&key);
+
+        // drop what expired or doesn't fit, oldest first
+        while let Some(oldest) = order.front() {
+            let expired = times
+                .get(oldest)
+                .is_none_or(|time| time.elapsed() >= self.ttl);
+            if !expired && times.len() < self.size {
+                break;
+            }
+            if let Some(oldest) = order.pop_front() {
+                times.remove(&oldest);
+            }
+        }
+
+        if times.insert(key.clone(), Instant::now()).is_some() {
+            order.retain(|k| k != &key);
+        }
+        order.push_back(key);
+    }
+
+    /// The remembered events, oldest first.
+    pub fn snapshot(&self) -> Vec<DedupEntry> {
+        let seen = self.seen.lock().unwrap();
+        let now = Utc::now();
+        seen.order
+            .iter()
+            .filter_map(|key| {
+                let elapsed = chrono::Duration::from_std(seen.times.get(key)?.elapsed()).ok()?;
+                Some(DedupEntry {
+                    source: key.0.clone(),
+                    id: key.1.clone(),
+                    time: now - elapsed,
+                })
+            })
+            .collect()
+    }
+
+    pub fn restore(&self, entries: Vec<DedupEntry>) {
+        let mut seen = self.seen.lock().unwrap();
+        let Seen { times, order, .. } = &mut *seen;
+        let (now, instant) = (Utc::now(), Instant::now());
+        let skip = entries.len().saturating_sub(self.size);
+        for entry in entries.into_iter().skip(skip) {
+            // what expired while being down stays forgotten
+            let time = (now - entry.time)
+                .to_std()
+                .ok()
+                .filter(|elapsed| *elapsed < self.ttl)
+                .and_then(|elapsed| instant.checked_sub(elapsed));
+            if let Some(time) = time {
+                let key = (entry.source, entry.id);
+                if times.insert(key.clone(), time).is_none() {
+                    order.push_back(key);
+                }
+            }
+        }
+    }
+}
+
+fn key(event: &Event) -> Key {
+    (event.source().to_string(), event.id().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cloudevents::{EventBuilder, EventBuilderV10};
+
+    fn deduplicator(size: usize, ttl_s: u64) -> Deduplicator {
+        Deduplicator::from_config(DedupConfig { size, ttl_s }).unwrap()
+    }
+
+    fn event(source: &str, id: &str) -> Event {
+        EventBuilderV10::new()
+            .id(id)
+            .source(source)
+            .ty("t")
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_disabled() {
+        assert!(Deduplicator::from_config(DedupConfig { size: 0, ttl_s: 1 }).is_none());
+    }
+
+    #[test]
+    fn test_claim() {
+        let dedup = deduplicator(10, 300);
+        let (a, b) = (event("src", "1"), event("other", "1"));
+
+        assert!(dedup.claim(&a));
+        // a redelivery while the first one is still processed
+        assert!(!dedup.claim(&a));
+        assert!(dedup.claim(&b));
+
+        dedup.record(&a);
+        assert!(!dedup.claim(&a));
+
+        // failed ones may be retried
+        dedup.release(&b);
+        assert!(dedup.claim(&b));
+    }
+
+    #[test]
+    fn test_ttl() {
+        let dedup = deduplicator(10, 0);
+        let a = event("src", "1");
+        assert!(dedup.claim(&a));
+        dedup.record(&a);
+        assert!(dedup.claim(&a));
+    }
+
+    #[test]
+    fn test_size() {
+        let dedup = deduplicator(2, 300);
+        for id in &["1", "2", "3"] {
+            let event = event("src", id);
+            assert!(dedup.claim(&event));
+            dedup.record(&event);
+        }
+        // the oldest was dropped
+        assert!(dedup.claim(&event("src", "1")));
+        assert!(!dedup.claim(&event("src", "3")));
+        assert_eq!(dedup.snapshot().len(), 2);
+    }
+
+    #[test]
+    fn test_restore() {
+        let dedup = deduplicator(2, 300);
+        let entry = |id: &str, age_s| DedupEntry {
+            source: "src".into(),
+            id: id.into(),
+            time: Utc::now() - chrono::Duration::seconds(age_s),
+        };
+        dedup.restore(vec![
+            entry("1", 10),
+            entry("2", 400),
+            entry("3", 10),
+            entry("4", 10),
+        ]);
+
+        // only the newest which fit, and didn't expire
+        assert!(dedup.claim(&event("src", "1")));
+        assert!(dedup.claim(&event("src", "2")));
+        assert!(!dedup.claim(&event("src", "3")));
+        assert!(!dedup.claim(&event("src", "4")));
+
+        let snapshot = dedup.snapshot();
+        assert_eq!(
+            snapshot.iter().map(|e| e.id.as_str()).collect::<Vec<_>>(),
+            vec!["3", "4"]
+        );
+    }
+}
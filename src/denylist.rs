@@ -0,0 +1,114 @@
+use crate::auth::Authenticated;
+use crate::config::Processor;
+use actix_web::{web, HttpResponse};
+use cloudevents::Event;
+use envconfig::Envconfig;
+use std::collections::BTreeSet;
+use std::str::FromStr;
+use std::sync::{Arc, RwLock};
+
+#[derive(Envconfig, Clone, Debug)]
+pub struct DenylistConfig {
+    /// Comma separated devices, like `my-app/my-device`, or `my-device` of any application.
+    #[envconfig(from = "DENYLIST", default = "")]
+    pub devices: String,
+    #[envconfig(from = "DENYLIST_ACTION", default = "drop")]
+    pub action: DenyAction,
+    /// Measurement of the points of denied devices, with `DENYLIST_ACTION=quarantine`.
+    #[envconfig(from = "DENYLIST_QUARANTINE_MEASUREMENT", default = "quarantine")]
+    pub measurement: String,
+}
+
+/// What happens to the events of decommissioned devices.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DenyAction {
+    Drop,
+    /// Write the points to the quarantine measurement, instead of the mapped ones.
+    Quarantine,
+}
+
+impl FromStr for DenyAction {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "drop" => Ok(DenyAction::Drop),
+            "quarantine" => Ok(DenyAction::Quarantine),
+            _ => anyhow::bail!("Unknown action: {}", s),
+        }
+    }
+}
+
+/// Devices which were decommissioned, but might still send events.
+///
+/// Besides the configured ones, devices marked deleted in the registry are denied too.
+/// The list can be changed at runtime, through `/admin/denylist`.
+#[derive(Clone, Debug)]
+pub struct Denylist {
+    devices: Arc<RwLock<BTreeSet<String>>>,
+    pub action: DenyAction,
+    pub measurement: String,
+}
+
+impl Denylist {
+    pub fn from_config(config: DenylistConfig) -> Self {
+        Self {
+            devices: Arc::new(RwLock::new(
+                crate::filter::split(Some(config.devices))
+                    .into_iter()
+                    .collect(),
+            )),
+            action: config.action,
+            measurement: config.measurement,
+        }
+    }
+
+    pub fn is_denied(&self, event: &Event) -> bool {
+        let (application, device) = match device(event) {
+            Some(device) => device,
+            None => return false,
+        };
+        let devices = self.devices.read().unwrap();
+        devices.contains(&device) || devices.contains(&format!("{}/{}", application, device))
+    }
+
+    fn list(&self) -> HttpResponse {
+        HttpResponse::Ok().json(&*self.devices.read().unwrap())
+    }
+}
+
+/// Application and device of an event, from the extensions set by Drogue Cloud.
+pub fn device(event: &Event) -> Option<(String, String)> {
+    let extension = |name| event.extension(name).map(ToString::to_string);
+    Some((extension("application")?, extension("device")?))
+}
+
+pub async fn list(_: Authenticated, processor: web::Data<Processor>) -> HttpResponse {
+    processor.denylist.list()
+}
+
+pub async fn add(
+    _: Authenticated,
+    device: web::Path<String>,
+    processor: web::Data<Processor>,
+) -> HttpResponse {
+    log::info!("Denying events of {}", device);
+    let denylist = &processor.denylist;
+    denylist
+        .devices
+        .write()
+        .unwrap()
+        .insert(device.into_inner());
+    denylist.list()
+}
+
+pub async fn remove(
+    _: Authenticated,
+    device: web::Path<String>,
+    processor: web::Data<Processor>,
+) -> HttpResponse {
+    log::info!("Accepting events of {} again", device);
+    let denylist = &processor.denylist;
+    denylist.devices.write().unwrap().remove(device.as_str());
+    denylist.list()
+}
@@ -0,0 +1,171 @@
+use crate::auth::Authenticated;
+use crate::config::{Mapping, Path, Processor};
+use actix_web::{web, HttpResponse};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+
+/// What the function runs with, as parsed from the environment.
+///
+/// Leaves out secrets, like passwords and keys, only telling whether they are used.
+#[derive(Debug, Serialize)]
+struct Effective {
+    influxdb: InfluxDb,
+    sinks: Vec<String>,
+    sink_policy: String,
+    precision: String,
+    payload_format: String,
+    payload_preset: Option<String>,
+    table_suffix: Option<String>,
+    batching: bool,
+    dry_run: bool,
+    mapping_profile_header: String,
+    mappings: Vec<MappingConfig>,
+}
+
+#[derive(Debug, Serialize)]
+struct InfluxDb {
+    uri: String,
+    database: String,
+    user: String,
+    retention_policy: Option<String>,
+    consistency: Option<&'static str>,
+    flavor: &'static str,
+}
+
+#[derive(Debug, Serialize)]
+struct MappingConfig {
+    measurement: String,
+    retention: Option<String>,
+    /// Name of a profile, to select it with the header.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    profile: Option<String>,
+    /// Event types of a profile.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    event_types: Option<Vec<String>>,
+    auto_fields: bool,
+    fields: BTreeMap<String, PathConfig>,
+    tags: BTreeMap<String, PathConfig>,
+    computed: BTreeMap<String, String>,
+    geohashes: BTreeMap<String, GeohashConfig>,
+}
+
+#[derive(Debug, Serialize)]
+struct GeohashConfig {
+    latitude: String,
+    longitude: String,
+    precision: usize,
+}
+
+#[derive(Debug, Serialize)]
+struct PathConfig {
+    path: String,
+    r#type: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    condition: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    regex: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    range: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    join: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    rate: Option<String>,
+    mapped: bool,
+    hashed: bool,
+    formatted: bool,
+    encrypted: bool,
+    dedup: bool,
+}
+
+pub async fn config(_: Authenticated, processor: web::Data<Processor>) -> HttpResponse {
+    let processor = processor.get_ref();
+    let debug = |value: &dyn std::fmt::Debug| format!("{:?}", value);
+
+    HttpResponse::Ok().json(Effective {
+        influxdb: InfluxDb {
+            uri: processor.client.redacted_url(),
+            database: processor.client.database().to_string(),
+            user: processor.client.user().to_string(),
+            retention_policy: processor.client.retention_policy().map(ToString::to_string),
+            consistency: processor
+                .client
+                .consistency()
+                .map(|consistency| consistency.as_str()),
+            flavor: processor.client.flavor().as_str(),
+        },
+        sinks: processor
+            .sink_kinds
+            .0
+            .iter()
+            .map(|kind| debug(kind))
+            .collect(),
+        sink_policy: debug(&processor.sink_policy),
+        precision: debug(&processor.precision),
+        payload_format: debug(&processor.payload_format),
+        payload_preset: processor.payload_preset.map(|preset| debug(&preset)),
+        table_suffix: processor.table_suffix.clone(),
+        batching: processor.batcher.is_some(),
+        dry_run: processor.dry_run,
+        mapping_profile_header: processor.mapping_profile_header.clone(),
+        mappings: processor.mappings.iter().map(mapping).collect(),
+    })
+}
+
+fn mapping(mapping: &Mapping) -> MappingConfig {
+    let paths = |paths: &HashMap<String, Path>| {
+        paths
+            .iter()
+            .map(|(name, path)| (name.clone(), path_config(path)))
+            .collect()
+    };
+
+    MappingConfig {
+        measurement: mapping.table.clone(),
+        retention: mapping.retention.clone(),
+        profile: mapping.profile.clone(),
+        event_types: mapping.event_types.clone(),
+        auto_fields: mapping.auto.is_some(),
+        fields: paths(&mapping.fields),
+        tags: paths(&mapping.tags),
+        computed: mapping
+            .computed
+            .iter()
+            .map(|(name, expression)| (name.clone(), expression.to_string()))
+            .collect(),
+        geohashes: mapping
+            .geohashes
+            .iter()
+            .map(|(name, geohash)| {
+                let config = GeohashConfig {
+                    latitude: geohash.latitude.clone(),
+                    longitude: geohash.longitude.clone(),
+                    precision: geohash.precision,
+                };
+                (name.clone(), config)
+            })
+            .collect(),
+    }
+}
+
+fn path_config(path: &Path) -> PathConfig {
+    PathConfig {
+        path: path.path.clone(),
+        r#type: path.r#type.name(),
+        condition: path
+            .condition
+            .as_ref()
+            .map(|condition| condition.as_str().to_string()),
+        regex: path
+            .extraction
+            .as_ref()
+            .map(|extraction| extraction.as_str().to_string()),
+        range: path.range.as_ref().map(ToString::to_string),
+        join: path.join.clone(),
+        rate: path.rate.map(|rate| format!("{:?}", rate)),
+        mapped: path.map.is_some(),
+        hashed: path.hashing.is_some(),
+        formatted: path.format.is_some(),
+        encrypted: path.encryption.is_some(),
+        dedup: path.dedup,
+    }
+}
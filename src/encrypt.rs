@@ -0,0 +1,71 @@
+use crate::error::ServiceError;
+use aes_gcm::aead::generic_array::GenericArray;
+use aes_gcm::aead::{Aead, NewAead};
+use aes_gcm::Aes256Gcm;
+use influxdb::Type;
+use rand::RngCore;
+use std::fmt;
+
+const NONCE_LEN: usize = 12;
+
+/// AES-256-GCM encryption of field values, configured as `<key id>:<env var with the key>`.
+///
+/// Values are written as text `<key id>:<base64 of nonce and ciphertext>`, the key
+/// is expected to be base64 encoded.
+#[derive(Clone)]
+pub struct Encryption {
+    key_id: String,
+    cipher: Aes256Gcm,
+}
+
+impl fmt::Debug for Encryption {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Encryption")
+            .field("key_id", &self.key_id)
+            .finish()
+    }
+}
+
+impl Encryption {
+    pub fn from_spec(spec: &str) -> anyhow::Result<Self> {
+        let (key_id, key_var) = spec.split_once(':').ok_or_else(|| {
+            anyhow::anyhow!("Invalid encryption spec, expected <key id>:<key env var>")
+        })?;
+
+        let key = std::env::var(key_var)
+            .map_err(|err| anyhow::anyhow!("Failed to read encryption key {}: {}", key_var, err))?;
+        let key = base64::decode(key.trim())?;
+        if key.len() != 32 {
+            anyhow::bail!("Encryption key {} must be 32 bytes long", key_var);
+        }
+
+        Ok(Self {
+            key_id: key_id.to_string(),
+            cipher: Aes256Gcm::new(GenericArray::from_slice(&key)),
+        })
+    }
+
+    pub fn encrypt(&self, value: &Type) -> Result<Type, ServiceError> {
+        let mut nonce = [0u8; NONCE_LEN];
+        rand::thread_rng().fill_bytes(&mut nonce);
+
+        let ciphertext = self
+            .cipher
+            .encrypt(
+                GenericArray::from_slice(&nonce),
+                value.to_string().as_bytes(),
+            )
+            .map_err(|_| ServiceError::PayloadParseError {
+                details: "Failed to encrypt value".into(),
+            })?;
+
+        let mut data = nonce.to_vec();
+        data.extend(ciphertext);
+
+        Ok(Type::Text(format!(
+            "{}:{}",
+            self.key_id,
+            base64::encode(data)
+        )))
+    }
+}
@@ -1,3 +1,4 @@
+use actix_web::http::header;
 use actix_web::{HttpResponse, ResponseError};
 use serde::{Deserialize, Serialize};
 use snafu::Snafu;
@@ -8,6 +9,77 @@ pub enum ServiceError {
     SelectorError { details: String },
     #[snafu(display("Failed processing payload: {details}", details=details))]
     PayloadParseError { details: String },
+    #[snafu(display(
+        "Failed converting field {field} ({path}): expected {expected}, found {value}",
+        field=field, path=path, expected=expected, value=value
+    ))]
+    ConversionError {
+        field: String,
+        path: String,
+        /// The selected value, as JSON.
+        value: String,
+        expected: &'static str,
+    },
+    #[snafu(display("Failed transforming payload: {details}", details=details))]
+    #[cfg_attr(not(any(feature = "script", feature = "jq")), allow(dead_code))]
+    TransformError { details: String },
+    #[snafu(display("Too many distinct tag values: {details}", details=details))]
+    CardinalityExceeded { details: String },
+    #[snafu(display("Shutting down"))]
+    ShuttingDown,
+    #[snafu(display("Ingestion is paused"))]
+    Paused { retry_after: u64 },
+    #[snafu(display("Write queue is full"))]
+    QueueFull,
+    #[snafu(display("Failed writing points: {details}", details=details))]
+    WriteError { details: String },
+    #[snafu(display("Too many events in flight"))]
+    TooManyRequests { retry_after: u64 },
+    #[snafu(display("Unknown source: {details}", details=details))]
+    UnknownSource { details: String },
+    #[snafu(display("Unknown mapping profile: {details}", details=details))]
+    UnknownProfile { details: String },
+    #[snafu(display("Registry not synced yet"))]
+    RegistryNotReady,
+    #[snafu(display("Not ready: {details}", details=details))]
+    NotReady { details: String },
+    #[snafu(display("Failed to look up name: {details}", details=details))]
+    NameLookupFailed { details: String },
+    #[snafu(display("Failed to look up schema: {details}", details=details))]
+    SchemaLookupFailed { details: String },
+    #[snafu(display("Value out of range: {details}", details=details))]
+    ValueOutOfRange { details: String },
+    #[snafu(display("Timestamp out of range: {details}", details=details))]
+    TimestampOutOfRange { details: String },
+    #[snafu(display("Unauthorized: {details}", details=details))]
+    Unauthorized { details: String, challenge: String },
+}
+
+impl ServiceError {
+    /// Short name of the kind of error, as reported to clients.
+    pub fn class(&self) -> &'static str {
+        match self {
+            ServiceError::SelectorError { .. } => "SelectorError",
+            ServiceError::PayloadParseError { .. } => "PayloadError",
+            ServiceError::ConversionError { .. } => "ConversionError",
+            ServiceError::TransformError { .. } => "TransformError",
+            ServiceError::CardinalityExceeded { .. } => "CardinalityExceeded",
+            ServiceError::ShuttingDown => "ShuttingDown",
+            ServiceError::Paused { .. } => "Paused",
+            ServiceError::QueueFull => "QueueFull",
+            ServiceError::WriteError { .. } => "WriteError",
+            ServiceError::TooManyRequests { .. } => "TooManyRequests",
+            ServiceError::UnknownSource { .. } => "UnknownSource",
+            ServiceError::UnknownProfile { .. } => "UnknownProfile",
+            ServiceError::RegistryNotReady => "RegistryNotReady",
+            ServiceError::NotReady { .. } => "NotReady",
+            ServiceError::NameLookupFailed { .. } => "NameLookupFailed",
+            ServiceError::SchemaLookupFailed { .. } => "SchemaLookupFailed",
+            ServiceError::ValueOutOfRange { .. } => "ValueOutOfRange",
+            ServiceError::TimestampOutOfRange { .. } => "TimestampOutOfRange",
+            ServiceError::Unauthorized { .. } => "Unauthorized",
+        }
+    }
 }
 
 impl ResponseError for ServiceError {
@@ -16,16 +88,117 @@ impl ResponseError for ServiceError {
         match self {
             ServiceError::SelectorError { .. } => {
                 HttpResponse::NotAcceptable().json(ErrorResponse {
-                    error: "SelectorError".into(),
+                    error: self.class().into(),
                     message,
                 })
             }
             ServiceError::PayloadParseError { .. } => {
                 HttpResponse::NotAcceptable().json(ErrorResponse {
-                    error: "PayloadError".into(),
+                    error: self.class().into(),
+                    message,
+                })
+            }
+            ServiceError::ConversionError {
+                field,
+                path,
+                value,
+                expected,
+            } => HttpResponse::NotAcceptable().json(ConversionErrorResponse {
+                error: self.class().into(),
+                message,
+                field: field.clone(),
+                path: path.clone(),
+                value: value.clone(),
+                expected: expected.to_string(),
+            }),
+            ServiceError::TransformError { .. } => {
+                HttpResponse::NotAcceptable().json(ErrorResponse {
+                    error: self.class().into(),
+                    message,
+                })
+            }
+            ServiceError::CardinalityExceeded { .. } => {
+                HttpResponse::NotAcceptable().json(ErrorResponse {
+                    error: self.class().into(),
+                    message,
+                })
+            }
+            ServiceError::ShuttingDown => HttpResponse::ServiceUnavailable().json(ErrorResponse {
+                error: self.class().into(),
+                message,
+            }),
+            ServiceError::Paused { retry_after } => HttpResponse::ServiceUnavailable()
+                .header(header::RETRY_AFTER, retry_after.to_string())
+                .json(ErrorResponse {
+                    error: self.class().into(),
+                    message,
+                }),
+            ServiceError::QueueFull => HttpResponse::ServiceUnavailable().json(ErrorResponse {
+                error: self.class().into(),
+                message,
+            }),
+            ServiceError::WriteError { .. } => {
+                HttpResponse::InternalServerError().json(ErrorResponse {
+                    error: self.class().into(),
                     message,
                 })
             }
+            ServiceError::TooManyRequests { retry_after } => HttpResponse::TooManyRequests()
+                .header(header::RETRY_AFTER, retry_after.to_string())
+                .json(ErrorResponse {
+                    error: self.class().into(),
+                    message,
+                }),
+            ServiceError::UnknownSource { .. } => HttpResponse::Forbidden().json(ErrorResponse {
+                error: self.class().into(),
+                message,
+            }),
+            ServiceError::UnknownProfile { .. } => HttpResponse::BadRequest().json(ErrorResponse {
+                error: self.class().into(),
+                message,
+            }),
+            ServiceError::RegistryNotReady => {
+                HttpResponse::ServiceUnavailable().json(ErrorResponse {
+                    error: self.class().into(),
+                    message,
+                })
+            }
+            ServiceError::NotReady { .. } => {
+                HttpResponse::ServiceUnavailable().json(ErrorResponse {
+                    error: self.class().into(),
+                    message,
+                })
+            }
+            ServiceError::NameLookupFailed { .. } => {
+                HttpResponse::ServiceUnavailable().json(ErrorResponse {
+                    error: self.class().into(),
+                    message,
+                })
+            }
+            ServiceError::SchemaLookupFailed { .. } => {
+                HttpResponse::ServiceUnavailable().json(ErrorResponse {
+                    error: self.class().into(),
+                    message,
+                })
+            }
+            ServiceError::ValueOutOfRange { .. } => {
+                HttpResponse::NotAcceptable().json(ErrorResponse {
+                    error: self.class().into(),
+                    message,
+                })
+            }
+            ServiceError::TimestampOutOfRange { .. } => {
+                HttpResponse::BadRequest().json(ErrorResponse {
+                    error: self.class().into(),
+                    message,
+                })
+            }
+            ServiceError::Unauthorized { challenge, .. } => HttpResponse::Unauthorized()
+                .header(header::WWW_AUTHENTICATE, challenge.as_str())
+                .json(ErrorResponse {
+                    error: self.class().into(),
+                    message,
+                }),
         }
     }
 }
@@ -35,3 +208,14 @@ pub struct ErrorResponse {
     pub error: String,
     pub message: String,
 }
+
+/// An [`ErrorResponse`], telling which value of the payload failed to convert.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ConversionErrorResponse {
+    pub error: String,
+    pub message: String,
+    pub field: String,
+    pub path: String,
+    pub value: String,
+    pub expected: String,
+}
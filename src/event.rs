@@ -0,0 +1,97 @@
+use actix_web::dev::{Decompress, Payload};
+use actix_web::error::{ErrorBadRequest, ErrorPayloadTooLarge};
+use actix_web::http::header;
+use actix_web::web::{Bytes, BytesMut};
+use actix_web::{FromRequest, HttpRequest};
+use cloudevents::binding::actix::HttpRequestDeserializer;
+use cloudevents::message::MessageDeserializer;
+use cloudevents::Event;
+use futures::future::LocalBoxFuture;
+use futures::{FutureExt, StreamExt};
+
+/// Limits of reading an [`IncomingEvent`], or an [`IncomingBatch`](crate::bulk::IncomingBatch).
+#[derive(Clone, Debug)]
+pub struct EventConfig {
+    limit: usize,
+    pub(crate) batch_limit: usize,
+}
+
+impl EventConfig {
+    /// Maximum size of the body, after decompressing it.
+    pub fn limit(mut self, limit: usize) -> Self {
+        self.limit = limit;
+        self
+    }
+
+    /// Maximum size of the body of a batch, after decompressing it.
+    pub fn batch_limit(mut self, limit: usize) -> Self {
+        self.batch_limit = limit;
+        self
+    }
+
+    pub fn get(req: &HttpRequest) -> Self {
+        req.app_data::<Self>().cloned().unwrap_or_default()
+    }
+}
+
+impl Default for EventConfig {
+    fn default() -> Self {
+        Self {
+            limit: 65536,
+            batch_limit: 1048576,
+        }
+    }
+}
+
+/// Structured or binary mode CloudEvent, rather than a plain body.
+pub fn is_cloud_event(req: &HttpRequest) -> bool {
+    let structured = req
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("application/cloudevents"));
+    structured || req.headers().contains_key("ce-specversion")
+}
+
+/// Read a body which may be compressed, up to the limit.
+pub async fn read_body(
+    mut payload: Decompress<Payload>,
+    limit: usize,
+) -> Result<Bytes, actix_web::Error> {
+    let mut body = BytesMut::new();
+    while let Some(chunk) = payload.next().await {
+        let chunk = chunk?;
+        if body.len() + chunk.len() > limit {
+            return Err(ErrorPayloadTooLarge(format!(
+                "Payload exceeds the limit of {} bytes",
+                limit
+            )));
+        }
+        body.extend_from_slice(&chunk);
+    }
+    Ok(body.freeze())
+}
+
+/// A CloudEvent, read from a body which may be compressed (`Content-Encoding`).
+pub struct IncomingEvent(pub Event);
+
+impl FromRequest for IncomingEvent {
+    type Config = EventConfig;
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let limit = EventConfig::get(req).limit;
+        let payload = Decompress::from_headers(payload.take(), req.headers());
+        let req = req.clone();
+
+        async move {
+            let body = read_body(payload, limit).await?;
+            HttpRequestDeserializer::new(&req, body)
+                .into_event()
+                .map(IncomingEvent)
+                .map_err(ErrorBadRequest)
+        }
+        .boxed_local()
+    }
+}
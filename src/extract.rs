@@ -0,0 +1,104 @@
+use crate::error::ServiceError;
+use serde_json::Value;
+use std::borrow::Cow;
+
+/// Extraction of a value out of a string, like `([0-9.]+)C` pulling `23.4` out of `23.4C`.
+///
+/// Takes the first capture group, or the whole match if there is none. Unless a string is
+/// expected, captures which look like numbers or booleans are converted to them.
+#[derive(Clone, Debug)]
+pub struct Extraction {
+    regex: regex::Regex,
+}
+
+impl Extraction {
+    pub fn from_spec(spec: &str) -> anyhow::Result<Self> {
+        let regex = regex::Regex::new(spec)
+            .map_err(|err| anyhow::anyhow!("Failed to parse regex {}: {}", spec, err))?;
+        Ok(Self { regex })
+    }
+
+    pub fn as_str(&self) -> &str {
+        self.regex.as_str()
+    }
+
+    /// Values other than strings are kept as they are.
+    pub fn apply<'a>(&self, value: &'a Value, text: bool) -> Result<Cow<'a, Value>, ServiceError> {
+        let s = match value {
+            Value::String(s) => s,
+            _ => return Ok(Cow::Borrowed(value)),
+        };
+
+        let captures = self
+            .regex
+            .captures(s)
+            .ok_or_else(|| ServiceError::PayloadParseError {
+                details: format!("{} doesn't match {}", s, self.regex),
+            })?;
+        let capture = captures
+            .get(1)
+            .or_else(|| captures.get(0))
+            .map_or("", |capture| capture.as_str());
+
+        if !text {
+            if let Ok(value @ (Value::Bool(_) | Value::Number(_))) = serde_json::from_str(capture) {
+                return Ok(Cow::Owned(value));
+            }
+        }
+        Ok(Cow::Owned(Value::String(capture.to_string())))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn apply(spec: &str, value: Value, text: bool) -> Result<Value, ServiceError> {
+        let extraction = Extraction::from_spec(spec).unwrap();
+        extraction
+            .apply(&value, text)
+            .map(|value| value.into_owned())
+    }
+
+    #[test]
+    fn test_capture() {
+        assert_eq!(
+            apply("([0-9.]+)C", json!("23.4C"), false).unwrap(),
+            json!(23.4)
+        );
+        assert_eq!(
+            apply("([0-9.]+)C", json!("23.4C"), true).unwrap(),
+            json!("23.4")
+        );
+        assert_eq!(
+            apply("state=(\\w+)", json!("state=true"), false).unwrap(),
+            json!(true)
+        );
+        assert_eq!(
+            apply("id-(\\w+)", json!("id-ab12"), false).unwrap(),
+            json!("ab12")
+        );
+        // without a group, the whole match
+        assert_eq!(apply("[0-9]+", json!("v42x"), false).unwrap(), json!(42));
+        // or if the group didn't take part
+        assert_eq!(apply("a(b)?", json!("a"), false).unwrap(), json!("a"));
+    }
+
+    #[test]
+    fn test_other_values() {
+        assert_eq!(apply("([0-9]+)", json!(12), false).unwrap(), json!(12));
+        assert_eq!(apply("([0-9]+)", json!(null), false).unwrap(), json!(null));
+    }
+
+    #[test]
+    fn test_no_match() {
+        assert!(apply("([0-9]+)C", json!("hot"), false).is_err());
+    }
+
+    #[test]
+    fn test_invalid() {
+        assert!(Extraction::from_spec("(").is_err());
+        assert_eq!(Extraction::from_spec("a+").unwrap().as_str(), "a+");
+    }
+}
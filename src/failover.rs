@@ -0,0 +1,152 @@
+use crate::config::InfluxDb;
+use crate::influx::InfluxClient;
+use crate::metrics::{INFLUXDB_ENDPOINT_ACTIVE, INFLUXDB_FAILOVERS};
+use crate::sink::Sink;
+use futures::future::LocalBoxFuture;
+use futures::FutureExt;
+use influxdb::{Error, WriteQuery};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Endpoint {
+    Primary,
+    Fallback,
+}
+
+impl Endpoint {
+    fn name(self) -> &'static str {
+        match self {
+            Endpoint::Primary => "primary",
+            Endpoint::Fallback => "fallback",
+        }
+    }
+}
+
+#[derive(Debug)]
+struct State {
+    active: Endpoint,
+    /// Consecutive writes to the primary which failed to connect.
+    failures: u32,
+    /// When the primary was checked last, while writing to the fallback.
+    checked: Instant,
+}
+
+/// Writes to `INFLUXDB_URI_FALLBACK` while the primary InfluxDB is unreachable.
+///
+/// Switches over after `INFLUXDB_FAILOVER_AFTER` consecutive writes failed to connect, errors
+/// reported by InfluxDB itself don't count. While on the fallback, pings the primary every
+/// `INFLUXDB_FAILBACK_INTERVAL_S`, and switches back once it answers.
+#[derive(Debug)]
+pub struct FailoverSink {
+    primary: InfluxClient,
+    fallback: InfluxClient,
+    after: u32,
+    interval: Duration,
+    state: Mutex<State>,
+}
+
+impl FailoverSink {
+    pub fn new(primary: InfluxClient, fallback: InfluxClient, config: &InfluxDb) -> Self {
+        activate(Endpoint::Primary);
+        Self {
+            primary,
+            fallback,
+            after: config.failover_after.max(1),
+            interval: Duration::from_secs(config.failback_interval_s),
+            state: Mutex::new(State {
+                active: Endpoint::Primary,
+                failures: 0,
+                checked: Instant::now(),
+            }),
+        }
+    }
+
+    fn client(&self, endpoint: Endpoint) -> &InfluxClient {
+        match endpoint {
+            Endpoint::Primary => &self.primary,
+            Endpoint::Fallback => &self.fallback,
+        }
+    }
+
+    fn switch(&self, to: Endpoint) {
+        let mut state = self.state.lock().unwrap();
+        if state.active == to {
+            return;
+        }
+        state.active = to;
+        state.failures = 0;
+        state.checked = Instant::now();
+        drop(state);
+
+        match to {
+            Endpoint::Primary => log::info!("Primary InfluxDB is back, failing back to it"),
+            Endpoint::Fallback => log::warn!("Primary InfluxDB is unreachable, failing over"),
+        }
+        INFLUXDB_FAILOVERS.with_label_values(&[to.name()]).inc();
+        activate(to);
+    }
+
+    /// The endpoint to write to, checking whether the primary is back if it's time.
+    async fn endpoint(&self) -> Endpoint {
+        {
+            let mut state = self.state.lock().unwrap();
+            if state.active == Endpoint::Primary || state.checked.elapsed() < self.interval {
+                return state.active;
+            }
+            state.checked = Instant::now();
+        }
+        if self.primary.ping().await.is_ok() {
+            self.switch(Endpoint::Primary);
+            return Endpoint::Primary;
+        }
+        Endpoint::Fallback
+    }
+
+    async fn write_points(&self, points: &[(Option<String>, WriteQuery)]) -> Result<(), Error> {
+        if self.endpoint().await == Endpoint::Fallback {
+            return self.fallback.write_routed(points).await;
+        }
+
+        let result = self.primary.write_routed(points).await;
+        let failed = {
+            let mut state = self.state.lock().unwrap();
+            match &result {
+                Err(Error::ConnectionError { .. }) => state.failures += 1,
+                _ => state.failures = 0,
+            }
+            state.failures >= self.after
+        };
+
+        if failed {
+            self.switch(Endpoint::Fallback);
+            return self.fallback.write_routed(points).await;
+        }
+        result
+    }
+}
+
+impl Sink for FailoverSink {
+    fn write<'a>(
+        &'a self,
+        points: &'a [(Option<String>, WriteQuery)],
+    ) -> LocalBoxFuture<'a, Result<(), Error>> {
+        self.write_points(points).boxed_local()
+    }
+
+    fn ping(&self) -> LocalBoxFuture<'_, Result<(), Error>> {
+        async move {
+            let active = self.state.lock().unwrap().active;
+            self.client(active).ping().await
+        }
+        .boxed_local()
+    }
+}
+
+fn activate(endpoint: Endpoint) {
+    for other in &[Endpoint::Primary, Endpoint::Fallback] {
+        INFLUXDB_ENDPOINT_ACTIVE
+            .with_label_values(&[other.name()])
+            .set((*other == endpoint) as i64);
+    }
+}
@@ -0,0 +1,392 @@
+use crate::error::ServiceError;
+use cloudevents::{AttributesReader, Event};
+use envconfig::Envconfig;
+use serde_json::Value;
+
+#[derive(Envconfig, Clone, Debug)]
+pub struct FilterConfig {
+    /// Comma separated list of accepted event types, a trailing `*` matches a prefix.
+    #[envconfig(from = "FILTER_TYPE")]
+    pub r#type: Option<String>,
+    /// Comma separated list of accepted event sources, a trailing `*` matches a prefix.
+    #[envconfig(from = "FILTER_SOURCE")]
+    pub source: Option<String>,
+    /// JSON path into the payload, which must select a value.
+    #[envconfig(from = "FILTER_PATH")]
+    pub path: Option<String>,
+    /// Value the selected value must be equal to, parsed as JSON if possible.
+    #[envconfig(from = "FILTER_VALUE")]
+    pub value: Option<String>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Filter {
+    types: Vec<String>,
+    sources: Vec<String>,
+    path: Option<jsonpath_lib::Compiled>,
+    value: Option<Value>,
+}
+
+impl Filter {
+    pub fn from_config(config: FilterConfig) -> anyhow::Result<Self> {
+        let path = config
+            .path
+            .map(|path| {
+                jsonpath_lib::Compiled::compile(&path)
+                    .map_err(|err| anyhow::anyhow!("Failed to parse filter path: {}", err))
+            })
+            .transpose()?;
+
+        if path.is_none() && config.value.is_some() {
+            anyhow::bail!("FILTER_VALUE requires FILTER_PATH");
+        }
+
+        Ok(Self {
+            types: split(config.r#type),
+            sources: split(config.source),
+            path,
+            value: config.value.map(parse_value),
+        })
+    }
+
+    /// Check the event's attributes.
+    pub fn accept_event(&self, event: &Event) -> bool {
+        matches(&self.types, event.ty()) && matches(&self.sources, &event.source().to_string())
+    }
+
+    /// Whether payloads are checked, which takes all of the payload.
+    pub fn has_payload_filter(&self) -> bool {
+        self.path.is_some()
+    }
+
+    /// Check the event's payload.
+    pub fn accept_payload(&self, json: &Value) -> Result<bool, ServiceError> {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return Ok(true),
+        };
+
+        let sel = path
+            .select(json)
+            .map_err(|err| ServiceError::SelectorError {
+                details: err.to_string(),
+            })?;
+
+        Ok(match &self.value {
+            Some(expected) => sel.contains(&expected),
+            None => !sel.is_empty(),
+        })
+    }
+}
+
+pub fn split(value: Option<String>) -> Vec<String> {
+    value
+        .iter()
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .filter(|value| !value.is_empty())
+        .map(ToString::to_string)
+        .collect()
+}
+
+pub fn matches(patterns: &[String], value: &str) -> bool {
+    patterns.is_empty()
+        || patterns
+            .iter()
+            .any(|pattern| match pattern.strip_suffix('*') {
+                Some(prefix) => value.starts_with(prefix),
+                None => pattern == value,
+            })
+}
+
+/// Match a glob, `*` standing for any number of characters and `?` for a single one.
+pub fn glob(pattern: &str, value: &str) -> bool {
+    let (pattern, value): (Vec<_>, Vec<_>) = (pattern.chars().collect(), value.chars().collect());
+    // position after the last `*`, and which characters it has taken so far
+    let (mut p, mut v, mut star) = (0, 0, None);
+    while v < value.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p + 1, v));
+                p += 1;
+            }
+            Some(c) if *c == '?' || *c == value[v] => {
+                p += 1;
+                v += 1;
+            }
+            _ => match star {
+                Some((after, taken)) => {
+                    p = after;
+                    v = taken + 1;
+                    star = Some((after, taken + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+fn parse_value(value: String) -> Value {
+    serde_json::from_str(&value).unwrap_or(Value::String(value))
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Operator {
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+/// Condition on the payload, like `$.valve_state == "open"`.
+///
+/// Without an operator, the selected value must exist and be neither `false` nor `null`.
+#[derive(Clone, Debug)]
+pub struct Condition {
+    text: String,
+    path: jsonpath_lib::Compiled,
+    comparison: Option<(Operator, Value)>,
+}
+
+impl Condition {
+    pub fn parse(condition: &str) -> anyhow::Result<Self> {
+        let (path, comparison) = match find_operator(condition) {
+            Some((pos, len, operator)) => {
+                let value = parse_value(condition[pos + len..].trim().to_string());
+                (&condition[..pos], Some((operator, value)))
+            }
+            None => (condition, None),
+        };
+
+        let path = jsonpath_lib::Compiled::compile(path.trim())
+            .map_err(|err| anyhow::anyhow!("Failed to parse condition path: {}", err))?;
+
+        Ok(Self {
+            text: condition.to_string(),
+            path,
+            comparison,
+        })
+    }
+
+    /// The condition, as configured.
+    pub fn as_str(&self) -> &str {
+        &self.text
+    }
+
+    pub fn holds(&self, json: &Value) -> Result<bool, ServiceError> {
+        let sel = self
+            .path
+            .select(json)
+            .map_err(|err| ServiceError::SelectorError {
+                details: err.to_string(),
+            })?;
+
+        let value = match sel.as_slice() {
+            [value] => *value,
+            _ => return Ok(false),
+        };
+
+        let (operator, expected) = match &self.comparison {
+            Some(comparison) => comparison,
+            None => return Ok(!matches!(value, Value::Null | Value::Bool(false))),
+        };
+
+        let ordering = match (value, expected) {
+            (Value::Number(a), Value::Number(b)) => a
+                .as_f64()
+                .zip(b.as_f64())
+                .and_then(|(a, b)| a.partial_cmp(&b)),
+            (Value::String(a), Value::String(b)) => Some(a.cmp(b)),
+            (a, b) if a == b => Some(std::cmp::Ordering::Equal),
+            _ => None,
+        };
+
+        Ok(match (operator, ordering) {
+            (Operator::Equal, ordering) => ordering.is_some_and(|o| o.is_eq()),
+            (Operator::NotEqual, ordering) => !ordering.is_some_and(|o| o.is_eq()),
+            (_, None) => false,
+            (Operator::Less, Some(o)) => o.is_lt(),
+            (Operator::LessOrEqual, Some(o)) => o.is_le(),
+            (Operator::Greater, Some(o)) => o.is_gt(),
+            (Operator::GreaterOrEqual, Some(o)) => o.is_ge(),
+        })
+    }
+}
+
+/// Find the comparison operator, outside of the path's brackets.
+fn find_operator(condition: &str) -> Option<(usize, usize, Operator)> {
+    const OPERATORS: &[(&str, Operator)] = &[
+        ("==", Operator::Equal),
+        ("!=", Operator::NotEqual),
+        ("<=", Operator::LessOrEqual),
+        (">=", Operator::GreaterOrEqual),
+        ("<", Operator::Less),
+        (">", Operator::Greater),
+    ];
+
+    let mut depth = 0;
+    for (pos, c) in condition.char_indices() {
+        match c {
+            '[' | '(' => depth += 1,
+            ']' | ')' => depth -= 1,
+            _ if depth == 0 => {
+                let rest = &condition[pos..];
+                if let Some((op, operator)) = OPERATORS.iter().find(|(op, _)| rest.starts_with(op))
+                {
+                    return Some((pos, op.len(), *operator));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cloudevents::{EventBuilder, EventBuilderV10};
+    use serde_json::json;
+
+    fn filter(r#type: Option<&str>, path: Option<&str>, value: Option<&str>) -> Filter {
+        Filter::from_config(FilterConfig {
+            r#type: r#type.map(Into::into),
+            source: None,
+            path: path.map(Into::into),
+            value: value.map(Into::into),
+        })
+        .unwrap()
+    }
+
+    fn event(ty: &str) -> Event {
+        EventBuilderV10::new()
+            .id("1")
+            .source("src")
+            .ty(ty)
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_split() {
+        assert_eq!(split(Some(" a, ,b ".into())), vec!["a", "b"]);
+        assert!(split(None).is_empty());
+    }
+
+    #[test]
+    fn test_matches() {
+        let patterns = split(Some("telemetry,status.*".into()));
+        assert!(matches(&patterns, "telemetry"));
+        assert!(matches(&patterns, "status.battery"));
+        assert!(!matches(&patterns, "telemetry.raw"));
+        assert!(matches(&[], "anything"));
+    }
+
+    #[test]
+    fn test_accept_event() {
+        let filter = filter(Some("telemetry"), None, None);
+        assert!(filter.accept_event(&event("telemetry")));
+        assert!(!filter.accept_event(&event("status")));
+        assert!(!filter.has_payload_filter());
+    }
+
+    #[test]
+    fn test_accept_payload() {
+        let filter = self::filter(None, Some("$.kind"), None);
+        assert!(filter.has_payload_filter());
+        assert!(filter.accept_payload(&json!({"kind": "a"})).unwrap());
+        assert!(!filter.accept_payload(&json!({"other": "a"})).unwrap());
+
+        // parsed as JSON if possible
+        let filter = self::filter(None, Some("$.version"), Some("2"));
+        assert!(filter.accept_payload(&json!({"version": 2})).unwrap());
+        assert!(!filter.accept_payload(&json!({"version": "2"})).unwrap());
+        let filter = self::filter(None, Some("$.kind"), Some("a"));
+        assert!(filter.accept_payload(&json!({"kind": "a"})).unwrap());
+    }
+
+    #[test]
+    fn test_invalid() {
+        let config = FilterConfig {
+            r#type: None,
+            source: None,
+            path: None,
+            value: Some("a".into()),
+        };
+        assert!(Filter::from_config(config).is_err());
+        let config = FilterConfig {
+            r#type: None,
+            source: None,
+            path: Some("$[".into()),
+            value: None,
+        };
+        assert!(Filter::from_config(config).is_err());
+    }
+
+    #[test]
+    fn test_condition() {
+        let holds = |condition: &str, json: Value| {
+            Condition::parse(condition).unwrap().holds(&json).unwrap()
+        };
+
+        assert!(holds(r#"$.valve == "open""#, json!({"valve": "open"})));
+        assert!(!holds(r#"$.valve == "open""#, json!({"valve": "closed"})));
+        assert!(holds(r#"$.valve != "open""#, json!({"valve": "closed"})));
+        assert!(holds("$.level > 2", json!({"level": 2.5})));
+        assert!(!holds("$.level > 2", json!({"level": 2})));
+        assert!(holds("$.level >= 2", json!({"level": 2})));
+        assert!(holds("$.level<3", json!({"level": 2})));
+        assert!(holds("$.level <= 2", json!({"level": 2})));
+        assert!(holds(r#"$.name < "b""#, json!({"name": "a"})));
+        assert!(holds("$.on == true", json!({"on": true})));
+        // values of different types are neither ordered, nor equal
+        assert!(!holds(r#"$.level > "1""#, json!({"level": 2})));
+        assert!(holds(r#"$.level != "2""#, json!({"level": 2})));
+        // missing values
+        assert!(!holds("$.level > 2", json!({})));
+        assert!(!holds("$.level != 2", json!({})));
+    }
+
+    #[test]
+    fn test_condition_exists() {
+        let holds = |json: Value| Condition::parse("$.on").unwrap().holds(&json).unwrap();
+        assert!(holds(json!({"on": 1})));
+        assert!(holds(json!({"on": "yes"})));
+        assert!(!holds(json!({"on": false})));
+        assert!(!holds(json!({"on": null})));
+        assert!(!holds(json!({})));
+    }
+
+    #[test]
+    fn test_condition_brackets() {
+        // operators within the path are part of it
+        let condition = Condition::parse(r#"$.readings[?(@.kind == 'a')].value > 1"#).unwrap();
+        assert_eq!(
+            condition.as_str(),
+            r#"$.readings[?(@.kind == 'a')].value > 1"#
+        );
+        let json = json!({"readings": [{"kind": "a", "value": 2}, {"kind": "b", "value": 0}]});
+        assert!(condition.holds(&json).unwrap());
+        assert!(Condition::parse("$[ == 1").is_err());
+    }
+
+    #[test]
+    fn test_glob() {
+        assert!(glob("sensors.*", "sensors.temp"));
+        assert!(glob("sensors.*", "sensors."));
+        assert!(!glob("sensors.*", "sensor"));
+        assert!(glob("*.temp", "a.b.temp"));
+        assert!(glob("a*b*c", "aXbYbZc"));
+        assert!(!glob("a*b*c", "aXbYbZ"));
+        assert!(glob("t?pe", "type"));
+        assert!(!glob("t?pe", "tpe"));
+        assert!(glob("**", ""));
+        assert!(glob("exact", "exact"));
+        assert!(!glob("exact", "exactly"));
+        assert!(glob("ö*", "öl"));
+    }
+}
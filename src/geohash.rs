@@ -0,0 +1,149 @@
+use crate::error::ServiceError;
+use serde_json::Value;
+
+const BASE32: &[u8] = b"0123456789bcdefghjkmnpqrstuvwxyz";
+
+/// Tag with the geohash of a position, for map panels which group points by it.
+///
+/// Configured as `GEOHASH_TAG_<NAME>=<latitude path>,<longitude path>`, selecting from the
+/// payload like fields do. The precision, `GEOHASH_PRECISION_TAG_<NAME>`, is the length of the
+/// hash, 7 characters being about 150 meters. Points without a position get no tag.
+#[derive(Clone, Debug)]
+pub struct Geohash {
+    pub latitude: String,
+    pub longitude: String,
+    latitude_selector: jsonpath_lib::Compiled,
+    longitude_selector: jsonpath_lib::Compiled,
+    pub precision: usize,
+}
+
+impl Geohash {
+    pub fn from_spec(spec: &str, precision: Option<String>) -> anyhow::Result<Self> {
+        let (latitude, longitude) = match spec.rsplit_once(',') {
+            Some((latitude, longitude)) => (latitude.trim(), longitude.trim()),
+            None => anyhow::bail!("Expected <latitude path>,<longitude path>, found {}", spec),
+        };
+        let compile = |path: &str| {
+            jsonpath_lib::Compiled::compile(path)
+                .map_err(|err| anyhow::anyhow!("Failed to parse JSON path: {}", err))
+        };
+        let precision = match precision {
+            Some(precision) => precision
+                .trim()
+                .parse()
+                .map_err(|err| anyhow::anyhow!("Invalid precision {}: {}", precision, err))?,
+            None => 7,
+        };
+        if !(1..=12).contains(&precision) {
+            anyhow::bail!("Precision must be between 1 and 12: {}", precision);
+        }
+
+        Ok(Self {
+            latitude_selector: compile(latitude)?,
+            longitude_selector: compile(longitude)?,
+            latitude: latitude.to_string(),
+            longitude: longitude.to_string(),
+            precision,
+        })
+    }
+
+    /// The hash of the position in the payload, `None` if it has none.
+    pub fn hash(&self, json: &Value) -> Result<Option<String>, ServiceError> {
+        let coordinate = |selector: &jsonpath_lib::Compiled, path: &str, range: f64| {
+            let value = match selector.select(json).ok().as_deref() {
+                Some([value]) => *value,
+                _ => return Ok(None),
+            };
+            match value.as_f64() {
+                Some(coordinate) if coordinate.abs() <= range => Ok(Some(coordinate)),
+                _ => Err(ServiceError::PayloadParseError {
+                    details: format!("Invalid coordinate at {}: {}", path, value),
+                }),
+            }
+        };
+
+        let latitude = coordinate(&self.latitude_selector, &self.latitude, 90.0)?;
+        let longitude = coordinate(&self.longitude_selector, &self.longitude, 180.0)?;
+        Ok(match (latitude, longitude) {
+            (Some(latitude), Some(longitude)) => Some(encode(latitude, longitude, self.precision)),
+            _ => None,
+        })
+    }
+}
+
+/// Interleave the bits of longitude and latitude, five at a time making a character.
+pub fn encode(latitude: f64, longitude: f64, precision: usize) -> String {
+    let mut latitudes = (-90.0, 90.0);
+    let mut longitudes = (-180.0, 180.0);
+    let mut hash = String::with_capacity(precision);
+    let mut even = true;
+
+    while hash.len() < precision {
+        let mut index = 0;
+        for _ in 0..5 {
+            let (range, value) = match even {
+                true => (&mut longitudes, longitude),
+                false => (&mut latitudes, latitude),
+            };
+            let mid = (range.0 + range.1) / 2.0;
+            index <<= 1;
+            if value >= mid {
+                index |= 1;
+                range.0 = mid;
+            } else {
+                range.1 = mid;
+            }
+            even = !even;
+        }
+        hash.push(BASE32[index] as char);
+    }
+
+    hash
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_encode() {
+        assert_eq!(encode(57.64911, 10.40744, 11), "u4pruydqqvj");
+        assert_eq!(encode(0.0, 0.0, 1), "s");
+        assert_eq!(encode(-90.0, -180.0, 3), "000");
+        assert_eq!(encode(90.0, 180.0, 3), "zzz");
+    }
+
+    #[test]
+    fn test_hash() {
+        let geohash = Geohash::from_spec("$.lat, $.lon", None).unwrap();
+        assert_eq!(geohash.precision, 7);
+        assert_eq!(
+            (geohash.latitude.as_str(), geohash.longitude.as_str()),
+            ("$.lat", "$.lon")
+        );
+
+        let hash = geohash
+            .hash(&json!({"lat": 57.64911, "lon": 10.40744}))
+            .unwrap();
+        assert_eq!(hash.as_deref(), Some("u4pruyd"));
+        // no position
+        assert_eq!(geohash.hash(&json!({"lat": 57.64911})).unwrap(), None);
+        assert!(geohash.hash(&json!({"lat": 91, "lon": 0})).is_err());
+        assert!(geohash.hash(&json!({"lat": "north", "lon": 0})).is_err());
+    }
+
+    #[test]
+    fn test_from_spec() {
+        let geohash = Geohash::from_spec("$.position[0],$.position[1]", Some("5".into())).unwrap();
+        let hash = geohash
+            .hash(&json!({"position": [57.64911, 10.40744]}))
+            .unwrap();
+        assert_eq!(hash.as_deref(), Some("u4pru"));
+
+        assert!(Geohash::from_spec("$.lat", None).is_err());
+        assert!(Geohash::from_spec("$.lat,$.lon", Some("0".into())).is_err());
+        assert!(Geohash::from_spec("$.lat,$.lon", Some("13".into())).is_err());
+        assert!(Geohash::from_spec("$.lat,$[", None).is_err());
+    }
+}
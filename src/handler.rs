@@ -1,52 +1,1065 @@
-use crate::config::{Path, Processor};
+use crate::auth::Authenticated;
+use crate::chunk::ChunkResult;
+use crate::config::{Mapping, Path, PayloadFormat, Processor};
+use crate::csv;
+use crate::denylist::DenyAction;
 use crate::error::ServiceError;
-use actix_web::{web, HttpResponse};
-use chrono::Utc;
-use cloudevents::AttributesReader;
-use cloudevents::{event::Data, Event};
-use influxdb::{InfluxDbWriteable, Timestamp, Type, WriteQuery};
+use crate::event::IncomingEvent;
+use crate::influx;
+use crate::line::Point;
+use crate::link::GATEWAY_TAG;
+use crate::logging;
+use crate::lpp;
+use crate::metrics::{PathCounter, EVENT_LATENCY, POINTS_UNCHANGED};
+use crate::ndjson;
+use crate::profile::Profile;
+use crate::redact::Secrets;
+use crate::series::{Commit, Pending, Series, SeriesKey};
+use crate::shutdown::Accepting;
+use crate::sticky::StickyTags;
+use crate::stream::{self, Elements};
+use crate::tags::TagGuard;
+use crate::xml;
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpRequest, HttpResponse, ResponseError};
+use chrono::{DateTime, Utc};
+use cloudevents::binding::actix::HttpResponseBuilderExt;
+use cloudevents::event::Data;
+use cloudevents::{AttributesReader, Event};
+use influxdb::{InfluxDbWriteable, Type, WriteQuery};
+use serde::{Deserialize, Serialize};
 use serde_json::Value;
+use std::borrow::Cow;
+use std::cell::RefCell;
 use std::collections::HashMap;
 
 // Implement your function's logic here
 pub async fn handle(
-    event: Event,
+    _: Accepting,
+    _: Authenticated,
+    IncomingEvent(event): IncomingEvent,
+    req: HttpRequest,
+    params: web::Query<HandleParams>,
     processor: web::Data<Processor>,
 ) -> Result<HttpResponse, actix_web::Error> {
-    log::debug!("Received Event: {:?}", event);
+    let params = HandleParams {
+        profile: profile(&req, &processor),
+        ..params.into_inner()
+    };
+    handle_event(event, params, &processor).await
+}
 
-    let data: Option<&Data> = event.data();
+/// The mapping profile selected by the request, see `MAPPING_PROFILE_HEADER`.
+pub fn profile(req: &HttpRequest, processor: &Processor) -> Option<String> {
+    req.headers()
+        .get(processor.mapping_profile_header.as_str())
+        .and_then(|value| value.to_str().ok())
+        .map(str::trim)
+        .filter(|value| !value.is_empty())
+        .map(ToString::to_string)
+}
 
-    let timestamp = event.time().cloned().unwrap_or_else(Utc::now);
-    let timestamp = Timestamp::from(timestamp);
+/// Process an event, deduplicating and accounting for it, wherever it came from.
+pub async fn handle_event(
+    event: Event,
+    params: HandleParams,
+    processor: &Processor,
+) -> Result<HttpResponse, actix_web::Error> {
+    let verbose = processor.verbose_response || params.debug;
+    let mut outcome = process_event(event, verbose, params.profile.as_deref(), processor).await?;
+    if let Some(reply) = outcome.reply.take() {
+        return HttpResponse::build(outcome.status.code())
+            .event(reply)
+            .await;
+    }
+    Ok(respond(outcome))
+}
 
-    let query = timestamp.into_query(processor.table.clone());
+impl Processor {
+    /// Process an event like one received over HTTP, for using the function as a library.
+    pub async fn process(&self, event: Event) -> Result<WriteOutcome, ServiceError> {
+        process_event(event, self.verbose_response, None, self).await
+    }
+}
 
-    // process values with payload only
+async fn process_event(
+    event: Event,
+    verbose: bool,
+    profile: Option<&str>,
+    processor: &Processor,
+) -> Result<WriteOutcome, ServiceError> {
+    let id = event.id().to_string();
+    let source = event.source().to_string();
+    let ty = event.ty().to_string();
+    // set by Drogue Cloud
+    let extension = |name| event.extension(name).map(ToString::to_string);
+    let (application, device) = (extension("application"), extension("device"));
 
-    let json = parse_payload(data)?;
-    let (query, num) = add_values(query, &processor, &json)?;
+    if let Some(dedup) = &processor.dedup {
+        if !dedup.claim(&event) {
+            log::debug!("Dropping duplicate event: {}", id);
+            return Ok(WriteOutcome::new(WriteStatus::Duplicate));
+        }
+    }
 
-    // create full events JSON for tags
+    let mut result = process(&event, verbose, profile, processor).await;
 
-    let event_json = serde_json::to_value(event)?;
-    let (query, _) = add_tags(query, &processor, &event_json)?;
+    if let (Some(reply), Ok(outcome)) = (&processor.reply, &mut result) {
+        if outcome.status == WriteStatus::Written {
+            match reply.event(&event, outcome) {
+                Ok(event) => outcome.reply = Some(event),
+                Err(err) => log::warn!("Failed to create reply event: {}", err),
+            }
+        }
+    }
 
-    // execute query
+    if let Some(dedup) = &processor.dedup {
+        match &result {
+            // partially written events may be retried
+            Ok(outcome) if outcome.status != WriteStatus::Partial => dedup.record(&event),
+            _ => dedup.release(&event),
+        }
+    }
+
+    let (status, outcome) = match &result {
+        Ok(outcome) => (outcome.status.code(), outcome.status.name()),
+        Err(err) => {
+            processor.recent_errors.record(Some(&id), err.class(), err);
+            (err.error_response().status(), err.class())
+        }
+    };
+
+    log::log!(
+        logging::event_level(),
+        id = id.as_str(),
+        source = source.as_str(),
+        type = ty.as_str(),
+        application = application.as_deref().unwrap_or_default(),
+        device = device.as_deref().unwrap_or_default(),
+        status = status.as_u16(),
+        outcome = outcome;
+        "Handled event {}: {}",
+        id,
+        outcome
+    );
 
-    if num > 0 {
-        let result = processor.client.query(&query).await;
+    result
+}
 
-        // process result
+async fn process(
+    event: &Event,
+    verbose: bool,
+    profile: Option<&str>,
+    processor: &Processor,
+) -> Result<WriteOutcome, ServiceError> {
+    let quarantine = match admit(event, processor)? {
+        Admission::Admitted { quarantine } => quarantine,
+        Admission::Skipped => return Ok(WriteOutcome::new(WriteStatus::Skipped)),
+    };
 
-        log::debug!("Result: {:?}", result);
+    processor.arrivals.observe(event);
 
-        match result {
-            Ok(_) => Ok(HttpResponse::Accepted().finish()),
-            Err(e) => Ok(HttpResponse::InternalServerError().body(e.to_string())),
+    if let (Some(shedder), Some(batcher)) = (&processor.shedder, &processor.batcher) {
+        if shedder.shed(event, batcher.queued()) {
+            return Ok(WriteOutcome::new(WriteStatus::Shed));
+        }
+    }
+
+    if let Some(streaming) = &processor.streaming {
+        if let Some(elements) = streamable(event.data(), event.datacontenttype(), processor) {
+            let mapper = Mapper::new(
+                event,
+                profile,
+                quarantine,
+                Vec::new(),
+                Vec::new(),
+                event.time().cloned(),
+                false,
+                processor,
+            )
+            .await?;
+            return process_stream(event, elements, streaming.chunk_size, &mapper, verbose).await;
+        }
+    }
+
+    match map(event, profile, quarantine, false, processor).await? {
+        Mapped::Skipped => Ok(WriteOutcome::new(WriteStatus::Skipped)),
+        Mapped::Metrics {
+            queries,
+            event_time,
+            secrets,
+        } => {
+            let mut outcome = WriteOutcome::new(WriteStatus::Written);
+            outcome.secrets = secrets;
+            let queries = queries
+                .into_iter()
+                .map(|(table, query)| {
+                    outcome.measurements.push(table);
+                    (None, query)
+                })
+                .collect();
+            write(event, queries, outcome, verbose, event_time, processor).await
+        }
+        Mapped::Records { mapper, records } => {
+            let mut outcome = WriteOutcome::new(WriteStatus::Written);
+            let queries = mapper.queries(&records, &mut outcome)?;
+            write(
+                event,
+                queries,
+                outcome,
+                verbose,
+                mapper.event_time,
+                processor,
+            )
+            .await
+        }
+    }
+}
+
+/// Whether an event is processed at all, by its attributes.
+pub enum Admission<'a> {
+    Admitted {
+        /// Measurement to write to instead, for denied devices.
+        quarantine: Option<&'a str>,
+    },
+    /// Filtered out, or the event of a denied device.
+    Skipped,
+}
+
+/// Check the attributes of an event, before looking at its payload.
+pub fn admit<'a>(event: &Event, processor: &'a Processor) -> Result<Admission<'a>, ServiceError> {
+    if !processor.filter.accept_event(event) {
+        log::debug!("Event filtered out by its attributes: {}", event.id());
+        return Ok(Admission::Skipped);
+    }
+
+    // decommissioned devices are denied, even if the registry still knows them
+
+    let deleted = match &processor.registry {
+        Some(registry) => registry.is_deleted(event)?,
+        None => false,
+    };
+    let quarantine = if deleted || processor.denylist.is_denied(event) {
+        match processor.denylist.action {
+            DenyAction::Drop => {
+                log::debug!("Dropping event of a denied device: {}", event.id());
+                return Ok(Admission::Skipped);
+            }
+            DenyAction::Quarantine => Some(processor.denylist.measurement.as_str()),
         }
     } else {
-        Ok(HttpResponse::NoContent().finish())
+        if let Some(registry) = &processor.registry {
+            registry.check(event)?;
+        }
+        None
+    };
+
+    Ok(Admission::Admitted { quarantine })
+}
+
+/// What the payload of an event is mapped with.
+pub enum Mapped<'a> {
+    /// Filtered out by the payload.
+    Skipped,
+    /// Telegraf metrics, which bring their own measurements.
+    Metrics {
+        queries: Vec<(String, WriteQuery)>,
+        event_time: Option<DateTime<Utc>>,
+        secrets: Secrets,
+    },
+    /// Records of the payload, for the mappings of the event.
+    Records {
+        mapper: Box<Mapper<'a>>,
+        records: Vec<(Value, DateTime<Utc>)>,
+    },
+}
+
+/// Decode and unwrap the payload of an admitted event, up to the records which are mapped.
+///
+/// Shared by the handler and the previews, with `preview` not changing anything which is
+/// remembered about the devices and their series.
+pub async fn map<'a>(
+    event: &Event,
+    profile: Option<&str>,
+    quarantine: Option<&'a str>,
+    preview: bool,
+    processor: &'a Processor,
+) -> Result<Mapped<'a>, ServiceError> {
+    let data: Option<&Data> = event.data();
+
+    let mut event_time = event.time().cloned();
+
+    // process values with payload only
+
+    let json = decode_payload(data, event.datacontenttype(), processor).await?;
+    let json = match &processor.inner {
+        Some(inner) => inner.decode(json)?,
+        None => json,
+    };
+    let json = match &processor.transformer {
+        Some(transformer) => transformer.transform(json)?,
+        None => json,
+    };
+    let json = match &processor.jq {
+        Some(jq) => jq.transform(json)?,
+        None => json,
+    };
+
+    // the receiving gateways are part of the envelope
+
+    let link = processor
+        .link_metrics
+        .as_ref()
+        .map(|link| link.measure(&json))
+        .unwrap_or_default();
+
+    // unwrap known envelopes, which bring their own tags and time
+
+    let (json, mut preset_tags) = match &processor.payload_preset {
+        Some(preset) => {
+            let unwrapped = preset.unwrap(json)?;
+            event_time = unwrapped.time.or(event_time);
+            (unwrapped.payload, unwrapped.tags)
+        }
+        None => (json, Vec::new()),
+    };
+    if let Some(gateway) = link.gateway {
+        preset_tags.push((GATEWAY_TAG, gateway));
+    }
+    let link_fields = link.fields;
+
+    // values of redacted paths may show up in errors from here on
+    let secrets = processor.redactor.secrets(std::iter::once(&json));
+
+    let mapped = async {
+        if let Some(payload_time) = &processor.payload_time {
+            event_time = payload_time.time(&json)?.or(event_time);
+        }
+        let time = event_time.unwrap_or_else(Utc::now);
+
+        if !processor.filter.accept_payload(&json)? {
+            log::debug!("Event filtered out by its payload: {}", event.id());
+            return Ok(Mapped::Skipped);
+        }
+
+        // Telegraf metrics bring their own measurements, tags and fields
+
+        if let Some(telegraf) = &processor.telegraf {
+            let guard = tag_guard(preview, processor);
+            return Ok(Mapped::Metrics {
+                queries: telegraf.queries(&json, time, &guard, processor)?,
+                event_time,
+                secrets: secrets.clone(),
+            });
+        }
+
+        let mut mapper = Mapper::new(
+            event,
+            profile,
+            quarantine,
+            preset_tags,
+            link_fields,
+            event_time,
+            preview,
+            processor,
+        )
+        .await?;
+        mapper.secrets = secrets.clone();
+
+        if log::log_enabled!(log::Level::Debug) {
+            log::debug!(
+                "Received Event: {}",
+                processor.redactor.redact_event(&mapper.event_json, &json)
+            );
+        }
+
+        // compact payloads carry several records, each with a time of its own, like CSV rows or
+        // NDJSON lines
+
+        let records = match &processor.records {
+            Some(records) => records.split(&json, time)?,
+            None if csv::is_csv(event.datacontenttype()) => processor.csv.split(json, time)?,
+            None if ndjson::is_ndjson(event.datacontenttype()) => {
+                ndjson::split(json, time, processor.payload_time.as_ref())?
+            }
+            None => vec![(json, time)],
+        };
+
+        Ok(Mapped::Records {
+            mapper: Box::new(mapper),
+            records,
+        })
+    };
+    mapped.await.map_err(|err| secrets.mask_error(err))
+}
+
+/// The point, with the secrets masked.
+fn mask_query(
+    query: &WriteQuery,
+    secrets: &Secrets,
+    processor: &Processor,
+) -> Result<WriteQuery, ServiceError> {
+    let mut point = Point::from_query(query).map_err(|err| ServiceError::PayloadParseError {
+        details: err.to_string(),
+    })?;
+    secrets.mask_point(&mut point);
+    Ok(point.into_query(processor.precision))
+}
+
+/// The guard of the tags, which previews only sanitize with.
+fn tag_guard(preview: bool, processor: &Processor) -> Cow<'_, TagGuard> {
+    match preview {
+        true => Cow::Owned(processor.tag_guard.sanitizing_only()),
+        false => Cow::Borrowed(&processor.tag_guard),
+    }
+}
+
+/// Map and write the elements of a JSON array in chunks, rather than parsing it as a whole.
+async fn process_stream(
+    event: &Event,
+    mut elements: Elements<'_>,
+    chunk_size: usize,
+    mapper: &Mapper<'_>,
+    verbose: bool,
+) -> Result<WriteOutcome, ServiceError> {
+    let (processor, event_time) = (mapper.processor, mapper.event_time);
+    let time = event_time.unwrap_or_else(Utc::now);
+
+    log::debug!("Streaming payload of event {}", event.id());
+
+    let record = |reading: Value| -> Result<_, ServiceError> {
+        let time = match &processor.payload_time {
+            Some(payload_time) => payload_time.time(&reading)?.unwrap_or(time),
+            None => time,
+        };
+        Ok((reading, time))
+    };
+
+    let mut total = WriteOutcome::new(WriteStatus::Skipped);
+    let mut records = Vec::with_capacity(chunk_size);
+    let (err, not_written) = loop {
+        // up to a chunk, what was read before an invalid element is written first
+        let (mut invalid, mut last) = (None, false);
+        while records.len() < chunk_size {
+            match elements.next() {
+                Some(Ok(reading)) => match record(reading) {
+                    Ok(record) => records.push(record),
+                    Err(err) => {
+                        invalid = Some((err, 1));
+                        break;
+                    }
+                },
+                // trailing data isn't an element which failed
+                Some(Err(err)) => {
+                    invalid = Some((err, !elements.ended() as usize));
+                    break;
+                }
+                None => {
+                    last = true;
+                    break;
+                }
+            }
+        }
+
+        if !records.is_empty() {
+            let mut outcome = WriteOutcome::new(WriteStatus::Written);
+            let result = match mapper.queries(&records, &mut outcome) {
+                Ok(queries) => write(event, queries, outcome, verbose, event_time, processor).await,
+                Err(err) => Err(err),
+            };
+            match result {
+                Ok(outcome) => total.add(outcome),
+                Err(err) => break (err, records.len() + invalid.map_or(0, |(_, n)| n)),
+            }
+        }
+        if let Some(invalid) = invalid {
+            break invalid;
+        }
+        if total.status == WriteStatus::Partial || last {
+            return Ok(total);
+        }
+        records.clear();
+    };
+
+    // what was written before stays written, the rest of the array is only counted
+    if total.points == 0 {
+        return Err(err);
+    }
+    let rest = elements.take_while(Result::is_ok).count();
+    processor
+        .recent_errors
+        .record(Some(event.id()), err.class(), &err);
+    total.status = WriteStatus::Partial;
+    total.chunks = Some(ChunkResult {
+        written: total.points,
+        failed: not_written + rest,
+        errors: vec![err.to_string()],
+    });
+    Ok(total)
+}
+
+/// The payload of an event which can be streamed, when nothing needs it as a whole.
+fn streamable<'a>(
+    data: Option<&'a Data>,
+    content_type: Option<&str>,
+    processor: &Processor,
+) -> Option<Elements<'a>> {
+    let whole = processor.payload_format != PayloadFormat::Json
+        || processor.inner.is_some()
+        || processor.transformer.is_some()
+        || processor.jq.is_some()
+        || processor.payload_preset.is_some()
+        || processor.link_metrics.is_some()
+        || processor.telegraf.is_some()
+        || processor.records.is_some()
+        || processor.filter.has_payload_filter()
+        || processor
+            .avro
+            .as_ref()
+            .is_some_and(|avro| avro.is_avro(content_type));
+    if whole || !content_type.is_none_or(is_json) {
+        return None;
+    }
+
+    match data {
+        Some(Data::String(s)) => stream::elements(s.as_bytes()),
+        Some(Data::Binary(b)) => stream::elements(b),
+        _ => None,
+    }
+}
+
+/// What is the same for all records of an event, when mapping them to points.
+pub struct Mapper<'a> {
+    processor: &'a Processor,
+    mappings: Vec<&'a Mapping>,
+    /// The full event, tags are taken from.
+    event_json: Value,
+    preset_tags: Vec<(&'a str, String)>,
+    sticky: Option<(&'a StickyTags, String)>,
+    /// Fields of the receiving gateways, added to every point.
+    link_fields: Vec<(&'static str, Type)>,
+    quarantine: Option<&'a str>,
+    event_time: Option<DateTime<Utc>>,
+    /// Only looking at the points, without counting or remembering anything of them.
+    preview: bool,
+    /// Of the payload as a whole, records add their own.
+    secrets: Secrets,
+}
+
+/// The point of a record, for one of the mappings.
+pub struct MappedPoint {
+    pub table: String,
+    pub query: WriteQuery,
+    /// The series, including the geohashes.
+    pub series: SeriesKey,
+    /// Mapped fields without a value.
+    pub skipped: usize,
+}
+
+impl<'a> Mapper<'a> {
+    #[allow(clippy::too_many_arguments)]
+    async fn new(
+        event: &Event,
+        profile: Option<&str>,
+        quarantine: Option<&'a str>,
+        preset_tags: Vec<(&'a str, String)>,
+        link_fields: Vec<(&'static str, Type)>,
+        event_time: Option<DateTime<Utc>>,
+        preview: bool,
+        processor: &'a Processor,
+    ) -> Result<Mapper<'a>, ServiceError> {
+        // create full events JSON for tags
+
+        let event_json =
+            serde_json::to_value(event).map_err(|err| ServiceError::PayloadParseError {
+                details: err.to_string(),
+            })?;
+
+        // friendly name of the device, which isn't part of the event
+
+        let mut preset_tags = preset_tags;
+        if let Some(names) = &processor.names {
+            if let Some(name) = names.resolve(event, &event_json).await? {
+                preset_tags.push((names.tag.as_str(), name));
+            }
+        }
+
+        // one query per mapping, skipping those without any values
+
+        let sticky = processor
+            .sticky
+            .as_ref()
+            .and_then(|sticky| Some((sticky, sticky.device(&event_json)?)));
+
+        Ok(Self {
+            processor,
+            mappings: processor.mappings_for(event.ty(), profile)?,
+            event_json,
+            preset_tags,
+            sticky,
+            link_fields,
+            quarantine,
+            event_time,
+            preview,
+            secrets: Secrets::default(),
+        })
+    }
+
+    pub fn mappings(&self) -> &[&'a Mapping] {
+        &self.mappings
+    }
+
+    /// The full event, as JSON.
+    pub fn event_json(&self) -> &Value {
+        &self.event_json
+    }
+
+    /// The measurement the points of a record are written to.
+    pub fn table(&self, mapping: &Mapping, time: DateTime<Utc>) -> Result<String, ServiceError> {
+        let processor = self.processor;
+        let skewed = match &processor.skew {
+            Some(skew) => skew.check(time)?,
+            None => false,
+        };
+        Ok(match self.quarantine {
+            Some(measurement) => measurement.to_string(),
+            None if skewed => processor.denylist.measurement.clone(),
+            None => processor.table(mapping, time),
+        })
+    }
+
+    /// The point of a record for a mapping, `None` if it has no values.
+    ///
+    /// What is remembered of its series is added to `pending`, for committing it once written.
+    pub fn point(
+        &self,
+        mapping: &Mapping,
+        json: &Value,
+        time: DateTime<Utc>,
+        pending: &RefCell<Pending>,
+    ) -> Result<Option<MappedPoint>, ServiceError> {
+        let secrets = self.secrets_of(std::iter::once(json));
+        self.mapped_point(mapping, json, time, pending, &secrets)
+            .map_err(|err| secrets.mask_error(err))
+    }
+
+    fn mapped_point(
+        &self,
+        mapping: &Mapping,
+        json: &Value,
+        time: DateTime<Utc>,
+        pending: &RefCell<Pending>,
+        secrets: &Secrets,
+    ) -> Result<Option<MappedPoint>, ServiceError> {
+        let processor = self.processor;
+        let count = !self.preview;
+        let guard = tag_guard(self.preview, processor);
+
+        let table = self.table(mapping, time)?;
+        let query = processor
+            .precision
+            .timestamp(time)
+            .into_query(table.as_str());
+        let sticky = self
+            .sticky
+            .as_ref()
+            .map(|(sticky, device)| (*sticky, device.as_str()));
+        let (query, tags) = add_tags(
+            query,
+            mapping,
+            &self.event_json,
+            sticky,
+            &self.preset_tags,
+            &guard,
+            count,
+        )?;
+        let (query, geohashes) = add_geohashes(query, mapping, json, &guard)?;
+        // counters are per series, which geohashes aren't part of, they move with the device
+        let series = Series::new(processor, &table, &tags, time, pending);
+        let profile = count.then_some(&processor.profile);
+        let (mut query, num, skipped) =
+            add_values(query, mapping, json, profile, Some(&series), count)?;
+
+        if num == 0 {
+            return Ok(None);
+        }
+
+        for (field, value) in &self.link_fields {
+            query = query.add_field(*field, value.clone());
+        }
+
+        // pipeline lag, as far as we can tell at this point
+
+        let query = match (&processor.latency_field, self.event_time) {
+            (Some(field), Some(time)) => {
+                query.add_field(field, (Utc::now() - time).num_milliseconds())
+            }
+            _ => query,
+        };
+
+        // what is held back of denied devices isn't looked after like the other points
+        let query = match self.quarantine {
+            Some(_) if !secrets.is_empty() => mask_query(&query, secrets, processor)?,
+            _ => query,
+        };
+
+        Ok(Some(MappedPoint {
+            series: SeriesKey::new(&table, tags.iter().chain(&geohashes)),
+            table,
+            query,
+            skipped,
+        }))
+    }
+
+    /// The secrets of the payload, and of the records.
+    fn secrets_of<'b>(&self, records: impl IntoIterator<Item = &'b Value>) -> Secrets {
+        let mut secrets = self.secrets.clone();
+        secrets.extend(self.processor.redactor.secrets(records));
+        secrets
+    }
+
+    /// The points of the records, for each mapping.
+    fn queries(
+        &self,
+        records: &[(Value, DateTime<Utc>)],
+        outcome: &mut WriteOutcome,
+    ) -> Result<Vec<(Option<String>, WriteQuery)>, ServiceError> {
+        outcome
+            .secrets
+            .extend(self.secrets_of(records.iter().map(|(json, _)| json)));
+        let pending = RefCell::new(std::mem::take(&mut outcome.pending));
+        let mut queries = Vec::new();
+        for ((json, time), mapping) in records
+            .iter()
+            .flat_map(|record| self.mappings.iter().map(move |mapping| (record, *mapping)))
+        {
+            let point = match self.point(mapping, json, *time, &pending)? {
+                Some(point) => point,
+                None => continue,
+            };
+            self.processor.series.observe(&point.series);
+            outcome.measurements.push(point.table);
+            outcome.fields_skipped += point.skipped;
+            queries.push((mapping.retention.clone(), point.query));
+        }
+        outcome.pending = pending.into_inner();
+        Ok(queries)
+    }
+}
+
+/// Write the points of an event, remembering their series only once that succeeded.
+///
+/// Points which are written later, like when batching, take that along, and commit it once
+/// all of them were written.
+async fn write(
+    event: &Event,
+    queries: Vec<(Option<String>, WriteQuery)>,
+    mut outcome: WriteOutcome,
+    verbose: bool,
+    event_time: Option<DateTime<Utc>>,
+    processor: &Processor,
+) -> Result<WriteOutcome, ServiceError> {
+    let commit = Commit::new(std::mem::take(&mut outcome.pending), processor);
+    let result = write_queries(
+        event,
+        queries,
+        outcome,
+        verbose,
+        event_time,
+        commit.clone(),
+        processor,
+    )
+    .await;
+    match &result {
+        // partially written events may be retried
+        Ok(outcome) if outcome.status != WriteStatus::Partial => {}
+        _ => commit.fail(),
+    }
+    result
+}
+
+/// Write the points of an event, or hand them to whatever writes them later.
+async fn write_queries(
+    event: &Event,
+    queries: Vec<(Option<String>, WriteQuery)>,
+    mut outcome: WriteOutcome,
+    verbose: bool,
+    event_time: Option<DateTime<Utc>>,
+    commit: Commit,
+    processor: &Processor,
+) -> Result<WriteOutcome, ServiceError> {
+    outcome.points = queries.len();
+    if verbose || processor.dry_run {
+        outcome.written = Some(Written::new(&queries)?);
+    }
+
+    if processor.dry_run {
+        if let Some(written) = &outcome.written {
+            for point in &written.points {
+                log::info!("Dry run - {}", outcome.secrets.mask(&point.line));
+            }
+        }
+        outcome.status = WriteStatus::DryRun;
+        return Ok(outcome);
+    }
+
+    // hold back points of open transactions
+
+    let (queries, commit) = match &processor.transactions {
+        Some(transactions) => match transactions.collect(event, queries, commit) {
+            Some(collected) => collected,
+            None => {
+                outcome.status = WriteStatus::Held;
+                return Ok(outcome);
+            }
+        },
+        None => (queries, commit),
+    };
+
+    // execute queries
+
+    if queries.is_empty() {
+        outcome.status = WriteStatus::Skipped;
+        return Ok(outcome);
+    }
+    // with a transaction completed, its earlier points are written too
+    outcome.points = queries.len();
+
+    write_points(
+        Some(event.id()),
+        queries,
+        outcome,
+        event_time,
+        commit,
+        processor,
+    )
+    .await
+}
+
+/// Write points, or hand them to the aggregator or batcher, which write them later.
+///
+/// Also writes the points of transactions, which are written without their last event.
+pub async fn write_points(
+    event_id: Option<&str>,
+    queries: Vec<(Option<String>, WriteQuery)>,
+    mut outcome: WriteOutcome,
+    event_time: Option<DateTime<Utc>>,
+    commit: Commit,
+    processor: &Processor,
+) -> Result<WriteOutcome, ServiceError> {
+    if let Some(aggregator) = &processor.aggregator {
+        aggregator.push(&queries, commit)?;
+        outcome.status = WriteStatus::Aggregated;
+        return Ok(outcome);
+    }
+
+    if let Some(batcher) = &processor.batcher {
+        if !batcher.push(queries, event_time, commit, &outcome.secrets) {
+            return Err(ServiceError::QueueFull);
+        }
+        outcome.status = WriteStatus::Queued;
+        return Ok(outcome);
+    }
+
+    let _permit = match &processor.in_flight {
+        Some(in_flight) => Some(in_flight.acquire()?),
+        None => None,
+    };
+
+    if let Some(chunker) = &processor.chunker {
+        let result = chunker.write(processor.sink.as_ref(), &queries).await;
+        return match (result.written, result.failed) {
+            (_, 0) => {
+                observe_latency(event_time);
+                Ok(outcome)
+            }
+            (0, _) => Err(ServiceError::WriteError {
+                details: outcome.secrets.mask(&result.errors.join("\n")),
+            }),
+            _ => {
+                for err in &result.errors {
+                    processor.recent_errors.record(
+                        event_id,
+                        "WriteError",
+                        outcome.secrets.mask(err),
+                    );
+                }
+                outcome.status = WriteStatus::Partial;
+                outcome.chunks = Some(result);
+                Ok(outcome)
+            }
+        };
+    }
+
+    let result = processor.sink.write(&queries).await;
+
+    // process result
+
+    log::debug!("Result: {}", outcome.secrets.mask(&format!("{:?}", result)));
+
+    match result {
+        Ok(_) => {
+            observe_latency(event_time);
+            Ok(outcome)
+        }
+        Err(e) => Err(ServiceError::WriteError {
+            details: outcome.secrets.mask(&e.to_string()),
+        }),
+    }
+}
+
+fn respond(outcome: WriteOutcome) -> HttpResponse {
+    match (outcome.status, outcome.chunks, outcome.written) {
+        (WriteStatus::Skipped, ..) => HttpResponse::NoContent().finish(),
+        (WriteStatus::Partial, Some(chunks), _) => {
+            HttpResponse::build(StatusCode::MULTI_STATUS).json(chunks)
+        }
+        (_, _, Some(written)) => HttpResponse::Accepted().json(written),
+        _ => HttpResponse::Accepted().finish(),
+    }
+}
+
+/// What became of an event, which was processed without failing.
+#[derive(Debug)]
+pub struct WriteOutcome {
+    pub status: WriteStatus,
+    /// Points written, or queued for writing.
+    pub points: usize,
+    /// Measurement of each point of the event.
+    pub measurements: Vec<String>,
+    /// Mapped fields, of those points, which had no value in the payload.
+    pub fields_skipped: usize,
+    /// The points, with `VERBOSE_RESPONSE` or a dry run.
+    pub written: Option<Written>,
+    /// What was written of a partially written event.
+    pub chunks: Option<ChunkResult>,
+    /// Event to reply with, with `REPLY_EVENTS`.
+    pub reply: Option<Event>,
+    /// What is remembered of the series, once the points are written.
+    pending: Pending,
+    /// Masked in the errors and logs of writing the points.
+    secrets: Secrets,
+}
+
+impl WriteOutcome {
+    pub fn new(status: WriteStatus) -> Self {
+        Self {
+            status,
+            points: 0,
+            measurements: Vec::new(),
+            fields_skipped: 0,
+            written: None,
+            chunks: None,
+            reply: None,
+            pending: Pending::default(),
+            secrets: Secrets::default(),
+        }
+    }
+
+    /// Add the outcome of writing another part of the event.
+    fn add(&mut self, other: WriteOutcome) {
+        if self.status == WriteStatus::Skipped || other.status == WriteStatus::Partial {
+            self.status = other.status;
+        }
+        if let Some(chunks) = other.chunks {
+            self.chunks = Some(ChunkResult {
+                written: self.points + chunks.written,
+                ..chunks
+            });
+        }
+        self.points += other.points;
+        self.measurements.extend(other.measurements);
+        self.fields_skipped += other.fields_skipped;
+        match (&mut self.written, other.written) {
+            (Some(written), Some(other)) => written.points.extend(other.points),
+            (written, other) => *written = written.take().or(other),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WriteStatus {
+    Written,
+    /// Queued for batched writing.
+    Queued,
+    /// Held back until its transaction completes.
+    Held,
+    /// Buffered, for writing the aggregates of its window.
+    Aggregated,
+    DryRun,
+    /// Filtered out, or without any values.
+    Skipped,
+    Duplicate,
+    /// Dropped by the shedder, because of the size of the queue.
+    Shed,
+    /// Some chunks failed to be written.
+    Partial,
+}
+
+impl WriteStatus {
+    /// The status it is reported with over HTTP.
+    pub fn code(&self) -> StatusCode {
+        match self {
+            WriteStatus::Skipped => StatusCode::NO_CONTENT,
+            WriteStatus::Partial => StatusCode::MULTI_STATUS,
+            _ => StatusCode::ACCEPTED,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            WriteStatus::Skipped => "skipped",
+            WriteStatus::Partial => "partial",
+            _ => "accepted",
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct HandleParams {
+    /// Respond with what was written.
+    #[serde(default)]
+    pub debug: bool,
+    /// Mapping profile to use, from the header rather than the query.
+    #[serde(skip)]
+    pub profile: Option<String>,
+}
+
+/// Points which were written, for debugging mappings.
+#[derive(Debug, Serialize)]
+pub struct Written {
+    pub points: Vec<WrittenPoint>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct WrittenPoint {
+    pub retention: Option<String>,
+    pub line: String,
+}
+
+impl Written {
+    fn new(queries: &[(Option<String>, WriteQuery)]) -> Result<Self, ServiceError> {
+        let points = queries
+            .iter()
+            .map(|(retention, query)| {
+                Ok(WrittenPoint {
+                    retention: retention.clone(),
+                    line: influx::line(query)?,
+                })
+            })
+            .collect::<Result<_, influxdb::Error>>()
+            .map_err(|err| ServiceError::WriteError {
+                details: err.to_string(),
+            })?;
+
+        Ok(Self { points })
+    }
+}
+
+fn observe_latency(event_time: Option<DateTime<Utc>>) {
+    if let Some(time) = event_time {
+        let latency = (Utc::now() - time).to_std().unwrap_or_default();
+        EVENT_LATENCY.observe(latency.as_secs_f64());
     }
 }
 
@@ -54,77 +1067,352 @@ fn add_to_query<F>(
     mut query: WriteQuery,
     processor: &HashMap<String, Path>,
     json: &Value,
-    f: F,
+    counter: Option<PathCounter>,
+    mut f: F,
 ) -> Result<(WriteQuery, usize), ServiceError>
 where
-    F: Fn(WriteQuery, &String, Type) -> WriteQuery,
+    F: FnMut(WriteQuery, &String, Type) -> Result<(WriteQuery, bool), ServiceError>,
 {
     let mut num = 0;
 
+    // only what was added counts
     let mut f = |query, field, value| {
-        num += 1;
-        f(query, field, value)
+        let (query, added) = f(query, field, value)?;
+        if added {
+            num += 1;
+        }
+        Ok::<_, ServiceError>(query)
     };
 
-    for (ref field, ref path) in processor {
-        let sel = path
-            .compiled
-            .select(&json)
-            .map_err(|err| ServiceError::SelectorError {
-                details: err.to_string(),
-            })?;
+    for (field, path) in processor {
+        if let Some(condition) = &path.condition {
+            if !condition.holds(json)? {
+                continue;
+            }
+        }
+
+        let count = |result| {
+            if let Some(counter) = &counter {
+                counter.count(field, result);
+            }
+        };
+
+        let sel = path.selector.select(json)?;
 
-        query = match sel.as_slice() {
+        let value = match sel.as_slice() {
             // no value, don't add
-            [] => Ok(query),
+            [] => {
+                count("missing");
+                continue;
+            }
             // single value, process
-            [v] => Ok(f(query, field, path.r#type.convert(v, path)?)),
-            // multiple values, error
-            [..] => Err(ServiceError::SelectorError {
-                details: format!("Selector found more than one value: {}", sel.len()),
-            }),
-        }?;
+            [v] => path.value(v),
+            // multiple values, join or error
+            [..] => match path.join(&sel) {
+                Some(value) => value,
+                None => {
+                    count("multiple");
+                    return Err(ServiceError::SelectorError {
+                        details: format!("Selector found more than one value: {}", sel.len()),
+                    });
+                }
+            },
+        };
+
+        // dropped values don't count either
+        match value {
+            Ok(Some(value)) => {
+                count("matched");
+                query = f(query, field, value)?;
+            }
+            Ok(None) => count("dropped"),
+            Err(err) => {
+                count("conversion_failed");
+                return Err(err);
+            }
+        }
     }
 
     Ok((query, num))
 }
 
-fn add_values(
+/// Add the fields of the mapping, with `count` adding the results to the metrics, which
+/// previews don't.
+///
+/// Counters are only written with the `series` of the point, otherwise they are left out, as
+/// is the point if it doesn't change any of the tracked values.
+pub fn add_values(
     query: WriteQuery,
-    processor: &Processor,
+    mapping: &Mapping,
     json: &Value,
-) -> Result<(WriteQuery, usize), ServiceError> {
-    add_to_query(query, &processor.fields, json, |query, field, value| {
-        query.add_field(field, value)
-    })
+    profile: Option<&Profile>,
+    series: Option<&Series>,
+    count: bool,
+) -> Result<(WriteQuery, usize, usize), ServiceError> {
+    // keep numeric values around, for computed fields
+    let mut values = HashMap::new();
+    let mut tracked = Vec::new();
+    let tracks_all = series.is_some_and(Series::tracks_all);
+
+    let counter = count.then(|| PathCounter::fields(&mapping.table));
+    let (mut query, mut num) = add_to_query(
+        query,
+        &mapping.fields,
+        json,
+        counter,
+        |query, field, value| {
+            let path = mapping.fields.get(field);
+            // counters without a previous value, or which aren't numbers, are left out
+            let value = match path.and_then(|path| path.rate) {
+                Some(kind) => match (series, number(&value)) {
+                    (Some(series), Some(value)) => match series.rate(field, kind, value) {
+                        Some(rate) => Type::Float(rate),
+                        None => return Ok((query, false)),
+                    },
+                    _ => return Ok((query, false)),
+                },
+                None => value,
+            };
+            if let Some(v) = number(&value) {
+                values.insert(field.clone(), v);
+            }
+            if tracks_all || path.is_some_and(|path| path.dedup) {
+                tracked.push((field.clone(), format!("{:?}", value)));
+            }
+            Ok((query.add_field(field, value), true))
+        },
+    )?;
+    let skipped = mapping.fields.len() - num;
+
+    if let Some(auto) = &mapping.auto {
+        for (field, value) in auto.fields(json) {
+            if mapping.fields.contains_key(&field) {
+                continue;
+            }
+            if let Type::Float(v) = value {
+                values.insert(field.clone(), v);
+            }
+            if tracks_all {
+                tracked.push((field.clone(), format!("{:?}", value)));
+            }
+            query = query.add_field(field, value);
+            num += 1;
+        }
+    }
+
+    // computed fields don't count, they only add to a point with values
+
+    let mut computed = HashMap::new();
+    for (field, expression) in &mapping.computed {
+        match expression.eval(&values) {
+            Some(value) => {
+                query = query.add_field(field, value);
+                computed.insert(field.clone(), value);
+            }
+            None => log::debug!("Unable to compute field: {}", field),
+        }
+    }
+
+    if let Some(profile) = profile {
+        values.extend(computed);
+        profile.record(&mapping.table, &values);
+    }
+
+    if let Some(series) = series {
+        if num > 0 && series.is_unchanged(tracked) {
+            log::debug!("Skipping unchanged point of {}", mapping.table);
+            if count {
+                POINTS_UNCHANGED.with_label_values(&[&mapping.table]).inc();
+            }
+            return Ok((query, 0, skipped));
+        }
+    }
+
+    Ok((query, num, skipped))
 }
 
-fn add_tags(
+pub fn add_tags(
     query: WriteQuery,
-    processor: &Processor,
+    mapping: &Mapping,
     json: &Value,
-) -> Result<(WriteQuery, usize), ServiceError> {
-    add_to_query(query, &processor.tags, json, |query, field, value| {
-        query.add_tag(field, value)
-    })
+    sticky: Option<(&StickyTags, &str)>,
+    preset_tags: &[(&str, String)],
+    guard: &TagGuard,
+    count: bool,
+) -> Result<(WriteQuery, HashMap<String, String>), ServiceError> {
+    let mut tags = HashMap::new();
+
+    let counter = count.then(|| PathCounter::tags(&mapping.table));
+    let (mut query, _) = add_to_query(
+        query,
+        &mapping.tags,
+        json,
+        counter,
+        |query, field, value| {
+            let value = match guard.admit(&mapping.table, field, &value.to_string())? {
+                Some(value) => value,
+                None => return Ok((query, false)),
+            };
+            tags.insert(field.clone(), value.clone());
+            Ok((query.add_tag(field, value), true))
+        },
+    )?;
+
+    // fill in what the device didn't send this time
+
+    if let Some((sticky, device)) = sticky {
+        let missing = match count {
+            true => sticky.apply(&mapping.table, device, &tags),
+            false => sticky.known(&mapping.table, device, &tags),
+        };
+        for (tag, value) in missing {
+            query = query.add_tag(&tag, value.clone());
+            tags.insert(tag, value);
+        }
+    }
+
+    for (tag, value) in preset_tags {
+        if let Some(value) = guard.admit(&mapping.table, tag, value)? {
+            query = query.add_tag(*tag, value.clone());
+            tags.insert(tag.to_string(), value);
+        }
+    }
+
+    Ok((query, tags))
 }
 
-fn parse_payload(data: Option<&Data>) -> Result<Value, ServiceError> {
-    match data {
-        Some(Data::Json(value)) => Ok(value.clone()),
-        Some(Data::String(s)) => {
-            serde_json::from_str::<Value>(&s).map_err(|err| ServiceError::PayloadParseError {
-                details: err.to_string(),
-            })
+/// The value of numeric fields, for computing others.
+fn number(value: &Type) -> Option<f64> {
+    match value {
+        Type::Float(v) => Some(*v),
+        Type::SignedInteger(v) => Some(*v as f64),
+        Type::UnsignedInteger(v) => Some(*v as f64),
+        _ => None,
+    }
+}
+
+/// Add the geohash tags of the mapping, with the position taken from the payload.
+pub fn add_geohashes(
+    mut query: WriteQuery,
+    mapping: &Mapping,
+    json: &Value,
+    guard: &TagGuard,
+) -> Result<(WriteQuery, HashMap<String, String>), ServiceError> {
+    let mut tags = HashMap::new();
+    for (tag, geohash) in &mapping.geohashes {
+        if let Some(hash) = geohash.hash(json)? {
+            if let Some(hash) = guard.admit(&mapping.table, tag, &hash)? {
+                query = query.add_tag(tag, hash.clone());
+                tags.insert(tag.clone(), hash);
+            }
         }
+    }
+    Ok((query, tags))
+}
 
-        Some(Data::Binary(b)) => {
-            serde_json::from_slice::<Value>(&b).map_err(|err| ServiceError::PayloadParseError {
-                details: err.to_string(),
-            })
+/// Parse the payload, fetching the schema first for Avro.
+pub async fn decode_payload(
+    data: Option<&Data>,
+    content_type: Option<&str>,
+    processor: &Processor,
+) -> Result<Value, ServiceError> {
+    match &processor.avro {
+        Some(avro) if avro.is_avro(content_type) => avro.decode(data).await,
+        _ => parse_payload(data, content_type, processor),
+    }
+}
+
+pub fn parse_payload(
+    data: Option<&Data>,
+    content_type: Option<&str>,
+    processor: &Processor,
+) -> Result<Value, ServiceError> {
+    if processor.payload_format == PayloadFormat::CayenneLpp {
+        match data {
+            Some(Data::String(s)) => return lpp::decode(s.as_bytes()),
+            Some(Data::Binary(b)) => return lpp::decode(b),
+            _ => {}
+        }
+    }
+
+    if ndjson::is_ndjson(content_type) {
+        let parse = |line: &[u8]| parse_json(line, processor.simd_json);
+        match data {
+            Some(Data::String(s)) => return ndjson::decode(s.as_bytes(), parse),
+            Some(Data::Binary(b)) => return ndjson::decode(b, parse),
+            _ => {}
+        }
+    }
+
+    if xml::is_xml(content_type) {
+        match data {
+            Some(Data::String(s)) => return xml::decode(s.as_bytes()),
+            Some(Data::Binary(b)) => return xml::decode(b),
+            _ => {}
+        }
+    }
+
+    if csv::is_csv(content_type) {
+        match data {
+            Some(Data::String(s)) => return processor.csv.decode(s.as_bytes()),
+            Some(Data::Binary(b)) => return processor.csv.decode(b),
+            _ => {}
         }
+    }
+
+    // hand what none of the built in formats is to the decoder, if there is one
+    if let (Some(decoder), Some(content_type)) = (&processor.decoder, content_type) {
+        if !is_json(content_type) {
+            match data {
+                Some(Data::String(s)) => return decoder.decode(s.as_bytes(), content_type),
+                Some(Data::Binary(b)) => return decoder.decode(b, content_type),
+                _ => {}
+            }
+        }
+    }
+
+    match data {
+        Some(Data::Json(value)) => Ok(value.clone()),
+        Some(Data::String(s)) => parse_json(s.as_bytes(), processor.simd_json),
+        Some(Data::Binary(b)) => parse_json(b, processor.simd_json),
         _ => Err(ServiceError::PayloadParseError {
             details: "Unknown event payload".to_string(),
         }),
     }
 }
+
+fn is_json(content_type: &str) -> bool {
+    let mime = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_lowercase();
+    mime == "application/json" || mime == "text/json" || mime.ends_with("+json")
+}
+
+#[cfg(feature = "simd")]
+fn parse_json(data: &[u8], simd_json: bool) -> Result<Value, ServiceError> {
+    if simd_json {
+        // simd-json parses in place, so it needs its own mutable copy
+        let mut data = data.to_vec();
+        simd_json::serde::from_slice::<Value>(&mut data).map_err(|err| {
+            ServiceError::PayloadParseError {
+                details: err.to_string(),
+            }
+        })
+    } else {
+        serde_json_parse(data)
+    }
+}
+
+#[cfg(not(feature = "simd"))]
+fn parse_json(data: &[u8], _: bool) -> Result<Value, ServiceError> {
+    serde_json_parse(data)
+}
+
+fn serde_json_parse(data: &[u8]) -> Result<Value, ServiceError> {
+    serde_json::from_slice::<Value>(data).map_err(|err| ServiceError::PayloadParseError {
+        details: err.to_string(),
+    })
+}
@@ -0,0 +1,53 @@
+use influxdb::Type;
+use openssl::sha::Sha256;
+use std::fmt;
+
+/// Salted hashing of tag values, configured as `sha256[:<env var with the salt>]`.
+///
+/// Keeps values usable for grouping, without storing the raw identifier.
+#[derive(Clone)]
+pub struct Hashing {
+    salt: Vec<u8>,
+}
+
+impl fmt::Debug for Hashing {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Hashing").finish()
+    }
+}
+
+impl Hashing {
+    pub fn from_spec(spec: &str) -> anyhow::Result<Self> {
+        let (algorithm, salt_var) = match spec.split_once(':') {
+            Some((algorithm, salt_var)) => (algorithm, Some(salt_var)),
+            None => (spec, None),
+        };
+
+        if !algorithm.eq_ignore_ascii_case("sha256") {
+            anyhow::bail!("Unsupported hash algorithm: {}", algorithm);
+        }
+
+        let salt = match salt_var {
+            Some(salt_var) => std::env::var(salt_var)
+                .map_err(|err| anyhow::anyhow!("Failed to read hash salt {}: {}", salt_var, err))?
+                .into_bytes(),
+            None => Vec::new(),
+        };
+
+        Ok(Self { salt })
+    }
+
+    pub fn hash(&self, value: &Type) -> Type {
+        let mut hasher = Sha256::new();
+        hasher.update(&self.salt);
+        hasher.update(value.to_string().as_bytes());
+
+        Type::Text(
+            hasher
+                .finish()
+                .iter()
+                .map(|b| format!("{:02x}", b))
+                .collect(),
+        )
+    }
+}
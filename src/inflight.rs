@@ -0,0 +1,65 @@
+use crate::error::ServiceError;
+use crate::metrics::WRITES_IN_FLIGHT;
+use envconfig::Envconfig;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+#[derive(Envconfig, Clone, Debug)]
+pub struct InFlightConfig {
+    /// Maximum number of events being written at the same time, unlimited when zero.
+    #[envconfig(from = "MAX_IN_FLIGHT", default = "0")]
+    pub max: usize,
+    /// Value of the `Retry-After` header, when rejecting events.
+    #[envconfig(from = "MAX_IN_FLIGHT_RETRY_AFTER_S", default = "1")]
+    pub retry_after_s: u64,
+}
+
+/// Rejects events while too many are being written, instead of piling them up.
+#[derive(Clone, Debug)]
+pub struct InFlight {
+    max: usize,
+    retry_after_s: u64,
+    current: Arc<AtomicUsize>,
+}
+
+/// Held while writing, gives the slot back when dropped.
+pub struct Permit(Arc<AtomicUsize>);
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::AcqRel);
+        WRITES_IN_FLIGHT.dec();
+    }
+}
+
+impl InFlight {
+    pub fn from_config(config: InFlightConfig) -> Option<Self> {
+        if config.max == 0 {
+            return None;
+        }
+
+        Some(Self {
+            max: config.max,
+            retry_after_s: config.retry_after_s,
+            current: Default::default(),
+        })
+    }
+
+    pub fn acquire(&self) -> Result<Permit, ServiceError> {
+        let acquired = self
+            .current
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |current| {
+                (current < self.max).then_some(current + 1)
+            })
+            .is_ok();
+
+        if acquired {
+            WRITES_IN_FLIGHT.inc();
+            Ok(Permit(self.current.clone()))
+        } else {
+            Err(ServiceError::TooManyRequests {
+                retry_after: self.retry_after_s,
+            })
+        }
+    }
+}
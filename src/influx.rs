@@ -0,0 +1,458 @@
+use crate::config::InfluxDb;
+use influxdb::{Error, Query, WriteQuery};
+use reqwest::StatusCode;
+use std::collections::BTreeMap;
+use std::fs;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// Writes queries to InfluxDB, replacing `influxdb::Client`, which doesn't allow configuring
+/// the underlying HTTP client.
+#[derive(Clone, Debug)]
+pub struct InfluxClient {
+    url: String,
+    db: String,
+    parameters: Vec<(&'static str, String)>,
+    retention_policy: Option<String>,
+    consistency: Option<Consistency>,
+    /// InfluxDB 2 takes the password of the 1.x compatibility API as token.
+    token: String,
+    flavor: Flavor,
+    org: Option<String>,
+    client: reqwest::Client,
+}
+
+impl InfluxClient {
+    pub fn new(config: &InfluxDb) -> anyhow::Result<Self> {
+        let mut tls = native_tls::TlsConnector::builder();
+
+        if let Some(ca) = &config.ca_cert {
+            let ca = fs::read(ca)?;
+            tls.add_root_certificate(native_tls::Certificate::from_pem(&ca)?);
+        }
+
+        match (&config.client_cert, &config.client_key) {
+            (Some(cert), Some(key)) => {
+                let identity = native_tls::Identity::from_pkcs8(&fs::read(cert)?, &fs::read(key)?)?;
+                tls.identity(identity);
+            }
+            (None, None) => {}
+            _ => anyhow::bail!("INFLUXDB_CLIENT_CERT and INFLUXDB_CLIENT_KEY must be set together"),
+        }
+
+        if config.consistency.is_some() && config.flavor != Flavor::InfluxDb1 {
+            anyhow::bail!("INFLUXDB_CONSISTENCY requires SINK_FLAVOR=influxdb1");
+        }
+        if config.retention_policy.is_some() && config.flavor == Flavor::VictoriaMetrics {
+            log::warn!(
+                "VictoriaMetrics has no retention policies, ignoring INFLUXDB_RETENTION_POLICY"
+            );
+        }
+
+        if config.insecure_skip_verify {
+            log::warn!("TLS certificate verification for InfluxDB is disabled");
+            tls.danger_accept_invalid_certs(true);
+        }
+
+        let duration = |value, unit: fn(u64) -> Duration| match value {
+            0 => None,
+            value => Some(unit(value)),
+        };
+
+        // all requests share the client, and with it the pool of connections
+        let mut client = reqwest::Client::builder()
+            .use_preconfigured_tls(tls.build()?)
+            .tcp_keepalive(duration(config.keep_alive_s, Duration::from_secs))
+            .pool_idle_timeout(duration(config.pool_idle_timeout_s, Duration::from_secs));
+        if let Some(timeout) = duration(config.timeout_ms, Duration::from_millis) {
+            client = client.timeout(timeout);
+        }
+        if let Some(timeout) = duration(config.connect_timeout_ms, Duration::from_millis) {
+            client = client.connect_timeout(timeout);
+        }
+        if let Some(max) = config.pool_max_idle {
+            client = client.pool_max_idle_per_host(max);
+        }
+        let client = client.build()?;
+
+        Ok(Self {
+            url: config.uri.clone(),
+            db: config.db.clone(),
+            parameters: vec![
+                ("db", config.db.clone()),
+                ("u", config.user.clone()),
+                ("p", config.password.clone()),
+            ],
+            retention_policy: config.retention_policy.clone(),
+            consistency: config.consistency,
+            token: config.password.clone(),
+            flavor: config.flavor,
+            org: config.org.clone(),
+            client,
+        })
+    }
+
+    pub fn database(&self) -> &str {
+        &self.db
+    }
+
+    /// The URL, without any credentials in it.
+    pub fn redacted_url(&self) -> String {
+        match reqwest::Url::parse(&self.url) {
+            Ok(mut url) => {
+                // only fails for URLs which can't have credentials anyway
+                let _ = url.set_username("");
+                let _ = url.set_password(None);
+                url.to_string()
+            }
+            Err(_) => self.url.clone(),
+        }
+    }
+
+    pub fn user(&self) -> &str {
+        &self.parameters[1].1
+    }
+
+    pub fn retention_policy(&self) -> Option<&str> {
+        self.retention_policy.as_deref()
+    }
+
+    pub fn consistency(&self) -> Option<Consistency> {
+        self.consistency
+    }
+
+    pub fn flavor(&self) -> Flavor {
+        self.flavor
+    }
+
+    /// VictoriaMetrics takes the credentials as basic authentication, or the password as bearer
+    /// token, like vmauth does, instead of as query parameters.
+    fn authorized(&self, request: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+        match (self.user(), self.token.as_str()) {
+            ("", "") => request,
+            ("", token) => request.bearer_auth(token),
+            (user, password) => request.basic_auth(user, Some(password)),
+        }
+    }
+
+    /// Check the connection, which also leaves an established one in the pool.
+    pub async fn ping(&self) -> Result<(), Error> {
+        let request = match self.flavor {
+            Flavor::VictoriaMetrics => {
+                self.authorized(self.client.get(&format!("{}/health", self.url)))
+            }
+            Flavor::InfluxDb1 | Flavor::InfluxDb2 => self.client.get(&format!("{}/ping", self.url)),
+        };
+        let response = request.send().await.map_err(connection_error)?;
+
+        if response.status().is_success() {
+            Ok(())
+        } else {
+            Err(Error::ProtocolError {
+                error: format!("Unexpected status: {}", response.status()),
+            })
+        }
+    }
+
+    /// Run an InfluxQL statement, like `CREATE DATABASE`.
+    pub async fn query(&self, statement: &str) -> Result<String, Error> {
+        if self.flavor == Flavor::VictoriaMetrics {
+            return Err(Error::ProtocolError {
+                error: "VictoriaMetrics doesn't support InfluxQL".to_string(),
+            });
+        }
+
+        let response = self
+            .client
+            .post(&format!("{}/query", self.url))
+            .query(&self.parameters)
+            .form(&[("q", statement)])
+            .send()
+            .await
+            .map_err(connection_error)?;
+
+        let status = response.status();
+        let s = response
+            .text()
+            .await
+            .map_err(|err| Error::DeserializationError {
+                error: err.to_string(),
+            })?;
+
+        if !status.is_success() || s.contains("\"error\"") {
+            return Err(Error::DatabaseError {
+                error: format!("influxdb error: \"{}\"", s),
+            });
+        }
+
+        Ok(s)
+    }
+
+    /// Request to the InfluxDB 2 API, like `/api/v2/buckets`.
+    pub async fn v2(
+        &self,
+        method: reqwest::Method,
+        path: &str,
+        query: &[(&str, &str)],
+        body: Option<&serde_json::Value>,
+    ) -> Result<serde_json::Value, Error> {
+        let mut request = self
+            .client
+            .request(method, &format!("{}{}", self.url, path))
+            .header("Authorization", format!("Token {}", self.token))
+            .query(query);
+        if let Some(body) = body {
+            request = request
+                .header("Content-Type", "application/json")
+                .body(body.to_string());
+        }
+
+        let response = request.send().await.map_err(connection_error)?;
+
+        let status = response.status();
+        let s = response
+            .text()
+            .await
+            .map_err(|err| Error::DeserializationError {
+                error: err.to_string(),
+            })?;
+
+        if !status.is_success() {
+            return Err(Error::DatabaseError {
+                error: format!("influxdb error: \"{}\"", s),
+            });
+        }
+
+        serde_json::from_str(&s).map_err(|err| Error::DeserializationError {
+            error: err.to_string(),
+        })
+    }
+
+    /// Write queries, with one request per retention policy.
+    pub async fn write_routed(
+        &self,
+        queries: &[(Option<String>, WriteQuery)],
+    ) -> Result<(), Error> {
+        let mut groups: BTreeMap<_, Vec<_>> = BTreeMap::new();
+        for (retention, query) in queries {
+            groups
+                .entry(retention.as_deref())
+                .or_default()
+                .push(query.clone());
+        }
+
+        for (retention, queries) in groups {
+            self.write_all(&queries, retention).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Write several queries with a single request, they must share the same precision.
+    pub async fn write_all(
+        &self,
+        queries: &[WriteQuery],
+        retention: Option<&str>,
+    ) -> Result<String, Error> {
+        let precision = match queries.first() {
+            Some(query) => query.get_precision(),
+            None => return Ok(String::new()),
+        };
+        let body = queries
+            .iter()
+            .map(line)
+            .collect::<Result<Vec<_>, _>>()?
+            .join("\n");
+
+        let retention = retention.or_else(|| self.retention_policy());
+        let request = match self.flavor {
+            Flavor::InfluxDb1 => self
+                .client
+                .post(&format!("{}/write", self.url))
+                .query(&self.parameters)
+                .query(&[("precision", precision)])
+                .query(&[("rp", retention)])
+                .query(&[("consistency", self.consistency.map(Consistency::as_str))]),
+            Flavor::InfluxDb2 => {
+                // buckets of retention policies are named like those `influxd upgrade` creates
+                let bucket = match retention {
+                    Some(retention) => format!("{}/{}", self.db, retention),
+                    None => self.db.clone(),
+                };
+                let precision = match precision.as_str() {
+                    "u" => "us".to_string(),
+                    _ => precision,
+                };
+                self.client
+                    .post(&format!("{}/api/v2/write", self.url))
+                    .header("Authorization", format!("Token {}", self.token))
+                    .query(&[("bucket", bucket)])
+                    .query(&[("org", self.org.as_deref())])
+                    .query(&[("precision", precision)])
+            }
+            // there are no retention policies, the database becomes the `db` label
+            Flavor::VictoriaMetrics => self
+                .authorized(self.client.post(&format!("{}/write", self.url)))
+                .query(&[("db", &self.db)])
+                .query(&[("precision", precision)]),
+        };
+
+        let response = request.body(body).send().await.map_err(connection_error)?;
+
+        let status = response.status();
+        match status {
+            StatusCode::UNAUTHORIZED => return Err(Error::AuthorizationError),
+            StatusCode::FORBIDDEN => return Err(Error::AuthenticationError),
+            _ => {}
+        }
+
+        let s = response
+            .text()
+            .await
+            .map_err(|err| Error::DeserializationError {
+                error: err.to_string(),
+            })?;
+
+        // only InfluxDB 1.x reports some errors, like partial writes, with a successful status
+        let failed = match self.flavor {
+            Flavor::InfluxDb1 => s.contains("\"error\""),
+            Flavor::InfluxDb2 | Flavor::VictoriaMetrics => false,
+        };
+        if !status.is_success() || failed {
+            return Err(Error::DatabaseError {
+                error: self.flavor.error(&s),
+            });
+        }
+
+        Ok(s)
+    }
+}
+
+/// How many nodes of an InfluxDB Enterprise cluster must confirm a write.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Consistency {
+    Any,
+    One,
+    Quorum,
+    All,
+}
+
+impl Consistency {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Consistency::Any => "any",
+            Consistency::One => "one",
+            Consistency::Quorum => "quorum",
+            Consistency::All => "all",
+        }
+    }
+}
+
+impl FromStr for Consistency {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "any" => Ok(Consistency::Any),
+            "one" => Ok(Consistency::One),
+            "quorum" => Ok(Consistency::Quorum),
+            "all" => Ok(Consistency::All),
+            _ => anyhow::bail!("Unknown write consistency: {}", s),
+        }
+    }
+}
+
+/// The kind of server written to, which all accept line protocol, but differ in how.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Flavor {
+    /// The `/write` API, with credentials as query parameters.
+    InfluxDb1,
+    /// The `/api/v2/write` API, with the password as token and the database as bucket.
+    InfluxDb2,
+    /// The `/write` API of VictoriaMetrics, with basic authentication and plain text errors.
+    VictoriaMetrics,
+}
+
+impl Flavor {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Flavor::InfluxDb1 => "influxdb1",
+            Flavor::InfluxDb2 => "influxdb2",
+            Flavor::VictoriaMetrics => "victoriametrics",
+        }
+    }
+
+    /// Error of a failed write, from the body of the response.
+    fn error(self, body: &str) -> String {
+        let message = match self {
+            // the whole body, as before flavors existed
+            Flavor::InfluxDb1 => body.to_string(),
+            Flavor::InfluxDb2 => serde_json::from_str::<serde_json::Value>(body)
+                .ok()
+                .and_then(|error| error["message"].as_str().map(ToString::to_string))
+                .unwrap_or_else(|| body.trim().to_string()),
+            Flavor::VictoriaMetrics => body.trim().to_string(),
+        };
+        match self {
+            Flavor::InfluxDb1 | Flavor::InfluxDb2 => format!("influxdb error: \"{}\"", message),
+            Flavor::VictoriaMetrics => format!("victoriametrics error: \"{}\"", message),
+        }
+    }
+}
+
+impl FromStr for Flavor {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "influxdb1" => Ok(Flavor::InfluxDb1),
+            "influxdb2" => Ok(Flavor::InfluxDb2),
+            "victoriametrics" => Ok(Flavor::VictoriaMetrics),
+            _ => anyhow::bail!("Unknown sink flavor: {}", s),
+        }
+    }
+}
+
+/// The error message of reqwest includes the URL, which carries the credentials.
+fn connection_error(err: reqwest::Error) -> Error {
+    let mut error = err.to_string();
+    if let Some(url) = err.url() {
+        let mut redacted = url.clone();
+        redacted.set_query(None);
+        error = error.replace(url.as_str(), redacted.as_str());
+    }
+    Error::ConnectionError { error }
+}
+
+/// Line protocol of a query.
+pub fn line(query: &WriteQuery) -> Result<String, Error> {
+    query
+        .build()
+        .map(|query| query.get())
+        .map_err(|err| Error::InvalidQueryError {
+            error: err.to_string(),
+        })
+}
+
+/// Line protocol of a query, with the timestamp in nanoseconds, for receivers which don't
+/// know the precision of the points.
+pub fn nanosecond_line(query: &WriteQuery) -> Result<String, Error> {
+    use influxdb::{InfluxDbWriteable, Timestamp};
+
+    if query.get_precision() == "ns" {
+        return line(query);
+    }
+
+    let point = crate::line::Point::from_query(query).map_err(|err| Error::InvalidQueryError {
+        error: err.to_string(),
+    })?;
+    let time = point.time.unwrap_or_else(chrono::Utc::now);
+    let mut query = Timestamp::from(time).into_query(point.measurement);
+    for (tag, value) in point.tags {
+        query = query.add_tag(tag, value);
+    }
+    for (field, value) in point.fields {
+        query = query.add_field(field, value);
+    }
+    line(&query)
+}
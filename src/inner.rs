@@ -0,0 +1,151 @@
+use crate::cbor;
+use crate::error::ServiceError;
+use crate::lpp;
+use envconfig::Envconfig;
+use serde_json::Value;
+use std::str::FromStr;
+
+#[derive(Envconfig, Clone, Debug)]
+pub struct InnerPayloadConfig {
+    /// JSON path to an encoded payload within the payload, like `$.data`.
+    #[envconfig(from = "INNER_PAYLOAD_PATH")]
+    pub path: Option<String>,
+    #[envconfig(from = "INNER_PAYLOAD_ENCODING", default = "base64")]
+    pub encoding: Encoding,
+    #[envconfig(from = "INNER_PAYLOAD_FORMAT", default = "json")]
+    pub format: Format,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Encoding {
+    Base64,
+    Hex,
+}
+
+impl FromStr for Encoding {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "base64" => Ok(Encoding::Base64),
+            "hex" => Ok(Encoding::Hex),
+            _ => anyhow::bail!("Unknown inner payload encoding: {}", s),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    Json,
+    Cbor,
+    CayenneLpp,
+}
+
+impl FromStr for Format {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "json" => Ok(Format::Json),
+            "cbor" => Ok(Format::Cbor),
+            "cayenne-lpp" | "lpp" => Ok(Format::CayenneLpp),
+            _ => anyhow::bail!("Unknown inner payload format: {}", s),
+        }
+    }
+}
+
+/// Decodes a payload carried as a string inside the payload, like the `data` of LoRaWAN
+/// uplinks.
+///
+/// The decoded payload takes the place of the string, so that its values are selected like
+/// `$.data.temperature`, next to those of the envelope. Payloads without the string are left
+/// as they are.
+#[derive(Clone, Debug)]
+pub struct InnerPayload {
+    path: String,
+    encoding: Encoding,
+    format: Format,
+}
+
+impl InnerPayload {
+    pub fn from_config(config: InnerPayloadConfig) -> anyhow::Result<Option<Self>> {
+        let path = match config.path {
+            Some(path) => path,
+            None => return Ok(None),
+        };
+        jsonpath_lib::Compiled::compile(&path)
+            .map_err(|err| anyhow::anyhow!("Failed to parse INNER_PAYLOAD_PATH: {}", err))?;
+
+        Ok(Some(Self {
+            path,
+            encoding: config.encoding,
+            format: config.format,
+        }))
+    }
+
+    pub fn decode(&self, json: Value) -> Result<Value, ServiceError> {
+        let mut result = Ok(());
+        let json = jsonpath_lib::replace_with(json, &self.path, &mut |value| match self
+            .decode_value(&value)
+        {
+            Ok(decoded) => Some(decoded),
+            Err(err) => {
+                result = Err(err);
+                Some(value)
+            }
+        })
+        .map_err(|err| ServiceError::SelectorError {
+            details: err.to_string(),
+        })?;
+
+        result.map(|_| json)
+    }
+
+    fn decode_value(&self, value: &Value) -> Result<Value, ServiceError> {
+        let encoded = value.as_str().ok_or_else(|| {
+            error(format!(
+                "Expected an encoded string at {}, found {}",
+                self.path, value
+            ))
+        })?;
+        let data = match self.encoding {
+            Encoding::Base64 => {
+                let encoded: String = encoded.split_whitespace().collect();
+                base64::decode(&encoded)
+                    .or_else(|_| base64::decode_config(&encoded, base64::URL_SAFE))
+                    .map_err(|err| error(format!("Invalid base64: {}", err)))?
+            }
+            Encoding::Hex => hex(encoded)?,
+        };
+
+        match self.format {
+            Format::Json => serde_json::from_slice(&data).map_err(|err| error(err.to_string())),
+            Format::Cbor => cbor::decode(&data),
+            Format::CayenneLpp => lpp::decode(&data),
+        }
+    }
+}
+
+fn hex(encoded: &str) -> Result<Vec<u8>, ServiceError> {
+    let digits = encoded
+        .trim()
+        .trim_start_matches("0x")
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .map(|c| c.to_digit(16).map(|digit| digit as u8))
+        .collect::<Option<Vec<_>>>()
+        .ok_or_else(|| error(format!("Invalid hex: {}", encoded)))?;
+    if digits.len() % 2 != 0 {
+        return Err(error(format!("Odd number of hex digits: {}", encoded)));
+    }
+    Ok(digits
+        .chunks(2)
+        .map(|pair| pair[0] << 4 | pair[1])
+        .collect())
+}
+
+fn error(details: String) -> ServiceError {
+    ServiceError::PayloadParseError {
+        details: format!("Invalid inner payload: {}", details),
+    }
+}
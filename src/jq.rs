@@ -0,0 +1,93 @@
+use crate::error::ServiceError;
+use envconfig::Envconfig;
+use serde_json::Value;
+
+#[derive(Envconfig, Clone, Debug)]
+pub struct JqConfig {
+    /// jq filter reshaping the parsed payload before extracting values, after the script.
+    #[envconfig(from = "JQ_TRANSFORM")]
+    pub filter: Option<String>,
+}
+
+/// Reshapes payloads with a jq filter, like `.data.readings | map({(.name): .value}) | add`.
+///
+/// The filter must produce exactly one value, which replaces the payload.
+#[cfg(feature = "jq")]
+#[derive(Clone)]
+pub struct JqTransform {
+    filter: std::sync::Arc<jaq_interpret::Filter>,
+}
+
+#[cfg(feature = "jq")]
+impl std::fmt::Debug for JqTransform {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("JqTransform").finish()
+    }
+}
+
+#[cfg(feature = "jq")]
+impl JqTransform {
+    pub fn from_config(config: JqConfig) -> anyhow::Result<Option<Self>> {
+        let filter = match config.filter {
+            Some(filter) => filter,
+            None => return Ok(None),
+        };
+
+        let (parsed, errors) = jaq_parse::parse(&filter, jaq_parse::main());
+        if let Some(err) = errors.first() {
+            anyhow::bail!("Failed to parse jq filter: {}", err);
+        }
+        let parsed = parsed.ok_or_else(|| anyhow::anyhow!("Empty jq filter"))?;
+
+        let mut defs = jaq_interpret::ParseCtx::new(Vec::new());
+        defs.insert_natives(jaq_core::core());
+        defs.insert_defs(jaq_std::std());
+        let compiled = defs.compile(parsed);
+        if let Some((err, _)) = defs.errs.first() {
+            anyhow::bail!("Failed to compile jq filter: {}", err);
+        }
+
+        Ok(Some(Self {
+            filter: std::sync::Arc::new(compiled),
+        }))
+    }
+
+    pub fn transform(&self, json: Value) -> Result<Value, ServiceError> {
+        use jaq_interpret::{Ctx, FilterT, RcIter, Val};
+
+        let inputs = RcIter::new(std::iter::empty());
+        let mut outputs = self.filter.run((Ctx::new([], &inputs), Val::from(json)));
+
+        let value = match (outputs.next(), outputs.next()) {
+            (Some(value), None) => value.map_err(transform_error)?,
+            (None, _) => return Err(transform_error("jq filter produced no value")),
+            (Some(_), Some(_)) => return Err(transform_error("jq filter produced several values")),
+        };
+        Ok(value.into())
+    }
+}
+
+#[cfg(feature = "jq")]
+fn transform_error<E: std::fmt::Display>(err: E) -> ServiceError {
+    ServiceError::TransformError {
+        details: err.to_string(),
+    }
+}
+
+#[cfg(not(feature = "jq"))]
+#[derive(Clone, Debug)]
+pub struct JqTransform;
+
+#[cfg(not(feature = "jq"))]
+impl JqTransform {
+    pub fn from_config(config: JqConfig) -> anyhow::Result<Option<Self>> {
+        if config.filter.is_some() {
+            anyhow::bail!("JQ_TRANSFORM requires the 'jq' feature");
+        }
+        Ok(None)
+    }
+
+    pub fn transform(&self, json: Value) -> Result<Value, ServiceError> {
+        Ok(json)
+    }
+}
@@ -0,0 +1,201 @@
+use crate::config::Processor;
+use crate::control::Control;
+use crate::shutdown::Shutdown;
+use crate::source::SourceConfig;
+use envconfig::Envconfig;
+
+#[cfg_attr(not(feature = "kafka"), allow(dead_code))]
+#[derive(Envconfig, Clone, Debug)]
+pub struct KafkaConfig {
+    #[envconfig(from = "KAFKA_BOOTSTRAP_SERVERS", default = "localhost:9092")]
+    pub bootstrap_servers: String,
+    #[envconfig(from = "KAFKA_TOPIC")]
+    pub topic: Option<String>,
+    #[envconfig(from = "KAFKA_GROUP_ID", default = "drogue-influxdb-function")]
+    pub group_id: String,
+}
+
+/// Additional client properties, `KAFKA_PROPERTY_SASL_MECHANISM` being `sasl.mechanism`.
+#[cfg(feature = "kafka")]
+const PROPERTY_PREFIX: &str = "KAFKA_PROPERTY_";
+
+/// Consume CloudEvents from a Kafka topic.
+///
+/// The offset of an event is committed once it is processed. Events which fail with a
+/// server side error, like failing to write to InfluxDB, are retried until they succeed.
+/// Batching, aggregation and transactions are rejected, as they hold back points after the
+/// event was acknowledged.
+#[cfg(feature = "kafka")]
+pub fn start(
+    source: SourceConfig,
+    config: KafkaConfig,
+    processor: actix_web::web::Data<Processor>,
+    shutdown: actix_web::web::Data<Shutdown>,
+    control: actix_web::web::Data<Control>,
+) -> anyhow::Result<()> {
+    use rdkafka::consumer::{CommitMode, Consumer, StreamConsumer};
+    use rdkafka::message::Message;
+    use std::time::Duration;
+
+    let topic = match config.topic {
+        Some(topic) => topic,
+        None => anyhow::bail!("SOURCE=kafka requires KAFKA_TOPIC"),
+    };
+
+    // these acknowledge events before their points are written, offsets would be committed
+    // for points which may still be lost
+    if processor.batcher.is_some() {
+        anyhow::bail!("SOURCE=kafka can't be used with BATCH_INTERVAL_MS");
+    }
+    if processor.aggregator.is_some() {
+        anyhow::bail!("SOURCE=kafka can't be used with AGGREGATE_WINDOW_S");
+    }
+    if processor.transactions.is_some() {
+        anyhow::bail!("SOURCE=kafka can't be used with TRANSACTION_ATTRIBUTE");
+    }
+
+    let mut client = rdkafka::ClientConfig::new();
+    client
+        .set("bootstrap.servers", &config.bootstrap_servers)
+        .set("group.id", &config.group_id)
+        .set("enable.auto.commit", "false")
+        .set("auto.offset.reset", "earliest");
+    for (key, value) in std::env::vars() {
+        if let Some(property) = key.strip_prefix(PROPERTY_PREFIX) {
+            client.set(property.to_lowercase().replace('_', "."), value);
+        }
+    }
+
+    let consumer: StreamConsumer<_, ActixRuntime> = client.create()?;
+    consumer.subscribe(&[&topic])?;
+
+    log::info!("Consuming events from {}", topic);
+
+    let backoff = source.retry_backoff();
+
+    actix_rt::spawn(async move {
+        while !shutdown.is_triggered() {
+            if control.is_paused() {
+                actix_rt::time::delay_for(Duration::from_secs(1)).await;
+                continue;
+            }
+
+            // wake up now and then, to notice the shutdown
+            let message =
+                match actix_rt::time::timeout(Duration::from_secs(1), consumer.recv()).await {
+                    Ok(Ok(message)) => message,
+                    Ok(Err(err)) => {
+                        log::warn!("Failed to receive from Kafka: {}", err);
+                        actix_rt::time::delay_for(backoff).await;
+                        continue;
+                    }
+                    Err(_) => continue,
+                };
+
+            let processed = match to_event(&message) {
+                Ok(event) => crate::source::deliver(event, &processor, &shutdown, backoff).await,
+                Err(err) => {
+                    // retrying won't make it any better
+                    log::warn!(
+                        "Skipping invalid event at offset {}: {}",
+                        message.offset(),
+                        err
+                    );
+                    true
+                }
+            };
+
+            if processed {
+                if let Err(err) = consumer.commit_message(&message, CommitMode::Async) {
+                    log::warn!("Failed to commit offset: {}", err);
+                }
+            }
+        }
+        log::info!("Stopped consuming events");
+    });
+
+    Ok(())
+}
+
+/// Read binary (`ce_*` headers) or structured mode CloudEvents.
+#[cfg(feature = "kafka")]
+fn to_event(message: &rdkafka::message::BorrowedMessage<'_>) -> anyhow::Result<cloudevents::Event> {
+    use cloudevents::{EventBuilder, EventBuilderV10};
+    use rdkafka::message::{Headers, Message};
+    use std::collections::HashMap;
+
+    let headers: HashMap<String, String> = message
+        .headers()
+        .map(|headers| {
+            headers
+                .iter()
+                .filter_map(|header| {
+                    let value = std::str::from_utf8(header.value?).ok()?;
+                    Some((header.key.to_lowercase(), value.to_string()))
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+    let payload = message.payload().unwrap_or_default();
+    let content_type = headers.get("content-type");
+
+    if content_type.is_some_and(|ct| ct.starts_with("application/cloudevents")) {
+        return Ok(serde_json::from_slice(payload)?);
+    }
+
+    let attribute = |name: &str| {
+        headers
+            .get(&format!("ce_{}", name))
+            .ok_or_else(|| anyhow::anyhow!("Missing header: ce_{}", name))
+    };
+
+    let mut builder = EventBuilderV10::new()
+        .id(attribute("id")?)
+        .source(attribute("source")?.as_str())
+        .ty(attribute("type")?);
+    for (key, value) in &headers {
+        match key.strip_prefix("ce_") {
+            Some("specversion" | "id" | "source" | "type" | "dataschema") | None => {}
+            Some("time") => builder = builder.time(value.as_str()),
+            Some("subject") => builder = builder.subject(value),
+            Some(extension) => builder = builder.extension(extension, value.as_str()),
+        }
+    }
+    if !payload.is_empty() {
+        let content_type = content_type.map_or("application/json", String::as_str);
+        builder = builder.data(content_type, payload.to_vec());
+    }
+
+    Ok(builder.build()?)
+}
+
+/// Runs the tasks of the consumer on the actix (tokio) runtime.
+#[cfg(feature = "kafka")]
+struct ActixRuntime;
+
+#[cfg(feature = "kafka")]
+impl rdkafka::util::AsyncRuntime for ActixRuntime {
+    type Delay = actix_rt::time::Delay;
+
+    fn spawn<T>(task: T)
+    where
+        T: std::future::Future<Output = ()> + Send + 'static,
+    {
+        actix_rt::spawn(task)
+    }
+
+    fn delay_for(duration: std::time::Duration) -> Self::Delay {
+        actix_rt::time::delay_for(duration)
+    }
+}
+
+#[cfg(not(feature = "kafka"))]
+pub fn start(
+    _: SourceConfig,
+    _: KafkaConfig,
+    _: actix_web::web::Data<Processor>,
+    _: actix_web::web::Data<Shutdown>,
+    _: actix_web::web::Data<Control>,
+) -> anyhow::Result<()> {
+    anyhow::bail!("SOURCE=kafka requires the 'kafka' feature")
+}
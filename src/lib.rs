@@ -0,0 +1,84 @@
+//! Process events as a library, through `config::init` and `config::Processor::process`.
+
+pub mod admin;
+mod aggregate;
+mod arrival;
+pub mod auth;
+mod auto;
+mod avro;
+mod batch;
+pub mod bulk;
+mod cbor;
+pub mod checkpoint;
+mod chunk;
+mod compute;
+pub mod config;
+pub mod control;
+pub mod convert;
+mod csv;
+mod dedup;
+mod denylist;
+mod effective;
+mod encrypt;
+pub mod error;
+mod event;
+mod extract;
+mod failover;
+mod filter;
+mod geohash;
+pub mod handler;
+mod hash;
+mod inflight;
+mod influx;
+mod inner;
+mod jq;
+pub mod kafka;
+mod line;
+pub mod lineprotocol;
+mod link;
+pub mod listener;
+pub mod logging;
+mod lpp;
+pub mod metrics;
+pub mod mqtt;
+mod names;
+mod ndjson;
+mod preset;
+mod problems;
+pub mod profile;
+pub mod provision;
+mod questdb;
+mod range;
+mod rate;
+pub mod recent;
+mod records;
+mod redact;
+mod registry;
+mod reply;
+pub mod schema;
+mod script;
+mod series;
+mod shed;
+pub mod shutdown;
+mod sink;
+mod skew;
+pub mod source;
+pub mod startup;
+#[cfg(feature = "static-mapping")]
+mod static_mapping;
+mod stats;
+mod sticky;
+mod stream;
+mod tagformat;
+mod tags;
+mod telegraf;
+mod timescale;
+mod timestamp;
+pub mod tls;
+mod transaction;
+mod udp;
+mod unchanged;
+mod valuemap;
+pub mod warmup;
+mod wasm;
+mod xml;
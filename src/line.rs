@@ -0,0 +1,260 @@
+use crate::config::Precision;
+use chrono::{DateTime, TimeZone, Utc};
+use influxdb::{InfluxDbWriteable, Type, WriteQuery};
+
+/// A point, parsed back from line protocol.
+///
+/// `WriteQuery` doesn't give access to what it contains, sinks which don't speak line
+/// protocol go through this.
+#[derive(Clone, Debug)]
+pub struct Point {
+    pub measurement: String,
+    pub tags: Vec<(String, String)>,
+    pub fields: Vec<(String, Type)>,
+    pub time: Option<DateTime<Utc>>,
+}
+
+impl Point {
+    pub fn from_query(query: &WriteQuery) -> anyhow::Result<Self> {
+        let line = crate::influx::line(query)?;
+        Self::parse(&line, &query.get_precision())
+    }
+
+    /// Parse a line, with the timestamp in the given precision (`ns`, `u`, `ms`, `s`).
+    pub fn parse(line: &str, precision: &str) -> anyhow::Result<Self> {
+        // quoted strings are field values, which come after the measurement and tags
+        let parts = split(line, ' ', 1);
+        let (head, fields, time) = match parts.as_slice() {
+            [head, fields] => (head, fields, None),
+            [head, fields, time] => (head, fields, Some(time)),
+            _ => anyhow::bail!("Invalid line: {}", line),
+        };
+
+        let mut head = split(head, ',', usize::MAX).into_iter();
+        let measurement = unescape(&head.next().unwrap_or_default());
+        let tags = head
+            .map(|tag| {
+                let (key, value) = key_value(&tag)?;
+                Ok((key, unescape(value)))
+            })
+            .collect::<anyhow::Result<_>>()?;
+
+        let fields = split(fields, ',', 0)
+            .iter()
+            .map(|field| {
+                let (key, value) = key_value(field)?;
+                Ok((key, field_value(value)?))
+            })
+            .collect::<anyhow::Result<_>>()?;
+
+        let time = time.map(|time| timestamp(time, precision)).transpose()?;
+
+        Ok(Self {
+            measurement,
+            tags,
+            fields,
+            time,
+        })
+    }
+
+    /// Write the point again, with its time in the given precision, or now if it has none.
+    pub fn into_query(self, precision: Precision) -> WriteQuery {
+        let time = self.time.unwrap_or_else(Utc::now);
+        let mut query = precision.timestamp(time).into_query(self.measurement);
+        for (tag, value) in self.tags {
+            query = query.add_tag(tag, value);
+        }
+        for (field, value) in self.fields {
+            query = query.add_field(field, value);
+        }
+        query
+    }
+}
+
+/// Split at a separator which isn't escaped or quoted, keeping the escapes.
+///
+/// Quotes are only looked at from the part `quoted_from` on, where a value right after `=`
+/// may be a quoted string, elsewhere they are just part of the name.
+fn split(s: &str, separator: char, quoted_from: usize) -> Vec<String> {
+    let mut parts = Vec::new();
+    let mut current = String::new();
+    let mut chars = s.chars();
+    let mut quoted = false;
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                current.push(c);
+                if let Some(c) = chars.next() {
+                    current.push(c);
+                }
+            }
+            '"' if parts.len() >= quoted_from && (quoted || current.ends_with('=')) => {
+                quoted = !quoted;
+                current.push(c);
+            }
+            c if c == separator && !quoted => parts.push(std::mem::take(&mut current)),
+            c => current.push(c),
+        }
+    }
+    parts.push(current);
+
+    parts
+}
+
+fn key_value(s: &str) -> anyhow::Result<(String, &str)> {
+    let mut escaped = false;
+    for (i, c) in s.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '=' => return Ok((unescape(&s[..i]), &s[i + 1..])),
+            _ => {}
+        }
+    }
+    anyhow::bail!("Missing value: {}", s)
+}
+
+fn unescape(s: &str) -> String {
+    let mut result = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => result.extend(chars.next()),
+            c => result.push(c),
+        }
+    }
+    result
+}
+
+fn field_value(s: &str) -> anyhow::Result<Type> {
+    if let Some(text) = s.strip_prefix('"').and_then(|s| s.strip_suffix('"')) {
+        return Ok(Type::Text(unescape(text)));
+    }
+    match s {
+        "t" | "T" | "true" | "True" | "TRUE" => return Ok(Type::Boolean(true)),
+        "f" | "F" | "false" | "False" | "FALSE" => return Ok(Type::Boolean(false)),
+        _ => {}
+    }
+    if let Some(integer) = s.strip_suffix('i') {
+        return Ok(Type::SignedInteger(integer.parse()?));
+    }
+    if let Some(integer) = s.strip_suffix('u') {
+        return Ok(Type::UnsignedInteger(integer.parse()?));
+    }
+    Ok(Type::Float(s.parse()?))
+}
+
+fn timestamp(s: &str, precision: &str) -> anyhow::Result<DateTime<Utc>> {
+    let value: i64 = s.parse()?;
+    let nanos = match precision {
+        "ns" => Some(value),
+        "u" => value.checked_mul(1_000),
+        "ms" => value.checked_mul(1_000_000),
+        "s" => value.checked_mul(1_000_000_000),
+        _ => anyhow::bail!("Unsupported precision: {}", precision),
+    };
+    nanos
+        .map(|nanos| Utc.timestamp_nanos(nanos))
+        .ok_or_else(|| anyhow::anyhow!("Timestamp out of range: {}", s))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use influxdb::InfluxDbWriteable;
+
+    fn fields(point: &Point) -> Vec<(&str, String)> {
+        point
+            .fields
+            .iter()
+            .map(|(field, value)| (field.as_str(), format!("{:?}", value)))
+            .collect()
+    }
+
+    #[test]
+    fn test_parse() {
+        let point = Point::parse(
+            r#"my\ m,host=a\,b,site=x\=y temp=21.5,n=3i,u=4u,ok=t,note="a, \"b\" c" 1500"#,
+            "ms",
+        )
+        .unwrap();
+        assert_eq!(point.measurement, "my m");
+        assert_eq!(
+            point.tags,
+            vec![
+                ("host".to_string(), "a,b".to_string()),
+                ("site".to_string(), "x=y".to_string())
+            ]
+        );
+        assert_eq!(
+            fields(&point),
+            vec![
+                ("temp", "Float(21.5)".to_string()),
+                ("n", "SignedInteger(3)".to_string()),
+                ("u", "UnsignedInteger(4)".to_string()),
+                ("ok", "Boolean(true)".to_string()),
+                ("note", r#"Text("a, \"b\" c")"#.to_string()),
+            ]
+        );
+        assert_eq!(point.time, Some(Utc.timestamp_millis_opt(1500).unwrap()));
+
+        let point = Point::parse("m v=1", "ns").unwrap();
+        assert!(point.tags.is_empty());
+        assert_eq!(point.time, None);
+    }
+
+    #[test]
+    fn test_quotes_outside_of_fields() {
+        let point = Point::parse(r#"m"x,t"ag=a"b v="x y",w=1"#, "ns").unwrap();
+        assert_eq!(point.measurement, r#"m"x"#);
+        assert_eq!(
+            point.tags,
+            vec![(r#"t"ag"#.to_string(), r#"a"b"#.to_string())]
+        );
+        assert_eq!(
+            fields(&point),
+            vec![
+                ("v", r#"Text("x y")"#.to_string()),
+                ("w", "Float(1.0)".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn test_from_query() {
+        let time = Utc.timestamp_opt(1_600_000_000, 0).unwrap();
+        let query = influxdb::Timestamp::Seconds(time.timestamp() as u128)
+            .into_query("m")
+            .add_tag("t", "a b")
+            .add_field("v", 1.5);
+        let point = Point::from_query(&query).unwrap();
+        assert_eq!(point.measurement, "m");
+        assert_eq!(point.tags, vec![("t".to_string(), "a b".to_string())]);
+        assert_eq!(fields(&point), vec![("v", "Float(1.5)".to_string())]);
+        assert_eq!(point.time, Some(time));
+    }
+
+    #[test]
+    fn test_truncated() {
+        assert!(Point::parse("m", "ns").is_err());
+        assert!(Point::parse("m,t=a", "ns").is_err());
+        assert!(Point::parse("m v=", "ns").is_err());
+        assert!(Point::parse(r#"m v="open"#, "ns").is_err());
+    }
+
+    #[test]
+    fn test_out_of_range() {
+        assert!(Point::parse("m v=1 9223372036854775807", "s").is_err());
+        assert!(Point::parse("m v=9223372036854775808i", "ns").is_err());
+    }
+
+    #[test]
+    fn test_malformed() {
+        assert!(Point::parse("m,t v=1", "ns").is_err());
+        assert!(Point::parse("m v=x", "ns").is_err());
+        assert!(Point::parse("m v=1 x", "ns").is_err());
+        assert!(Point::parse("m v=1 1", "h").is_err());
+        assert!(Point::parse("m v=1 1 2", "ns").is_err());
+    }
+}
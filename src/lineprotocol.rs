@@ -0,0 +1,242 @@
+use crate::auth::Authenticated;
+use crate::config::Processor;
+use crate::error::ServiceError;
+use crate::event::{self, read_body, EventConfig};
+use crate::influx;
+use crate::line::Point;
+use crate::redact::Secrets;
+use crate::series::Commit;
+use crate::shutdown::Accepting;
+use actix_web::dev::{Decompress, Payload};
+use actix_web::error::{ErrorBadRequest, ErrorUnsupportedMediaType};
+use actix_web::{web, FromRequest, HttpRequest, HttpResponse};
+use chrono::Utc;
+use cloudevents::binding::actix::HttpRequestDeserializer;
+use cloudevents::event::Data;
+use cloudevents::message::MessageDeserializer;
+use cloudevents::AttributesReader;
+use futures::future::LocalBoxFuture;
+use futures::FutureExt;
+use influxdb::{InfluxDbWriteable, WriteQuery};
+use serde::Deserialize;
+
+/// Line protocol, as the body, or as the `text/plain` data of a CloudEvent.
+pub struct IncomingLines(pub String);
+
+impl FromRequest for IncomingLines {
+    type Config = ();
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let limit = EventConfig::get(req).batch_limit;
+        let payload = Decompress::from_headers(payload.take(), req.headers());
+        let req = req.clone();
+
+        async move {
+            let body = read_body(payload, limit).await?;
+            if !event::is_cloud_event(&req) {
+                return Ok(IncomingLines(String::from_utf8_lossy(&body).into_owned()));
+            }
+
+            let event = HttpRequestDeserializer::new(&req, body)
+                .into_event()
+                .map_err(ErrorBadRequest)?;
+            if !event
+                .datacontenttype()
+                .is_some_and(|content_type| content_type.starts_with("text/plain"))
+            {
+                return Err(ErrorUnsupportedMediaType("Expected text/plain data"));
+            }
+            match event.data() {
+                Some(Data::String(s)) => Ok(IncomingLines(s.clone())),
+                Some(Data::Binary(b)) => Ok(IncomingLines(String::from_utf8_lossy(b).into_owned())),
+                _ => Err(ErrorBadRequest("Expected line protocol data")),
+            }
+        }
+        .boxed_local()
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LineParams {
+    /// Of the timestamps, like with `/write` of InfluxDB.
+    #[serde(default = "default_precision")]
+    precision: String,
+    /// Retention policy to write to.
+    rp: Option<String>,
+}
+
+fn default_precision() -> String {
+    "ns".to_string()
+}
+
+/// Write line protocol, for gateways which already speak it.
+///
+/// Each line is validated and written with the configured precision, all of them or none.
+pub async fn handle(
+    _: Accepting,
+    _: Authenticated,
+    IncomingLines(lines): IncomingLines,
+    params: web::Query<LineParams>,
+    processor: web::Data<Processor>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let queries = parse(&lines, &params, &processor)?;
+    if queries.is_empty() {
+        return Ok(HttpResponse::NoContent().finish());
+    }
+
+    if processor.dry_run {
+        for (_, query) in &queries {
+            log::info!("Dry run - {}", influx::line(query).unwrap_or_default());
+        }
+        return Ok(HttpResponse::Accepted().finish());
+    }
+
+    if let Some(batcher) = &processor.batcher {
+        return match batcher.push(queries, None, Commit::default(), &Secrets::default()) {
+            true => Ok(HttpResponse::Accepted().finish()),
+            false => Err(ServiceError::QueueFull.into()),
+        };
+    }
+
+    let _permit = match &processor.in_flight {
+        Some(in_flight) => Some(in_flight.acquire()?),
+        None => None,
+    };
+
+    match processor.sink.write(&queries).await {
+        Ok(()) => Ok(HttpResponse::Accepted().finish()),
+        Err(err) => {
+            processor.recent_errors.record(None, "WriteError", &err);
+            Err(ServiceError::WriteError {
+                details: err.to_string(),
+            }
+            .into())
+        }
+    }
+}
+
+fn parse(
+    lines: &str,
+    params: &LineParams,
+    processor: &Processor,
+) -> Result<Vec<(Option<String>, WriteQuery)>, ServiceError> {
+    let precision = match params.precision.as_str() {
+        "n" => "ns",
+        "us" => "u",
+        precision => precision,
+    };
+
+    lines
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty() && !line.starts_with('#'))
+        .map(|(index, line)| {
+            let point =
+                Point::parse(line, precision).map_err(|err| ServiceError::PayloadParseError {
+                    details: format!("Line {}: {}", index + 1, err),
+                })?;
+
+            // measurements and tags are looked after like those of mapped events
+            let time = point.time.unwrap_or_else(Utc::now);
+            let table = format!("{}{}", processor.measurement_prefix, point.measurement);
+            let table = processor.measurement(&table, time);
+            let mut query = processor
+                .precision
+                .timestamp(time)
+                .into_query(table.as_str());
+            for (tag, value) in point.tags {
+                if let Some(value) = processor.tag_guard.admit(&table, &tag, &value)? {
+                    query = query.add_tag(tag, value);
+                }
+            }
+            for (field, value) in point.fields {
+                query = query.add_field(field, value);
+            }
+            Ok((params.rp.clone(), query))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Configured from the environment, like the service, with what each test sets the same.
+    fn processor() -> web::Data<Processor> {
+        for (name, value) in &[
+            ("INFLUXDB_URI", "http://localhost:8086"),
+            ("INFLUXDB_DATABASE", "db"),
+            ("INFLUXDB_USERNAME", "u"),
+            ("INFLUXDB_PASSWORD", "p"),
+            ("INFLUXDB_TABLE", "t"),
+            ("FIELD_V", "$.v"),
+        ] {
+            std::env::set_var(name, value);
+        }
+        crate::config::init().unwrap().processor
+    }
+
+    fn params(precision: &str, rp: Option<&str>) -> LineParams {
+        LineParams {
+            precision: precision.into(),
+            rp: rp.map(Into::into),
+        }
+    }
+
+    fn lines(queries: &[(Option<String>, WriteQuery)]) -> Vec<String> {
+        queries
+            .iter()
+            .map(|(_, query)| influx::line(query).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_parse() {
+        actix_rt::System::new("test").block_on(async {
+            let processor = processor();
+            let body = "# comment\nm,room=a v=1,s=\"x\" 1600000000\n\nm v=2i 1600000001\n";
+            let queries = parse(body, &params("s", Some("week")), &processor).unwrap();
+            assert_eq!(
+                lines(&queries),
+                vec![
+                    "m,room=a v=1,s=\"x\" 1600000000000000000",
+                    "m v=2i 1600000001000000000"
+                ]
+            );
+            assert!(queries
+                .iter()
+                .all(|(retention, _)| retention.as_deref() == Some("week")));
+        });
+    }
+
+    #[test]
+    fn test_precision() {
+        actix_rt::System::new("test").block_on(async {
+            let processor = processor();
+            let queries = parse("m v=1 1600000000000", &params("ms", None), &processor).unwrap();
+            assert_eq!(lines(&queries), vec!["m v=1 1600000000000000000"]);
+            // like InfluxDB, `n` and `us` as well
+            let queries = parse("m v=1 5", &params("n", None), &processor).unwrap();
+            assert_eq!(lines(&queries), vec!["m v=1 5"]);
+            let queries = parse("m v=1 5", &params("us", None), &processor).unwrap();
+            assert_eq!(lines(&queries), vec!["m v=1 5000"]);
+        });
+    }
+
+    #[test]
+    fn test_invalid() {
+        actix_rt::System::new("test").block_on(async {
+            let processor = processor();
+            let err = parse("m v=1\nm v=", &params("ns", None), &processor).unwrap_err();
+            match err {
+                ServiceError::PayloadParseError { details } => {
+                    assert!(details.starts_with("Line 2: "), "{}", details)
+                }
+                err => panic!("Unexpected error: {:?}", err),
+            }
+            assert!(parse("m v=1 1", &params("h", None), &processor).is_err());
+        });
+    }
+}
@@ -0,0 +1,134 @@
+use crate::preset::Preset;
+use envconfig::Envconfig;
+use influxdb::Type;
+use serde_json::Value;
+
+#[derive(Envconfig, Clone, Debug)]
+pub struct LinkConfig {
+    /// Write link metrics of the receiving gateways.
+    #[envconfig(from = "LINK_METRICS", default = "false")]
+    pub enabled: bool,
+    /// JSON pointer to the array of gateways, defaults to the one of `PAYLOAD_PRESET`.
+    #[envconfig(from = "LINK_METRICS_ARRAY")]
+    pub array: Option<String>,
+    /// JSON pointers into each gateway's entry.
+    #[envconfig(from = "LINK_METRICS_GATEWAY_ID")]
+    pub gateway_id: Option<String>,
+    #[envconfig(from = "LINK_METRICS_RSSI")]
+    pub rssi: Option<String>,
+    #[envconfig(from = "LINK_METRICS_SNR")]
+    pub snr: Option<String>,
+}
+
+/// Best, worst and mean RSSI/SNR across the gateways which received an uplink.
+///
+/// Runs on the payload before unwrapping the envelope, which is where the gateways are.
+#[derive(Clone, Debug)]
+pub struct LinkMetrics {
+    array: String,
+    gateway_id: String,
+    rssi: String,
+    snr: String,
+}
+
+/// Tag with the id of the gateway with the best RSSI.
+pub const GATEWAY_TAG: &str = "gateway_id";
+
+#[derive(Debug, Default)]
+pub struct Link {
+    pub fields: Vec<(&'static str, Type)>,
+    pub gateway: Option<String>,
+}
+
+impl LinkMetrics {
+    pub fn from_config(config: LinkConfig, preset: Option<Preset>) -> anyhow::Result<Option<Self>> {
+        if !config.enabled {
+            return Ok(None);
+        }
+
+        let (array, gateway_id, rssi, snr) = match preset {
+            Some(Preset::TtnV3) => (
+                "/uplink_message/rx_metadata",
+                "/gateway_ids/gateway_id",
+                "/rssi",
+                "/snr",
+            ),
+            Some(Preset::ChirpStackV4) => ("/rxInfo", "/gatewayId", "/rssi", "/snr"),
+            Some(Preset::Telegraf) | None => ("", "/gateway_id", "/rssi", "/snr"),
+        };
+
+        let array = config.array.unwrap_or_else(|| array.to_string());
+        if array.is_empty() {
+            anyhow::bail!("LINK_METRICS_ARRAY is required without a PAYLOAD_PRESET");
+        }
+
+        Ok(Some(Self {
+            array,
+            gateway_id: config.gateway_id.unwrap_or_else(|| gateway_id.to_string()),
+            rssi: config.rssi.unwrap_or_else(|| rssi.to_string()),
+            snr: config.snr.unwrap_or_else(|| snr.to_string()),
+        }))
+    }
+
+    pub fn measure(&self, json: &Value) -> Link {
+        let gateways = match json.pointer(&self.array).and_then(Value::as_array) {
+            Some(gateways) if !gateways.is_empty() => gateways,
+            _ => return Link::default(),
+        };
+
+        let mut link = Link::default();
+        link.fields
+            .push(("gateway_count", Type::SignedInteger(gateways.len() as i64)));
+
+        let rssi: Vec<_> = gateways
+            .iter()
+            .filter_map(|gateway| Some((gateway, gateway.pointer(&self.rssi)?.as_f64()?)))
+            .collect();
+        let snr: Vec<_> = gateways
+            .iter()
+            .filter_map(|gateway| gateway.pointer(&self.snr)?.as_f64())
+            .collect();
+
+        // first one wins on a tie
+        let serving = rssi
+            .iter()
+            .fold(None, |best: Option<&(&Value, f64)>, entry| match best {
+                Some(best) if best.1 >= entry.1 => Some(best),
+                _ => Some(entry),
+            });
+        link.gateway = serving
+            .and_then(|(gateway, _)| gateway.pointer(&self.gateway_id))
+            .map(|id| match id {
+                Value::String(s) => s.clone(),
+                id => id.to_string(),
+            });
+
+        let rssi: Vec<_> = rssi.into_iter().map(|(_, rssi)| rssi).collect();
+        add_stats(
+            &mut link.fields,
+            &rssi,
+            ["rssi_best", "rssi_worst", "rssi_mean"],
+        );
+        add_stats(
+            &mut link.fields,
+            &snr,
+            ["snr_best", "snr_worst", "snr_mean"],
+        );
+
+        link
+    }
+}
+
+fn add_stats(fields: &mut Vec<(&'static str, Type)>, values: &[f64], names: [&'static str; 3]) {
+    if values.is_empty() {
+        return;
+    }
+
+    let best = values.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+    let worst = values.iter().copied().fold(f64::INFINITY, f64::min);
+    let mean = values.iter().sum::<f64>() / values.len() as f64;
+
+    fields.push((names[0], Type::Float(best)));
+    fields.push((names[1], Type::Float(worst)));
+    fields.push((names[2], Type::Float(mean)));
+}
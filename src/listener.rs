@@ -0,0 +1,75 @@
+use crate::auth::AuthConfig;
+use crate::tls::TlsConfig;
+use envconfig::Envconfig;
+use std::fmt;
+
+/// The listener events are sent to by Knative.
+#[derive(Envconfig, Clone, Debug)]
+pub struct PrimaryConfig {
+    /// Address to listen on, like `0.0.0.0:8080`, overrides `PORT` if set.
+    #[envconfig(from = "BIND_ADDR")]
+    pub bind_addr: Option<String>,
+    #[envconfig(from = "PORT", default = "8080")]
+    pub port: u16,
+}
+
+impl PrimaryConfig {
+    pub fn addr(&self) -> String {
+        self.bind_addr
+            .clone()
+            .unwrap_or_else(|| format!("127.0.0.1:{}", self.port))
+    }
+}
+
+/// A second listener, with TLS and authentication of its own, in front of the same pipeline.
+///
+/// Like plaintext on the pod network for Knative, and TLS with a token on a NodePort for
+/// field gateways.
+#[derive(Envconfig, Clone)]
+pub struct SecondaryConfig {
+    /// Address to listen on, like `0.0.0.0:8443`, disabled if unset.
+    #[envconfig(from = "SECONDARY_BIND_ADDR")]
+    pub bind_addr: Option<String>,
+    #[envconfig(from = "SECONDARY_TLS_CERT_FILE")]
+    pub tls_cert_file: Option<String>,
+    #[envconfig(from = "SECONDARY_TLS_KEY_FILE")]
+    pub tls_key_file: Option<String>,
+    #[envconfig(from = "SECONDARY_TLS_CLIENT_CA_FILE")]
+    pub tls_client_ca_file: Option<String>,
+    #[envconfig(from = "SECONDARY_AUTH_TOKEN")]
+    pub auth_token: Option<String>,
+    #[envconfig(from = "SECONDARY_AUTH_BASIC_USERNAME")]
+    pub auth_basic_username: Option<String>,
+    #[envconfig(from = "SECONDARY_AUTH_BASIC_PASSWORD")]
+    pub auth_basic_password: Option<String>,
+}
+
+impl fmt::Debug for SecondaryConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SecondaryConfig")
+            .field("bind_addr", &self.bind_addr)
+            .field("tls_cert_file", &self.tls_cert_file)
+            .field("tls_key_file", &self.tls_key_file)
+            .field("tls_client_ca_file", &self.tls_client_ca_file)
+            .field("auth_basic_username", &self.auth_basic_username)
+            .finish()
+    }
+}
+
+impl SecondaryConfig {
+    pub fn tls(&self) -> TlsConfig {
+        TlsConfig {
+            cert_file: self.tls_cert_file.clone(),
+            key_file: self.tls_key_file.clone(),
+            client_ca_file: self.tls_client_ca_file.clone(),
+        }
+    }
+
+    pub fn auth(&self) -> AuthConfig {
+        AuthConfig {
+            token: self.auth_token.clone(),
+            basic_username: self.auth_basic_username.clone(),
+            basic_password: self.auth_basic_password.clone(),
+        }
+    }
+}
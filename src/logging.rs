@@ -0,0 +1,84 @@
+use chrono::Utc;
+use env_logger as elog;
+use envconfig::Envconfig;
+use log::kv::{self, Key, Value as KvValue, VisitSource};
+use serde_json::{Map, Value};
+use std::io::Write;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+#[derive(Envconfig, Clone, Debug)]
+pub struct LogConfig {
+    #[envconfig(from = "LOG_FORMAT", default = "plain")]
+    pub format: LogFormat,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LogFormat {
+    Plain,
+    /// One JSON object per line, including the key/values of a record.
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "plain" => Ok(LogFormat::Plain),
+            "json" => Ok(LogFormat::Json),
+            _ => anyhow::bail!("Unknown log format: {}", s),
+        }
+    }
+}
+
+static JSON: AtomicBool = AtomicBool::new(false);
+
+pub fn init(config: LogConfig) {
+    let mut builder =
+        elog::Builder::from_env(elog::Env::default().default_filter_or("info,actix_web=warn"));
+
+    if config.format == LogFormat::Json {
+        JSON.store(true, Ordering::Relaxed);
+        builder.format(|buf, record| {
+            let mut json = Map::new();
+            json.insert("time".into(), Utc::now().to_rfc3339().into());
+            json.insert("level".into(), record.level().as_str().into());
+            json.insert("target".into(), record.target().into());
+            json.insert("message".into(), record.args().to_string().into());
+            let _ = record.key_values().visit(&mut Fields(&mut json));
+            writeln!(buf, "{}", Value::Object(json))
+        });
+    }
+
+    builder.init();
+}
+
+/// Level of the record logged for each handled event.
+///
+/// That's only interesting for ingesting logs, so it stays out of the way otherwise.
+pub fn event_level() -> log::Level {
+    if JSON.load(Ordering::Relaxed) {
+        log::Level::Info
+    } else {
+        log::Level::Debug
+    }
+}
+
+struct Fields<'a>(&'a mut Map<String, Value>);
+
+impl<'kvs> VisitSource<'kvs> for Fields<'_> {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: KvValue<'kvs>) -> Result<(), kv::Error> {
+        let value = if let Some(v) = value.to_bool() {
+            Value::from(v)
+        } else if let Some(v) = value.to_i64() {
+            Value::from(v)
+        } else if let Some(v) = value.to_f64() {
+            Value::from(v)
+        } else {
+            Value::from(value.to_string())
+        };
+        self.0.insert(key.to_string(), value);
+        Ok(())
+    }
+}
@@ -0,0 +1,153 @@
+use crate::error::ServiceError;
+use serde_json::{json, Map, Value};
+
+/// Decode a Cayenne Low Power Payload frame.
+///
+/// Values are named `<type>_<channel>`, like `temperature_1`. Types with several values, like
+/// `gps`, become objects.
+pub fn decode(data: &[u8]) -> Result<Value, ServiceError> {
+    let mut result = Map::new();
+    let mut data = data;
+
+    while !data.is_empty() {
+        let (channel, r#type) = match data {
+            [channel, r#type, ..] => (*channel, *r#type),
+            _ => return Err(error("Truncated header")),
+        };
+        let (name, size) = describe(r#type)
+            .ok_or_else(|| error(format!("Unknown type {} on channel {}", r#type, channel)))?;
+        let value = data
+            .get(2..2 + size)
+            .ok_or_else(|| error(format!("Truncated value on channel {}", channel)))?;
+
+        result.insert(format!("{}_{}", name, channel), convert(r#type, value));
+        data = &data[2 + size..];
+    }
+
+    Ok(Value::Object(result))
+}
+
+fn describe(r#type: u8) -> Option<(&'static str, usize)> {
+    Some(match r#type {
+        0 => ("digital_input", 1),
+        1 => ("digital_output", 1),
+        2 => ("analog_input", 2),
+        3 => ("analog_output", 2),
+        100 => ("generic", 4),
+        101 => ("illuminance", 2),
+        102 => ("presence", 1),
+        103 => ("temperature", 2),
+        104 => ("humidity", 1),
+        113 => ("accelerometer", 6),
+        115 => ("barometer", 2),
+        116 => ("voltage", 2),
+        117 => ("current", 2),
+        118 => ("frequency", 4),
+        120 => ("percentage", 1),
+        121 => ("altitude", 2),
+        125 => ("concentration", 2),
+        128 => ("power", 2),
+        130 => ("distance", 4),
+        131 => ("energy", 4),
+        132 => ("direction", 2),
+        133 => ("time", 4),
+        134 => ("gyrometer", 6),
+        135 => ("colour", 3),
+        136 => ("gps", 9),
+        142 => ("switch", 1),
+        _ => return None,
+    })
+}
+
+fn convert(r#type: u8, value: &[u8]) -> Value {
+    match r#type {
+        2 | 3 => json!(signed(value) as f64 / 100.0),
+        103 => json!(signed(value) as f64 / 10.0),
+        104 => json!(unsigned(value) as f64 / 2.0),
+        115 => json!(unsigned(value) as f64 / 10.0),
+        116 => json!(unsigned(value) as f64 / 100.0),
+        117 | 130 | 131 => json!(unsigned(value) as f64 / 1000.0),
+        121 => json!(signed(value)),
+        113 | 134 => {
+            let scale = if r#type == 113 { 1000.0 } else { 100.0 };
+            json!({
+                "x": signed(&value[0..2]) as f64 / scale,
+                "y": signed(&value[2..4]) as f64 / scale,
+                "z": signed(&value[4..6]) as f64 / scale,
+            })
+        }
+        135 => json!({ "r": value[0], "g": value[1], "b": value[2] }),
+        136 => json!({
+            "latitude": signed(&value[0..3]) as f64 / 10000.0,
+            "longitude": signed(&value[3..6]) as f64 / 10000.0,
+            "altitude": signed(&value[6..9]) as f64 / 100.0,
+        }),
+        _ => json!(unsigned(value)),
+    }
+}
+
+/// Big endian, unsigned.
+fn unsigned(value: &[u8]) -> u64 {
+    value.iter().fold(0, |acc, b| acc << 8 | *b as u64)
+}
+
+/// Big endian, two's complement.
+fn signed(value: &[u8]) -> i64 {
+    let bits = value.len() * 8;
+    let raw = unsigned(value) as i64;
+    if raw & (1 << (bits - 1)) != 0 {
+        raw - (1 << bits)
+    } else {
+        raw
+    }
+}
+
+fn error<S: Into<String>>(details: S) -> ServiceError {
+    ServiceError::PayloadParseError {
+        details: format!("Invalid Cayenne LPP payload: {}", details.into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn details(result: Result<Value, ServiceError>) -> String {
+        match result {
+            Err(ServiceError::PayloadParseError { details }) => details,
+            other => panic!("Unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_decode() {
+        let data = [
+            0x03, 0x67, 0x01, 0x10, // temperature 27.2
+            0x05, 0x67, 0xff, 0xd7, // temperature -4.1
+            0x06, 0x68, 0x61, // humidity 48.5
+            0x01, 0x88, 0x06, 0x76, 0x5f, 0xf2, 0x96, 0x0a, 0x00, 0x03, 0xe8, // gps
+        ];
+        assert_eq!(
+            decode(&data).unwrap(),
+            json!({
+                "temperature_3": 27.2,
+                "temperature_5": -4.1,
+                "humidity_6": 48.5,
+                "gps_1": {"latitude": 42.3519, "longitude": -87.9094, "altitude": 10.0},
+            })
+        );
+        assert_eq!(decode(&[]).unwrap(), json!({}));
+    }
+
+    #[test]
+    fn test_truncated() {
+        assert!(details(decode(&[0x03])).contains("Truncated header"));
+        assert!(details(decode(&[0x03, 0x67, 0x01])).contains("Truncated value on channel 3"));
+        assert!(details(decode(&[0x01, 0x88, 0x06])).contains("Truncated value on channel 1"));
+    }
+
+    #[test]
+    fn test_malformed() {
+        assert!(details(decode(&[0x02, 0xff, 0x00])).contains("Unknown type 255 on channel 2"));
+    }
+}
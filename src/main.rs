@@ -1,32 +1,206 @@
 use actix_web::{web, App, HttpResponse, HttpServer};
-use env_logger as elog;
-
-mod config;
-mod error;
-mod handler;
+use envconfig::Envconfig;
+use function::{
+    admin, auth, bulk, checkpoint, config, control, handler, kafka, lineprotocol, listener,
+    logging, metrics, mqtt, profile, provision, recent, schema, shutdown, source, startup, tls,
+    warmup,
+};
+use std::time::Duration;
 
 #[actix_web::main]
-async fn main() -> std::io::Result<()> {
-    elog::from_env(elog::Env::default().default_filter_or("info,actix_web=warn")).init();
+async fn main() -> anyhow::Result<()> {
+    logging::init(logging::LogConfig::init_from_env()?);
 
-    let port: u16 = match std::env::var("PORT") {
-        Ok(v) => v.parse().unwrap(),
-        Err(_) => 8080,
-    };
+    let listener = listener::PrimaryConfig::init_from_env()?;
+
+    let tls = tls::TlsConfig::init_from_env()?;
+    let shutdown_config = shutdown::ShutdownConfig::init_from_env()?;
 
-    // Create the HTTP server
-    HttpServer::new(|| {
-        App::new()
-            .wrap(actix_web::middleware::Logger::default())
-            .configure(config::config)
-            .route("/", web::post().to(handler::handle))
-            .route(
-                "/health/{_:(readiness|liveness)}",
-                web::get().to(HttpResponse::Ok),
+    let startup_config = startup::StartupConfig::init_from_env()?;
+    let (service, readiness) = match config::init() {
+        Ok(service) => {
+            let readiness = startup::Readiness::new(&startup_config, &service.processor);
+            (Some(service), readiness)
+        }
+        Err(err) if startup_config.strict => return Err(err.context("Error configuring service")),
+        Err(err) => {
+            log::error!("Error configuring service: {}", err);
+            let readiness = startup::Readiness::failed(&startup_config, err.to_string());
+            (None, readiness)
+        }
+    };
+    readiness.probe(&startup_config).await?;
+    let readiness = web::Data::new(readiness);
+    let checkpoint_config = checkpoint::CheckpointConfig::init_from_env()?;
+    if let Some(service) = &service {
+        if !service.processor.dry_run {
+            provision::run(
+                provision::ProvisionConfig::init_from_env()?,
+                &service.processor.client,
             )
-    })
-    .bind(("127.0.0.1", port))?
-    .workers(1)
-    .run()
-    .await
+            .await;
+        }
+        if let Err(err) = checkpoint::restore(&checkpoint_config, &service.processor) {
+            log::warn!("Failed to restore checkpoint: {}", err);
+        }
+        warmup::run(warmup::WarmupConfig::init_from_env()?, &service.processor).await;
+    }
+
+    let shutdown = web::Data::new(shutdown::Shutdown::default());
+    let control = web::Data::new(control::Control::from_config(
+        control::ControlConfig::init_from_env()?,
+    ));
+
+    if let Some(service) = &service {
+        let source = source::SourceConfig::init_from_env()?;
+        let processor = service.processor.clone();
+        match source.source {
+            source::Source::Http => {}
+            source::Source::Kafka => kafka::start(
+                source,
+                kafka::KafkaConfig::init_from_env()?,
+                processor,
+                shutdown.clone(),
+                control.clone(),
+            )?,
+            source::Source::Mqtt => mqtt::start(
+                source,
+                mqtt::MqttConfig::init_from_env()?,
+                processor,
+                shutdown.clone(),
+                control.clone(),
+            )?,
+        }
+    }
+
+    // independent of the service, so that a failed configuration doesn't open the endpoints
+    let authenticator = auth::Authenticator::from_config(auth::AuthConfig::init_from_env()?)?;
+    if !authenticator.is_enabled() {
+        log::warn!("No authentication configured, accepting all events");
+    }
+    let authenticator = web::Data::new(authenticator);
+    let secondary = listener::SecondaryConfig::init_from_env()?;
+
+    // Create the HTTP servers, one per listener, as they differ in the authentication
+    let server = |authenticator: web::Data<auth::Authenticator>| {
+        let service = service.clone();
+        let shutdown = shutdown.clone();
+        let control = control.clone();
+        let readiness = readiness.clone();
+        HttpServer::new(move || {
+            let service = service.clone();
+            App::new()
+                .app_data(shutdown.clone())
+                .app_data(control.clone())
+                .app_data(readiness.clone())
+                .app_data(authenticator.clone())
+                .configure(move |cfg| config::config(cfg, service.as_ref()))
+                .route("/", web::post().to(handler::handle))
+                .route("/batch", web::post().to(bulk::handle))
+                .route("/lineprotocol", web::post().to(lineprotocol::handle))
+                .route("/metrics", web::get().to(metrics::metrics))
+                .route("/metrics/snapshot", web::get().to(metrics::snapshot))
+                .route("/metrics/reset", web::post().to(metrics::reset))
+                .route("/schema", web::get().to(schema::schema))
+                .route("/errors/recent", web::get().to(recent::recent))
+                .route("/profile", web::get().to(profile::profile))
+                .configure(control::config)
+                .configure(admin::config)
+                .route("/health/readiness", web::get().to(startup::readiness))
+                .route("/health/liveness", web::get().to(HttpResponse::Ok))
+                .wrap(actix_web::middleware::Logger::default())
+        })
+        .workers(1)
+        .disable_signals()
+        .shutdown_timeout(shutdown_config.timeout_s)
+    };
+
+    let primary = match tls.acceptor()? {
+        Some(acceptor) => server(authenticator.clone()).bind_openssl(listener.addr(), acceptor)?,
+        None => server(authenticator.clone()).bind(listener.addr())?,
+    };
+    let mut servers = vec![primary.run()];
+
+    if let Some(addr) = &secondary.bind_addr {
+        let authenticator = auth::Authenticator::from_config(secondary.auth())?;
+        if !authenticator.is_enabled() {
+            log::warn!(
+                "No authentication configured for {}, accepting all events",
+                addr
+            );
+        }
+        let authenticator = web::Data::new(authenticator);
+        let server = match secondary.tls().acceptor()? {
+            Some(acceptor) => server(authenticator).bind_openssl(addr, acceptor)?,
+            None => server(authenticator).bind(addr)?,
+        };
+        servers.push(server.run());
+    }
+
+    // stop taking events, let in-flight requests finish
+
+    actix_rt::spawn({
+        let servers = servers.clone();
+        async move {
+            shutdown::signal().await;
+            log::info!("Shutting down");
+            shutdown.trigger();
+            for server in servers {
+                server.stop(true).await;
+            }
+        }
+    });
+
+    for result in futures::future::join_all(servers).await {
+        result?;
+    }
+
+    // write what was buffered, open transactions and windows are kept in the checkpoint instead
+
+    if let Some(service) = service
+        .as_ref()
+        .filter(|_| checkpoint_config.file.is_none())
+    {
+        if let Some(transactions) = &service.processor.transactions {
+            let timeout = Duration::from_secs(shutdown_config.timeout_s);
+            if actix_rt::time::timeout(timeout, transactions.drain(&service.processor))
+                .await
+                .is_err()
+            {
+                log::warn!("Timeout writing open transactions");
+            }
+        }
+    }
+
+    if let Some(aggregator) = service
+        .as_ref()
+        .and_then(|s| s.processor.aggregator.as_ref())
+        .filter(|_| checkpoint_config.file.is_none())
+    {
+        let timeout = Duration::from_secs(shutdown_config.timeout_s);
+        if actix_rt::time::timeout(timeout, aggregator.drain())
+            .await
+            .is_err()
+        {
+            log::warn!("Timeout writing aggregated points");
+        }
+    }
+
+    if let Some(batcher) = service.as_ref().and_then(|s| s.processor.batcher.as_ref()) {
+        let timeout = Duration::from_secs(shutdown_config.timeout_s);
+        if actix_rt::time::timeout(timeout, batcher.drain())
+            .await
+            .is_err()
+        {
+            log::warn!("Timeout writing buffered points");
+        }
+    }
+
+    if let Some(service) = &service {
+        if let Err(err) = checkpoint::save(&checkpoint_config, &service.processor) {
+            log::warn!("Failed to save checkpoint: {}", err);
+        }
+    }
+
+    Ok(())
 }
@@ -0,0 +1,262 @@
+use crate::auth::Authenticated;
+use actix_web::HttpResponse;
+use chrono::{DateTime, Utc};
+use lazy_static::lazy_static;
+use prometheus::proto::MetricType;
+use prometheus::{
+    register_histogram, register_int_counter_vec, register_int_gauge, register_int_gauge_vec,
+    Encoder, Histogram, IntCounterVec, IntGauge, IntGaugeVec, TextEncoder,
+};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+
+lazy_static! {
+    pub static ref EVENT_LATENCY: Histogram = register_histogram!(
+        "event_latency_seconds",
+        "Time between the event's time attribute and it being written",
+        vec![0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 300.0]
+    )
+    .unwrap();
+    pub static ref EVENT_AGE: Histogram = register_histogram!(
+        "event_age_seconds",
+        "Time between the event's time attribute and its arrival",
+        vec![
+            0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 300.0,
+            3600.0, 86400.0
+        ]
+    )
+    .unwrap();
+    pub static ref EVENTS_OUT_OF_ORDER: IntCounterVec = register_int_counter_vec!(
+        "events_out_of_order_total",
+        "Events older than the last one received from the same source",
+        &["type"]
+    )
+    .unwrap();
+    pub static ref EVENTS_SHED: IntCounterVec = register_int_counter_vec!(
+        "events_shed_total",
+        "Low priority events dropped while the write queue was backed up",
+        &["type"]
+    )
+    .unwrap();
+    pub static ref POINTS_UNCHANGED: IntCounterVec = register_int_counter_vec!(
+        "points_unchanged_total",
+        "Points skipped, as their values didn't change since the last one written",
+        &["measurement"]
+    )
+    .unwrap();
+    pub static ref POINTS_TOO_LATE: IntCounterVec = register_int_counter_vec!(
+        "points_too_late_total",
+        "Points dropped by the aggregation, their window already having been written",
+        &["measurement"]
+    )
+    .unwrap();
+    static ref PATH_VALUES: IntCounterVec = register_int_counter_vec!(
+        "path_values_total",
+        "What became of the configured fields and tags, by the result of selecting and converting their value",
+        &["measurement", "kind", "name", "result"]
+    )
+    .unwrap();
+    pub static ref INFLUXDB_ENDPOINT_ACTIVE: IntGaugeVec = register_int_gauge_vec!(
+        "influxdb_endpoint_active",
+        "Whether points are written to the primary or the fallback InfluxDB",
+        &["endpoint"]
+    )
+    .unwrap();
+    pub static ref INFLUXDB_FAILOVERS: IntCounterVec = register_int_counter_vec!(
+        "influxdb_failovers_total",
+        "Switches between the primary and the fallback InfluxDB, by the endpoint switched to",
+        &["endpoint"]
+    )
+    .unwrap();
+    pub static ref WRITE_QUEUE_DEPTH: IntGauge =
+        register_int_gauge!("write_queue_depth", "Points waiting to be written in batches")
+            .unwrap();
+    pub static ref WRITE_QUEUE_DROPPED: IntCounterVec = register_int_counter_vec!(
+        "write_queue_dropped_total",
+        "Points dropped or rejected because the write queue was full, by the queue policy",
+        &["policy"]
+    )
+    .unwrap();
+    pub static ref WRITES_IN_FLIGHT: IntGauge =
+        register_int_gauge!("writes_in_flight", "Events currently being written").unwrap();
+    static ref BASELINE: Mutex<Baseline> = Mutex::new(Baseline {
+        since: Utc::now(),
+        values: HashMap::new(),
+    });
+}
+
+/// Counts the results of a mapping's fields or tags, for spotting payloads which changed.
+///
+/// Values selected by `AUTO_FIELDS` aren't counted, their names depend on the payload.
+#[derive(Clone, Copy, Debug)]
+pub struct PathCounter<'a> {
+    measurement: &'a str,
+    kind: &'static str,
+}
+
+impl<'a> PathCounter<'a> {
+    pub fn fields(measurement: &'a str) -> Self {
+        Self {
+            measurement,
+            kind: "field",
+        }
+    }
+
+    pub fn tags(measurement: &'a str) -> Self {
+        Self {
+            measurement,
+            kind: "tag",
+        }
+    }
+
+    /// Count a result, like `matched`, `missing` or `conversion_failed`.
+    pub fn count(&self, name: &str, result: &str) {
+        PATH_VALUES
+            .with_label_values(&[self.measurement, self.kind, name, result])
+            .inc();
+    }
+}
+
+/// Values at the last reset, `/metrics` itself keeps counting as Prometheus expects.
+pub struct Baseline {
+    since: DateTime<Utc>,
+    values: HashMap<SampleKey, (f64, f64)>,
+}
+
+impl Baseline {
+    pub fn now() -> Self {
+        Self::from_samples(&gather())
+    }
+
+    fn from_samples(samples: &[(SampleKey, MetricType, (f64, f64))]) -> Self {
+        Self {
+            since: Utc::now(),
+            values: samples
+                .iter()
+                .map(|(key, _, value)| (key.clone(), *value))
+                .collect(),
+        }
+    }
+
+    /// What was counted since, gauges being their current value.
+    pub fn snapshot(&self) -> Snapshot {
+        self.delta(gather())
+    }
+
+    /// The snapshot, and the baseline to continue counting from.
+    pub fn next(&self) -> (Snapshot, Baseline) {
+        let samples = gather();
+        let next = Self::from_samples(&samples);
+        (self.delta(samples), next)
+    }
+
+    fn delta(&self, samples: Vec<(SampleKey, MetricType, (f64, f64))>) -> Snapshot {
+        let mut metrics: BTreeMap<String, Vec<Sample>> = BTreeMap::new();
+
+        for (key, r#type, (value, sum)) in samples {
+            let (base, base_sum) = match r#type {
+                MetricType::GAUGE => (0.0, 0.0),
+                _ => self.values.get(&key).copied().unwrap_or_default(),
+            };
+            let (name, labels) = key;
+            let sample = match r#type {
+                MetricType::HISTOGRAM => Sample {
+                    labels: labels.into_iter().collect(),
+                    value: None,
+                    count: Some((value - base) as u64),
+                    sum: Some(sum - base_sum),
+                },
+                _ => Sample {
+                    labels: labels.into_iter().collect(),
+                    value: Some(value - base),
+                    count: None,
+                    sum: None,
+                },
+            };
+            metrics.entry(name).or_default().push(sample);
+        }
+
+        Snapshot {
+            since: self.since,
+            metrics,
+        }
+    }
+}
+
+/// Name of the metric, and its labels.
+type SampleKey = (String, Vec<(String, String)>);
+
+#[derive(Debug, Serialize)]
+pub struct Snapshot {
+    since: DateTime<Utc>,
+    metrics: BTreeMap<String, Vec<Sample>>,
+}
+
+#[derive(Debug, Serialize)]
+struct Sample {
+    labels: BTreeMap<String, String>,
+    /// Of counters and gauges.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    value: Option<f64>,
+    /// Of histograms.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    count: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sum: Option<f64>,
+}
+
+/// Current values, the count and sum of histograms.
+fn gather() -> Vec<(SampleKey, MetricType, (f64, f64))> {
+    let mut samples = Vec::new();
+    for family in prometheus::gather() {
+        for metric in family.get_metric() {
+            let labels = metric
+                .get_label()
+                .iter()
+                .map(|label| (label.get_name().to_string(), label.get_value().to_string()))
+                .collect();
+            let value = match family.get_field_type() {
+                MetricType::COUNTER => (metric.get_counter().get_value(), 0.0),
+                MetricType::GAUGE => (metric.get_gauge().get_value(), 0.0),
+                MetricType::HISTOGRAM => {
+                    let histogram = metric.get_histogram();
+                    (
+                        histogram.get_sample_count() as f64,
+                        histogram.get_sample_sum(),
+                    )
+                }
+                _ => continue,
+            };
+            samples.push((
+                (family.get_name().to_string(), labels),
+                family.get_field_type(),
+                value,
+            ));
+        }
+    }
+    samples
+}
+
+pub async fn metrics() -> HttpResponse {
+    let encoder = TextEncoder::new();
+    let mut buffer = Vec::new();
+
+    match encoder.encode(&prometheus::gather(), &mut buffer) {
+        Ok(_) => HttpResponse::Ok()
+            .content_type(encoder.format_type())
+            .body(buffer),
+        Err(err) => HttpResponse::InternalServerError().body(err.to_string()),
+    }
+}
+
+/// Metrics as JSON, counting from the last reset, for asserting on them in tests.
+pub async fn snapshot() -> HttpResponse {
+    HttpResponse::Ok().json(BASELINE.lock().unwrap().snapshot())
+}
+
+pub async fn reset(_: Authenticated) -> HttpResponse {
+    *BASELINE.lock().unwrap() = Baseline::now();
+    log::info!("Metrics reset");
+    HttpResponse::NoContent().finish()
+}
@@ -0,0 +1,181 @@
+use crate::config::Processor;
+use crate::control::Control;
+use crate::shutdown::Shutdown;
+use crate::source::SourceConfig;
+use envconfig::Envconfig;
+use std::fmt;
+
+#[cfg_attr(not(feature = "mqtt"), allow(dead_code))]
+#[derive(Envconfig, Clone)]
+pub struct MqttConfig {
+    /// Like the MQTT integration endpoint of Drogue Cloud.
+    #[envconfig(from = "MQTT_HOST")]
+    pub host: Option<String>,
+    #[envconfig(from = "MQTT_PORT", default = "8883")]
+    pub port: u16,
+    #[envconfig(from = "MQTT_TLS", default = "true")]
+    pub tls: bool,
+    #[envconfig(from = "MQTT_USERNAME")]
+    pub username: Option<String>,
+    /// Password, or access token.
+    #[envconfig(from = "MQTT_PASSWORD")]
+    pub password: Option<String>,
+    /// Comma separated, like `app/my-app`.
+    #[envconfig(from = "MQTT_TOPICS", default = "")]
+    pub topics: String,
+    #[envconfig(from = "MQTT_CLIENT_ID", default = "drogue-influxdb-function")]
+    pub client_id: String,
+}
+
+impl fmt::Debug for MqttConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MqttConfig")
+            .field("host", &self.host)
+            .field("port", &self.port)
+            .field("tls", &self.tls)
+            .field("username", &self.username)
+            .field("topics", &self.topics)
+            .field("client_id", &self.client_id)
+            .finish()
+    }
+}
+
+/// Subscribe to an MQTT broker, and process what is published.
+///
+/// Messages are CloudEvents in structured mode, as Drogue Cloud sends them to MQTT 3.1.1
+/// clients, or plain payloads which become events of type `mqtt.message`. They are
+/// acknowledged once processed, events failing with a server side error are retried.
+#[cfg(feature = "mqtt")]
+pub fn start(
+    source: SourceConfig,
+    config: MqttConfig,
+    processor: actix_web::web::Data<Processor>,
+    shutdown: actix_web::web::Data<Shutdown>,
+    control: actix_web::web::Data<Control>,
+) -> anyhow::Result<()> {
+    use futures::{SinkExt, StreamExt};
+    use rumqttc::{Client, Event, MqttOptions, Packet, QoS, Transport};
+    use std::time::Duration;
+
+    let host = match config.host {
+        Some(host) => host,
+        None => anyhow::bail!("SOURCE=mqtt requires MQTT_HOST"),
+    };
+    let topics = crate::filter::split(Some(config.topics));
+    if topics.is_empty() {
+        anyhow::bail!("SOURCE=mqtt requires MQTT_TOPICS");
+    }
+
+    let mut options = MqttOptions::new(config.client_id, host.clone(), config.port);
+    options
+        .set_manual_acks(true)
+        .set_keep_alive(Duration::from_secs(30));
+    if let Some(username) = config.username {
+        options.set_credentials(username, config.password.unwrap_or_default());
+    }
+    if config.tls {
+        options.set_transport(Transport::tls_with_default_config());
+    }
+
+    let backoff = source.retry_backoff();
+    let (client, mut connection) = Client::new(options, 10);
+    let (mut tx, mut rx) = futures::channel::mpsc::channel(10);
+
+    // the client brings its own runtime, which needs a thread of its own
+    std::thread::spawn({
+        let client = client.clone();
+        move || {
+            for notification in connection.iter() {
+                match notification {
+                    Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                        log::info!("Connected to {}, subscribing to {:?}", host, topics);
+                        for topic in &topics {
+                            if let Err(err) = client.try_subscribe(topic, QoS::AtLeastOnce) {
+                                log::warn!("Failed to subscribe to {}: {}", topic, err);
+                            }
+                        }
+                    }
+                    Ok(Event::Incoming(Packet::Publish(publish))) => {
+                        if futures::executor::block_on(tx.send(publish)).is_err() {
+                            break;
+                        }
+                    }
+                    Ok(_) => {}
+                    Err(err) => {
+                        if tx.is_closed() {
+                            break;
+                        }
+                        log::warn!("MQTT connection failed: {}", err);
+                        std::thread::sleep(backoff);
+                    }
+                }
+            }
+        }
+    });
+
+    actix_rt::spawn(async move {
+        while !shutdown.is_triggered() {
+            if control.is_paused() {
+                actix_rt::time::delay_for(Duration::from_secs(1)).await;
+                continue;
+            }
+
+            // wake up now and then, to notice the shutdown
+            let publish = match actix_rt::time::timeout(Duration::from_secs(1), rx.next()).await {
+                Ok(Some(publish)) => publish,
+                Ok(None) => break,
+                Err(_) => continue,
+            };
+
+            let processed = match to_event(&publish) {
+                Ok(event) => crate::source::deliver(event, &processor, &shutdown, backoff).await,
+                Err(err) => {
+                    // retrying won't make it any better
+                    log::warn!("Skipping invalid message on {}: {}", publish.topic, err);
+                    true
+                }
+            };
+
+            if processed {
+                if let Err(err) = client.try_ack(&publish) {
+                    log::warn!("Failed to acknowledge message: {}", err);
+                }
+            }
+        }
+
+        drop(rx);
+        let _ = client.try_disconnect();
+        log::info!("Stopped consuming events");
+    });
+
+    Ok(())
+}
+
+#[cfg(feature = "mqtt")]
+fn to_event(publish: &rumqttc::Publish) -> anyhow::Result<cloudevents::Event> {
+    use cloudevents::{EventBuilder, EventBuilderV10};
+
+    if let Ok(event) = serde_json::from_slice(&publish.payload) {
+        return Ok(event);
+    }
+
+    let json: serde_json::Value = serde_json::from_slice(&publish.payload)?;
+    Ok(EventBuilderV10::new()
+        .id(format!("{:016x}", rand::random::<u64>()))
+        .source(publish.topic.as_str())
+        .ty("mqtt.message")
+        .time(chrono::Utc::now())
+        .data("application/json", json)
+        .build()?)
+}
+
+#[cfg(not(feature = "mqtt"))]
+pub fn start(
+    _: SourceConfig,
+    _: MqttConfig,
+    _: actix_web::web::Data<Processor>,
+    _: actix_web::web::Data<Shutdown>,
+    _: actix_web::web::Data<Control>,
+) -> anyhow::Result<()> {
+    anyhow::bail!("SOURCE=mqtt requires the 'mqtt' feature")
+}
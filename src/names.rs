@@ -0,0 +1,263 @@
+use crate::error::ServiceError;
+use crate::registry::Registry;
+use cloudevents::Event;
+use envconfig::Envconfig;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+#[derive(Envconfig, Clone, Debug)]
+pub struct NamesConfig {
+    /// Where friendly names come from: `file`, `http` or `registry`, disabled if unset.
+    #[envconfig(from = "NAMES_SOURCE")]
+    pub source: Option<NameSource>,
+    /// Tag to write the friendly name to.
+    #[envconfig(from = "NAMES_TAG", default = "name")]
+    pub tag: String,
+    /// JSON path into the event, selecting the raw identifier, like a device EUI.
+    #[envconfig(from = "NAMES_KEY_PATH", default = "$.device")]
+    pub key_path: String,
+    /// JSON object of identifiers and their names, with `NAMES_SOURCE=file`.
+    #[envconfig(from = "NAMES_FILE")]
+    pub file: Option<String>,
+    /// Responding with the name as plain text, like `http://names/devices/{id}`.
+    #[envconfig(from = "NAMES_URL")]
+    pub url: Option<String>,
+    /// Label of the device holding its name, with `NAMES_SOURCE=registry`.
+    #[envconfig(from = "NAMES_LABEL", default = "name")]
+    pub label: String,
+    #[envconfig(from = "NAMES_CACHE_TTL_S", default = "300")]
+    pub cache_ttl_s: u64,
+    #[envconfig(from = "NAMES_CACHE_SIZE", default = "10000")]
+    pub cache_size: usize,
+    /// What to tag when there is no name, or looking it up failed.
+    #[envconfig(from = "NAMES_FALLBACK", default = "id")]
+    pub fallback: NameFallback,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NameSource {
+    File,
+    Http,
+    /// Labels of the devices, in the application of the event, using the `REGISTRY_*`
+    /// settings.
+    Registry,
+}
+
+impl FromStr for NameSource {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "file" => Ok(NameSource::File),
+            "http" => Ok(NameSource::Http),
+            "registry" => Ok(NameSource::Registry),
+            _ => anyhow::bail!("Unknown name source: {}", s),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NameFallback {
+    /// Tag the raw identifier instead.
+    Id,
+    /// Leave out the tag.
+    Skip,
+    /// Fail the event, so that it is retried later, unknown names still use the identifier.
+    Reject,
+}
+
+impl FromStr for NameFallback {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "id" => Ok(NameFallback::Id),
+            "skip" => Ok(NameFallback::Skip),
+            "reject" => Ok(NameFallback::Reject),
+            _ => anyhow::bail!("Unknown fallback: {}", s),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+enum Lookup {
+    File(Arc<HashMap<String, String>>),
+    Http {
+        url: String,
+        client: reqwest::Client,
+    },
+    Registry {
+        registry: Registry,
+        label: String,
+    },
+}
+
+type Cache = HashMap<String, (Instant, Option<String>)>;
+
+/// Translates raw device identifiers into stable, friendly names, which are added as a tag.
+///
+/// Names are cached for a while, including the absence of one. Failed lookups aren't cached.
+#[derive(Clone, Debug)]
+pub struct Names {
+    lookup: Lookup,
+    pub tag: String,
+    key_path: jsonpath_lib::Compiled,
+    ttl: Duration,
+    cache_size: usize,
+    fallback: NameFallback,
+    cache: Arc<Mutex<Cache>>,
+}
+
+impl Names {
+    pub fn from_config(
+        config: NamesConfig,
+        registry: Option<Registry>,
+    ) -> anyhow::Result<Option<Self>> {
+        let lookup = match config.source {
+            None => return Ok(None),
+            Some(NameSource::File) => {
+                let file = match config.file {
+                    Some(file) => file,
+                    None => anyhow::bail!("NAMES_SOURCE=file requires NAMES_FILE"),
+                };
+                let names = serde_json::from_slice(&std::fs::read(&file)?)
+                    .map_err(|err| anyhow::anyhow!("Failed to parse {}: {}", file, err))?;
+                Lookup::File(Arc::new(names))
+            }
+            Some(NameSource::Http) => match config.url {
+                Some(url) if url.contains("{id}") => Lookup::Http {
+                    url,
+                    client: reqwest::Client::new(),
+                },
+                Some(_) => anyhow::bail!("NAMES_URL must contain {{id}}"),
+                None => anyhow::bail!("NAMES_SOURCE=http requires NAMES_URL"),
+            },
+            Some(NameSource::Registry) => match registry {
+                Some(registry) => Lookup::Registry {
+                    registry,
+                    label: config.label,
+                },
+                None => anyhow::bail!("NAMES_SOURCE=registry requires REGISTRY_URL"),
+            },
+        };
+
+        let key_path = jsonpath_lib::Compiled::compile(&config.key_path)
+            .map_err(|err| anyhow::anyhow!("Failed to parse NAMES_KEY_PATH: {}", err))?;
+
+        Ok(Some(Self {
+            lookup,
+            tag: config.tag,
+            key_path,
+            ttl: Duration::from_secs(config.cache_ttl_s),
+            cache_size: config.cache_size,
+            fallback: config.fallback,
+            cache: Default::default(),
+        }))
+    }
+
+    /// The name to tag, `None` if the event has no identifier or the fallback skips it.
+    pub async fn resolve(
+        &self,
+        event: &Event,
+        event_json: &Value,
+    ) -> Result<Option<String>, ServiceError> {
+        let id = match self.key_path.select(event_json).ok().as_deref() {
+            Some([Value::String(s)]) => s.clone(),
+            Some([value]) => value.to_string(),
+            _ => return Ok(None),
+        };
+
+        let key = self.cache_key(&id, event);
+        let name = match self.cached(&key) {
+            Some(name) => Ok(name),
+            None => match self.lookup(&id, event).await {
+                Ok(name) => {
+                    self.remember(&key, name.clone());
+                    Ok(name)
+                }
+                Err(err) => Err(err),
+            },
+        };
+
+        match (name, self.fallback) {
+            (Ok(Some(name)), _) => Ok(Some(name)),
+            (Ok(None), NameFallback::Skip) => Ok(None),
+            (Ok(None), _) => Ok(Some(id)),
+            (Err(err), NameFallback::Reject) => Err(ServiceError::NameLookupFailed {
+                details: format!("{}: {:#}", id, err),
+            }),
+            (Err(err), fallback) => {
+                log::warn!("Failed to look up the name of {}: {:#}", id, err);
+                Ok(Some(id).filter(|_| fallback == NameFallback::Id))
+            }
+        }
+    }
+
+    fn cached(&self, key: &str) -> Option<Option<String>> {
+        let mut cache = self.cache.lock().unwrap();
+        match cache.get(key) {
+            Some((time, name)) if time.elapsed() < self.ttl => Some(name.clone()),
+            Some(_) => {
+                cache.remove(key);
+                None
+            }
+            None => None,
+        }
+    }
+
+    fn remember(&self, key: &str, name: Option<String>) {
+        if matches!(self.lookup, Lookup::File(_)) || self.ttl.as_secs() == 0 {
+            return;
+        }
+
+        let mut cache = self.cache.lock().unwrap();
+        if !cache.contains_key(key) && cache.len() >= self.cache_size {
+            // make room, we don't track which one is the oldest
+            if let Some(evict) = cache.keys().next().cloned() {
+                cache.remove(&evict);
+            }
+        }
+        cache.insert(key.to_string(), (Instant::now(), name));
+    }
+
+    async fn lookup(&self, id: &str, event: &Event) -> anyhow::Result<Option<String>> {
+        match &self.lookup {
+            Lookup::File(names) => Ok(names.get(id).cloned()),
+            Lookup::Http { url, client } => {
+                let response = client.get(&url.replace("{id}", &encode(id))).send().await?;
+                if response.status() == reqwest::StatusCode::NOT_FOUND {
+                    return Ok(None);
+                }
+                let name = response.error_for_status()?.text().await?;
+                Ok(Some(name.trim().to_string()).filter(|name| !name.is_empty()))
+            }
+            Lookup::Registry { registry, label } => match crate::denylist::device(event) {
+                Some((application, _)) => registry.label(&application, id, label).await,
+                None => Ok(None),
+            },
+        }
+    }
+
+    /// Devices are only unique within their application.
+    fn cache_key(&self, id: &str, event: &Event) -> String {
+        match (&self.lookup, crate::denylist::device(event)) {
+            (Lookup::Registry { .. }, Some((application, _))) => format!("{}/{}", application, id),
+            _ => id.to_string(),
+        }
+    }
+}
+
+/// Percent encode everything but unreserved characters, for a path segment.
+fn encode(id: &str) -> String {
+    id.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (b as char).to_string()
+            }
+            b => format!("%{:02X}", b),
+        })
+        .collect()
+}
@@ -0,0 +1,57 @@
+use crate::error::ServiceError;
+use crate::timestamp::PayloadTime;
+use chrono::{DateTime, Utc};
+use serde_json::Value;
+
+/// Whether the payload is newline delimited JSON, one reading per line.
+pub fn is_ndjson(content_type: Option<&str>) -> bool {
+    let mime = content_type
+        .and_then(|content_type| content_type.split(';').next())
+        .unwrap_or_default()
+        .trim()
+        .to_lowercase();
+    mime == "application/x-ndjson" || mime == "application/jsonl"
+}
+
+/// Parse each non-empty line, into an array of readings.
+pub fn decode<F>(data: &[u8], parse: F) -> Result<Value, ServiceError>
+where
+    F: Fn(&[u8]) -> Result<Value, ServiceError>,
+{
+    data.split(|b| *b == b'\n')
+        .enumerate()
+        .filter(|(_, line)| !line.iter().all(u8::is_ascii_whitespace))
+        .map(|(index, line)| {
+            parse(line).map_err(|err| match err {
+                ServiceError::PayloadParseError { details } => ServiceError::PayloadParseError {
+                    details: format!("Invalid JSON on line {}: {}", index + 1, details),
+                },
+                err => err,
+            })
+        })
+        .collect::<Result<_, _>>()
+        .map(Value::Array)
+}
+
+/// The readings of a decoded payload, each taking its time from `TIMESTAMP_PATH`.
+pub fn split(
+    json: Value,
+    time: DateTime<Utc>,
+    payload_time: Option<&PayloadTime>,
+) -> Result<Vec<(Value, DateTime<Utc>)>, ServiceError> {
+    let readings = match json {
+        Value::Array(readings) => readings,
+        json => return Ok(vec![(json, time)]),
+    };
+
+    readings
+        .into_iter()
+        .map(|reading| {
+            let time = match payload_time {
+                Some(payload_time) => payload_time.time(&reading)?.unwrap_or(time),
+                None => time,
+            };
+            Ok((reading, time))
+        })
+        .collect()
+}
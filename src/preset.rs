@@ -0,0 +1,101 @@
+use crate::error::ServiceError;
+use chrono::{DateTime, Utc};
+use serde_json::Value;
+use std::str::FromStr;
+
+/// Known payload envelopes, which are unwrapped before extracting values.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Preset {
+    /// The Things Stack (v3) uplink message.
+    TtnV3,
+    /// ChirpStack (v4) uplink event.
+    ChirpStackV4,
+    /// Metrics of Telegraf, which aren't unwrapped, but written as they are.
+    Telegraf,
+}
+
+/// The result of unwrapping an envelope.
+#[derive(Debug)]
+pub struct Unwrapped {
+    /// Source of the fields.
+    pub payload: Value,
+    pub tags: Vec<(&'static str, String)>,
+    pub time: Option<DateTime<Utc>>,
+}
+
+impl FromStr for Preset {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "ttn-v3" | "ttn" => Ok(Preset::TtnV3),
+            "chirpstack-v4" | "chirpstack" => Ok(Preset::ChirpStackV4),
+            "telegraf" => Ok(Preset::Telegraf),
+            _ => anyhow::bail!("Unknown payload preset: {}", s),
+        }
+    }
+}
+
+impl Preset {
+    pub fn unwrap(&self, json: Value) -> Result<Unwrapped, ServiceError> {
+        match self {
+            Preset::TtnV3 => Ok(Unwrapped {
+                tags: tags(
+                    &json,
+                    &[
+                        ("device_id", "/end_device_ids/device_id"),
+                        (
+                            "application_id",
+                            "/end_device_ids/application_ids/application_id",
+                        ),
+                        ("dev_eui", "/end_device_ids/dev_eui"),
+                    ],
+                ),
+                time: time(&json, "/received_at"),
+                payload: payload(json, "/uplink_message/decoded_payload")?,
+            }),
+            Preset::ChirpStackV4 => Ok(Unwrapped {
+                tags: tags(
+                    &json,
+                    &[
+                        ("dev_eui", "/deviceInfo/devEui"),
+                        ("device_name", "/deviceInfo/deviceName"),
+                        ("application_name", "/deviceInfo/applicationName"),
+                    ],
+                ),
+                time: time(&json, "/time"),
+                payload: payload(json, "/object")?,
+            }),
+            Preset::Telegraf => Ok(Unwrapped {
+                tags: Vec::new(),
+                time: None,
+                payload: json,
+            }),
+        }
+    }
+}
+
+fn payload(mut json: Value, pointer: &str) -> Result<Value, ServiceError> {
+    json.pointer_mut(pointer)
+        .map(Value::take)
+        .ok_or_else(|| ServiceError::PayloadParseError {
+            details: format!("Missing payload in envelope: {}", pointer),
+        })
+}
+
+fn tags(json: &Value, tags: &[(&'static str, &str)]) -> Vec<(&'static str, String)> {
+    tags.iter()
+        .filter_map(|(tag, pointer)| match json.pointer(pointer)? {
+            Value::String(s) => Some((*tag, s.clone())),
+            Value::Null => None,
+            value => Some((*tag, value.to_string())),
+        })
+        .collect()
+}
+
+fn time(json: &Value, pointer: &str) -> Option<DateTime<Utc>> {
+    json.pointer(pointer)
+        .and_then(Value::as_str)
+        .and_then(|time| DateTime::parse_from_rfc3339(time).ok())
+        .map(|time| time.with_timezone(&Utc))
+}
@@ -0,0 +1,95 @@
+use envconfig::Envconfig;
+use std::fmt;
+
+/// All problems found in the configuration, so that they can be fixed in one go.
+#[derive(Debug, Default)]
+pub struct Problems {
+    problems: Vec<Problem>,
+}
+
+#[derive(Debug)]
+pub struct Problem {
+    /// The environment variable at fault, if known.
+    pub key: Option<String>,
+    pub message: String,
+}
+
+impl Problems {
+    pub fn is_empty(&self) -> bool {
+        self.problems.is_empty()
+    }
+
+    pub fn add(&mut self, key: Option<&str>, message: impl fmt::Display) {
+        self.problems.push(Problem {
+            key: key.map(ToString::to_string),
+            message: message.to_string(),
+        });
+    }
+
+    /// Record the error of a result, if any.
+    pub fn check<T, E>(&mut self, result: Result<T, E>) -> Option<T>
+    where
+        E: Into<anyhow::Error>,
+    {
+        self.check_key(None, result)
+    }
+
+    /// Record the error of a result, caused by the given environment variable.
+    pub fn check_key<T, E>(&mut self, key: Option<&str>, result: Result<T, E>) -> Option<T>
+    where
+        E: Into<anyhow::Error>,
+    {
+        match result {
+            Ok(value) => Some(value),
+            Err(err) => {
+                let err: anyhow::Error = err.into();
+                // include the causes, like the parse error of a JSON path
+                self.add(key, format!("{:#}", err));
+                None
+            }
+        }
+    }
+
+    /// Read a configuration from the environment.
+    pub fn env<C: Envconfig>(&mut self) -> Option<C> {
+        self.check(C::init_from_env())
+    }
+
+    /// Read a configuration from the environment, and build something from it.
+    pub fn build<C, T, F>(&mut self, f: F) -> Option<T>
+    where
+        C: Envconfig,
+        F: FnOnce(C) -> anyhow::Result<T>,
+    {
+        let config = self.env::<C>()?;
+        self.check(f(config))
+    }
+
+    pub fn into_result(self) -> Result<(), Self> {
+        if self.is_empty() {
+            Ok(())
+        } else {
+            Err(self)
+        }
+    }
+}
+
+/// A component, after checking that there are no problems, which means it was built.
+pub fn built<T>(component: Option<T>) -> anyhow::Result<T> {
+    component.ok_or_else(|| anyhow::anyhow!("Configuration failed without a problem"))
+}
+
+impl fmt::Display for Problems {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} configuration problem(s):", self.problems.len())?;
+        for problem in &self.problems {
+            match &problem.key {
+                Some(key) => write!(f, "\n  {}: {}", key, problem.message)?,
+                None => write!(f, "\n  {}", problem.message)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for Problems {}
@@ -0,0 +1,88 @@
+use crate::auth::Authenticated;
+use crate::config::Processor;
+use actix_web::{web, HttpResponse};
+use chrono::{DateTime, Duration, Utc};
+use envconfig::Envconfig;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+
+#[derive(Envconfig, Clone, Debug)]
+pub struct ProfileConfig {
+    /// Start over once the statistics of a field are older than this, never when zero.
+    #[envconfig(from = "PROFILE_WINDOW_S", default = "3600")]
+    pub window_s: i64,
+}
+
+/// Statistics of numeric field values, for `GET /profile`.
+#[derive(Clone, Debug)]
+pub struct Profile {
+    window: Option<Duration>,
+    tables: Arc<Mutex<BTreeMap<String, BTreeMap<String, Stats>>>>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+struct Stats {
+    since: DateTime<Utc>,
+    count: u64,
+    min: f64,
+    max: f64,
+    mean: f64,
+    last: f64,
+}
+
+impl Stats {
+    fn new(value: f64) -> Self {
+        Self {
+            since: Utc::now(),
+            count: 1,
+            min: value,
+            max: value,
+            mean: value,
+            last: value,
+        }
+    }
+
+    fn add(&mut self, value: f64) {
+        self.count += 1;
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+        self.mean += (value - self.mean) / self.count as f64;
+        self.last = value;
+    }
+}
+
+impl Profile {
+    pub fn from_config(config: ProfileConfig) -> Self {
+        Self {
+            window: Some(Duration::seconds(config.window_s)).filter(|w| *w > Duration::zero()),
+            tables: Default::default(),
+        }
+    }
+
+    pub fn record(&self, table: &str, values: &HashMap<String, f64>) {
+        if values.is_empty() {
+            return;
+        }
+
+        let mut tables = self.tables.lock().unwrap();
+        let fields = tables.entry(table.to_string()).or_default();
+        let now = Utc::now();
+
+        for (field, value) in values {
+            match fields.get_mut(field) {
+                Some(stats) if self.window.is_none_or(|w| now - stats.since < w) => {
+                    stats.add(*value)
+                }
+                _ => {
+                    fields.insert(field.clone(), Stats::new(*value));
+                }
+            }
+        }
+    }
+}
+
+pub async fn profile(_: Authenticated, processor: web::Data<Processor>) -> HttpResponse {
+    let tables = processor.profile.tables.lock().unwrap().clone();
+    HttpResponse::Ok().json(tables)
+}
@@ -0,0 +1,380 @@
+use crate::influx::{Flavor, InfluxClient};
+use envconfig::Envconfig;
+use reqwest::Method;
+use serde::Deserialize;
+use serde_json::json;
+use std::fs;
+use std::str::FromStr;
+
+#[derive(Envconfig, Clone, Debug)]
+pub struct ProvisionConfig {
+    /// Create the database (or bucket) at startup, if it's missing.
+    #[envconfig(from = "INFLUXDB_AUTO_CREATE", default = "false")]
+    pub auto_create: bool,
+    /// Retention of created data, like `30d`, infinite if not set.
+    #[envconfig(from = "INFLUXDB_RETENTION")]
+    pub retention: Option<Retention>,
+    /// Retention policy to create as the database's default, InfluxDB 1.x only.
+    #[envconfig(from = "INFLUXDB_RETENTION_POLICY", default = "autogen")]
+    pub retention_policy: String,
+    /// Organization of the bucket, creates an InfluxDB 2 bucket instead of a database.
+    #[envconfig(from = "INFLUXDB_ORG")]
+    pub org: Option<String>,
+    /// JSON file declaring retention policies, continuous queries and tasks to create.
+    #[envconfig(from = "INFLUXDB_BOOTSTRAP_FILE")]
+    pub bootstrap_file: Option<String>,
+}
+
+/// What to create besides the database, like downsampling of the written data.
+///
+/// Retention policies and continuous queries are InfluxDB 1.x only, tasks InfluxDB 2 only.
+/// Existing ones are updated to match.
+#[derive(Deserialize, Clone, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Bootstrap {
+    pub retention_policies: Vec<RetentionPolicy>,
+    pub continuous_queries: Vec<ContinuousQuery>,
+    pub tasks: Vec<Task>,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct RetentionPolicy {
+    pub name: String,
+    /// Like `INFLUXDB_RETENTION`, `INF` if not set.
+    pub duration: Option<String>,
+    #[serde(default = "default_replication")]
+    pub replication: u32,
+    pub shard_duration: Option<String>,
+    #[serde(default)]
+    pub default: bool,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct ContinuousQuery {
+    pub name: String,
+    /// Like `EVERY 1h FOR 2h`.
+    pub resample: Option<String>,
+    /// The `SELECT ... INTO ... GROUP BY time(...)` statement.
+    pub query: String,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Task {
+    pub name: String,
+    /// Like `1h`.
+    pub every: String,
+    /// The script, without the `option task` line.
+    pub flux: String,
+}
+
+fn default_replication() -> u32 {
+    1
+}
+
+/// Duration in seconds, parsed from `<number><unit>` with the units `s`, `m`, `h`, `d` and `w`.
+#[derive(Clone, Copy, Debug)]
+pub struct Retention(u64);
+
+impl Retention {
+    pub fn as_secs(&self) -> u64 {
+        self.0
+    }
+}
+
+impl FromStr for Retention {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+        let (value, unit) = s.split_at(split);
+        let value: u64 = value
+            .parse()
+            .map_err(|_| anyhow::anyhow!("Invalid retention: {}", s))?;
+        let factor = match unit {
+            "s" => 1,
+            "m" => 60,
+            "h" => 60 * 60,
+            "d" => 24 * 60 * 60,
+            "w" => 7 * 24 * 60 * 60,
+            _ => anyhow::bail!("Invalid retention unit: {}", s),
+        };
+        Ok(Retention(value * factor))
+    }
+}
+
+/// Create what the function writes to, failures are only logged.
+pub async fn run(config: ProvisionConfig, client: &InfluxClient) {
+    let db = client.database();
+
+    if client.flavor() == Flavor::VictoriaMetrics {
+        if config.auto_create || config.bootstrap_file.is_some() {
+            log::warn!("VictoriaMetrics has nothing to create, skipping provisioning");
+        }
+        return;
+    }
+
+    if config.auto_create {
+        let result = match &config.org {
+            Some(org) => create_bucket(client, org, db, config.retention).await,
+            None => create_database(client, db, &config).await,
+        };
+        if let Err(err) = result {
+            log::error!("Failed to create {}: {}", db, err);
+        }
+    }
+
+    if let Some(file) = &config.bootstrap_file {
+        if let Err(err) = bootstrap(client, file, config.org.as_deref()).await {
+            log::error!("Failed to bootstrap {} from {}: {}", db, file, err);
+        }
+    }
+}
+
+async fn bootstrap(client: &InfluxClient, file: &str, org: Option<&str>) -> anyhow::Result<()> {
+    let bootstrap: Bootstrap = serde_json::from_slice(&fs::read(file)?)?;
+    let db = client.database();
+
+    match org {
+        Some(org) => {
+            if !bootstrap.retention_policies.is_empty() || !bootstrap.continuous_queries.is_empty()
+            {
+                anyhow::bail!("Retention policies and continuous queries require InfluxDB 1.x");
+            }
+            for task in &bootstrap.tasks {
+                create_task(client, org, task).await?;
+            }
+        }
+        None => {
+            if !bootstrap.tasks.is_empty() {
+                anyhow::bail!("Tasks require InfluxDB 2 (INFLUXDB_ORG)");
+            }
+            for policy in &bootstrap.retention_policies {
+                create_retention_policy(client, db, policy).await?;
+            }
+            for query in &bootstrap.continuous_queries {
+                create_continuous_query(client, db, query).await?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+async fn create_retention_policy(
+    client: &InfluxClient,
+    db: &str,
+    policy: &RetentionPolicy,
+) -> anyhow::Result<()> {
+    let mut statement = format!(
+        "{} ON {} DURATION {} REPLICATION {}",
+        quote(&policy.name),
+        quote(db),
+        duration(policy.duration.as_deref())?,
+        policy.replication
+    );
+    if let Some(shard_duration) = &policy.shard_duration {
+        statement.push_str(&format!(
+            " SHARD DURATION {}",
+            duration(Some(shard_duration))?
+        ));
+    }
+    if policy.default {
+        statement.push_str(" DEFAULT");
+    }
+
+    if client
+        .query(&format!("CREATE RETENTION POLICY {}", statement))
+        .await
+        .is_err()
+    {
+        client
+            .query(&format!("ALTER RETENTION POLICY {}", statement))
+            .await?;
+    }
+    log::info!("Applied retention policy {}", policy.name);
+
+    Ok(())
+}
+
+async fn create_continuous_query(
+    client: &InfluxClient,
+    db: &str,
+    query: &ContinuousQuery,
+) -> anyhow::Result<()> {
+    let resample = match &query.resample {
+        Some(resample) => format!(" RESAMPLE {}", resample),
+        None => String::new(),
+    };
+    let create = format!(
+        "CREATE CONTINUOUS QUERY {} ON {}{} BEGIN {} END",
+        quote(&query.name),
+        quote(db),
+        resample,
+        query.query
+    );
+
+    // a no-op if it exists unchanged, continuous queries can't be altered otherwise
+    if client.query(&create).await.is_err() {
+        client
+            .query(&format!(
+                "DROP CONTINUOUS QUERY {} ON {}",
+                quote(&query.name),
+                quote(db)
+            ))
+            .await?;
+        client.query(&create).await?;
+    }
+    log::info!("Applied continuous query {}", query.name);
+
+    Ok(())
+}
+
+async fn create_task(client: &InfluxClient, org: &str, task: &Task) -> anyhow::Result<()> {
+    task.every.parse::<Retention>()?;
+    let flux = format!(
+        "option task = {{name: {}, every: {}}}\n\n{}",
+        json!(task.name),
+        task.every,
+        task.flux
+    );
+
+    let existing = client
+        .v2(
+            Method::GET,
+            "/api/v2/tasks",
+            &[("org", org), ("name", &task.name)],
+            None,
+        )
+        .await?;
+    match existing["tasks"][0]["id"].as_str() {
+        Some(id) => {
+            client
+                .v2(
+                    Method::PATCH,
+                    &format!("/api/v2/tasks/{}", id),
+                    &[],
+                    Some(&json!({ "flux": flux })),
+                )
+                .await?;
+        }
+        None => {
+            client
+                .v2(
+                    Method::POST,
+                    "/api/v2/tasks",
+                    &[],
+                    Some(&json!({ "org": org, "flux": flux })),
+                )
+                .await?;
+        }
+    }
+    log::info!("Applied task {}", task.name);
+
+    Ok(())
+}
+
+/// Validate a duration, which InfluxQL and Flux take as a literal.
+fn duration(value: Option<&str>) -> anyhow::Result<String> {
+    match value {
+        None | Some("INF") => Ok("INF".to_string()),
+        Some(value) => value.parse::<Retention>().map(|_| value.to_string()),
+    }
+}
+
+async fn create_database(
+    client: &InfluxClient,
+    db: &str,
+    config: &ProvisionConfig,
+) -> anyhow::Result<()> {
+    // a no-op if the database exists
+    client
+        .query(&format!("CREATE DATABASE {}", quote(db)))
+        .await?;
+    log::info!("Database {} is ready", db);
+
+    if let Some(Retention(seconds)) = config.retention {
+        let policy = format!(
+            "{} ON {} DURATION {}s REPLICATION 1",
+            quote(&config.retention_policy),
+            quote(db),
+            seconds
+        );
+        let created = client
+            .query(&format!("CREATE RETENTION POLICY {} DEFAULT", policy))
+            .await;
+        if created.is_err() {
+            // most likely it exists, like the default "autogen"
+            client
+                .query(&format!("ALTER RETENTION POLICY {} DEFAULT", policy))
+                .await?;
+        }
+        log::info!(
+            "Applied retention policy {} ({}s)",
+            config.retention_policy,
+            seconds
+        );
+    }
+
+    Ok(())
+}
+
+async fn create_bucket(
+    client: &InfluxClient,
+    org: &str,
+    bucket: &str,
+    retention: Option<Retention>,
+) -> anyhow::Result<()> {
+    let existing = client
+        .v2(
+            Method::GET,
+            "/api/v2/buckets",
+            &[("org", org), ("name", bucket)],
+            None,
+        )
+        .await?;
+    if existing["buckets"]
+        .as_array()
+        .is_some_and(|buckets| !buckets.is_empty())
+    {
+        log::info!("Bucket {} exists", bucket);
+        return Ok(());
+    }
+
+    let orgs = client
+        .v2(Method::GET, "/api/v2/orgs", &[("org", org)], None)
+        .await?;
+    let org_id = orgs["orgs"][0]["id"]
+        .as_str()
+        .ok_or_else(|| anyhow::anyhow!("Unknown organization: {}", org))?;
+
+    let retention_rules = match retention {
+        Some(Retention(seconds)) => json!([{ "type": "expire", "everySeconds": seconds }]),
+        None => json!([]),
+    };
+    client
+        .v2(
+            Method::POST,
+            "/api/v2/buckets",
+            &[],
+            Some(&json!({
+                "orgID": org_id,
+                "name": bucket,
+                "retentionRules": retention_rules,
+            })),
+        )
+        .await?;
+    log::info!("Created bucket {}", bucket);
+
+    Ok(())
+}
+
+/// Quote an identifier for InfluxQL.
+fn quote(identifier: &str) -> String {
+    format!(
+        "\"{}\"",
+        identifier.replace('\\', "\\\\").replace('"', "\\\"")
+    )
+}
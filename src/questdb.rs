@@ -0,0 +1,147 @@
+use crate::sink::Sink;
+use envconfig::Envconfig;
+use futures::future::LocalBoxFuture;
+use influxdb::{Error, WriteQuery};
+
+#[cfg_attr(not(feature = "questdb"), allow(dead_code))]
+#[derive(Envconfig, Clone, Debug)]
+pub struct QuestDbConfig {
+    #[envconfig(from = "QUESTDB_HOST", default = "localhost")]
+    pub host: String,
+    /// Port of the ILP (InfluxDB line protocol) listener.
+    #[envconfig(from = "QUESTDB_ILP_PORT", default = "9009")]
+    pub port: u16,
+}
+
+/// Writes points to QuestDB, as line protocol over a TCP connection to its ILP listener.
+///
+/// Measurements become tables, tags symbol columns and fields columns, which QuestDB creates
+/// as they show up. Timestamps are sent in nanoseconds, as ILP over TCP expects them, retention
+/// policies are ignored. Writes aren't confirmed, QuestDB closes the connection on invalid
+/// lines instead, which is noticed when writing next.
+#[cfg(feature = "questdb")]
+pub struct QuestDbSink {
+    config: QuestDbConfig,
+    stream: futures::lock::Mutex<Option<actix_rt::net::TcpStream>>,
+}
+
+#[cfg(feature = "questdb")]
+impl std::fmt::Debug for QuestDbSink {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("QuestDbSink")
+            .field("config", &self.config)
+            .finish()
+    }
+}
+
+#[cfg(feature = "questdb")]
+impl QuestDbSink {
+    pub fn from_config(config: QuestDbConfig) -> anyhow::Result<Self> {
+        Ok(Self {
+            config,
+            stream: Default::default(),
+        })
+    }
+
+    /// Connect lazily, and again once QuestDB closed the connection.
+    async fn connect(&self, stream: &mut Option<actix_rt::net::TcpStream>) -> Result<(), Error> {
+        use futures::FutureExt;
+        use tokio::io::AsyncReadExt;
+
+        if let Some(connected) = stream {
+            // QuestDB never sends anything, being readable means the connection is gone
+            let mut buf = [0u8; 1];
+            if connected.read(&mut buf).now_or_never().is_some() {
+                log::info!("QuestDB closed the connection, reconnecting");
+                *stream = None;
+            }
+        }
+
+        if stream.is_none() {
+            let connected =
+                actix_rt::net::TcpStream::connect((self.config.host.as_str(), self.config.port))
+                    .await
+                    .map_err(|err| Error::ConnectionError {
+                        error: format!(
+                            "Failed to connect to QuestDB at {}:{}: {}",
+                            self.config.host, self.config.port, err
+                        ),
+                    })?;
+            connected
+                .set_nodelay(true)
+                .map_err(|err| Error::ConnectionError {
+                    error: err.to_string(),
+                })?;
+            *stream = Some(connected);
+        }
+
+        Ok(())
+    }
+
+    async fn write_points(&self, points: &[(Option<String>, WriteQuery)]) -> Result<(), Error> {
+        use tokio::io::AsyncWriteExt;
+
+        let mut payload = String::new();
+        for (_, query) in points {
+            payload.push_str(&crate::influx::nanosecond_line(query)?);
+            payload.push('\n');
+        }
+
+        let mut stream = self.stream.lock().await;
+        self.connect(&mut stream).await?;
+        let result = stream.as_mut().unwrap().write_all(payload.as_bytes()).await;
+        if let Err(err) = result {
+            *stream = None;
+            return Err(Error::ConnectionError {
+                error: format!("Failed to write to QuestDB: {}", err),
+            });
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(feature = "questdb")]
+impl Sink for QuestDbSink {
+    fn write<'a>(
+        &'a self,
+        points: &'a [(Option<String>, WriteQuery)],
+    ) -> LocalBoxFuture<'a, Result<(), Error>> {
+        use futures::FutureExt;
+        self.write_points(points).boxed_local()
+    }
+
+    fn ping(&self) -> LocalBoxFuture<'_, Result<(), Error>> {
+        use futures::FutureExt;
+        async move {
+            let mut stream = self.stream.lock().await;
+            self.connect(&mut stream).await
+        }
+        .boxed_local()
+    }
+}
+
+#[cfg(not(feature = "questdb"))]
+#[derive(Debug)]
+pub struct QuestDbSink;
+
+#[cfg(not(feature = "questdb"))]
+impl QuestDbSink {
+    pub fn from_config(_: QuestDbConfig) -> anyhow::Result<Self> {
+        anyhow::bail!("SINK=questdb requires the 'questdb' feature")
+    }
+}
+
+#[cfg(not(feature = "questdb"))]
+impl Sink for QuestDbSink {
+    fn write<'a>(
+        &'a self,
+        _: &'a [(Option<String>, WriteQuery)],
+    ) -> LocalBoxFuture<'a, Result<(), Error>> {
+        use futures::FutureExt;
+        futures::future::ready(Err(Error::ConnectionError {
+            error: "SINK=questdb requires the 'questdb' feature".to_string(),
+        }))
+        .boxed_local()
+    }
+}
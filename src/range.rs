@@ -0,0 +1,101 @@
+use crate::error::ServiceError;
+use influxdb::Type;
+use std::str::FromStr;
+
+/// What happens to values outside of the range.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RangePolicy {
+    /// Reject the whole event.
+    Reject,
+    /// Write the nearest bound instead.
+    Clamp,
+    /// Write the point without the field.
+    DropField,
+}
+
+impl FromStr for RangePolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "reject" => Ok(RangePolicy::Reject),
+            "clamp" => Ok(RangePolicy::Clamp),
+            "drop-field" => Ok(RangePolicy::DropField),
+            _ => anyhow::bail!("Unknown range policy: {}", s),
+        }
+    }
+}
+
+/// Bounds of a numeric field, catching readings like `6553.5` of a failed sensor.
+///
+/// Values which aren't numbers are left alone.
+#[derive(Clone, Debug)]
+pub struct Range {
+    min: Option<f64>,
+    max: Option<f64>,
+    policy: RangePolicy,
+}
+
+impl Range {
+    pub fn from_spec(
+        min: Option<String>,
+        max: Option<String>,
+        policy: Option<String>,
+    ) -> anyhow::Result<Option<Self>> {
+        let bound = |bound: Option<String>| {
+            bound
+                .map(|bound| {
+                    bound
+                        .trim()
+                        .parse::<f64>()
+                        .map_err(|_| anyhow::anyhow!("Invalid bound: {}", bound))
+                })
+                .transpose()
+        };
+        let (min, max) = (bound(min)?, bound(max)?);
+        let policy = policy.as_deref().unwrap_or("reject").parse()?;
+
+        match (min, max) {
+            (None, None) => Ok(None),
+            (Some(min), Some(max)) if min > max => {
+                anyhow::bail!("Minimum {} is greater than the maximum {}", min, max)
+            }
+            _ => Ok(Some(Self { min, max, policy })),
+        }
+    }
+
+    /// The value to write, `None` if the field should be dropped.
+    pub fn apply(&self, path: &str, value: Type) -> Result<Option<Type>, ServiceError> {
+        let v = match value {
+            Type::Float(v) => v,
+            Type::SignedInteger(v) => v as f64,
+            Type::UnsignedInteger(v) => v as f64,
+            value => return Ok(Some(value)),
+        };
+        let bound = match (self.min, self.max) {
+            (Some(min), _) if v < min => min,
+            (_, Some(max)) if v > max => max,
+            _ => return Ok(Some(value)),
+        };
+
+        match self.policy {
+            RangePolicy::Reject => Err(ServiceError::ValueOutOfRange {
+                details: format!("{} of {} is outside of {}", v, path, self),
+            }),
+            RangePolicy::DropField => Ok(None),
+            RangePolicy::Clamp => Ok(Some(match value {
+                Type::SignedInteger(_) => Type::SignedInteger(bound as i64),
+                Type::UnsignedInteger(_) => Type::UnsignedInteger(bound as u64),
+                _ => Type::Float(bound),
+            })),
+        }
+    }
+}
+
+impl std::fmt::Display for Range {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let min = self.min.unwrap_or(f64::NEG_INFINITY);
+        let max = self.max.unwrap_or(f64::INFINITY);
+        write!(f, "[{}, {}]", min, max)
+    }
+}
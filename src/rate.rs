@@ -0,0 +1,272 @@
+use chrono::{DateTime, Duration, Utc};
+use envconfig::Envconfig;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+
+#[derive(Envconfig, Clone, Debug)]
+pub struct RateConfig {
+    /// Forget the previous value of a counter, once it's older than this.
+    #[envconfig(from = "RATE_STATE_TTL_S", default = "3600")]
+    pub ttl_s: i64,
+    /// Counters remembered, those of new series aren't converted beyond that.
+    #[envconfig(from = "RATE_STATE_MAX_SERIES", default = "100000")]
+    pub max_series: usize,
+}
+
+/// What a counter is written as, configured with `RATE_FIELD_<NAME>`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RateKind {
+    /// Increase per second.
+    Rate,
+    /// Increase since the previous value.
+    Delta,
+}
+
+impl FromStr for RateKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "true" | "rate" => Ok(RateKind::Rate),
+            "delta" => Ok(RateKind::Delta),
+            _ => anyhow::bail!("Unknown rate, expected true, rate or delta: {}", s),
+        }
+    }
+}
+
+/// Previous value of a counter, as persisted in a checkpoint.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CounterEntry {
+    pub series: String,
+    pub field: String,
+    pub value: f64,
+    pub time: DateTime<Utc>,
+}
+
+/// Previous values of counters, for writing their increase instead.
+///
+/// Counters are remembered per series, the measurement and its tags, and field. The first
+/// value of a counter only gets remembered, a value lower than the previous one is taken as a
+/// reset, the counter having started over from zero. Values which aren't newer than the
+/// previous one are dropped.
+#[derive(Clone, Debug)]
+pub struct Rates {
+    ttl: Duration,
+    max_series: usize,
+    previous: Arc<Mutex<HashMap<Counter, Sample>>>,
+}
+
+/// Series and field of a counter.
+pub type Counter = (String, String);
+/// Value of a counter, and when it had it.
+pub type Sample = (f64, DateTime<Utc>);
+
+impl Rates {
+    pub fn from_config(config: RateConfig) -> Self {
+        Self {
+            ttl: Duration::seconds(config.ttl_s),
+            max_series: config.max_series,
+            previous: Default::default(),
+        }
+    }
+
+    /// The value to write instead, `None` if there is none yet.
+    ///
+    /// The previous value is the one last written, or about to be with the same event, in
+    /// `pending`. The new value is added to `pending`, and only remembered once it's committed.
+    pub fn convert(
+        &self,
+        series: &str,
+        time: DateTime<Utc>,
+        field: &str,
+        kind: RateKind,
+        value: f64,
+        pending: &mut HashMap<Counter, Sample>,
+    ) -> Option<f64> {
+        let mut previous = self.previous.lock().unwrap();
+        let key = (series.to_string(), field.to_string());
+
+        let last = pending
+            .get(&key)
+            .or_else(|| previous.get(&key))
+            .copied()
+            .filter(|(_, last)| time - *last <= self.ttl);
+        if let Some((_, last)) = last {
+            if time <= last {
+                return None;
+            }
+        }
+
+        if !previous.contains_key(&key) && previous.len() >= self.max_series {
+            let ttl = self.ttl;
+            previous.retain(|_, (_, last)| time - *last <= ttl);
+            if previous.len() >= self.max_series {
+                log::debug!("Too many counters, not converting {} of {}", field, series);
+                return None;
+            }
+        }
+        pending.insert(key, (value, time));
+
+        let (last, last_time) = last?;
+        // a counter which went down has been reset
+        let delta = if value >= last { value - last } else { value };
+        Some(match kind {
+            RateKind::Delta => delta,
+            RateKind::Rate => {
+                let elapsed = (time - last_time).num_nanoseconds()? as f64 / 1e9;
+                delta / elapsed
+            }
+        })
+    }
+
+    /// Remember the values of counters which have been written.
+    pub fn commit(&self, pending: HashMap<Counter, Sample>) {
+        if pending.is_empty() {
+            return;
+        }
+        let mut previous = self.previous.lock().unwrap();
+        for (counter, sample) in pending {
+            if previous.contains_key(&counter) || previous.len() < self.max_series {
+                previous.insert(counter, sample);
+            }
+        }
+    }
+
+    pub fn snapshot(&self) -> Vec<CounterEntry> {
+        self.previous
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|((series, field), (value, time))| CounterEntry {
+                series: series.clone(),
+                field: field.clone(),
+                value: *value,
+                time: *time,
+            })
+            .collect()
+    }
+
+    pub fn restore(&self, entries: Vec<CounterEntry>) {
+        let mut previous = self.previous.lock().unwrap();
+        for entry in entries.into_iter().take(self.max_series) {
+            previous.insert((entry.series, entry.field), (entry.value, entry.time));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rates(max_series: usize) -> Rates {
+        Rates::from_config(RateConfig {
+            ttl_s: 3600,
+            max_series,
+        })
+    }
+
+    /// Convert a value, and commit it.
+    fn write(rates: &Rates, time: DateTime<Utc>, kind: RateKind, value: f64) -> Option<f64> {
+        let mut pending = HashMap::new();
+        let converted = rates.convert("s", time, "c", kind, value, &mut pending);
+        rates.commit(pending);
+        converted
+    }
+
+    #[test]
+    fn test_kind() {
+        assert_eq!("true".parse::<RateKind>().unwrap(), RateKind::Rate);
+        assert_eq!("Delta".parse::<RateKind>().unwrap(), RateKind::Delta);
+        assert!("false".parse::<RateKind>().is_err());
+    }
+
+    #[test]
+    fn test_rate() {
+        let rates = rates(10);
+        let now = Utc::now();
+        assert_eq!(write(&rates, now, RateKind::Rate, 10.0), None);
+        assert_eq!(
+            write(&rates, now + Duration::seconds(2), RateKind::Rate, 20.0),
+            Some(5.0)
+        );
+        assert_eq!(
+            write(&rates, now + Duration::seconds(3), RateKind::Delta, 27.0),
+            Some(7.0)
+        );
+        // a reset
+        assert_eq!(
+            write(&rates, now + Duration::seconds(4), RateKind::Delta, 3.0),
+            Some(3.0)
+        );
+        // not newer than the previous one
+        assert_eq!(
+            write(&rates, now + Duration::seconds(4), RateKind::Delta, 5.0),
+            None
+        );
+    }
+
+    #[test]
+    fn test_ttl() {
+        let rates = rates(10);
+        let now = Utc::now();
+        assert_eq!(write(&rates, now, RateKind::Delta, 10.0), None);
+        // too old to compare with, starts over
+        let later = now + Duration::seconds(3601);
+        assert_eq!(write(&rates, later, RateKind::Delta, 20.0), None);
+        assert_eq!(
+            write(&rates, later + Duration::seconds(1), RateKind::Delta, 25.0),
+            Some(5.0)
+        );
+    }
+
+    #[test]
+    fn test_pending() {
+        let rates = rates(10);
+        let now = Utc::now();
+        assert_eq!(write(&rates, now, RateKind::Delta, 10.0), None);
+
+        // a failed write doesn't move the baseline
+        let mut pending = HashMap::new();
+        let time = now + Duration::seconds(1);
+        assert_eq!(
+            rates.convert("s", time, "c", RateKind::Delta, 15.0, &mut pending),
+            Some(5.0)
+        );
+        // later values of the same event compare to the pending one
+        assert_eq!(
+            rates.convert(
+                "s",
+                time + Duration::seconds(1),
+                "c",
+                RateKind::Delta,
+                16.0,
+                &mut pending
+            ),
+            Some(1.0)
+        );
+        drop(pending);
+        assert_eq!(
+            write(&rates, now + Duration::seconds(3), RateKind::Delta, 18.0),
+            Some(8.0)
+        );
+    }
+
+    #[test]
+    fn test_max_series() {
+        let rates = rates(1);
+        let now = Utc::now();
+        let mut pending = HashMap::new();
+        rates.convert("a", now, "c", RateKind::Delta, 1.0, &mut pending);
+        rates.commit(pending);
+
+        let mut pending = HashMap::new();
+        assert_eq!(
+            rates.convert("b", now, "c", RateKind::Delta, 1.0, &mut pending),
+            None
+        );
+        assert!(pending.is_empty());
+        assert_eq!(rates.snapshot().len(), 1);
+    }
+}
@@ -0,0 +1,66 @@
+use crate::auth::Authenticated;
+use crate::config::Processor;
+use actix_web::{web, HttpResponse};
+use chrono::{DateTime, Utc};
+use envconfig::Envconfig;
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+#[derive(Envconfig, Clone, Debug)]
+pub struct RecentErrorsConfig {
+    /// Number of errors to keep, disabled when zero.
+    #[envconfig(from = "RECENT_ERRORS_SIZE", default = "100")]
+    pub size: usize,
+}
+
+/// The last processing and write errors, for `GET /errors/recent`.
+#[derive(Clone, Debug, Default)]
+pub struct RecentErrors {
+    size: usize,
+    errors: Arc<Mutex<VecDeque<RecentError>>>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct RecentError {
+    pub time: DateTime<Utc>,
+    /// Id of the event, not known for failed batches.
+    pub event_id: Option<String>,
+    pub class: String,
+    pub message: String,
+}
+
+impl RecentErrors {
+    pub fn from_config(config: RecentErrorsConfig) -> Self {
+        Self {
+            size: config.size,
+            errors: Default::default(),
+        }
+    }
+
+    pub fn record(&self, event_id: Option<&str>, class: &str, message: impl ToString) {
+        if self.size == 0 {
+            return;
+        }
+
+        let mut errors = self.errors.lock().unwrap();
+        if errors.len() >= self.size {
+            errors.pop_front();
+        }
+        errors.push_back(RecentError {
+            time: Utc::now(),
+            event_id: event_id.map(ToString::to_string),
+            class: class.to_string(),
+            message: message.to_string(),
+        });
+    }
+
+    /// Newest first.
+    pub fn list(&self) -> Vec<RecentError> {
+        self.errors.lock().unwrap().iter().rev().cloned().collect()
+    }
+}
+
+pub async fn recent(_: Authenticated, processor: web::Data<Processor>) -> HttpResponse {
+    HttpResponse::Ok().json(processor.recent_errors.list())
+}
@@ -0,0 +1,156 @@
+use crate::error::ServiceError;
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use envconfig::Envconfig;
+use serde_json::Value;
+use std::str::FromStr;
+
+#[derive(Envconfig, Clone, Debug)]
+pub struct RecordsConfig {
+    /// JSON pointer to an array of records, each of which becomes a point of its own.
+    #[envconfig(from = "RECORDS_ARRAY")]
+    pub array: Option<String>,
+    /// JSON pointer to the base time in the payload, the time of the event if unset.
+    #[envconfig(from = "RECORDS_TIME_BASE")]
+    pub base: Option<String>,
+    /// Unit of a numeric base time, counted since the epoch.
+    #[envconfig(from = "RECORDS_TIME_BASE_UNIT", default = "s")]
+    pub base_unit: TimeUnit,
+    /// JSON pointer to the offset to the base time, within each record.
+    #[envconfig(from = "RECORDS_TIME_OFFSET")]
+    pub offset: Option<String>,
+    #[envconfig(from = "RECORDS_TIME_OFFSET_UNIT", default = "s")]
+    pub offset_unit: TimeUnit,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TimeUnit {
+    Seconds,
+    Milliseconds,
+    Microseconds,
+    Nanoseconds,
+}
+
+impl FromStr for TimeUnit {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "s" => Ok(TimeUnit::Seconds),
+            "ms" => Ok(TimeUnit::Milliseconds),
+            "us" => Ok(TimeUnit::Microseconds),
+            "ns" => Ok(TimeUnit::Nanoseconds),
+            _ => anyhow::bail!("Unknown time unit: {}", s),
+        }
+    }
+}
+
+impl TimeUnit {
+    fn duration(self, value: f64) -> Option<Duration> {
+        let nanos = match self {
+            TimeUnit::Seconds => value * 1e9,
+            TimeUnit::Milliseconds => value * 1e6,
+            TimeUnit::Microseconds => value * 1e3,
+            TimeUnit::Nanoseconds => value,
+        };
+        if nanos.is_finite() && nanos.abs() < i64::MAX as f64 {
+            Some(Duration::nanoseconds(nanos as i64))
+        } else {
+            None
+        }
+    }
+
+    pub fn since_epoch(self, value: f64) -> Option<DateTime<Utc>> {
+        Utc.timestamp_opt(0, 0)
+            .single()?
+            .checked_add_signed(self.duration(value)?)
+    }
+}
+
+/// Splits compact payloads, carrying several samples, into one record per sample.
+///
+/// The time of each record is the base time plus its offset, like `ts_base` and
+/// `samples[i].dt`. Fields are selected from the record, tags from the event.
+#[derive(Clone, Debug)]
+pub struct Records {
+    array: String,
+    base: Option<String>,
+    base_unit: TimeUnit,
+    offset: Option<String>,
+    offset_unit: TimeUnit,
+}
+
+impl Records {
+    pub fn from_config(config: RecordsConfig) -> anyhow::Result<Option<Self>> {
+        let array = match config.array {
+            Some(array) => array,
+            None => {
+                if config.base.is_some() || config.offset.is_some() {
+                    anyhow::bail!(
+                        "RECORDS_TIME_BASE and RECORDS_TIME_OFFSET require RECORDS_ARRAY"
+                    );
+                }
+                return Ok(None);
+            }
+        };
+
+        Ok(Some(Self {
+            array,
+            base: config.base,
+            base_unit: config.base_unit,
+            offset: config.offset,
+            offset_unit: config.offset_unit,
+        }))
+    }
+
+    /// The records with their time, the time of the event being the default base time.
+    pub fn split(
+        &self,
+        json: &Value,
+        time: DateTime<Utc>,
+    ) -> Result<Vec<(Value, DateTime<Utc>)>, ServiceError> {
+        let records = json
+            .pointer(&self.array)
+            .and_then(Value::as_array)
+            .ok_or_else(|| ServiceError::SelectorError {
+                details: format!("No array of records at {}", self.array),
+            })?;
+
+        let base = match &self.base {
+            Some(pointer) => match json.pointer(pointer) {
+                Some(value) => base_time(value, self.base_unit).ok_or_else(|| {
+                    ServiceError::PayloadParseError {
+                        details: format!("Invalid base time at {}: {}", pointer, value),
+                    }
+                })?,
+                None => time,
+            },
+            None => time,
+        };
+
+        records
+            .iter()
+            .map(|record| {
+                let offset = match self.offset.as_ref().and_then(|p| record.pointer(p)) {
+                    Some(value) => value
+                        .as_f64()
+                        .and_then(|offset| self.offset_unit.duration(offset))
+                        .ok_or_else(|| ServiceError::PayloadParseError {
+                            details: format!("Invalid time offset: {}", value),
+                        })?,
+                    None => Duration::zero(),
+                };
+                Ok((record.clone(), base + offset))
+            })
+            .collect()
+    }
+}
+
+/// Numeric since the epoch, or RFC 3339.
+pub fn base_time(value: &Value, unit: TimeUnit) -> Option<DateTime<Utc>> {
+    match value {
+        Value::String(s) => DateTime::parse_from_rfc3339(s)
+            .ok()
+            .map(|time| time.with_timezone(&Utc)),
+        value => unit.since_epoch(value.as_f64()?),
+    }
+}
@@ -0,0 +1,151 @@
+use crate::error::ServiceError;
+use crate::line::Point;
+use influxdb::Type;
+use serde_json::Value;
+use std::fmt;
+
+const MASK: &str = "***";
+
+/// Masks configured payload paths before data ends up in logs.
+#[derive(Clone, Debug, Default)]
+pub struct Redactor {
+    paths: Vec<String>,
+}
+
+impl Redactor {
+    /// Parse a comma separated list of JSON paths, like `$.location,$.owner.email`.
+    pub fn from_paths(paths: &str) -> anyhow::Result<Self> {
+        let paths = paths
+            .split(',')
+            .map(str::trim)
+            .filter(|path| !path.is_empty())
+            .map(|path| {
+                jsonpath_lib::Compiled::compile(path)
+                    .map(|_| path.to_string())
+                    .map_err(|err| anyhow::anyhow!("Failed to parse redaction path: {}", err))
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(Self { paths })
+    }
+
+    /// Redact a payload.
+    pub fn redact(&self, json: &Value) -> Value {
+        let mut json = json.clone();
+        for path in &self.paths {
+            json = match jsonpath_lib::replace_with(json.clone(), path, &mut |_| Some(MASK.into()))
+            {
+                Ok(redacted) => redacted,
+                // never fall back to the unredacted value
+                Err(_) => Value::String(MASK.into()),
+            };
+        }
+        json
+    }
+
+    /// The values at the redacted paths of payloads.
+    pub fn secrets<'a>(&self, payloads: impl IntoIterator<Item = &'a Value>) -> Secrets {
+        let mut secrets = Secrets::default();
+        if self.paths.is_empty() {
+            return secrets;
+        }
+        for payload in payloads {
+            for path in &self.paths {
+                for value in jsonpath_lib::select(payload, path).unwrap_or_default() {
+                    secrets.add(value);
+                }
+            }
+        }
+        secrets
+            .0
+            .sort_by_key(|secret| std::cmp::Reverse(secret.len()));
+        secrets.0.dedup();
+        secrets
+    }
+
+    /// Serialized CloudEvent with its data replaced by the redacted, parsed payload.
+    pub fn redact_event(&self, event: &Value, payload: &Value) -> Value {
+        let mut event = event.clone();
+        if let Some(event) = event.as_object_mut() {
+            event.remove("data_base64");
+            event.insert("data".into(), self.redact(payload));
+        }
+        event
+    }
+}
+
+/// Values of redacted paths, masked wherever else they would show up, like in errors.
+#[derive(Clone, Default)]
+pub struct Secrets(Vec<String>);
+
+impl fmt::Debug for Secrets {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Secrets({})", self.0.len())
+    }
+}
+
+impl Secrets {
+    fn add(&mut self, value: &Value) {
+        match value {
+            Value::String(s) if !s.is_empty() => self.0.push(s.clone()),
+            Value::Number(n) => self.0.push(n.to_string()),
+            Value::Array(values) => values.iter().for_each(|value| self.add(value)),
+            Value::Object(values) => values.values().for_each(|value| self.add(value)),
+            _ => {}
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn extend(&mut self, other: Secrets) {
+        self.0.extend(other.0);
+        self.0.sort_by_key(|secret| std::cmp::Reverse(secret.len()));
+        self.0.dedup();
+    }
+
+    pub fn mask(&self, text: &str) -> String {
+        let mut text = text.to_string();
+        for secret in &self.0 {
+            text = text.replace(secret.as_str(), MASK);
+        }
+        text
+    }
+
+    /// The error, without the secrets in its details.
+    pub fn mask_error(&self, mut err: ServiceError) -> ServiceError {
+        if self.is_empty() {
+            return err;
+        }
+        match &mut err {
+            ServiceError::SelectorError { details }
+            | ServiceError::PayloadParseError { details }
+            | ServiceError::TransformError { details }
+            | ServiceError::CardinalityExceeded { details }
+            | ServiceError::WriteError { details }
+            | ServiceError::NameLookupFailed { details }
+            | ServiceError::SchemaLookupFailed { details }
+            | ServiceError::ValueOutOfRange { details }
+            | ServiceError::TimestampOutOfRange { details } => *details = self.mask(details),
+            ServiceError::ConversionError { value, .. } => *value = self.mask(value),
+            _ => {}
+        }
+        err
+    }
+
+    /// Mask the tags and fields of a point which have a secret as their value.
+    pub fn mask_point(&self, point: &mut Point) {
+        for (_, value) in &mut point.tags {
+            *value = self.mask(value);
+        }
+        for (_, value) in &mut point.fields {
+            *value = match &*value {
+                Type::Text(text) => Type::Text(self.mask(text)),
+                Type::Boolean(_) => continue,
+                number if self.0.contains(&number.to_string()) => Type::Text(MASK.into()),
+                _ => continue,
+            };
+        }
+    }
+}
@@ -0,0 +1,241 @@
+use crate::error::ServiceError;
+use cloudevents::{AttributesReader, Event};
+use envconfig::Envconfig;
+use serde_json::Value;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+/// Size of the pages when listing devices.
+const PAGE_SIZE: usize = 1000;
+
+#[derive(Envconfig, Clone)]
+pub struct RegistryConfig {
+    /// Drogue Cloud API, like `https://api.sandbox.drogue.cloud`, disabled if unset.
+    #[envconfig(from = "REGISTRY_URL")]
+    pub url: Option<String>,
+    /// Comma separated, all applications the token has access to if empty.
+    #[envconfig(from = "REGISTRY_APPLICATIONS", default = "")]
+    pub applications: String,
+    /// Together with the token, uses basic authentication instead of a bearer token.
+    #[envconfig(from = "REGISTRY_USERNAME")]
+    pub username: Option<String>,
+    #[envconfig(from = "REGISTRY_TOKEN")]
+    pub token: Option<String>,
+    #[envconfig(from = "REGISTRY_SYNC_INTERVAL_S", default = "300")]
+    pub sync_interval_s: u64,
+}
+
+impl fmt::Debug for RegistryConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RegistryConfig")
+            .field("url", &self.url)
+            .field("applications", &self.applications)
+            .field("username", &self.username)
+            .field("sync_interval_s", &self.sync_interval_s)
+            .finish()
+    }
+}
+
+/// Devices per application.
+type Devices = HashMap<String, HashSet<String>>;
+
+#[derive(Debug, Default)]
+struct Synced {
+    devices: Devices,
+    /// Marked deleted, but not gone yet.
+    deleted: Devices,
+}
+
+/// Only accepts events of devices known to the Drogue Cloud registry.
+///
+/// Events carry their application and device in the `application` and `device` extensions.
+/// Until the first sync succeeded, events are rejected as temporarily unavailable. Devices
+/// being deleted are still known, but denied like those on the denylist.
+#[derive(Clone)]
+pub struct Registry {
+    url: reqwest::Url,
+    applications: Vec<String>,
+    username: Option<String>,
+    token: Option<String>,
+    interval: Duration,
+    client: reqwest::Client,
+    synced: Arc<RwLock<Option<Synced>>>,
+}
+
+impl fmt::Debug for Registry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Registry")
+            .field("url", &self.url.as_str())
+            .field("applications", &self.applications)
+            .finish()
+    }
+}
+
+impl Registry {
+    pub fn from_config(config: RegistryConfig) -> anyhow::Result<Option<Self>> {
+        let url = match config.url {
+            Some(url) => reqwest::Url::parse(&url)?,
+            None => return Ok(None),
+        };
+        if config.username.is_some() && config.token.is_none() {
+            anyhow::bail!("REGISTRY_USERNAME requires REGISTRY_TOKEN");
+        }
+
+        Ok(Some(Self {
+            url,
+            applications: crate::filter::split(Some(config.applications)),
+            username: config.username,
+            token: config.token,
+            interval: Duration::from_secs(config.sync_interval_s.max(1)),
+            client: reqwest::Client::new(),
+            synced: Default::default(),
+        }))
+    }
+
+    /// Start syncing periodically on the current arbiter.
+    pub fn start(&self) {
+        let registry = self.clone();
+        actix_rt::spawn(async move {
+            let mut interval = actix_rt::time::interval(registry.interval);
+            loop {
+                interval.tick().await;
+                match registry.sync().await {
+                    Ok(synced) => {
+                        let count: usize = synced.devices.values().map(HashSet::len).sum();
+                        log::debug!(
+                            "Synced {} devices of {} applications",
+                            count,
+                            synced.devices.len()
+                        );
+                        *registry.synced.write().unwrap() = Some(synced);
+                    }
+                    Err(err) => log::warn!("Failed to sync the registry: {:#}", err),
+                }
+            }
+        });
+    }
+
+    pub fn check(&self, event: &Event) -> Result<(), ServiceError> {
+        if self.contains(event, |synced| &synced.devices)? {
+            Ok(())
+        } else {
+            Err(ServiceError::UnknownSource {
+                details: event.source().to_string(),
+            })
+        }
+    }
+
+    /// The device is being deleted, and should be denied.
+    pub fn is_deleted(&self, event: &Event) -> Result<bool, ServiceError> {
+        self.contains(event, |synced| &synced.deleted)
+    }
+
+    fn contains(
+        &self,
+        event: &Event,
+        devices: impl FnOnce(&Synced) -> &Devices,
+    ) -> Result<bool, ServiceError> {
+        let synced = self.synced.read().unwrap();
+        let devices = devices(synced.as_ref().ok_or(ServiceError::RegistryNotReady)?);
+
+        Ok(match crate::denylist::device(event) {
+            Some((application, device)) => devices
+                .get(&application)
+                .is_some_and(|devices| devices.contains(&device)),
+            None => false,
+        })
+    }
+
+    async fn sync(&self) -> anyhow::Result<Synced> {
+        let applications = if self.applications.is_empty() {
+            self.list(&[])
+                .await?
+                .iter()
+                .filter_map(name)
+                .collect::<Vec<_>>()
+        } else {
+            self.applications.clone()
+        };
+
+        let mut synced = Synced::default();
+        for application in applications {
+            let (deleted, devices): (Vec<_>, Vec<_>) = self
+                .list(&[&application, "devices"])
+                .await?
+                .into_iter()
+                .partition(|device| device.pointer("/metadata/deletionTimestamp").is_some());
+            let names = |devices: Vec<Value>| devices.iter().filter_map(name).collect();
+            synced.devices.insert(application.clone(), names(devices));
+            synced.deleted.insert(application, names(deleted));
+        }
+        Ok(synced)
+    }
+
+    /// A label of a device, `None` if either doesn't exist.
+    pub async fn label(
+        &self,
+        application: &str,
+        device: &str,
+        label: &str,
+    ) -> anyhow::Result<Option<String>> {
+        let response = self
+            .request(&[application, "devices", device])?
+            .send()
+            .await?;
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+
+        let device: Value = serde_json::from_slice(&response.error_for_status()?.bytes().await?)?;
+        Ok(device
+            .pointer("/metadata/labels")
+            .and_then(|labels| labels.get(label))
+            .and_then(Value::as_str)
+            .map(ToString::to_string))
+    }
+
+    /// List all resources below `apps`, page by page.
+    async fn list(&self, path: &[&str]) -> anyhow::Result<Vec<Value>> {
+        let mut items = Vec::new();
+        loop {
+            let request = self.request(path)?.query(&[
+                ("limit", PAGE_SIZE.to_string()),
+                ("offset", items.len().to_string()),
+            ]);
+
+            let response = request.send().await?.error_for_status()?;
+            let page: Vec<Value> = serde_json::from_slice(&response.bytes().await?)?;
+            let done = page.len() < PAGE_SIZE;
+            items.extend(page);
+            if done {
+                return Ok(items);
+            }
+        }
+    }
+
+    /// Authenticated request for a resource below `apps`.
+    fn request(&self, path: &[&str]) -> anyhow::Result<reqwest::RequestBuilder> {
+        let mut url = self.url.clone();
+        url.path_segments_mut()
+            .map_err(|_| anyhow::anyhow!("Invalid registry URL: {}", self.url))?
+            .pop_if_empty()
+            .extend(&["api", "registry", "v1alpha1", "apps"])
+            .extend(path);
+
+        let request = self.client.get(url);
+        Ok(match (&self.username, &self.token) {
+            (Some(username), token) => request.basic_auth(username, token.as_ref()),
+            (None, Some(token)) => request.bearer_auth(token),
+            (None, None) => request,
+        })
+    }
+}
+
+fn name(resource: &Value) -> Option<String> {
+    resource
+        .pointer("/metadata/name")
+        .and_then(Value::as_str)
+        .map(ToString::to_string)
+}
@@ -0,0 +1,70 @@
+use crate::handler::WriteOutcome;
+use chrono::Utc;
+use cloudevents::{AttributesReader, Event, EventBuilder, EventBuilderV10};
+use envconfig::Envconfig;
+use serde_json::json;
+
+#[derive(Envconfig, Clone, Debug)]
+pub struct ReplyConfig {
+    /// Respond to written events with an event, for Knative triggers to chain on.
+    #[envconfig(from = "REPLY_EVENTS", default = "false")]
+    pub enabled: bool,
+    #[envconfig(from = "REPLY_EVENT_TYPE", default = "io.drogue.influxdb.written.v1")]
+    pub r#type: String,
+    #[envconfig(from = "REPLY_EVENT_SOURCE", default = "drogue-influxdb-function")]
+    pub source: String,
+}
+
+/// Creates the event replied with, once the points of an event were written.
+///
+/// Keeps the subject and the Drogue Cloud extensions of the written event, so that triggers
+/// can filter on the device.
+#[derive(Clone, Debug)]
+pub struct Reply {
+    r#type: String,
+    source: String,
+}
+
+impl Reply {
+    pub fn from_config(config: ReplyConfig) -> Option<Self> {
+        if !config.enabled {
+            return None;
+        }
+        Some(Self {
+            r#type: config.r#type,
+            source: config.source,
+        })
+    }
+
+    pub fn event(&self, written: &Event, outcome: &WriteOutcome) -> anyhow::Result<Event> {
+        let time = Utc::now();
+        let mut measurements = outcome.measurements.clone();
+        measurements.sort();
+        measurements.dedup();
+
+        let mut builder = EventBuilderV10::new()
+            .id(format!("{:016x}", rand::random::<u64>()))
+            .source(self.source.as_str())
+            .ty(self.r#type.as_str())
+            .time(time)
+            .data(
+                "application/json",
+                json!({
+                    "event": written.id(),
+                    "measurements": measurements,
+                    "points": outcome.points,
+                    "timestamp": time.to_rfc3339(),
+                }),
+            );
+        if let Some(subject) = written.subject() {
+            builder = builder.subject(subject);
+        }
+        for name in &["application", "device"] {
+            if let Some(value) = written.extension(name) {
+                builder = builder.extension(name, value.to_string());
+            }
+        }
+
+        Ok(builder.build()?)
+    }
+}
@@ -0,0 +1,101 @@
+use crate::auth::Authenticated;
+use crate::config::{Mapping, Processor};
+use crate::sink::Sink;
+use actix_web::{web, HttpResponse};
+use chrono::Utc;
+use envconfig::Envconfig;
+use influxdb::{InfluxDbWriteable, Timestamp};
+use serde::Serialize;
+use std::sync::Arc;
+use std::time::Duration;
+
+#[derive(Envconfig, Clone, Debug)]
+pub struct SchemaConfig {
+    /// Interval of writing the schema to InfluxDB, disabled when zero.
+    #[envconfig(from = "SCHEMA_INTERVAL_S", default = "0")]
+    pub interval_s: u64,
+    #[envconfig(from = "SCHEMA_MEASUREMENT", default = "_schema")]
+    pub measurement: String,
+}
+
+/// A field or tag, as written by the function.
+#[derive(Clone, Debug, Serialize)]
+pub struct SchemaEntry {
+    pub measurement: String,
+    pub kind: &'static str,
+    pub name: String,
+    pub path: String,
+    pub r#type: &'static str,
+}
+
+pub fn entries(mappings: &[Mapping]) -> Vec<SchemaEntry> {
+    let mut entries = Vec::new();
+
+    for mapping in mappings {
+        let entry = |kind, name: &String, path: String, r#type| SchemaEntry {
+            measurement: mapping.table.clone(),
+            kind,
+            name: name.clone(),
+            path,
+            r#type,
+        };
+
+        for (name, path) in &mapping.fields {
+            entries.push(entry("field", name, path.path.clone(), path.r#type.name()));
+        }
+        for (name, expression) in &mapping.computed {
+            entries.push(entry("computed", name, expression.to_string(), "float"));
+        }
+        for (name, path) in &mapping.tags {
+            entries.push(entry("tag", name, path.path.clone(), "string"));
+        }
+    }
+
+    // mappings split by retention policy share their tags
+    entries.sort_by(|a, b| (&a.measurement, &a.name).cmp(&(&b.measurement, &b.name)));
+    entries.dedup_by(|a, b| {
+        (a.measurement == b.measurement) && (a.kind == b.kind) && (a.name == b.name)
+    });
+    entries
+}
+
+pub async fn schema(_: Authenticated, processor: web::Data<Processor>) -> HttpResponse {
+    HttpResponse::Ok().json(entries(&processor.mappings))
+}
+
+/// Start writing the schema periodically on the current arbiter, if enabled.
+pub fn start(config: SchemaConfig, sink: Arc<dyn Sink>, mappings: &[Mapping]) {
+    if config.interval_s == 0 {
+        return;
+    }
+
+    let entries = entries(mappings);
+    actix_rt::spawn(async move {
+        let mut interval = actix_rt::time::interval(Duration::from_secs(config.interval_s));
+        loop {
+            interval.tick().await;
+            write(&config.measurement, &*sink, &entries).await;
+        }
+    });
+}
+
+async fn write(measurement: &str, sink: &dyn Sink, entries: &[SchemaEntry]) {
+    let timestamp = Timestamp::from(Utc::now());
+    let queries: Vec<_> = entries
+        .iter()
+        .map(|entry| {
+            let query = timestamp
+                .into_query(measurement)
+                .add_tag("measurement", entry.measurement.clone())
+                .add_tag("kind", entry.kind)
+                .add_tag("name", entry.name.clone())
+                .add_field("path", entry.path.clone())
+                .add_field("type", entry.r#type);
+            (None, query)
+        })
+        .collect();
+
+    if let Err(err) = sink.write(&queries).await {
+        log::warn!("Failed to write schema: {}", err);
+    }
+}
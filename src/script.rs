@@ -0,0 +1,96 @@
+use crate::error::ServiceError;
+use envconfig::Envconfig;
+use serde_json::Value;
+
+#[derive(Envconfig, Clone, Debug)]
+pub struct ScriptConfig {
+    /// Rhai script, run with the parsed payload as `payload` before extracting values.
+    #[envconfig(from = "TRANSFORM_SCRIPT_FILE")]
+    pub file: Option<String>,
+    /// Upper bound of operations per run, guarding against runaway scripts.
+    #[cfg_attr(not(feature = "script"), allow(dead_code))]
+    #[envconfig(from = "TRANSFORM_MAX_OPERATIONS", default = "100000")]
+    pub max_operations: u64,
+}
+
+/// Transforms payloads with a user provided script.
+///
+/// The script modifies, or re-assigns, `payload`. Whatever it holds afterwards is used.
+#[cfg(feature = "script")]
+#[derive(Clone)]
+pub struct Transformer {
+    engine: std::sync::Arc<rhai::Engine>,
+    ast: std::sync::Arc<rhai::AST>,
+}
+
+#[cfg(feature = "script")]
+impl std::fmt::Debug for Transformer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Transformer").finish()
+    }
+}
+
+#[cfg(feature = "script")]
+impl Transformer {
+    pub fn from_config(config: ScriptConfig) -> anyhow::Result<Option<Self>> {
+        let file = match config.file {
+            Some(file) => file,
+            None => return Ok(None),
+        };
+
+        let mut engine = rhai::Engine::new();
+        engine.set_max_operations(config.max_operations);
+
+        let script = std::fs::read_to_string(&file)?;
+        let ast = engine
+            .compile(&script)
+            .map_err(|err| anyhow::anyhow!("Failed to compile {}: {}", file, err))?;
+
+        Ok(Some(Self {
+            engine: std::sync::Arc::new(engine),
+            ast: std::sync::Arc::new(ast),
+        }))
+    }
+
+    pub fn transform(&self, json: Value) -> Result<Value, ServiceError> {
+        let payload = rhai::serde::to_dynamic(json).map_err(transform_error)?;
+
+        let mut scope = rhai::Scope::new();
+        scope.push_dynamic("payload", payload);
+
+        self.engine
+            .run_ast_with_scope(&mut scope, &self.ast)
+            .map_err(transform_error)?;
+
+        let result = scope
+            .get_value::<rhai::Dynamic>("payload")
+            .unwrap_or_default();
+
+        rhai::serde::from_dynamic::<Value>(&result).map_err(transform_error)
+    }
+}
+
+#[cfg(feature = "script")]
+fn transform_error<E: std::fmt::Display>(err: E) -> ServiceError {
+    ServiceError::TransformError {
+        details: err.to_string(),
+    }
+}
+
+#[cfg(not(feature = "script"))]
+#[derive(Clone, Debug)]
+pub struct Transformer;
+
+#[cfg(not(feature = "script"))]
+impl Transformer {
+    pub fn from_config(config: ScriptConfig) -> anyhow::Result<Option<Self>> {
+        if config.file.is_some() {
+            anyhow::bail!("TRANSFORM_SCRIPT_FILE requires the 'script' feature");
+        }
+        Ok(None)
+    }
+
+    pub fn transform(&self, json: Value) -> Result<Value, ServiceError> {
+        Ok(json)
+    }
+}
@@ -0,0 +1,331 @@
+use crate::config::Processor;
+use crate::rate::{self, RateKind, Rates};
+use crate::unchanged::{self, Unchanged};
+use chrono::{DateTime, Utc};
+use openssl::sha::sha256;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// A point's series, the measurement and its tags, for what is remembered of it.
+#[derive(Debug)]
+pub struct Series<'a> {
+    key: String,
+    time: DateTime<Utc>,
+    rates: &'a Rates,
+    unchanged: &'a Unchanged,
+    pending: &'a RefCell<Pending>,
+}
+
+impl<'a> Series<'a> {
+    pub fn new(
+        processor: &'a Processor,
+        table: &str,
+        tags: &HashMap<String, String>,
+        time: DateTime<Utc>,
+        pending: &'a RefCell<Pending>,
+    ) -> Self {
+        Self {
+            key: SeriesKey::new(table, tags).to_string(),
+            time,
+            rates: &processor.rates,
+            unchanged: &processor.unchanged,
+            pending,
+        }
+    }
+
+    /// The increase of a counter, `None` if there is none yet.
+    pub fn rate(&self, field: &str, kind: RateKind, value: f64) -> Option<f64> {
+        let pending = &mut self.pending.borrow_mut().counters;
+        self.rates
+            .convert(&self.key, self.time, field, kind, value, pending)
+    }
+
+    /// Whether the point can be skipped, its values being the ones last written.
+    pub fn is_unchanged(&self, values: Vec<(String, String)>) -> bool {
+        let pending = &mut self.pending.borrow_mut().unchanged;
+        self.unchanged.check(&self.key, self.time, values, pending)
+    }
+
+    /// Whether changes to a field, which isn't tracked itself, decide over writing the point.
+    pub fn tracks_all(&self) -> bool {
+        self.unchanged.tracks_all()
+    }
+}
+
+/// What is remembered of the series of an event's points, once they are written.
+///
+/// Held back while mapping, so that an event which fails to be written, and is delivered
+/// again, is mapped the same way again, instead of being taken as unchanged, or its counters
+/// as not newer than the previous value.
+#[derive(Debug, Default)]
+pub struct Pending {
+    counters: HashMap<rate::Counter, rate::Sample>,
+    unchanged: HashMap<String, unchanged::Written>,
+}
+
+/// What is pending of an event's series, committed once all of its points were written.
+///
+/// Shared by the points while they are queued, aggregated or held back with a transaction.
+/// Commits when the last of them is done with, unless one of them failed to be written.
+#[derive(Clone, Debug, Default)]
+pub struct Commit(Arc<Committing>);
+
+#[derive(Debug, Default)]
+struct Committing {
+    pending: Option<(Pending, Rates, Unchanged)>,
+    /// Of other events, whose points are written together with these.
+    joined: Vec<Commit>,
+    failed: AtomicBool,
+}
+
+impl Commit {
+    pub fn new(pending: Pending, processor: &Processor) -> Self {
+        Self::of(pending, &processor.rates, &processor.unchanged)
+    }
+
+    fn of(pending: Pending, rates: &Rates, unchanged: &Unchanged) -> Self {
+        Self(Arc::new(Committing {
+            pending: Some((pending, rates.clone(), unchanged.clone())),
+            joined: Vec::new(),
+            failed: AtomicBool::new(false),
+        }))
+    }
+
+    /// Commit those of several events together, like the ones of a transaction.
+    pub fn join(commits: Vec<Commit>) -> Self {
+        Self(Arc::new(Committing {
+            pending: None,
+            joined: commits,
+            failed: AtomicBool::new(false),
+        }))
+    }
+
+    /// Whether both are of the same event.
+    pub fn is(&self, other: &Commit) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+
+    /// Drop what is pending, as a point failed to be written, or was dropped.
+    pub fn fail(&self) {
+        self.0.failed.store(true, Ordering::Release);
+    }
+}
+
+impl Drop for Committing {
+    fn drop(&mut self) {
+        if *self.failed.get_mut() {
+            self.joined.iter().for_each(Commit::fail);
+        } else if let Some((pending, rates, unchanged)) = self.pending.take() {
+            rates.commit(pending.counters);
+            unchanged.commit(pending.unchanged);
+        }
+    }
+}
+
+/// The measurement and the sorted tags of a point, written like `temperature,room=1,site=a`.
+#[derive(Debug)]
+pub struct SeriesKey {
+    pub measurement: String,
+    pub tags: BTreeMap<String, String>,
+}
+
+impl SeriesKey {
+    pub fn new<'a, I>(measurement: &str, tags: I) -> Self
+    where
+        I: IntoIterator<Item = (&'a String, &'a String)>,
+    {
+        Self {
+            measurement: measurement.to_string(),
+            tags: tags
+                .into_iter()
+                .map(|(tag, value)| (tag.clone(), value.clone()))
+                .collect(),
+        }
+    }
+}
+
+impl fmt::Display for SeriesKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.measurement)?;
+        for (tag, value) in &self.tags {
+            write!(f, ",{}={}", tag, value)?;
+        }
+        Ok(())
+    }
+}
+
+/// Bits of the hash selecting a register, 4096 registers estimate within about 2%.
+const PRECISION: u32 = 12;
+
+/// Estimate of the distinct series written since start, for planning cardinality.
+///
+/// A HyperLogLog, so that it takes the same few kilobytes whatever the number of series.
+#[derive(Clone)]
+pub struct SeriesEstimate {
+    registers: Arc<Mutex<Vec<u8>>>,
+}
+
+impl fmt::Debug for SeriesEstimate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SeriesEstimate")
+            .field("estimate", &self.estimate())
+            .finish()
+    }
+}
+
+impl Default for SeriesEstimate {
+    fn default() -> Self {
+        Self {
+            registers: Arc::new(Mutex::new(vec![0; 1 << PRECISION])),
+        }
+    }
+}
+
+impl SeriesEstimate {
+    pub fn observe(&self, key: &SeriesKey) {
+        let hash = sha256(key.to_string().as_bytes());
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(&hash[..8]);
+        let hash = u64::from_be_bytes(bytes);
+
+        let index = (hash >> (64 - PRECISION)) as usize;
+        // position of the first set bit of the rest, the rarer the more series there are
+        let rank = ((hash << PRECISION).leading_zeros() + 1).min(64 - PRECISION + 1) as u8;
+
+        let mut registers = self.registers.lock().unwrap();
+        if registers[index] < rank {
+            registers[index] = rank;
+        }
+    }
+
+    pub fn estimate(&self) -> u64 {
+        let registers = self.registers.lock().unwrap();
+        let m = registers.len() as f64;
+        let sum = registers
+            .iter()
+            .map(|rank| 2f64.powi(-(*rank as i32)))
+            .sum::<f64>();
+        let zeros = registers.iter().filter(|rank| **rank == 0).count();
+
+        let alpha = 0.7213 / (1.0 + 1.079 / m);
+        let estimate = alpha * m * m / sum;
+        // few series are counted better by the empty registers
+        let estimate = if estimate <= 2.5 * m && zeros > 0 {
+            m * (m / zeros as f64).ln()
+        } else {
+            estimate
+        };
+        estimate.round() as u64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rate::RateConfig;
+    use crate::unchanged::UnchangedConfig;
+
+    fn state() -> (Rates, Unchanged) {
+        let rates = Rates::from_config(RateConfig {
+            ttl_s: 3600,
+            max_series: 10,
+        });
+        let unchanged = Unchanged::from_config(UnchangedConfig {
+            all: true,
+            window_s: 300,
+            max_series: 10,
+        });
+        (rates, unchanged)
+    }
+
+    /// What is pending with a point of `series`, of value `value`.
+    fn pending(unchanged: &Unchanged, series: &str, value: &str) -> Pending {
+        let mut pending = Pending::default();
+        let values = vec![("v".to_string(), value.to_string())];
+        assert!(!unchanged.check(series, Utc::now(), values, &mut pending.unchanged));
+        pending
+    }
+
+    fn is_unchanged(unchanged: &Unchanged, series: &str, value: &str) -> bool {
+        let values = vec![("v".to_string(), value.to_string())];
+        unchanged.check(series, Utc::now(), values, &mut HashMap::new())
+    }
+
+    #[test]
+    fn test_commit() {
+        let (rates, unchanged) = state();
+        let commit = Commit::of(pending(&unchanged, "a", "1"), &rates, &unchanged);
+        let queued = commit.clone();
+
+        drop(commit);
+        // still waiting to be written
+        assert!(!is_unchanged(&unchanged, "a", "1"));
+        drop(queued);
+        assert!(is_unchanged(&unchanged, "a", "1"));
+    }
+
+    #[test]
+    fn test_fail() {
+        let (rates, unchanged) = state();
+        let commit = Commit::of(pending(&unchanged, "a", "1"), &rates, &unchanged);
+        let queued = commit.clone();
+        queued.fail();
+        drop(queued);
+        drop(commit);
+        assert!(!is_unchanged(&unchanged, "a", "1"));
+    }
+
+    #[test]
+    fn test_rate_baseline() {
+        let (rates, unchanged) = state();
+        let now = Utc::now();
+        let convert = |time, value, pending: &mut Pending| {
+            rates.convert(
+                "a",
+                time,
+                "c",
+                RateKind::Delta,
+                value,
+                &mut pending.counters,
+            )
+        };
+
+        let mut pending = Pending::default();
+        assert_eq!(convert(now, 10.0, &mut pending), None);
+        drop(Commit::of(pending, &rates, &unchanged));
+
+        // queued, but failed to be written later
+        let mut pending = Pending::default();
+        let second = now + chrono::Duration::seconds(1);
+        assert_eq!(convert(second, 15.0, &mut pending), Some(5.0));
+        let commit = Commit::of(pending, &rates, &unchanged);
+        commit.clone().fail();
+        drop(commit);
+
+        let mut pending = Pending::default();
+        let third = now + chrono::Duration::seconds(2);
+        assert_eq!(convert(third, 20.0, &mut pending), Some(10.0));
+    }
+
+    #[test]
+    fn test_join() {
+        let (rates, unchanged) = state();
+        let a = Commit::of(pending(&unchanged, "a", "1"), &rates, &unchanged);
+        let b = Commit::of(pending(&unchanged, "b", "1"), &rates, &unchanged);
+        assert!(a.is(&a.clone()));
+        assert!(!a.is(&b));
+
+        drop(Commit::join(vec![a, b]));
+        assert!(is_unchanged(&unchanged, "a", "1"));
+        assert!(is_unchanged(&unchanged, "b", "1"));
+
+        let c = Commit::of(pending(&unchanged, "c", "1"), &rates, &unchanged);
+        let joined = Commit::join(vec![c]);
+        joined.fail();
+        drop(joined);
+        assert!(!is_unchanged(&unchanged, "c", "1"));
+    }
+}
@@ -0,0 +1,66 @@
+use crate::filter;
+use crate::metrics::EVENTS_SHED;
+use cloudevents::{AttributesReader, Event};
+use envconfig::Envconfig;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+#[derive(Envconfig, Clone, Debug)]
+pub struct ShedConfig {
+    /// Number of queued points, above which low priority events get dropped.
+    #[envconfig(from = "SHED_HIGH_WATER_MARK", default = "0")]
+    pub high_water_mark: usize,
+    /// Comma separated list of low priority event types, a trailing `*` matches a prefix.
+    #[envconfig(from = "SHED_TYPES", default = "")]
+    pub types: String,
+    /// Log one out of this many dropped events.
+    #[envconfig(from = "SHED_SAMPLE_RATE", default = "100")]
+    pub sample_rate: u64,
+}
+
+/// Drops low priority events while the write queue is backed up.
+#[derive(Clone, Debug)]
+pub struct Shedder {
+    high_water_mark: usize,
+    types: Vec<String>,
+    sample_rate: u64,
+    shed: Arc<AtomicU64>,
+}
+
+impl Shedder {
+    pub fn from_config(config: ShedConfig) -> Option<Self> {
+        let types = filter::split(Some(config.types));
+        if config.high_water_mark == 0 || types.is_empty() {
+            return None;
+        }
+
+        Some(Self {
+            high_water_mark: config.high_water_mark,
+            types,
+            sample_rate: config.sample_rate.max(1),
+            shed: Default::default(),
+        })
+    }
+
+    /// Check if the event should be dropped, with `queued` points waiting to be written.
+    pub fn shed(&self, event: &Event, queued: usize) -> bool {
+        if queued < self.high_water_mark || !filter::matches(&self.types, event.ty()) {
+            return false;
+        }
+
+        EVENTS_SHED.with_label_values(&[event.ty()]).inc();
+
+        let shed = self.shed.fetch_add(1, Ordering::Relaxed);
+        if shed.is_multiple_of(self.sample_rate) {
+            log::warn!(
+                "Dropping low priority events, {} queued - sample: id: {}, type: {}, source: {}",
+                queued,
+                event.id(),
+                event.ty(),
+                event.source()
+            );
+        }
+
+        true
+    }
+}
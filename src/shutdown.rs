@@ -0,0 +1,77 @@
+use crate::control::Control;
+use crate::error::ServiceError;
+use actix_web::dev::Payload;
+use actix_web::{web, FromRequest, HttpRequest};
+use envconfig::Envconfig;
+use futures::future::{ready, Ready};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+#[derive(Envconfig, Clone, Debug)]
+pub struct ShutdownConfig {
+    /// Time to finish in-flight requests and buffered writes, after receiving SIGTERM.
+    #[envconfig(from = "SHUTDOWN_TIMEOUT_S", default = "30")]
+    pub timeout_s: u64,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Shutdown {
+    flag: Arc<AtomicBool>,
+}
+
+impl Shutdown {
+    pub fn trigger(&self) {
+        self.flag.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_triggered(&self) -> bool {
+        self.flag.load(Ordering::Relaxed)
+    }
+}
+
+/// Wait for SIGTERM or SIGINT.
+pub async fn signal() {
+    use actix_rt::signal::unix::{signal, SignalKind};
+
+    match (
+        signal(SignalKind::terminate()),
+        signal(SignalKind::interrupt()),
+    ) {
+        (Ok(mut term), Ok(mut int)) => {
+            futures::future::select(Box::pin(term.recv()), Box::pin(int.recv())).await;
+        }
+        _ => {
+            log::warn!("Failed to register signal handlers, using Ctrl-C only");
+            let _ = actix_rt::signal::ctrl_c().await;
+        }
+    }
+}
+
+/// Extractor rejecting requests once the shutdown started, or while paused.
+///
+/// Put it first in the handler's arguments.
+pub struct Accepting;
+
+impl FromRequest for Accepting {
+    type Config = ();
+    type Error = ServiceError;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let shutting_down = req
+            .app_data::<web::Data<Shutdown>>()
+            .is_some_and(|shutdown| shutdown.is_triggered());
+        let paused = req
+            .app_data::<web::Data<Control>>()
+            .filter(|control| control.is_paused());
+
+        let result = match (shutting_down, paused) {
+            (true, _) => Err(ServiceError::ShuttingDown),
+            (_, Some(control)) => Err(ServiceError::Paused {
+                retry_after: control.retry_after_s,
+            }),
+            _ => Ok(Accepting),
+        };
+        ready(result)
+    }
+}
@@ -0,0 +1,264 @@
+use crate::config::InfluxDb;
+use crate::failover::FailoverSink;
+use crate::influx::{self, InfluxClient};
+use crate::questdb::{QuestDbConfig, QuestDbSink};
+use crate::timescale::{PgConfig, TimescaleSink};
+use crate::udp::{Protocol, UdpSink};
+use futures::future::{join_all, ready, LocalBoxFuture};
+use futures::FutureExt;
+use influxdb::{Error, WriteQuery};
+use std::fmt::Debug;
+use std::str::FromStr;
+use std::sync::Arc;
+
+/// Where points are written to.
+pub trait Sink: Debug + Send + Sync {
+    /// Write points, together with the retention policy of each.
+    fn write<'a>(
+        &'a self,
+        points: &'a [(Option<String>, WriteQuery)],
+    ) -> LocalBoxFuture<'a, Result<(), Error>>;
+
+    /// Check the connection, if there is one.
+    fn ping(&self) -> LocalBoxFuture<'_, Result<(), Error>> {
+        ready(Ok(())).boxed_local()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SinkKind {
+    InfluxDb,
+    /// A second InfluxDB, at `INFLUXDB_MIRROR_URI`, sharing the rest of the configuration.
+    InfluxDbMirror,
+    /// Print the line protocol, for local development without a database.
+    Stdout,
+    /// PostgreSQL or TimescaleDB, requires the `timescaledb` feature.
+    TimescaleDb,
+    /// QuestDB, over its line protocol listener, requires the `questdb` feature.
+    QuestDb,
+}
+
+impl FromStr for SinkKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "influxdb" => Ok(SinkKind::InfluxDb),
+            "influxdb-mirror" => Ok(SinkKind::InfluxDbMirror),
+            "stdout" => Ok(SinkKind::Stdout),
+            "timescaledb" | "postgres" => Ok(SinkKind::TimescaleDb),
+            "questdb" => Ok(SinkKind::QuestDb),
+            _ => anyhow::bail!("Unknown sink: {}", s),
+        }
+    }
+}
+
+impl SinkKind {
+    pub fn create(
+        self,
+        influx: &InfluxDb,
+        client: &InfluxClient,
+        pg: &PgConfig,
+        questdb: &QuestDbConfig,
+    ) -> anyhow::Result<Arc<dyn Sink>> {
+        Ok(match self {
+            SinkKind::InfluxDb if influx.protocol == Protocol::Udp => {
+                if influx.fallback_uri.is_some() {
+                    anyhow::bail!("INFLUXDB_URI_FALLBACK can't be used with UDP");
+                }
+                Arc::new(UdpSink::new(influx)?)
+            }
+            SinkKind::InfluxDb => match &influx.fallback_uri {
+                Some(uri) => {
+                    let fallback = InfluxDb {
+                        uri: uri.clone(),
+                        ..influx.clone()
+                    };
+                    Arc::new(FailoverSink::new(
+                        client.clone(),
+                        InfluxClient::new(&fallback)?,
+                        influx,
+                    ))
+                }
+                None => Arc::new(InfluxSink {
+                    client: client.clone(),
+                }),
+            },
+            SinkKind::InfluxDbMirror => {
+                let uri = match &influx.mirror_uri {
+                    Some(uri) => uri.clone(),
+                    None => anyhow::bail!("The influxdb-mirror sink requires INFLUXDB_MIRROR_URI"),
+                };
+                let mirror = InfluxDb {
+                    uri,
+                    ..influx.clone()
+                };
+                Arc::new(InfluxSink {
+                    client: InfluxClient::new(&mirror)?,
+                })
+            }
+            SinkKind::Stdout => Arc::new(StdoutSink),
+            SinkKind::TimescaleDb => Arc::new(TimescaleSink::from_config(pg.clone())?),
+            SinkKind::QuestDb => Arc::new(QuestDbSink::from_config(questdb.clone())?),
+        })
+    }
+}
+
+/// The sinks to write to, like `influxdb,stdout`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SinkKinds(pub Vec<SinkKind>);
+
+impl FromStr for SinkKinds {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let kinds = s
+            .split(',')
+            .map(|kind| kind.trim().parse())
+            .collect::<Result<Vec<SinkKind>, _>>()?;
+
+        let mut seen = Vec::new();
+        for kind in &kinds {
+            if seen.contains(kind) {
+                anyhow::bail!("Sink configured more than once: {:?}", kind);
+            }
+            seen.push(*kind);
+        }
+
+        Ok(SinkKinds(kinds))
+    }
+}
+
+impl SinkKinds {
+    /// A single sink, or one writing to all of them.
+    pub fn create(
+        &self,
+        policy: SinkPolicy,
+        influx: &InfluxDb,
+        client: &InfluxClient,
+        pg: &PgConfig,
+        questdb: &QuestDbConfig,
+    ) -> anyhow::Result<Arc<dyn Sink>> {
+        let mut sinks = self
+            .0
+            .iter()
+            .map(|kind| Ok((*kind, kind.create(influx, client, pg, questdb)?)))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        if sinks.len() == 1 {
+            return Ok(sinks.remove(0).1);
+        }
+        Ok(Arc::new(TeeSink { sinks, policy }))
+    }
+}
+
+/// How failures of some of the sinks end up in the result, and so in the response.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SinkPolicy {
+    /// Write one sink after the other, failing with the first failing one.
+    FailFast,
+    /// Write to all sinks, failing only if none of them succeeds.
+    BestEffort,
+}
+
+impl FromStr for SinkPolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "fail-fast" => Ok(SinkPolicy::FailFast),
+            "best-effort" => Ok(SinkPolicy::BestEffort),
+            _ => anyhow::bail!("Unknown sink policy: {}", s),
+        }
+    }
+}
+
+/// Writes points to several sinks.
+#[derive(Debug)]
+pub struct TeeSink {
+    sinks: Vec<(SinkKind, Arc<dyn Sink>)>,
+    policy: SinkPolicy,
+}
+
+impl TeeSink {
+    async fn all<'a, F>(&'a self, op: F) -> Result<(), Error>
+    where
+        F: Fn(&'a dyn Sink) -> LocalBoxFuture<'a, Result<(), Error>>,
+    {
+        match self.policy {
+            SinkPolicy::FailFast => {
+                for (_, sink) in &self.sinks {
+                    op(sink.as_ref()).await?;
+                }
+                Ok(())
+            }
+            SinkPolicy::BestEffort => {
+                let results = join_all(self.sinks.iter().map(|(_, sink)| op(sink.as_ref()))).await;
+
+                let mut failure = None;
+                let mut succeeded = false;
+                for ((kind, _), result) in self.sinks.iter().zip(results) {
+                    match result {
+                        Ok(()) => succeeded = true,
+                        Err(err) => {
+                            log::warn!("Failed to write to {:?}: {}", kind, err);
+                            failure = Some(err);
+                        }
+                    }
+                }
+
+                match failure {
+                    Some(err) if !succeeded => Err(err),
+                    _ => Ok(()),
+                }
+            }
+        }
+    }
+}
+
+impl Sink for TeeSink {
+    fn write<'a>(
+        &'a self,
+        points: &'a [(Option<String>, WriteQuery)],
+    ) -> LocalBoxFuture<'a, Result<(), Error>> {
+        self.all(move |sink| sink.write(points)).boxed_local()
+    }
+
+    fn ping(&self) -> LocalBoxFuture<'_, Result<(), Error>> {
+        self.all(|sink| sink.ping()).boxed_local()
+    }
+}
+
+#[derive(Debug)]
+pub struct InfluxSink {
+    client: InfluxClient,
+}
+
+impl Sink for InfluxSink {
+    fn write<'a>(
+        &'a self,
+        points: &'a [(Option<String>, WriteQuery)],
+    ) -> LocalBoxFuture<'a, Result<(), Error>> {
+        self.client.write_routed(points).boxed_local()
+    }
+
+    fn ping(&self) -> LocalBoxFuture<'_, Result<(), Error>> {
+        self.client.ping().boxed_local()
+    }
+}
+
+#[derive(Debug)]
+pub struct StdoutSink;
+
+impl Sink for StdoutSink {
+    fn write<'a>(
+        &'a self,
+        points: &'a [(Option<String>, WriteQuery)],
+    ) -> LocalBoxFuture<'a, Result<(), Error>> {
+        let result = points.iter().try_for_each(|(_, query)| {
+            println!("{}", influx::line(query)?);
+            Ok(())
+        });
+        ready(result).boxed_local()
+    }
+}
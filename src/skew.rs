@@ -0,0 +1,83 @@
+use crate::error::ServiceError;
+use crate::provision::Retention;
+use chrono::{DateTime, Duration, Utc};
+use envconfig::Envconfig;
+use std::str::FromStr;
+
+#[derive(Envconfig, Clone, Debug)]
+pub struct SkewConfig {
+    /// How old points may be, like `30d`, unlimited if not set.
+    #[envconfig(from = "MAX_PAST_SKEW")]
+    pub max_past: Option<Retention>,
+    /// How far ahead of our clock points may be, like `5m`, unlimited if not set.
+    #[envconfig(from = "MAX_FUTURE_SKEW")]
+    pub max_future: Option<Retention>,
+    #[envconfig(from = "SKEW_ACTION", default = "reject")]
+    pub action: SkewAction,
+}
+
+/// What happens to points outside of the window.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SkewAction {
+    Reject,
+    /// Write the points to the quarantine measurement of the denylist, instead of the mapped ones.
+    Quarantine,
+}
+
+impl FromStr for SkewAction {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "reject" => Ok(SkewAction::Reject),
+            "quarantine" => Ok(SkewAction::Quarantine),
+            _ => anyhow::bail!("Unknown action: {}", s),
+        }
+    }
+}
+
+/// Window around the current time, keeping devices with broken clocks off the dashboards.
+///
+/// Checks the time the point would be written with, after taking it from the payload.
+#[derive(Clone, Debug)]
+pub struct SkewWindow {
+    max_past: Option<Duration>,
+    max_future: Option<Duration>,
+    pub action: SkewAction,
+}
+
+impl SkewWindow {
+    pub fn from_config(config: SkewConfig) -> Option<Self> {
+        let duration = |retention: Retention| Duration::seconds(retention.as_secs() as i64);
+        if config.max_past.is_none() && config.max_future.is_none() {
+            return None;
+        }
+        Some(Self {
+            max_past: config.max_past.map(duration),
+            max_future: config.max_future.map(duration),
+            action: config.action,
+        })
+    }
+
+    /// If the point should be quarantined, fails if it should be rejected.
+    pub fn check(&self, time: DateTime<Utc>) -> Result<bool, ServiceError> {
+        let now = Utc::now();
+        let skew = match (self.max_past, self.max_future) {
+            (Some(max), _) if time < now - max => {
+                format!("{} is more than {}s in the past", time, max.num_seconds())
+            }
+            (_, Some(max)) if time > now + max => {
+                format!("{} is more than {}s in the future", time, max.num_seconds())
+            }
+            _ => return Ok(false),
+        };
+
+        match self.action {
+            SkewAction::Reject => Err(ServiceError::TimestampOutOfRange { details: skew }),
+            SkewAction::Quarantine => {
+                log::debug!("Quarantining point: {}", skew);
+                Ok(true)
+            }
+        }
+    }
+}
@@ -0,0 +1,73 @@
+use crate::config::Processor;
+use crate::handler::{self, HandleParams};
+use crate::shutdown::Shutdown;
+use actix_web::http::StatusCode;
+use cloudevents::Event;
+use envconfig::Envconfig;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// Where events come from, HTTP is always available for everything else.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Source {
+    Http,
+    Kafka,
+    Mqtt,
+}
+
+impl FromStr for Source {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "http" => Ok(Source::Http),
+            "kafka" => Ok(Source::Kafka),
+            "mqtt" => Ok(Source::Mqtt),
+            _ => anyhow::bail!("Unknown source: {}", s),
+        }
+    }
+}
+
+#[derive(Envconfig, Clone, Debug)]
+pub struct SourceConfig {
+    #[envconfig(from = "SOURCE", default = "http")]
+    pub source: Source,
+    /// Time to wait before processing an event again, after failing to write it.
+    #[cfg_attr(not(any(feature = "kafka", feature = "mqtt")), allow(dead_code))]
+    #[envconfig(from = "SOURCE_RETRY_BACKOFF_MS", default = "1000")]
+    pub retry_backoff_ms: u64,
+}
+
+impl SourceConfig {
+    #[cfg_attr(not(any(feature = "kafka", feature = "mqtt")), allow(dead_code))]
+    pub fn retry_backoff(&self) -> Duration {
+        Duration::from_millis(self.retry_backoff_ms)
+    }
+}
+
+/// Process an event until it doesn't fail on our side, `false` if interrupted by the shutdown.
+///
+/// Being too busy counts as failing. Sources acknowledge an event only once this returned `true`.
+#[cfg_attr(not(any(feature = "kafka", feature = "mqtt")), allow(dead_code))]
+pub async fn deliver(
+    event: Event,
+    processor: &Processor,
+    shutdown: &Shutdown,
+    backoff: Duration,
+) -> bool {
+    loop {
+        let status =
+            match handler::handle_event(event.clone(), HandleParams::default(), processor).await {
+                Ok(response) => response.status(),
+                Err(err) => err.as_response_error().error_response().status(),
+            };
+
+        if !status.is_server_error() && status != StatusCode::TOO_MANY_REQUESTS {
+            return true;
+        }
+        if shutdown.is_triggered() {
+            return false;
+        }
+        actix_rt::time::delay_for(backoff).await;
+    }
+}
@@ -0,0 +1,107 @@
+use crate::batch::Batcher;
+use crate::config::Processor;
+use crate::error::ServiceError;
+use crate::sink::Sink;
+use actix_web::{web, HttpResponse};
+use envconfig::Envconfig;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+#[derive(Envconfig, Clone, Debug)]
+pub struct StartupConfig {
+    /// Exit, instead of running without processing events, if the configuration is invalid
+    /// or InfluxDB can't be reached.
+    #[envconfig(from = "STRICT_STARTUP", default = "false")]
+    pub strict: bool,
+    #[envconfig(from = "STARTUP_PROBE_TIMEOUT_S", default = "10")]
+    pub probe_timeout_s: u64,
+}
+
+/// What `/health/readiness` reports.
+///
+/// Not ready if the configuration failed, or until InfluxDB could be reached. Retries the
+/// connection when asked, so that the function becomes ready once InfluxDB is. Not ready
+/// either while the write queue is full.
+#[derive(Debug)]
+pub struct Readiness {
+    problem: Option<String>,
+    sink: Option<Arc<dyn Sink>>,
+    batcher: Option<Batcher>,
+    connected: AtomicBool,
+    timeout: Duration,
+}
+
+impl Readiness {
+    /// Not ready, because the configuration failed.
+    pub fn failed(config: &StartupConfig, problem: String) -> Self {
+        Self {
+            problem: Some(problem),
+            sink: None,
+            batcher: None,
+            connected: AtomicBool::new(false),
+            timeout: Duration::from_secs(config.probe_timeout_s),
+        }
+    }
+
+    /// Ready once InfluxDB can be reached, right away in dry-run mode.
+    pub fn new(config: &StartupConfig, processor: &Processor) -> Self {
+        Self {
+            problem: None,
+            sink: Some(processor.sink.clone()),
+            batcher: processor.batcher.clone(),
+            connected: AtomicBool::new(processor.dry_run),
+            timeout: Duration::from_secs(config.probe_timeout_s),
+        }
+    }
+
+    /// Probe the connection, with `STRICT_STARTUP` failing if there is none.
+    pub async fn probe(&self, config: &StartupConfig) -> anyhow::Result<()> {
+        match self.check().await {
+            Err(err) if config.strict => anyhow::bail!("{}", err),
+            Err(err) => {
+                log::warn!("{}", err);
+                Ok(())
+            }
+            Ok(()) => {
+                log::info!("Connected to InfluxDB");
+                Ok(())
+            }
+        }
+    }
+
+    async fn check(&self) -> Result<(), ServiceError> {
+        if let Some(problem) = &self.problem {
+            return Err(ServiceError::NotReady {
+                details: problem.clone(),
+            });
+        }
+        if let Some(batcher) = self.batcher.as_ref().filter(|batcher| batcher.is_full()) {
+            return Err(ServiceError::NotReady {
+                details: format!("Write queue is full, {} points queued", batcher.queued()),
+            });
+        }
+        if self.connected.load(Ordering::Relaxed) {
+            return Ok(());
+        }
+        let sink = match &self.sink {
+            Some(sink) => sink,
+            None => return Ok(()),
+        };
+
+        let details = match actix_rt::time::timeout(self.timeout, sink.ping()).await {
+            Ok(Ok(_)) => {
+                self.connected.store(true, Ordering::Relaxed);
+                return Ok(());
+            }
+            Ok(Err(err)) => format!("Failed to connect to InfluxDB: {}", err),
+            Err(_) => "Timeout connecting to InfluxDB".to_string(),
+        };
+        Err(ServiceError::NotReady { details })
+    }
+}
+
+pub async fn readiness(readiness: web::Data<Readiness>) -> Result<HttpResponse, ServiceError> {
+    readiness.check().await?;
+    Ok(HttpResponse::Ok().finish())
+}
@@ -0,0 +1,10 @@
+use serde_json::Value;
+
+pub struct StaticPath {
+    pub name: &'static str,
+    pub path: &'static str,
+    pub r#type: &'static str,
+    pub select: fn(&Value) -> Vec<&Value>,
+}
+
+include!(concat!(env!("OUT_DIR"), "/static_mapping.rs"));
@@ -0,0 +1,83 @@
+use crate::metrics::{Baseline, Snapshot};
+use chrono::Utc;
+use envconfig::Envconfig;
+use std::time::Duration;
+
+#[derive(Envconfig, Clone, Debug)]
+pub struct StatsConfig {
+    /// Where to send the events to, like the `K_SINK` of a Knative `SinkBinding`.
+    #[envconfig(from = "STATS_SINK_URL")]
+    pub url: Option<String>,
+    #[envconfig(from = "STATS_INTERVAL_S", default = "60")]
+    pub interval_s: u64,
+    #[envconfig(from = "STATS_EVENT_TYPE", default = "io.drogue.influxdb.stats")]
+    pub r#type: String,
+    #[envconfig(from = "STATS_EVENT_SOURCE", default = "drogue-influxdb-function")]
+    pub source: String,
+}
+
+/// Sends the metrics as CloudEvents, for monitoring which doesn't scrape Prometheus.
+///
+/// Each event carries what was counted since the previous one, like `/metrics/snapshot`.
+/// If sending fails, the next event covers both intervals.
+#[derive(Clone, Debug)]
+pub struct Stats {
+    url: String,
+    interval: Duration,
+    r#type: String,
+    source: String,
+    client: reqwest::Client,
+}
+
+impl Stats {
+    pub fn from_config(config: StatsConfig) -> anyhow::Result<Option<Self>> {
+        let url = match config.url {
+            Some(url) => url,
+            None => return Ok(None),
+        };
+        reqwest::Url::parse(&url)
+            .map_err(|err| anyhow::anyhow!("Invalid STATS_SINK_URL {}: {}", url, err))?;
+
+        Ok(Some(Self {
+            url,
+            interval: Duration::from_secs(config.interval_s.max(1)),
+            r#type: config.r#type,
+            source: config.source,
+            client: reqwest::Client::new(),
+        }))
+    }
+
+    /// Start sending periodically on the current arbiter.
+    pub fn start(self) {
+        actix_rt::spawn(async move {
+            let mut interval = actix_rt::time::interval(self.interval);
+            let mut baseline = Baseline::now();
+            // the first tick is immediate
+            interval.tick().await;
+            loop {
+                interval.tick().await;
+                let (snapshot, next) = baseline.next();
+                match self.send(&snapshot).await {
+                    Ok(()) => baseline = next,
+                    Err(err) => log::warn!("Failed to send stats to {}: {}", self.url, err),
+                }
+            }
+        });
+    }
+
+    async fn send(&self, snapshot: &Snapshot) -> anyhow::Result<()> {
+        self.client
+            .post(&self.url)
+            .header("ce-specversion", "1.0")
+            .header("ce-id", format!("{:016x}", rand::random::<u64>()))
+            .header("ce-source", self.source.as_str())
+            .header("ce-type", self.r#type.as_str())
+            .header("ce-time", Utc::now().to_rfc3339())
+            .header("content-type", "application/json")
+            .body(serde_json::to_vec(snapshot)?)
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,145 @@
+use envconfig::Envconfig;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+#[derive(Envconfig, Clone, Debug)]
+pub struct StickyConfig {
+    /// Comma separated list of tags, which are re-applied from the device's previous event.
+    #[envconfig(from = "STICKY_TAGS", default = "")]
+    pub tags: String,
+    /// JSON path into the event, identifying the device.
+    #[envconfig(from = "STICKY_KEY_PATH", default = "$.source")]
+    pub key_path: String,
+    #[envconfig(from = "STICKY_MAX_DEVICES", default = "10000")]
+    pub max_devices: usize,
+}
+
+type Cache = HashMap<(String, String), HashMap<String, String>>;
+
+/// Tags of a device, as persisted in a checkpoint.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct StickyEntry {
+    pub table: String,
+    pub device: String,
+    pub tags: HashMap<String, String>,
+}
+
+/// Remembers the last value of selected tags, per device and measurement.
+#[derive(Clone, Debug)]
+pub struct StickyTags {
+    tags: Vec<String>,
+    key_path: jsonpath_lib::Compiled,
+    max_devices: usize,
+    cache: Arc<Mutex<Cache>>,
+}
+
+impl StickyTags {
+    pub fn from_config(config: StickyConfig) -> anyhow::Result<Option<Self>> {
+        let tags: Vec<_> = config
+            .tags
+            .split(',')
+            .map(str::trim)
+            .filter(|tag| !tag.is_empty())
+            .map(str::to_lowercase)
+            .collect();
+
+        if tags.is_empty() {
+            return Ok(None);
+        }
+
+        let key_path = jsonpath_lib::Compiled::compile(&config.key_path)
+            .map_err(|err| anyhow::anyhow!("Failed to parse STICKY_KEY_PATH: {}", err))?;
+
+        Ok(Some(Self {
+            tags,
+            key_path,
+            max_devices: config.max_devices,
+            cache: Default::default(),
+        }))
+    }
+
+    /// Find the device of the event.
+    pub fn device(&self, event: &Value) -> Option<String> {
+        match self.key_path.select(event).ok()?.as_slice() {
+            [Value::String(s)] => Some(s.clone()),
+            [value] => Some(value.to_string()),
+            _ => None,
+        }
+    }
+
+    /// Remember the sticky tags which are present, and return the missing ones.
+    pub fn apply(
+        &self,
+        table: &str,
+        device: &str,
+        tags: &HashMap<String, String>,
+    ) -> Vec<(String, String)> {
+        let mut cache = self.cache.lock().unwrap();
+
+        let key = (table.to_string(), device.to_string());
+        if !cache.contains_key(&key) && cache.len() >= self.max_devices {
+            // make room, we don't track which one is the oldest
+            if let Some(evict) = cache.keys().next().cloned() {
+                cache.remove(&evict);
+            }
+        }
+        let known = cache.entry(key).or_default();
+
+        let mut missing = Vec::new();
+        for tag in &self.tags {
+            match tags.get(tag) {
+                Some(value) => {
+                    known.insert(tag.clone(), value.clone());
+                }
+                None => {
+                    if let Some(value) = known.get(tag) {
+                        missing.push((tag.clone(), value.clone()));
+                    }
+                }
+            }
+        }
+
+        missing
+    }
+
+    /// The missing sticky tags, without remembering anything, for looking at sample events.
+    pub fn known(
+        &self,
+        table: &str,
+        device: &str,
+        tags: &HashMap<String, String>,
+    ) -> Vec<(String, String)> {
+        let cache = self.cache.lock().unwrap();
+        let known = match cache.get(&(table.to_string(), device.to_string())) {
+            Some(known) => known,
+            None => return Vec::new(),
+        };
+        self.tags
+            .iter()
+            .filter(|tag| !tags.contains_key(*tag))
+            .filter_map(|tag| Some((tag.clone(), known.get(tag)?.clone())))
+            .collect()
+    }
+
+    pub fn snapshot(&self) -> Vec<StickyEntry> {
+        self.cache
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|((table, device), tags)| StickyEntry {
+                table: table.clone(),
+                device: device.clone(),
+                tags: tags.clone(),
+            })
+            .collect()
+    }
+
+    pub fn restore(&self, entries: Vec<StickyEntry>) {
+        let mut cache = self.cache.lock().unwrap();
+        for entry in entries.into_iter().take(self.max_devices) {
+            cache.insert((entry.table, entry.device), entry.tags);
+        }
+    }
+}
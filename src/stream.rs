@@ -0,0 +1,211 @@
+use crate::error::ServiceError;
+use envconfig::Envconfig;
+use serde_json::Value;
+
+#[derive(Envconfig, Clone, Debug)]
+pub struct StreamConfig {
+    /// Parse payloads which are JSON arrays one element at a time, each becoming a reading of
+    /// its own, like NDJSON lines, instead of parsing the whole array first.
+    #[envconfig(from = "STREAM_JSON_ARRAYS", default = "false")]
+    pub enabled: bool,
+    /// Elements which are mapped, and written, at a time.
+    #[envconfig(from = "STREAM_CHUNK_SIZE", default = "1000")]
+    pub chunk_size: usize,
+}
+
+/// Maps and writes large arrays in chunks, so that only a chunk of them is held as JSON.
+///
+/// Stages which need the whole payload, like transformers, presets, records and payload
+/// filters, turn streaming off, the payload is processed like any other then.
+#[derive(Clone, Debug)]
+pub struct Streaming {
+    pub chunk_size: usize,
+}
+
+impl Streaming {
+    pub fn from_config(config: StreamConfig) -> anyhow::Result<Option<Self>> {
+        if !config.enabled {
+            return Ok(None);
+        }
+        if config.chunk_size == 0 {
+            anyhow::bail!("STREAM_CHUNK_SIZE must be greater than zero");
+        }
+        Ok(Some(Self {
+            chunk_size: config.chunk_size,
+        }))
+    }
+}
+
+/// The elements of a JSON array, `None` if the payload isn't one.
+pub fn elements(data: &[u8]) -> Option<Elements<'_>> {
+    let rest = skip_whitespace(data).strip_prefix(b"[")?;
+    Some(Elements {
+        rest,
+        index: 0,
+        closed: false,
+        done: false,
+    })
+}
+
+/// Parses the elements of an array one by one, failing at the first invalid one.
+pub struct Elements<'a> {
+    rest: &'a [u8],
+    index: usize,
+    /// The array ended, what follows is checked after its last element.
+    closed: bool,
+    done: bool,
+}
+
+impl<'a> Elements<'a> {
+    /// The array ended, an error now is about what follows it, not an element.
+    pub fn ended(&self) -> bool {
+        self.closed
+    }
+
+    fn fail(&mut self, details: String) -> Option<Result<Value, ServiceError>> {
+        self.done = true;
+        Some(Err(ServiceError::PayloadParseError {
+            details: format!("Invalid JSON array, at element {}: {}", self.index, details),
+        }))
+    }
+
+    /// Consume the end of the array, which may only be followed by whitespace.
+    fn end(&mut self, rest: &'a [u8]) -> Option<Result<Value, ServiceError>> {
+        self.done = true;
+        match skip_whitespace(rest).is_empty() {
+            true => None,
+            false => self.fail("Trailing data".to_string()),
+        }
+    }
+}
+
+impl<'a> Iterator for Elements<'a> {
+    type Item = Result<Value, ServiceError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        if self.closed {
+            return self.end(self.rest);
+        }
+
+        let rest = skip_whitespace(self.rest);
+        if self.index == 0 {
+            if let Some(rest) = rest.strip_prefix(b"]") {
+                return self.end(rest);
+            }
+        }
+
+        let mut values = serde_json::Deserializer::from_slice(rest).into_iter::<Value>();
+        let value = match values.next() {
+            Some(Ok(value)) => value,
+            Some(Err(err)) => return self.fail(err.to_string()),
+            None => return self.fail("Missing end of array".to_string()),
+        };
+        let rest = skip_whitespace(&rest[values.byte_offset()..]);
+
+        match rest.first() {
+            Some(b',') => {
+                self.rest = &rest[1..];
+                self.index += 1;
+            }
+            Some(b']') => {
+                self.rest = &rest[1..];
+                self.closed = true;
+            }
+            Some(_) => return self.fail("Expected , or ]".to_string()),
+            None => return self.fail("Missing end of array".to_string()),
+        }
+        Some(Ok(value))
+    }
+}
+
+fn skip_whitespace(data: &[u8]) -> &[u8] {
+    let start = data
+        .iter()
+        .position(|b| !b.is_ascii_whitespace())
+        .unwrap_or(data.len());
+    &data[start..]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn collect(data: &[u8]) -> Vec<Result<Value, String>> {
+        elements(data)
+            .unwrap()
+            .map(|element| element.map_err(|err| err.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_elements() {
+        assert_eq!(
+            collect(b" [ {\"a\": [1, 2]} , 3,\"x\" ] \n"),
+            vec![Ok(json!({"a": [1, 2]})), Ok(json!(3)), Ok(json!("x"))]
+        );
+        assert_eq!(collect(b"[]"), vec![]);
+        assert!(elements(b"{\"a\": 1}").is_none());
+    }
+
+    #[test]
+    fn test_truncated() {
+        let elements = collect(b"[1, 2");
+        assert_eq!(elements[0], Ok(json!(1)));
+        assert!(elements[1]
+            .as_ref()
+            .unwrap_err()
+            .contains("Missing end of array"));
+        assert_eq!(elements.len(), 2);
+
+        let elements = collect(b"[1, {\"a\":");
+        assert!(elements[1].as_ref().unwrap_err().contains("at element 1"));
+    }
+
+    #[test]
+    fn test_trailing_data() {
+        let mut elements = elements(b"[1, 2] x").unwrap();
+        assert_eq!(elements.next().unwrap().unwrap(), json!(1));
+        assert_eq!(elements.next().unwrap().unwrap(), json!(2));
+        assert!(elements.ended());
+        let err = elements.next().unwrap().unwrap_err();
+        assert!(err.to_string().contains("Trailing data"));
+        assert!(elements.next().is_none());
+
+        assert!(collect(b"[] x")[0]
+            .as_ref()
+            .unwrap_err()
+            .contains("Trailing data"));
+    }
+
+    #[test]
+    fn test_malformed() {
+        let elements = collect(b"[1 2]");
+        assert_eq!(elements.len(), 1);
+        assert!(elements[0]
+            .as_ref()
+            .unwrap_err()
+            .contains("Expected , or ]"));
+        assert!(collect(b"[,]")[0].is_err());
+    }
+
+    #[test]
+    fn test_config() {
+        let config = |enabled, chunk_size| StreamConfig {
+            enabled,
+            chunk_size,
+        };
+        assert!(Streaming::from_config(config(false, 0)).unwrap().is_none());
+        assert!(Streaming::from_config(config(true, 0)).is_err());
+        assert_eq!(
+            Streaming::from_config(config(true, 10))
+                .unwrap()
+                .unwrap()
+                .chunk_size,
+            10
+        );
+    }
+}
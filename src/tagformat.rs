@@ -0,0 +1,233 @@
+use influxdb::Type;
+
+/// Formatting of tag values, so that numbers and booleans become stable strings.
+///
+/// Configured with `FORMAT_TAG_` as a printf like directive, `%d`, `%f`, `%.<n>f` or `%s`,
+/// with optional text around it, like `room-%d`. Strings which are numbers are formatted like
+/// numbers, other strings are kept as they are. `CASE_TAG_` (`lower` or `upper`) is applied to
+/// the result, `PREFIX_TAG_` and `SUFFIX_TAG_` are added last.
+#[derive(Clone, Debug)]
+pub struct TagFormat {
+    directive: Option<(String, Directive, String)>,
+    case: Option<Case>,
+    prefix: String,
+    suffix: String,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Directive {
+    /// Rounded to an integer, booleans become `1` and `0`.
+    Integer,
+    Decimals(usize),
+    Text,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Case {
+    Lower,
+    Upper,
+}
+
+impl TagFormat {
+    /// `None` if none of the options are set.
+    pub fn from_spec(
+        format: Option<String>,
+        case: Option<String>,
+        prefix: Option<String>,
+        suffix: Option<String>,
+    ) -> anyhow::Result<Option<Self>> {
+        if format.is_none() && case.is_none() && prefix.is_none() && suffix.is_none() {
+            return Ok(None);
+        }
+
+        let directive = format.as_deref().map(parse).transpose()?;
+        let case = match case.as_deref().map(str::to_lowercase).as_deref() {
+            None => None,
+            Some("lower") => Some(Case::Lower),
+            Some("upper") => Some(Case::Upper),
+            Some(case) => anyhow::bail!("Unknown case, expected lower or upper: {}", case),
+        };
+
+        Ok(Some(Self {
+            directive,
+            case,
+            prefix: prefix.unwrap_or_default(),
+            suffix: suffix.unwrap_or_default(),
+        }))
+    }
+
+    pub fn apply(&self, value: &Type) -> Type {
+        let mut text = match &self.directive {
+            Some((before, directive, after)) => {
+                format!("{}{}{}", before, directive.format(value), after)
+            }
+            None => value.to_string(),
+        };
+        match self.case {
+            Some(Case::Lower) => text = text.to_lowercase(),
+            Some(Case::Upper) => text = text.to_uppercase(),
+            None => {}
+        }
+        Type::Text(format!("{}{}{}", self.prefix, text, self.suffix))
+    }
+}
+
+impl Directive {
+    fn format(&self, value: &Type) -> String {
+        let number = match value {
+            Type::Float(v) => Some(*v),
+            Type::SignedInteger(v) => Some(*v as f64),
+            Type::UnsignedInteger(v) => Some(*v as f64),
+            Type::Boolean(b) if *self == Directive::Integer => Some(if *b { 1.0 } else { 0.0 }),
+            Type::Text(s) => s.trim().parse::<f64>().ok().filter(|v| v.is_finite()),
+            Type::Boolean(_) => None,
+        };
+
+        match (self, number) {
+            // integers keep their precision
+            (Directive::Integer, _)
+                if matches!(value, Type::SignedInteger(_) | Type::UnsignedInteger(_)) =>
+            {
+                value.to_string()
+            }
+            (Directive::Integer, Some(number)) => format!("{}", number.round() as i64),
+            (Directive::Decimals(decimals), Some(number)) => {
+                format!("{:.*}", *decimals, number)
+            }
+            _ => value.to_string(),
+        }
+    }
+}
+
+/// Split a format into the text before the directive, the directive and the text after it.
+fn parse(format: &str) -> anyhow::Result<(String, Directive, String)> {
+    let mut before = String::new();
+    let mut found = None;
+    let mut after = String::new();
+
+    let mut chars = format.chars().peekable();
+    while let Some(c) = chars.next() {
+        let text = match found {
+            Some(_) => &mut after,
+            None => &mut before,
+        };
+        if c != '%' {
+            text.push(c);
+            continue;
+        }
+        if chars.peek() == Some(&'%') {
+            chars.next();
+            text.push('%');
+            continue;
+        }
+        if found.is_some() {
+            anyhow::bail!("More than one directive in tag format: {}", format);
+        }
+
+        let mut spec = String::new();
+        for c in chars.by_ref() {
+            spec.push(c);
+            if c.is_ascii_alphabetic() {
+                break;
+            }
+        }
+        found = Some(match spec.as_str() {
+            "d" | "i" => Directive::Integer,
+            "f" => Directive::Decimals(6),
+            "s" => Directive::Text,
+            spec => match spec
+                .strip_prefix('.')
+                .and_then(|spec| spec.strip_suffix('f'))
+                .and_then(|decimals| decimals.parse().ok())
+            {
+                Some(decimals) => Directive::Decimals(decimals),
+                None => anyhow::bail!("Unsupported tag format directive: %{}", spec),
+            },
+        });
+    }
+
+    match found {
+        Some(directive) => Ok((before, directive, after)),
+        None => anyhow::bail!("Missing directive in tag format: {}", format),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn format(format: &str) -> TagFormat {
+        TagFormat::from_spec(Some(format.into()), None, None, None)
+            .unwrap()
+            .unwrap()
+    }
+
+    fn text(value: Type) -> String {
+        match value {
+            Type::Text(text) => text,
+            value => panic!("Not text: {:?}", value),
+        }
+    }
+
+    #[test]
+    fn test_none() {
+        assert!(TagFormat::from_spec(None, None, None, None)
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn test_integer() {
+        let format = format("room-%d");
+        assert_eq!(text(format.apply(&Type::Float(2.6))), "room-3");
+        assert_eq!(text(format.apply(&Type::SignedInteger(-4))), "room--4");
+        assert_eq!(
+            text(format.apply(&Type::UnsignedInteger(u64::MAX))),
+            format!("room-{}", u64::MAX)
+        );
+        assert_eq!(text(format.apply(&Type::Boolean(true))), "room-1");
+        // strings which are numbers, like numbers
+        assert_eq!(text(format.apply(&Type::Text(" 7.2 ".into()))), "room-7");
+        assert_eq!(text(format.apply(&Type::Text("hall".into()))), "room-hall");
+    }
+
+    #[test]
+    fn test_decimals() {
+        assert_eq!(text(format("%.2f").apply(&Type::Float(1.005_1))), "1.01");
+        assert_eq!(
+            text(format("%f").apply(&Type::SignedInteger(1))),
+            "1.000000"
+        );
+        assert_eq!(text(format("%.0f%%").apply(&Type::Float(99.6))), "100%");
+        assert_eq!(text(format("%.1f").apply(&Type::Boolean(true))), "true");
+        assert_eq!(text(format("%s").apply(&Type::Float(1.5))), "1.5");
+    }
+
+    #[test]
+    fn test_case() {
+        let format = TagFormat::from_spec(
+            Some("%s".into()),
+            Some("Upper".into()),
+            Some("dev-".into()),
+            Some("-x".into()),
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(text(format.apply(&Type::Text("ab".into()))), "dev-AB-x");
+
+        let format = TagFormat::from_spec(None, Some("lower".into()), None, None)
+            .unwrap()
+            .unwrap();
+        assert_eq!(text(format.apply(&Type::Text("AB".into()))), "ab");
+    }
+
+    #[test]
+    fn test_invalid() {
+        let invalid = |format: &str| TagFormat::from_spec(Some(format.into()), None, None, None);
+        assert!(invalid("room").is_err());
+        assert!(invalid("%d-%d").is_err());
+        assert!(invalid("%x").is_err());
+        assert!(invalid("%.af").is_err());
+        assert!(TagFormat::from_spec(None, Some("title".into()), None, None).is_err());
+    }
+}
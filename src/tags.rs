@@ -0,0 +1,151 @@
+use crate::error::ServiceError;
+use envconfig::Envconfig;
+use openssl::sha::sha256;
+use std::collections::{HashMap, HashSet};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+
+#[derive(Envconfig, Clone, Debug)]
+pub struct TagConfig {
+    /// Tag values are cut off after this many characters.
+    #[envconfig(from = "MAX_TAG_LENGTH", default = "256")]
+    pub max_length: usize,
+    /// Distinct values allowed per measurement and tag, unlimited when zero.
+    #[envconfig(from = "MAX_TAG_VALUES", default = "0")]
+    pub max_values: usize,
+    #[envconfig(from = "MAX_TAG_VALUES_OVERFLOW", default = "reject")]
+    pub overflow: Overflow,
+    #[envconfig(from = "MAX_TAG_VALUES_BUCKETS", default = "16")]
+    pub overflow_buckets: u32,
+}
+
+/// What happens to values beyond `MAX_TAG_VALUES`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Overflow {
+    /// Reject the event.
+    Reject,
+    /// Replace the value by one of a fixed number of hash buckets.
+    Hash,
+}
+
+impl FromStr for Overflow {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "reject" => Ok(Overflow::Reject),
+            "hash" => Ok(Overflow::Hash),
+            _ => anyhow::bail!("Unknown tag overflow handling: {}", s),
+        }
+    }
+}
+
+/// Distinct values, by measurement and tag.
+type Values = HashMap<(String, String), HashSet<String>>;
+
+/// Keeps malformed tag values from creating a series explosion in InfluxDB.
+#[derive(Clone, Debug)]
+pub struct TagGuard {
+    max_length: usize,
+    cardinality: Option<Cardinality>,
+}
+
+#[derive(Clone, Debug)]
+struct Cardinality {
+    max_values: usize,
+    overflow: Overflow,
+    buckets: u32,
+    seen: Arc<Mutex<Values>>,
+}
+
+impl TagGuard {
+    pub fn from_config(config: TagConfig) -> anyhow::Result<Self> {
+        if config.overflow == Overflow::Hash && config.overflow_buckets == 0 {
+            anyhow::bail!("MAX_TAG_VALUES_BUCKETS must be greater than zero");
+        }
+
+        let cardinality = match config.max_values {
+            0 => None,
+            max_values => Some(Cardinality {
+                max_values,
+                overflow: config.overflow,
+                buckets: config.overflow_buckets,
+                seen: Default::default(),
+            }),
+        };
+
+        Ok(Self {
+            max_length: config.max_length,
+            cardinality,
+        })
+    }
+
+    /// The same guard, without counting values, for looking at sample events.
+    pub fn sanitizing_only(&self) -> Self {
+        Self {
+            max_length: self.max_length,
+            cardinality: None,
+        }
+    }
+
+    /// Clean up a tag value, `None` if nothing is left of it.
+    pub fn sanitize(&self, value: &str) -> Option<String> {
+        let value: String = value
+            .trim()
+            .chars()
+            .filter(|c| !c.is_control())
+            .take(self.max_length)
+            .collect();
+        if value.is_empty() {
+            None
+        } else {
+            Some(value)
+        }
+    }
+
+    /// Sanitize a value and check it against the cardinality limit.
+    pub fn admit(
+        &self,
+        table: &str,
+        tag: &str,
+        value: &str,
+    ) -> Result<Option<String>, ServiceError> {
+        let value = match self.sanitize(value) {
+            Some(value) => value,
+            None => return Ok(None),
+        };
+
+        let cardinality = match &self.cardinality {
+            Some(cardinality) => cardinality,
+            None => return Ok(Some(value)),
+        };
+
+        let mut seen = cardinality.seen.lock().unwrap();
+        let values = seen
+            .entry((table.to_string(), tag.to_string()))
+            .or_default();
+
+        if values.contains(&value) {
+            return Ok(Some(value));
+        }
+        if values.len() < cardinality.max_values {
+            values.insert(value.clone());
+            return Ok(Some(value));
+        }
+
+        match cardinality.overflow {
+            Overflow::Reject => Err(ServiceError::CardinalityExceeded {
+                details: format!(
+                    "Tag '{}' of '{}' exceeds {} distinct values",
+                    tag, table, cardinality.max_values
+                ),
+            }),
+            Overflow::Hash => {
+                let hash = sha256(value.as_bytes());
+                let bucket =
+                    u32::from_be_bytes([hash[0], hash[1], hash[2], hash[3]]) % cardinality.buckets;
+                Ok(Some(format!("overflow-{}", bucket)))
+            }
+        }
+    }
+}
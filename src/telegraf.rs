@@ -0,0 +1,124 @@
+use crate::config::Processor;
+use crate::error::ServiceError;
+use crate::preset::Preset;
+use crate::records::TimeUnit;
+use crate::tags::TagGuard;
+use chrono::{DateTime, Utc};
+use envconfig::Envconfig;
+use influxdb::{InfluxDbWriteable, Type, WriteQuery};
+use serde_json::Value;
+
+#[derive(Envconfig, Clone, Debug)]
+pub struct TelegrafConfig {
+    /// Unit of the timestamps, like `json_timestamp_units` of the serializer.
+    #[envconfig(from = "TELEGRAF_TIMESTAMP_UNIT", default = "s")]
+    pub unit: TimeUnit,
+}
+
+/// Metrics in the JSON format of Telegraf, written as they are instead of being mapped.
+///
+/// Takes a single metric, or a batch (`{"metrics": [...]}`). Numbers are written as floats,
+/// as the format doesn't tell integers apart.
+#[derive(Clone, Debug)]
+pub struct Telegraf {
+    unit: TimeUnit,
+    prefix: String,
+}
+
+impl Telegraf {
+    pub fn from_config(
+        config: TelegrafConfig,
+        preset: Option<Preset>,
+        prefix: &str,
+    ) -> Option<Self> {
+        if preset != Some(Preset::Telegraf) {
+            return None;
+        }
+        Some(Self {
+            unit: config.unit,
+            prefix: prefix.to_string(),
+        })
+    }
+
+    /// Points, with their measurement, at `time` if a metric has no timestamp.
+    pub fn queries(
+        &self,
+        json: &Value,
+        time: DateTime<Utc>,
+        guard: &TagGuard,
+        processor: &Processor,
+    ) -> Result<Vec<(String, WriteQuery)>, ServiceError> {
+        let metrics = match json.get("metrics") {
+            Some(Value::Array(metrics)) => metrics.iter().collect(),
+            _ => vec![json],
+        };
+        metrics
+            .into_iter()
+            .map(|metric| self.query(metric, time, guard, processor))
+            .collect()
+    }
+
+    fn query(
+        &self,
+        metric: &Value,
+        time: DateTime<Utc>,
+        guard: &TagGuard,
+        processor: &Processor,
+    ) -> Result<(String, WriteQuery), ServiceError> {
+        let invalid = |details: &str| ServiceError::PayloadParseError {
+            details: format!("Invalid Telegraf metric, {}", details),
+        };
+
+        let name = metric["name"]
+            .as_str()
+            .ok_or_else(|| invalid("missing name"))?;
+        let time = match &metric["timestamp"] {
+            Value::Null => time,
+            timestamp => timestamp
+                .as_f64()
+                .and_then(|timestamp| self.unit.since_epoch(timestamp))
+                .ok_or_else(|| invalid("bad timestamp"))?,
+        };
+
+        let table = processor.measurement(&format!("{}{}", self.prefix, name), time);
+        let mut query = processor
+            .precision
+            .timestamp(time)
+            .into_query(table.as_str());
+
+        if let Some(tags) = metric["tags"].as_object() {
+            for (tag, value) in tags {
+                let value = match value {
+                    Value::String(s) => s.clone(),
+                    value => value.to_string(),
+                };
+                if let Some(value) = guard.admit(&table, tag, &value)? {
+                    query = query.add_tag(tag, value);
+                }
+            }
+        }
+
+        let fields = metric["fields"]
+            .as_object()
+            .ok_or_else(|| invalid("missing fields"))?;
+        let mut num = 0;
+        for (field, value) in fields {
+            let value = match value {
+                Value::Bool(b) => Type::Boolean(*b),
+                Value::String(s) => Type::Text(s.clone()),
+                Value::Number(n) => match n.as_f64() {
+                    Some(n) => Type::Float(n),
+                    None => continue,
+                },
+                _ => continue,
+            };
+            query = query.add_field(field, value);
+            num += 1;
+        }
+        if num == 0 {
+            return Err(invalid("no fields"));
+        }
+
+        Ok((table, query))
+    }
+}
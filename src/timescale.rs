@@ -0,0 +1,376 @@
+use crate::sink::Sink;
+use envconfig::Envconfig;
+use futures::future::LocalBoxFuture;
+use influxdb::{Error, WriteQuery};
+use std::fmt;
+
+#[cfg_attr(not(feature = "timescaledb"), allow(dead_code))]
+#[derive(Envconfig, Clone)]
+pub struct PgConfig {
+    #[envconfig(from = "PG_HOST", default = "localhost")]
+    pub host: String,
+    #[envconfig(from = "PG_PORT", default = "5432")]
+    pub port: u16,
+    #[envconfig(from = "PG_USER", default = "postgres")]
+    pub user: String,
+    #[envconfig(from = "PG_PASSWORD")]
+    pub password: Option<String>,
+    #[envconfig(from = "PG_DATABASE", default = "postgres")]
+    pub database: String,
+    /// Turn new tables into TimescaleDB hypertables, disable for plain PostgreSQL.
+    #[envconfig(from = "PG_HYPERTABLE", default = "true")]
+    pub hypertable: bool,
+}
+
+impl fmt::Debug for PgConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PgConfig")
+            .field("host", &self.host)
+            .field("port", &self.port)
+            .field("user", &self.user)
+            .field("database", &self.database)
+            .field("hypertable", &self.hypertable)
+            .finish()
+    }
+}
+
+/// Writes points to PostgreSQL, or TimescaleDB, with a table per measurement.
+///
+/// Tables get a `time` column, and a column per tag (`TEXT`, indexed) and field, which are
+/// created as they show up. Retention policies don't apply and are ignored. The points of a
+/// write are inserted within one transaction.
+#[cfg(feature = "timescaledb")]
+pub struct TimescaleSink {
+    config: PgConfig,
+    state: futures::lock::Mutex<State>,
+}
+
+#[cfg(feature = "timescaledb")]
+#[derive(Default)]
+struct State {
+    client: Option<tokio_postgres::Client>,
+    /// Tables known to exist, with their columns.
+    tables: std::collections::HashMap<String, std::collections::HashSet<String>>,
+}
+
+#[cfg(feature = "timescaledb")]
+impl fmt::Debug for TimescaleSink {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TimescaleSink")
+            .field("config", &self.config)
+            .finish()
+    }
+}
+
+#[cfg(feature = "timescaledb")]
+impl TimescaleSink {
+    pub fn from_config(config: PgConfig) -> anyhow::Result<Self> {
+        Ok(Self {
+            config,
+            state: Default::default(),
+        })
+    }
+
+    /// Connect lazily, and again once the connection is lost.
+    async fn connect(&self, state: &mut State) -> Result<(), Error> {
+        if state
+            .client
+            .as_ref()
+            .is_none_or(|client| client.is_closed())
+        {
+            let mut config = tokio_postgres::Config::new();
+            config
+                .host(&self.config.host)
+                .port(self.config.port)
+                .user(&self.config.user)
+                .dbname(&self.config.database);
+            if let Some(password) = &self.config.password {
+                config.password(password);
+            }
+
+            let (client, connection) =
+                config.connect(tokio_postgres::NoTls).await.map_err(|err| {
+                    Error::ConnectionError {
+                        error: err.to_string(),
+                    }
+                })?;
+            actix_rt::spawn(async move {
+                if let Err(err) = connection.await {
+                    log::warn!("PostgreSQL connection failed: {}", err);
+                }
+            });
+
+            state.client = Some(client);
+            // the schema may have changed in the meantime
+            state.tables.clear();
+        }
+
+        Ok(())
+    }
+
+    async fn write_points(&self, points: &[(Option<String>, WriteQuery)]) -> Result<(), Error> {
+        let points = points
+            .iter()
+            .map(|(_, query)| crate::line::Point::from_query(query))
+            .collect::<anyhow::Result<Vec<_>>>()
+            .map_err(|err| Error::InvalidQueryError {
+                error: err.to_string(),
+            })?;
+
+        let rows = rows(&points)?;
+
+        let mut state = self.state.lock().await;
+        self.connect(&mut state).await?;
+        let State { client, tables } = &mut *state;
+        let client = client.as_mut().unwrap();
+
+        // the schema is changed up front, the cache of it would be off if that was rolled back
+        for point in &points {
+            self.prepare_table(client, tables, point)
+                .await
+                .map_err(database_error)?;
+        }
+
+        // all or nothing of the batch, with a statement per run of rows with the same columns
+        let transaction = client.transaction().await.map_err(database_error)?;
+        for rows in &rows {
+            insert(&transaction, rows).await.map_err(database_error)?;
+        }
+        transaction.commit().await.map_err(database_error)?;
+
+        Ok(())
+    }
+
+    /// Create the table and columns for the point, if they don't exist yet.
+    async fn prepare_table(
+        &self,
+        client: &tokio_postgres::Client,
+        tables: &mut std::collections::HashMap<String, std::collections::HashSet<String>>,
+        point: &crate::line::Point,
+    ) -> Result<(), tokio_postgres::Error> {
+        let table = ident(&point.measurement);
+
+        if !tables.contains_key(&point.measurement) {
+            log::debug!("Creating table {}", table);
+            client
+                .batch_execute(&format!(
+                    "CREATE TABLE IF NOT EXISTS {} (time TIMESTAMPTZ NOT NULL)",
+                    table
+                ))
+                .await?;
+            if self.config.hypertable {
+                client
+                    .execute(
+                        "SELECT create_hypertable($1::text::regclass, 'time', if_not_exists => TRUE)",
+                        &[&table],
+                    )
+                    .await?;
+            }
+            tables.insert(point.measurement.clone(), Default::default());
+        }
+
+        let columns = tables.get_mut(&point.measurement).unwrap();
+
+        for (tag, _) in &point.tags {
+            if columns.contains(tag) {
+                continue;
+            }
+            client
+                .batch_execute(&format!(
+                    "ALTER TABLE {table} ADD COLUMN IF NOT EXISTS {column} TEXT; \
+                     CREATE INDEX IF NOT EXISTS {index} ON {table} ({column}, time DESC)",
+                    table = table,
+                    column = ident(tag),
+                    index = ident(&format!("{}_{}_idx", point.measurement, tag)),
+                ))
+                .await?;
+            columns.insert(tag.clone());
+        }
+
+        for (field, value) in &point.fields {
+            if columns.contains(field) {
+                continue;
+            }
+            client
+                .batch_execute(&format!(
+                    "ALTER TABLE {} ADD COLUMN IF NOT EXISTS {} {}",
+                    table,
+                    ident(field),
+                    column_type(value)
+                ))
+                .await?;
+            columns.insert(field.clone());
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(feature = "timescaledb")]
+impl Sink for TimescaleSink {
+    fn write<'a>(
+        &'a self,
+        points: &'a [(Option<String>, WriteQuery)],
+    ) -> LocalBoxFuture<'a, Result<(), Error>> {
+        use futures::FutureExt;
+        self.write_points(points).boxed_local()
+    }
+
+    fn ping(&self) -> LocalBoxFuture<'_, Result<(), Error>> {
+        use futures::FutureExt;
+        async move {
+            let mut state = self.state.lock().await;
+            self.connect(&mut state).await?;
+            let client = state.client.as_ref().unwrap();
+            client
+                .simple_query("SELECT 1")
+                .await
+                .map_err(database_error)?;
+            Ok(())
+        }
+        .boxed_local()
+    }
+}
+
+/// Parameters of a statement are numbered with 16 bits.
+#[cfg(feature = "timescaledb")]
+const MAX_PARAMETERS: usize = u16::MAX as usize;
+
+/// Successive points of the same measurement and columns, inserted with one statement.
+#[cfg(feature = "timescaledb")]
+struct Rows<'a> {
+    measurement: &'a str,
+    columns: Vec<&'a str>,
+    values: Vec<Box<dyn tokio_postgres::types::ToSql + Sync>>,
+}
+
+#[cfg(feature = "timescaledb")]
+fn rows(points: &[crate::line::Point]) -> Result<Vec<Rows<'_>>, Error> {
+    use influxdb::Type;
+    use std::convert::TryFrom;
+    use tokio_postgres::types::ToSql;
+
+    let mut rows: Vec<Rows> = Vec::new();
+    for point in points {
+        let mut columns = vec!["time"];
+        let mut values: Vec<Box<dyn ToSql + Sync>> =
+            vec![Box::new(point.time.unwrap_or_else(chrono::Utc::now))];
+
+        for (tag, value) in &point.tags {
+            columns.push(tag);
+            values.push(Box::new(value.clone()));
+        }
+        for (field, value) in &point.fields {
+            columns.push(field);
+            values.push(match value {
+                Type::Boolean(v) => Box::new(*v),
+                Type::Float(v) => Box::new(*v),
+                Type::SignedInteger(v) => Box::new(*v),
+                Type::UnsignedInteger(v) => {
+                    Box::new(i64::try_from(*v).map_err(|_| Error::InvalidQueryError {
+                        error: format!("Value of {} out of range for BIGINT: {}", field, v),
+                    })?)
+                }
+                Type::Text(v) => Box::new(v.clone()),
+            });
+        }
+
+        match rows.last_mut() {
+            Some(last)
+                if last.measurement == point.measurement
+                    && last.columns == columns
+                    && last.values.len() + values.len() <= MAX_PARAMETERS =>
+            {
+                last.values.extend(values)
+            }
+            _ => rows.push(Rows {
+                measurement: &point.measurement,
+                columns,
+                values,
+            }),
+        }
+    }
+
+    Ok(rows)
+}
+
+#[cfg(feature = "timescaledb")]
+async fn insert(
+    transaction: &tokio_postgres::Transaction<'_>,
+    rows: &Rows<'_>,
+) -> Result<u64, tokio_postgres::Error> {
+    use tokio_postgres::types::ToSql;
+
+    let width = rows.columns.len();
+    let placeholders = (0..rows.values.len() / width)
+        .map(|row| {
+            let row = (1..=width)
+                .map(|column| format!("${}", row * width + column))
+                .collect::<Vec<_>>();
+            format!("({})", row.join(", "))
+        })
+        .collect::<Vec<_>>()
+        .join(", ");
+    let statement = format!(
+        "INSERT INTO {} ({}) VALUES {}",
+        ident(rows.measurement),
+        rows.columns
+            .iter()
+            .map(|column| ident(column))
+            .collect::<Vec<_>>()
+            .join(", "),
+        placeholders
+    );
+    let params: Vec<&(dyn ToSql + Sync)> = rows.values.iter().map(|value| value.as_ref()).collect();
+
+    transaction.execute(statement.as_str(), &params).await
+}
+
+#[cfg(feature = "timescaledb")]
+fn column_type(value: &influxdb::Type) -> &'static str {
+    use influxdb::Type;
+    match value {
+        Type::Boolean(_) => "BOOLEAN",
+        Type::Float(_) => "DOUBLE PRECISION",
+        Type::SignedInteger(_) | Type::UnsignedInteger(_) => "BIGINT",
+        Type::Text(_) => "TEXT",
+    }
+}
+
+/// Quote an identifier, measurements and fields may contain anything.
+#[cfg(feature = "timescaledb")]
+fn ident(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+#[cfg(feature = "timescaledb")]
+fn database_error(err: tokio_postgres::Error) -> Error {
+    Error::DatabaseError {
+        error: format!("postgres error: {}", err),
+    }
+}
+
+#[cfg(not(feature = "timescaledb"))]
+#[derive(Debug)]
+pub struct TimescaleSink;
+
+#[cfg(not(feature = "timescaledb"))]
+impl TimescaleSink {
+    pub fn from_config(_: PgConfig) -> anyhow::Result<Self> {
+        anyhow::bail!("SINK=timescaledb requires the 'timescaledb' feature")
+    }
+}
+
+#[cfg(not(feature = "timescaledb"))]
+impl Sink for TimescaleSink {
+    fn write<'a>(
+        &'a self,
+        _: &'a [(Option<String>, WriteQuery)],
+    ) -> LocalBoxFuture<'a, Result<(), Error>> {
+        use futures::FutureExt;
+        futures::future::ready(Err(Error::ConnectionError {
+            error: "SINK=timescaledb requires the 'timescaledb' feature".to_string(),
+        }))
+        .boxed_local()
+    }
+}
@@ -0,0 +1,99 @@
+use crate::error::ServiceError;
+use crate::records::TimeUnit;
+use chrono::{DateTime, NaiveDateTime, TimeZone, Utc};
+use chrono_tz::Tz;
+use envconfig::Envconfig;
+use serde_json::Value;
+
+#[derive(Envconfig, Clone, Debug)]
+pub struct TimestampConfig {
+    /// JSON path into the payload, selecting the time of the point instead of the event's.
+    #[envconfig(from = "TIMESTAMP_PATH")]
+    pub path: Option<String>,
+    /// chrono format of formatted strings, like `%Y-%m-%d %H:%M:%S`, RFC 3339 if unset.
+    #[envconfig(from = "TIMESTAMP_FORMAT_STRING")]
+    pub format: Option<String>,
+    /// Time zone of formatted strings without an offset, like `Europe/Berlin` or `CET`.
+    #[envconfig(from = "TIMESTAMP_TZ", default = "UTC")]
+    pub tz: String,
+    /// Unit of numeric timestamps, counted since the epoch.
+    #[envconfig(from = "TIMESTAMP_UNIT", default = "s")]
+    pub unit: TimeUnit,
+}
+
+/// Takes the time of the point from the payload.
+///
+/// Strings are parsed with the format, or as RFC 3339, numbers are counted since the epoch.
+/// Payloads without a timestamp keep the time of the event.
+#[derive(Clone, Debug)]
+pub struct PayloadTime {
+    path: jsonpath_lib::Compiled,
+    format: Option<String>,
+    tz: Tz,
+    unit: TimeUnit,
+}
+
+impl PayloadTime {
+    pub fn from_config(config: TimestampConfig) -> anyhow::Result<Option<Self>> {
+        let path = match &config.path {
+            Some(path) => jsonpath_lib::Compiled::compile(path)
+                .map_err(|err| anyhow::anyhow!("Failed to parse TIMESTAMP_PATH: {}", err))?,
+            None => {
+                if config.format.is_some() {
+                    anyhow::bail!("TIMESTAMP_FORMAT_STRING requires TIMESTAMP_PATH");
+                }
+                return Ok(None);
+            }
+        };
+        let tz = config
+            .tz
+            .parse()
+            .map_err(|err| anyhow::anyhow!("Unknown time zone {}: {}", &config.tz, err))?;
+
+        Ok(Some(Self {
+            path,
+            format: config.format,
+            tz,
+            unit: config.unit,
+        }))
+    }
+
+    pub fn time(&self, json: &Value) -> Result<Option<DateTime<Utc>>, ServiceError> {
+        let value = match self.path.select(json).ok().as_deref() {
+            Some([value]) => *value,
+            _ => return Ok(None),
+        };
+
+        let time = match value {
+            Value::String(s) => self.parse(s),
+            value => value
+                .as_f64()
+                .and_then(|value| self.unit.since_epoch(value)),
+        };
+        time.map(Some)
+            .ok_or_else(|| ServiceError::PayloadParseError {
+                details: format!("Invalid timestamp: {}", value),
+            })
+    }
+
+    fn parse(&self, s: &str) -> Option<DateTime<Utc>> {
+        let format = match &self.format {
+            Some(format) => format,
+            None => {
+                return DateTime::parse_from_rfc3339(s)
+                    .ok()
+                    .map(|time| time.with_timezone(&Utc))
+            }
+        };
+
+        // the format may bring its own offset (`%z`)
+        if let Ok(time) = DateTime::parse_from_str(s, format) {
+            return Some(time.with_timezone(&Utc));
+        }
+        let local = NaiveDateTime::parse_from_str(s, format).ok()?;
+        self.tz
+            .from_local_datetime(&local)
+            .earliest()
+            .map(|time| time.with_timezone(&Utc))
+    }
+}
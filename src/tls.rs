@@ -0,0 +1,36 @@
+use envconfig::Envconfig;
+use openssl::ssl::{SslAcceptor, SslAcceptorBuilder, SslFiletype, SslMethod, SslVerifyMode};
+
+#[derive(Envconfig, Clone, Debug)]
+pub struct TlsConfig {
+    #[envconfig(from = "TLS_CERT_FILE")]
+    pub cert_file: Option<String>,
+    #[envconfig(from = "TLS_KEY_FILE")]
+    pub key_file: Option<String>,
+    /// Enables client certificate verification against this CA.
+    #[envconfig(from = "TLS_CLIENT_CA_FILE")]
+    pub client_ca_file: Option<String>,
+}
+
+impl TlsConfig {
+    pub fn acceptor(&self) -> anyhow::Result<Option<SslAcceptorBuilder>> {
+        let (cert_file, key_file) = match (&self.cert_file, &self.key_file) {
+            (Some(cert_file), Some(key_file)) => (cert_file, key_file),
+            (None, None) => return Ok(None),
+            _ => anyhow::bail!("TLS_CERT_FILE and TLS_KEY_FILE must be set together"),
+        };
+
+        let mut builder = SslAcceptor::mozilla_intermediate(SslMethod::tls())?;
+        builder.set_private_key_file(key_file, SslFiletype::PEM)?;
+        builder.set_certificate_chain_file(cert_file)?;
+        builder.check_private_key()?;
+
+        if let Some(ca) = &self.client_ca_file {
+            log::info!("Enabling client certificate verification");
+            builder.set_ca_file(ca)?;
+            builder.set_verify(SslVerifyMode::PEER | SslVerifyMode::FAIL_IF_NO_PEER_CERT);
+        }
+
+        Ok(Some(builder))
+    }
+}
@@ -0,0 +1,431 @@
+use crate::batch::Flushed;
+use crate::config::{Precision, Processor};
+use crate::handler::{self, WriteOutcome, WriteStatus};
+use crate::line::Point;
+use crate::series::Commit;
+use actix_web::web;
+use chrono::{DateTime, Utc};
+use cloudevents::Event;
+use envconfig::Envconfig;
+use influxdb::WriteQuery;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+#[derive(Envconfig, Clone, Debug)]
+pub struct TransactionConfig {
+    /// Extension attribute correlating the events of a transaction, disabled if not set.
+    #[envconfig(from = "TRANSACTION_ATTRIBUTE")]
+    pub attribute: Option<String>,
+    /// Extension attribute marking the last event of a transaction, with a value of `true`.
+    #[envconfig(from = "TRANSACTION_END_ATTRIBUTE", default = "transactionend")]
+    pub end_attribute: String,
+    /// Write incomplete transactions after this long.
+    #[envconfig(from = "TRANSACTION_TIMEOUT_MS", default = "30000")]
+    pub timeout_ms: u64,
+    #[envconfig(from = "TRANSACTION_MAX_OPEN", default = "10000")]
+    pub max_open: usize,
+}
+
+type Points = Vec<(Option<String>, WriteQuery)>;
+
+/// Open transaction, as persisted in a checkpoint.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TransactionEntry {
+    pub id: String,
+    pub started: DateTime<Utc>,
+    pub points: Vec<PointEntry>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PointEntry {
+    pub retention: Option<String>,
+    /// In line protocol.
+    pub line: String,
+    pub precision: String,
+}
+
+#[derive(Debug)]
+struct Open {
+    points: Points,
+    started: Instant,
+    commits: Vec<Commit>,
+}
+
+/// Holds back the points of a transaction, until its last event arrived.
+#[derive(Clone, Debug)]
+pub struct Transactions {
+    attribute: String,
+    end_attribute: String,
+    timeout: Duration,
+    max_open: usize,
+    open: Arc<Mutex<HashMap<String, Open>>>,
+}
+
+impl Transactions {
+    pub fn from_config(config: TransactionConfig) -> anyhow::Result<Option<Self>> {
+        let attribute = match config.attribute {
+            Some(attribute) => attribute,
+            None => return Ok(None),
+        };
+        if config.timeout_ms == 0 {
+            anyhow::bail!("TRANSACTION_TIMEOUT_MS must be greater than zero");
+        }
+
+        Ok(Some(Self {
+            attribute,
+            end_attribute: config.end_attribute,
+            timeout: Duration::from_millis(config.timeout_ms),
+            max_open: config.max_open,
+            open: Default::default(),
+        }))
+    }
+
+    /// Start writing timed out transactions on the current arbiter.
+    pub fn start(&self, processor: web::Data<Processor>) {
+        let timeout = self.timeout;
+        let expiring = self.clone();
+        actix_rt::spawn(async move {
+            let period = (timeout / 2).max(Duration::from_millis(1));
+            let mut interval = actix_rt::time::interval(period);
+            loop {
+                interval.tick().await;
+                for (id, open) in expiring.take(|open| open.started.elapsed() >= timeout) {
+                    log::warn!(
+                        "Transaction {} timed out, writing {} points",
+                        id,
+                        open.points.len()
+                    );
+                    write(&processor, open).await;
+                }
+            }
+        });
+    }
+
+    /// Add the points of an event, returns what should be written now, with what is pending of
+    /// the events they are of.
+    pub fn collect(
+        &self,
+        event: &Event,
+        points: Points,
+        commit: Commit,
+    ) -> Option<(Points, Commit)> {
+        let id = match event.extension(&self.attribute) {
+            Some(id) => id.to_string(),
+            None => return Some((points, commit)),
+        };
+        let end = event
+            .extension(&self.end_attribute)
+            .is_some_and(|end| end.to_string() == "true");
+
+        let mut open = self.open.lock().unwrap();
+
+        if end {
+            return Some(match open.remove(&id) {
+                Some(mut open) => {
+                    open.points.extend(points);
+                    open.commits.push(commit);
+                    (open.points, Commit::join(open.commits))
+                }
+                None => (points, commit),
+            });
+        }
+
+        if !open.contains_key(&id) && open.len() >= self.max_open {
+            log::warn!(
+                "Too many open transactions, writing points of {} right away",
+                id
+            );
+            return Some((points, commit));
+        }
+
+        let open = open.entry(id).or_insert_with(|| Open {
+            points: Vec::new(),
+            started: Instant::now(),
+            commits: Vec::new(),
+        });
+        open.points.extend(points);
+        open.commits.push(commit);
+
+        None
+    }
+
+    /// Write the points of all open transactions right away, without waiting for their last
+    /// event.
+    ///
+    /// Points handed to the aggregator or batcher are counted once those write them.
+    pub async fn drain(&self, processor: &Processor) -> Flushed {
+        let mut flushed = Flushed::default();
+        for (id, open) in self.take(|_| true) {
+            log::info!(
+                "Writing {} points of open transaction {}",
+                open.points.len(),
+                id
+            );
+            flushed.add(write(processor, open).await);
+        }
+        flushed
+    }
+
+    /// Take the open transactions, for a checkpoint.
+    ///
+    /// Their points weren't written, so the values they track are not remembered.
+    pub fn snapshot(&self) -> Vec<TransactionEntry> {
+        let now = Utc::now();
+        self.take(|_| true)
+            .into_iter()
+            .map(|(id, open)| {
+                Commit::join(open.commits).fail();
+                let points = open
+                    .points
+                    .iter()
+                    .filter_map(|(retention, query)| match crate::influx::line(query) {
+                        Ok(line) => Some(PointEntry {
+                            retention: retention.clone(),
+                            line,
+                            precision: query.get_precision(),
+                        }),
+                        Err(err) => {
+                            log::warn!("Failed to keep point of transaction {}: {}", id, err);
+                            None
+                        }
+                    })
+                    .collect();
+                let started = chrono::Duration::from_std(open.started.elapsed())
+                    .map(|elapsed| now - elapsed)
+                    .unwrap_or(now);
+                TransactionEntry {
+                    id,
+                    started,
+                    points,
+                }
+            })
+            .collect()
+    }
+
+    /// Open transactions of a checkpoint, those which timed out meanwhile are written with the
+    /// next ones.
+    pub fn restore(&self, entries: Vec<TransactionEntry>, precision: Precision) {
+        let mut open = self.open.lock().unwrap();
+        let (now, instant) = (Utc::now(), Instant::now());
+        for entry in entries {
+            let TransactionEntry {
+                id,
+                started,
+                points,
+            } = entry;
+            let points = points
+                .into_iter()
+                .filter_map(|point| match Point::parse(&point.line, &point.precision) {
+                    Ok(parsed) => Some((point.retention, parsed.into_query(precision))),
+                    Err(err) => {
+                        log::warn!("Failed to restore point of transaction {}: {}", id, err);
+                        None
+                    }
+                })
+                .collect::<Vec<_>>();
+            let started = (now - started)
+                .to_std()
+                .ok()
+                .and_then(|elapsed| instant.checked_sub(elapsed))
+                .unwrap_or(instant);
+            let open = open.entry(id).or_insert_with(|| Open {
+                points: Vec::new(),
+                started,
+                commits: Vec::new(),
+            });
+            // points of the checkpoint came first
+            open.points.splice(0..0, points);
+            open.started = open.started.min(started);
+        }
+    }
+
+    fn take<F>(&self, filter: F) -> Vec<(String, Open)>
+    where
+        F: Fn(&Open) -> bool,
+    {
+        let mut open = self.open.lock().unwrap();
+        let taken: Vec<_> = open
+            .iter()
+            .filter(|(_, open)| filter(open))
+            .map(|(id, _)| id.clone())
+            .collect();
+
+        taken
+            .into_iter()
+            .filter_map(|id| open.remove_entry(&id))
+            .collect()
+    }
+}
+
+/// Write the points of a transaction, like those of an event.
+async fn write(processor: &Processor, open: Open) -> Flushed {
+    let points = open.points.len();
+    let commit = Commit::join(open.commits);
+    let outcome = WriteOutcome::new(WriteStatus::Written);
+    let result =
+        handler::write_points(None, open.points, outcome, None, commit.clone(), processor).await;
+
+    match result {
+        Ok(outcome) => match (outcome.status, outcome.chunks) {
+            (WriteStatus::Written, _) => Flushed {
+                written: points,
+                failed: 0,
+            },
+            (WriteStatus::Partial, Some(chunks)) => {
+                log::warn!("Failed to write {} points of transaction", chunks.failed);
+                commit.fail();
+                Flushed {
+                    written: chunks.written,
+                    failed: chunks.failed,
+                }
+            }
+            // written later, by the aggregator or batcher
+            _ => Flushed::default(),
+        },
+        Err(err) => {
+            log::warn!("Failed to write transaction: {}", err);
+            commit.fail();
+            Flushed {
+                written: 0,
+                failed: points,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cloudevents::{EventBuilder, EventBuilderV10};
+    use influxdb::{InfluxDbWriteable, Timestamp};
+
+    fn transactions(max_open: usize) -> Transactions {
+        Transactions::from_config(TransactionConfig {
+            attribute: Some("transaction".into()),
+            end_attribute: "transactionend".into(),
+            timeout_ms: 30_000,
+            max_open,
+        })
+        .unwrap()
+        .unwrap()
+    }
+
+    fn event(transaction: Option<&str>, end: bool) -> Event {
+        let mut builder = EventBuilderV10::new().id("1").source("src").ty("t");
+        if let Some(transaction) = transaction {
+            builder = builder.extension("transaction", transaction);
+        }
+        if end {
+            builder = builder.extension("transactionend", "true");
+        }
+        builder.build().unwrap()
+    }
+
+    fn points(value: i64) -> Points {
+        let query = Timestamp::Seconds(1).into_query("m").add_field("v", value);
+        vec![(None, query)]
+    }
+
+    fn lines(points: &[(Option<String>, WriteQuery)]) -> Vec<String> {
+        points
+            .iter()
+            .map(|(_, query)| crate::influx::line(query).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_disabled() {
+        let config = TransactionConfig {
+            attribute: None,
+            end_attribute: "transactionend".into(),
+            timeout_ms: 30_000,
+            max_open: 10,
+        };
+        assert!(Transactions::from_config(config).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_collect() {
+        let transactions = transactions(10);
+
+        let (written, _) = transactions
+            .collect(&event(None, false), points(0), Commit::default())
+            .unwrap();
+        assert_eq!(lines(&written), vec!["m v=0i 1"]);
+
+        assert!(transactions
+            .collect(&event(Some("a"), false), points(1), Commit::default())
+            .is_none());
+        assert!(transactions
+            .collect(&event(Some("b"), false), points(2), Commit::default())
+            .is_none());
+        let (written, _) = transactions
+            .collect(&event(Some("a"), true), points(3), Commit::default())
+            .unwrap();
+        assert_eq!(lines(&written), vec!["m v=1i 1", "m v=3i 1"]);
+
+        let taken = transactions.take(|_| true);
+        assert_eq!(taken.len(), 1);
+        assert_eq!(taken[0].0, "b");
+    }
+
+    #[test]
+    fn test_max_open() {
+        let transactions = transactions(1);
+        assert!(transactions
+            .collect(&event(Some("a"), false), points(1), Commit::default())
+            .is_none());
+        let (written, _) = transactions
+            .collect(&event(Some("b"), false), points(2), Commit::default())
+            .unwrap();
+        assert_eq!(lines(&written), vec!["m v=2i 1"]);
+        // an open one still takes more
+        assert!(transactions
+            .collect(&event(Some("a"), false), points(3), Commit::default())
+            .is_none());
+    }
+
+    #[test]
+    fn test_snapshot() {
+        let transactions = transactions(10);
+        assert!(transactions
+            .collect(&event(Some("a"), false), points(1), Commit::default())
+            .is_none());
+
+        let entries = transactions.snapshot();
+        assert!(transactions.take(|_| true).is_empty());
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].id, "a");
+        assert_eq!(entries[0].points[0].line, "m v=1i 1");
+
+        let entries = serde_json::from_value(serde_json::to_value(entries).unwrap()).unwrap();
+        let restored = self::transactions(10);
+        restored.restore(entries, Precision::Seconds);
+        // arriving after the restart, appended to those of the checkpoint
+        let (written, _) = restored
+            .collect(&event(Some("a"), true), points(2), Commit::default())
+            .unwrap();
+        assert_eq!(lines(&written), vec!["m v=1i 1", "m v=2i 1"]);
+    }
+
+    #[test]
+    fn test_restore_started() {
+        let transactions = transactions(10);
+        let entry = TransactionEntry {
+            id: "a".into(),
+            started: Utc::now() - chrono::Duration::seconds(60),
+            points: Vec::new(),
+        };
+        transactions.restore(vec![entry], Precision::Seconds);
+        // timed out while being down
+        let timeout = Duration::from_secs(30);
+        assert_eq!(
+            transactions
+                .take(|open| open.started.elapsed() >= timeout)
+                .len(),
+            1
+        );
+    }
+}
@@ -0,0 +1,107 @@
+use crate::config::InfluxDb;
+use crate::influx;
+use crate::sink::Sink;
+use futures::future::{ready, LocalBoxFuture};
+use futures::FutureExt;
+use influxdb::{Error, WriteQuery};
+use std::net::{ToSocketAddrs, UdpSocket};
+use std::str::FromStr;
+
+/// How points are sent to InfluxDB.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Protocol {
+    Http,
+    /// Line protocol datagrams, to a UDP listener of InfluxDB, nothing is confirmed.
+    Udp,
+}
+
+impl FromStr for Protocol {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "http" => Ok(Protocol::Http),
+            "udp" => Ok(Protocol::Udp),
+            _ => anyhow::bail!("Unknown InfluxDB protocol: {}", s),
+        }
+    }
+}
+
+/// Sends points as line protocol over UDP, packing as many lines into a datagram as fit.
+///
+/// The UDP listener decides the database and retention policy, those of the points are not
+/// sent. Timestamps are sent in nanoseconds, the default precision of the listener. Points are lost without notice if InfluxDB isn't listening, only errors of the
+/// local socket are reported.
+#[derive(Debug)]
+pub struct UdpSink {
+    socket: UdpSocket,
+    payload_size: usize,
+}
+
+impl UdpSink {
+    pub fn new(influx: &InfluxDb) -> anyhow::Result<Self> {
+        let host = match &influx.udp_host {
+            Some(host) => host.clone(),
+            None => reqwest::Url::parse(&influx.uri)?
+                .host_str()
+                .map(ToString::to_string)
+                .ok_or_else(|| anyhow::anyhow!("Missing host of INFLUXDB_URI"))?,
+        };
+        let addr = (host.as_str(), influx.udp_port)
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| anyhow::anyhow!("Failed to resolve {}", host))?;
+
+        let local = match addr.is_ipv4() {
+            true => "0.0.0.0:0",
+            false => "[::]:0",
+        };
+        let socket = UdpSocket::bind(local)?;
+        socket.connect(addr)?;
+
+        log::info!("Sending points over UDP to {}", addr);
+
+        Ok(Self {
+            socket,
+            payload_size: influx.udp_payload_size.max(1),
+        })
+    }
+
+    fn send_all(&self, points: &[(Option<String>, WriteQuery)]) -> Result<(), Error> {
+        let mut payload = String::new();
+        for (_, query) in points {
+            let line = influx::nanosecond_line(query)?;
+            // lines longer than a datagram are sent on their own, and may be dropped
+            if !payload.is_empty() && payload.len() + 1 + line.len() > self.payload_size {
+                self.send(&payload)?;
+                payload.clear();
+            }
+            if !payload.is_empty() {
+                payload.push('\n');
+            }
+            payload.push_str(&line);
+        }
+        match payload.is_empty() {
+            true => Ok(()),
+            false => self.send(&payload),
+        }
+    }
+
+    fn send(&self, payload: &str) -> Result<(), Error> {
+        self.socket
+            .send(payload.as_bytes())
+            .map(|_| ())
+            .map_err(|err| Error::ConnectionError {
+                error: format!("Failed to send datagram: {}", err),
+            })
+    }
+}
+
+impl Sink for UdpSink {
+    fn write<'a>(
+        &'a self,
+        points: &'a [(Option<String>, WriteQuery)],
+    ) -> LocalBoxFuture<'a, Result<(), Error>> {
+        ready(self.send_all(points)).boxed_local()
+    }
+}
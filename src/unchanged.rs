@@ -0,0 +1,259 @@
+use chrono::{DateTime, Duration, Utc};
+use envconfig::Envconfig;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+#[derive(Envconfig, Clone, Debug)]
+pub struct UnchangedConfig {
+    /// Skip unchanged points looking at all fields, not only those with `DEDUP_FIELD_<NAME>`.
+    #[envconfig(from = "DEDUP_FIELDS", default = "false")]
+    pub all: bool,
+    /// Write unchanged points anyway, once the last one written is older than this.
+    #[envconfig(from = "DEDUP_FIELDS_WINDOW_S", default = "300")]
+    pub window_s: i64,
+    /// Series remembered, points of new series are written beyond that.
+    #[envconfig(from = "DEDUP_FIELDS_MAX_SERIES", default = "100000")]
+    pub max_series: usize,
+}
+
+/// Tracked values of the last point of a series, as persisted in a checkpoint.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct UnchangedEntry {
+    pub series: String,
+    pub values: Vec<(String, String)>,
+    pub time: DateTime<Utc>,
+}
+
+/// Skips points of a series which report the same values as the last one written.
+///
+/// Only the tracked fields are compared, points are written when any of them changes, one
+/// goes missing, or the window passed since the last one was written, as a sign of life.
+#[derive(Clone, Debug)]
+pub struct Unchanged {
+    all: bool,
+    window: Duration,
+    max_series: usize,
+    written: Arc<Mutex<HashMap<String, Written>>>,
+}
+
+/// Tracked values of the last point written, and its time.
+pub type Written = (Vec<(String, String)>, DateTime<Utc>);
+
+impl Unchanged {
+    pub fn from_config(config: UnchangedConfig) -> Self {
+        Self {
+            all: config.all,
+            window: Duration::seconds(config.window_s),
+            max_series: config.max_series,
+            written: Default::default(),
+        }
+    }
+
+    pub fn tracks_all(&self) -> bool {
+        self.all
+    }
+
+    /// Check the tracked values of a point, against those last written, or about to be with
+    /// the same event, in `pending`. Those of a point to be written are added to `pending`, and
+    /// only remembered once they are committed.
+    pub fn check(
+        &self,
+        series: &str,
+        time: DateTime<Utc>,
+        mut values: Vec<(String, String)>,
+        pending: &mut HashMap<String, Written>,
+    ) -> bool {
+        if values.is_empty() {
+            return false;
+        }
+        values.sort();
+
+        let mut written = self.written.lock().unwrap();
+        if let Some((last, last_time)) = pending.get(series).or_else(|| written.get(series)) {
+            if *last == values && time >= *last_time && time - *last_time < self.window {
+                return true;
+            }
+        }
+
+        if !written.contains_key(series) && written.len() >= self.max_series {
+            let window = self.window;
+            written.retain(|_, (_, last_time)| time - *last_time < window);
+            if written.len() >= self.max_series {
+                log::debug!("Too many series, not tracking {}", series);
+                return false;
+            }
+        }
+        pending.insert(series.to_string(), (values, time));
+
+        false
+    }
+
+    /// Remember the values of points which have been written.
+    pub fn commit(&self, pending: HashMap<String, Written>) {
+        if pending.is_empty() {
+            return;
+        }
+        let mut written = self.written.lock().unwrap();
+        for (series, values) in pending {
+            // others may have filled up the series in the meantime
+            if written.contains_key(&series) || written.len() < self.max_series {
+                written.insert(series, values);
+            }
+        }
+    }
+
+    pub fn snapshot(&self) -> Vec<UnchangedEntry> {
+        self.written
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(series, (values, time))| UnchangedEntry {
+                series: series.clone(),
+                values: values.clone(),
+                time: *time,
+            })
+            .collect()
+    }
+
+    pub fn restore(&self, entries: Vec<UnchangedEntry>) {
+        let mut written = self.written.lock().unwrap();
+        for entry in entries.into_iter().take(self.max_series) {
+            written.insert(entry.series, (entry.values, entry.time));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unchanged(all: bool, max_series: usize) -> Unchanged {
+        Unchanged::from_config(UnchangedConfig {
+            all,
+            window_s: 300,
+            max_series,
+        })
+    }
+
+    fn values(values: &[(&str, &str)]) -> Vec<(String, String)> {
+        values
+            .iter()
+            .map(|(field, value)| (field.to_string(), value.to_string()))
+            .collect()
+    }
+
+    /// Check a point, and commit it if it's written.
+    fn write(unchanged: &Unchanged, series: &str, time: DateTime<Utc>, v: &[(&str, &str)]) -> bool {
+        let mut pending = HashMap::new();
+        let skipped = unchanged.check(series, time, values(v), &mut pending);
+        unchanged.commit(pending);
+        skipped
+    }
+
+    #[test]
+    fn test_check() {
+        let unchanged = unchanged(false, 10);
+        let now = Utc::now();
+        assert!(!write(&unchanged, "a", now, &[("t", "1"), ("h", "2")]));
+        // in any order
+        assert!(write(
+            &unchanged,
+            "a",
+            now + Duration::seconds(1),
+            &[("h", "2"), ("t", "1")]
+        ));
+        assert!(!write(
+            &unchanged,
+            "a",
+            now + Duration::seconds(2),
+            &[("t", "2"), ("h", "2")]
+        ));
+        assert!(!write(
+            &unchanged,
+            "a",
+            now + Duration::seconds(3),
+            &[("t", "2")]
+        ));
+        assert!(!write(&unchanged, "b", now, &[("t", "2")]));
+        // nothing tracked
+        assert!(!write(&unchanged, "c", now, &[]));
+        assert!(!write(&unchanged, "c", now, &[]));
+    }
+
+    #[test]
+    fn test_window() {
+        let unchanged = unchanged(false, 10);
+        let now = Utc::now();
+        assert!(!write(&unchanged, "a", now, &[("t", "1")]));
+        assert!(write(
+            &unchanged,
+            "a",
+            now + Duration::seconds(299),
+            &[("t", "1")]
+        ));
+        // a sign of life, once the window passed
+        assert!(!write(
+            &unchanged,
+            "a",
+            now + Duration::seconds(300),
+            &[("t", "1")]
+        ));
+        // older points are written
+        assert!(!write(
+            &unchanged,
+            "a",
+            now - Duration::seconds(1),
+            &[("t", "1")]
+        ));
+    }
+
+    #[test]
+    fn test_pending() {
+        let unchanged = unchanged(true, 10);
+        let now = Utc::now();
+        let mut pending = HashMap::new();
+        assert!(!unchanged.check("a", now, values(&[("t", "1")]), &mut pending));
+        // of the same event, before it was written
+        assert!(unchanged.check("a", now, values(&[("t", "1")]), &mut pending));
+        assert!(!unchanged.check("a", now, values(&[("t", "1")]), &mut HashMap::new()));
+
+        // failed events aren't remembered
+        drop(pending);
+        assert!(!write(&unchanged, "a", now, &[("t", "1")]));
+        assert!(unchanged.tracks_all());
+    }
+
+    #[test]
+    fn test_max_series() {
+        let unchanged = unchanged(false, 1);
+        let now = Utc::now();
+        assert!(!write(&unchanged, "a", now, &[("t", "1")]));
+        assert!(!write(&unchanged, "b", now, &[("t", "1")]));
+        assert!(!write(&unchanged, "b", now, &[("t", "1")]));
+        assert!(write(&unchanged, "a", now, &[("t", "1")]));
+
+        // expired series make room
+        let later = now + Duration::seconds(600);
+        assert!(!write(&unchanged, "b", later, &[("t", "1")]));
+        assert!(write(&unchanged, "b", later, &[("t", "1")]));
+        assert_eq!(unchanged.snapshot().len(), 1);
+    }
+
+    #[test]
+    fn test_restore() {
+        let unchanged = unchanged(false, 10);
+        let now = Utc::now();
+        unchanged.restore(vec![UnchangedEntry {
+            series: "a".into(),
+            values: values(&[("t", "1")]),
+            time: now,
+        }]);
+        assert!(write(
+            &unchanged,
+            "a",
+            now + Duration::seconds(1),
+            &[("t", "1")]
+        ));
+    }
+}
@@ -0,0 +1,87 @@
+use serde_json::Value;
+
+/// Translation of device specific values, configured as `on:true,off:false,1:true,0:false`.
+///
+/// Matches strings ignoring their case, and numbers or booleans by their text. Targets become
+/// booleans, numbers or strings, like they would be in JSON. Values without a match are kept.
+#[derive(Clone, Debug)]
+pub struct ValueMap {
+    entries: Vec<(String, Value)>,
+}
+
+impl ValueMap {
+    pub fn from_spec(spec: &str) -> anyhow::Result<Self> {
+        let entries = spec
+            .split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+            .map(|entry| match entry.split_once(':') {
+                Some((from, to)) => Ok((from.trim().to_lowercase(), target(to.trim()))),
+                None => anyhow::bail!("Invalid value map entry, expected <from>:<to>: {}", entry),
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        if entries.is_empty() {
+            anyhow::bail!("Empty value map");
+        }
+
+        Ok(Self { entries })
+    }
+
+    /// The value to use instead, `None` if there is no match.
+    pub fn translate(&self, value: &Value) -> Option<&Value> {
+        let text = match value {
+            Value::String(s) => s.to_lowercase(),
+            Value::Number(_) | Value::Bool(_) => value.to_string(),
+            _ => return None,
+        };
+
+        self.entries
+            .iter()
+            .find(|(from, _)| *from == text)
+            .map(|(_, to)| to)
+    }
+}
+
+fn target(to: &str) -> Value {
+    match serde_json::from_str(to) {
+        Ok(value @ (Value::Bool(_) | Value::Number(_))) => value,
+        _ => Value::String(to.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_translate() {
+        let map = ValueMap::from_spec("on:true, OFF:false,1:true,0:false,ok:fine,low:1.5").unwrap();
+        assert_eq!(map.translate(&json!("On")), Some(&json!(true)));
+        assert_eq!(map.translate(&json!("off")), Some(&json!(false)));
+        assert_eq!(map.translate(&json!(1)), Some(&json!(true)));
+        assert_eq!(map.translate(&json!("0")), Some(&json!(false)));
+        assert_eq!(map.translate(&json!("ok")), Some(&json!("fine")));
+        assert_eq!(map.translate(&json!("low")), Some(&json!(1.5)));
+        // no match
+        assert_eq!(map.translate(&json!("unknown")), None);
+        assert_eq!(map.translate(&json!(1.0)), None);
+        assert_eq!(map.translate(&json!(null)), None);
+        assert_eq!(map.translate(&json!(["on"])), None);
+    }
+
+    #[test]
+    fn test_bool_source() {
+        let map = ValueMap::from_spec("true:open,false:closed").unwrap();
+        assert_eq!(map.translate(&json!(true)), Some(&json!("open")));
+        assert_eq!(map.translate(&json!("False")), Some(&json!("closed")));
+    }
+
+    #[test]
+    fn test_invalid() {
+        assert!(ValueMap::from_spec("").is_err());
+        assert!(ValueMap::from_spec(" , ").is_err());
+        assert!(ValueMap::from_spec("on:true,off").is_err());
+    }
+}
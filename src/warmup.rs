@@ -0,0 +1,60 @@
+use crate::config::Processor;
+use envconfig::Envconfig;
+use serde_json::Value;
+use std::time::Duration;
+
+#[derive(Envconfig, Clone, Debug)]
+pub struct WarmupConfig {
+    /// Warm up before accepting requests.
+    #[envconfig(from = "WARMUP", default = "false")]
+    pub enabled: bool,
+    /// Sample payload to run all selectors against.
+    #[envconfig(from = "WARMUP_SAMPLE_FILE")]
+    pub sample_file: Option<String>,
+    #[envconfig(from = "WARMUP_TIMEOUT_S", default = "10")]
+    pub timeout_s: u64,
+}
+
+/// Connect to InfluxDB and exercise the selectors, so that the first event doesn't pay for it.
+///
+/// Failures are only logged, the function will try again when processing events.
+pub async fn run(config: WarmupConfig, processor: &Processor) {
+    if !config.enabled {
+        return;
+    }
+
+    log::info!("Warming up");
+
+    if let Some(file) = &config.sample_file {
+        match read_sample(file) {
+            Ok(sample) => {
+                let mut selected = 0;
+                for mapping in &processor.mappings {
+                    for path in mapping.fields.values().chain(mapping.tags.values()) {
+                        match path.selector.select(&sample) {
+                            Ok(values) => selected += values.len(),
+                            Err(err) => log::warn!("Selector {} failed: {}", path.path, err),
+                        }
+                    }
+                }
+                log::debug!("Selected {} values from the sample", selected);
+            }
+            Err(err) => log::warn!("Failed to read sample payload {}: {}", file, err),
+        }
+    }
+
+    if processor.dry_run {
+        return;
+    }
+
+    let timeout = Duration::from_secs(config.timeout_s);
+    match actix_rt::time::timeout(timeout, processor.sink.ping()).await {
+        Ok(Ok(_)) => log::info!("Connected to InfluxDB"),
+        Ok(Err(err)) => log::warn!("Failed to connect to InfluxDB: {}", err),
+        Err(_) => log::warn!("Timeout connecting to InfluxDB"),
+    }
+}
+
+fn read_sample(file: &str) -> anyhow::Result<Value> {
+    Ok(serde_json::from_slice(&std::fs::read(file)?)?)
+}
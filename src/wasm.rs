@@ -0,0 +1,134 @@
+use crate::error::ServiceError;
+use envconfig::Envconfig;
+use serde_json::Value;
+
+#[derive(Envconfig, Clone, Debug)]
+pub struct WasmConfig {
+    /// WASM module decoding payloads of content types other than JSON.
+    #[envconfig(from = "WASM_DECODER_FILE")]
+    pub file: Option<String>,
+    /// Fuel available to a single call, guarding against runaway decoders.
+    #[cfg_attr(not(feature = "wasm"), allow(dead_code))]
+    #[envconfig(from = "WASM_DECODER_FUEL", default = "10000000")]
+    pub fuel: u64,
+}
+
+/// Decodes payloads using a WASM module.
+///
+/// The module must export its `memory`, `alloc(len: i32) -> i32` and
+/// `decode(ptr: i32, len: i32, content_type_ptr: i32, content_type_len: i32) -> i64`. The
+/// result of `decode` is the location of the JSON document, `ptr << 32 | len`, or negative
+/// on failure. Every call gets a fresh instance.
+#[cfg(feature = "wasm")]
+#[derive(Clone)]
+pub struct WasmDecoder {
+    engine: wasmi::Engine,
+    module: std::sync::Arc<wasmi::Module>,
+    fuel: u64,
+}
+
+#[cfg(feature = "wasm")]
+impl std::fmt::Debug for WasmDecoder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("WasmDecoder")
+            .field("fuel", &self.fuel)
+            .finish()
+    }
+}
+
+#[cfg(feature = "wasm")]
+impl WasmDecoder {
+    pub fn from_config(config: WasmConfig) -> anyhow::Result<Option<Self>> {
+        let file = match config.file {
+            Some(file) => file,
+            None => return Ok(None),
+        };
+
+        let mut wasm_config = wasmi::Config::default();
+        wasm_config.consume_fuel(true);
+        let engine = wasmi::Engine::new(&wasm_config);
+
+        let module = wasmi::Module::new(&engine, &std::fs::read(&file)?)
+            .map_err(|err| anyhow::anyhow!("Failed to load {}: {}", file, err))?;
+
+        Ok(Some(Self {
+            engine,
+            module: std::sync::Arc::new(module),
+            fuel: config.fuel,
+        }))
+    }
+
+    pub fn decode(&self, data: &[u8], content_type: &str) -> Result<Value, ServiceError> {
+        let mut store = wasmi::Store::new(&self.engine, ());
+        store.set_fuel(self.fuel).map_err(decode_error)?;
+
+        let instance = wasmi::Linker::<()>::new(&self.engine)
+            .instantiate(&mut store, &self.module)
+            .and_then(|instance| instance.start(&mut store))
+            .map_err(decode_error)?;
+
+        let memory = instance
+            .get_memory(&store, "memory")
+            .ok_or_else(|| decode_error("Module doesn't export its memory"))?;
+        let alloc = instance
+            .get_typed_func::<i32, i32>(&store, "alloc")
+            .map_err(decode_error)?;
+        let decode = instance
+            .get_typed_func::<(i32, i32, i32, i32), i64>(&store, "decode")
+            .map_err(decode_error)?;
+
+        let mut copy = |bytes: &[u8]| -> Result<(i32, i32), ServiceError> {
+            let len = bytes.len() as i32;
+            let ptr = alloc.call(&mut store, len).map_err(decode_error)?;
+            memory
+                .write(&mut store, ptr as usize, bytes)
+                .map_err(decode_error)?;
+            Ok((ptr, len))
+        };
+
+        let (data_ptr, data_len) = copy(data)?;
+        let (ct_ptr, ct_len) = copy(content_type.as_bytes())?;
+
+        let result = decode
+            .call(&mut store, (data_ptr, data_len, ct_ptr, ct_len))
+            .map_err(decode_error)?;
+        if result < 0 {
+            return Err(decode_error(format!("Decoder failed: {}", result)));
+        }
+
+        let (ptr, len) = ((result >> 32) as usize, (result & 0xFFFF_FFFF) as usize);
+        let json = memory
+            .data(&store)
+            .get(ptr..ptr + len)
+            .ok_or_else(|| decode_error("Decoder result out of bounds"))?;
+
+        serde_json::from_slice(json).map_err(decode_error)
+    }
+}
+
+#[cfg(feature = "wasm")]
+fn decode_error<E: std::fmt::Display>(err: E) -> ServiceError {
+    ServiceError::PayloadParseError {
+        details: format!("WASM decoder: {}", err),
+    }
+}
+
+#[cfg(not(feature = "wasm"))]
+#[derive(Clone, Debug)]
+pub struct WasmDecoder;
+
+#[cfg(not(feature = "wasm"))]
+impl WasmDecoder {
+    pub fn from_config(config: WasmConfig) -> anyhow::Result<Option<Self>> {
+        if config.file.is_some() {
+            anyhow::bail!("WASM_DECODER_FILE requires the 'wasm' feature");
+        }
+        Ok(None)
+    }
+
+    pub fn decode(&self, _: &[u8], _: &str) -> Result<Value, ServiceError> {
+        Err(ServiceError::PayloadParseError {
+            details: "WASM_DECODER_FILE requires the 'wasm' feature".to_string(),
+        })
+    }
+}
@@ -0,0 +1,300 @@
+use crate::csv;
+use crate::error::ServiceError;
+use serde_json::{Map, Value};
+
+/// Whether the payload is XML, like `application/xml` or `application/soap+xml`.
+pub fn is_xml(content_type: Option<&str>) -> bool {
+    let mime = content_type
+        .and_then(|content_type| content_type.split(';').next())
+        .unwrap_or_default()
+        .trim()
+        .to_lowercase();
+    mime == "application/xml" || mime == "text/xml" || mime.ends_with("+xml")
+}
+
+/// Convert an XML document to JSON, so that it can be mapped like any other payload.
+///
+/// The root element becomes the only key of the object, like `$.telemetry.temperature`.
+/// Attributes are keyed with an `@`, repeated elements become arrays. Elements with only text
+/// become their value, numbers and booleans being recognized, the text of elements which
+/// also have attributes or children is keyed `#text`. Empty elements are left out.
+pub fn decode(data: &[u8]) -> Result<Value, ServiceError> {
+    let text = std::str::from_utf8(data).map_err(|err| error(err.to_string()))?;
+    let mut parser = Parser {
+        rest: text,
+        depth: 0,
+    };
+
+    parser.skip_misc()?;
+    let (name, value) = parser.element()?;
+    parser.skip_misc()?;
+    if !parser.rest.is_empty() {
+        return Err(error("Content after the root element"));
+    }
+
+    let mut root = Map::new();
+    root.insert(name, value);
+    Ok(Value::Object(root))
+}
+
+/// Nesting of elements, beyond which the payload is rejected.
+const MAX_DEPTH: usize = 64;
+
+struct Parser<'a> {
+    rest: &'a str,
+    depth: usize,
+}
+
+impl<'a> Parser<'a> {
+    /// Skip whitespace, comments, processing instructions and the document type.
+    fn skip_misc(&mut self) -> Result<(), ServiceError> {
+        loop {
+            self.rest = self.rest.trim_start();
+            if self.rest.starts_with("<?") {
+                self.skip_past("?>")?;
+            } else if self.rest.starts_with("<!--") {
+                self.skip_past("-->")?;
+            } else if self.rest.starts_with("<!DOCTYPE") {
+                self.skip_past(">")?;
+            } else {
+                return Ok(());
+            }
+        }
+    }
+
+    fn skip_past(&mut self, end: &str) -> Result<&'a str, ServiceError> {
+        match self.rest.find(end) {
+            Some(index) => {
+                let skipped = &self.rest[..index];
+                self.rest = &self.rest[index + end.len()..];
+                Ok(skipped)
+            }
+            None => Err(error(format!("Missing {}", end))),
+        }
+    }
+
+    fn name(&mut self) -> Result<&'a str, ServiceError> {
+        let end = self
+            .rest
+            .find(|c: char| c.is_whitespace() || "/>=".contains(c))
+            .unwrap_or(self.rest.len());
+        if end == 0 {
+            return Err(error("Missing name"));
+        }
+        let name = &self.rest[..end];
+        self.rest = &self.rest[end..];
+        Ok(name)
+    }
+
+    /// An element, starting at its `<`.
+    fn element(&mut self) -> Result<(String, Value), ServiceError> {
+        self.rest = self
+            .rest
+            .strip_prefix('<')
+            .ok_or_else(|| error("Expected an element"))?;
+        let name = self.name()?.to_string();
+        let mut object = Map::new();
+
+        // attributes, until the end of the start tag
+        loop {
+            self.rest = self.rest.trim_start();
+            if let Some(rest) = self.rest.strip_prefix("/>") {
+                self.rest = rest;
+                return Ok((name, element(object, "")));
+            }
+            if let Some(rest) = self.rest.strip_prefix('>') {
+                self.rest = rest;
+                break;
+            }
+            let attribute = self.name()?;
+            self.rest = self
+                .rest
+                .trim_start()
+                .strip_prefix('=')
+                .ok_or_else(|| error(format!("Missing value of attribute {}", attribute)))?
+                .trim_start();
+            let quote = match self.rest.chars().next() {
+                Some(quote @ ('"' | '\'')) => quote,
+                _ => return Err(error(format!("Unquoted attribute {}", attribute))),
+            };
+            self.rest = &self.rest[1..];
+            let value = self.skip_past(&quote.to_string())?;
+            object.insert(format!("@{}", attribute), csv::infer(unescape(value)?));
+        }
+
+        // content, until the end tag
+        let mut text = String::new();
+        loop {
+            let index = self
+                .rest
+                .find('<')
+                .ok_or_else(|| error(format!("Missing end of element {}", name)))?;
+            text.push_str(&unescape(&self.rest[..index])?);
+            self.rest = &self.rest[index..];
+
+            if let Some(rest) = self.rest.strip_prefix("</") {
+                self.rest = rest;
+                let end = self.name()?;
+                if end != name {
+                    return Err(error(format!("Expected end of {}, found {}", name, end)));
+                }
+                self.rest = self
+                    .rest
+                    .trim_start()
+                    .strip_prefix('>')
+                    .ok_or_else(|| error(format!("Unterminated end of {}", name)))?;
+                break;
+            } else if let Some(rest) = self.rest.strip_prefix("<![CDATA[") {
+                self.rest = rest;
+                text.push_str(self.skip_past("]]>")?);
+            } else if self.rest.starts_with("<!--") {
+                self.skip_past("-->")?;
+            } else if self.rest.starts_with("<?") {
+                self.skip_past("?>")?;
+            } else {
+                if self.depth >= MAX_DEPTH {
+                    return Err(error("Nested too deep"));
+                }
+                self.depth += 1;
+                let (child, value) = self.element()?;
+                self.depth -= 1;
+                insert(&mut object, child, value);
+            }
+        }
+
+        Ok((name, element(object, text.trim())))
+    }
+}
+
+fn element(mut object: Map<String, Value>, text: &str) -> Value {
+    match (object.is_empty(), text.is_empty()) {
+        (true, true) => Value::Null,
+        (true, false) => csv::infer(text.to_string()),
+        (false, true) => Value::Object(object),
+        (false, false) => {
+            object.insert("#text".to_string(), csv::infer(text.to_string()));
+            Value::Object(object)
+        }
+    }
+}
+
+/// Add a child, turning repeated ones into an array, empty ones are left out.
+fn insert(object: &mut Map<String, Value>, name: String, value: Value) {
+    if value.is_null() {
+        return;
+    }
+    match object.get_mut(&name) {
+        Some(Value::Array(values)) => values.push(value),
+        Some(existing) => {
+            let first = existing.take();
+            *existing = Value::Array(vec![first, value]);
+        }
+        None => {
+            object.insert(name, value);
+        }
+    }
+}
+
+fn unescape(text: &str) -> Result<String, ServiceError> {
+    let mut result = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(index) = rest.find('&') {
+        result.push_str(&rest[..index]);
+        rest = &rest[index + 1..];
+        let end = rest.find(';').ok_or_else(|| error("Unterminated entity"))?;
+        let entity = &rest[..end];
+        let c = match entity {
+            "lt" => '<',
+            "gt" => '>',
+            "amp" => '&',
+            "quot" => '"',
+            "apos" => '\'',
+            entity => entity
+                .strip_prefix("#x")
+                .map(|hex| u32::from_str_radix(hex, 16))
+                .or_else(|| entity.strip_prefix('#').map(str::parse))
+                .and_then(Result::ok)
+                .and_then(char::from_u32)
+                .ok_or_else(|| error(format!("Unknown entity: &{};", entity)))?,
+        };
+        result.push(c);
+        rest = &rest[end + 1..];
+    }
+    result.push_str(rest);
+
+    Ok(result)
+}
+
+fn error(details: impl Into<String>) -> ServiceError {
+    ServiceError::PayloadParseError {
+        details: format!("Invalid XML: {}", details.into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn details(result: Result<Value, ServiceError>) -> String {
+        match result {
+            Err(ServiceError::PayloadParseError { details }) => details,
+            other => panic!("Unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_decode() {
+        let data = br#"<?xml version="1.0"?>
+            <!-- a reading -->
+            <telemetry device="a&amp;b">
+                <temp unit="C">21.5</temp>
+                <ok>true</ok>
+                <tag>x</tag><tag>y</tag>
+                <empty/>
+                <note><![CDATA[<raw>]]></note>
+            </telemetry>"#;
+        assert_eq!(
+            decode(data).unwrap(),
+            json!({"telemetry": {
+                "@device": "a&b",
+                "temp": {"@unit": "C", "#text": 21.5},
+                "ok": true,
+                "tag": ["x", "y"],
+                "note": "<raw>",
+            }})
+        );
+    }
+
+    #[test]
+    fn test_content_type() {
+        assert!(is_xml(Some("application/xml; charset=utf-8")));
+        assert!(is_xml(Some("application/soap+xml")));
+        assert!(!is_xml(Some("application/json")));
+        assert!(!is_xml(None));
+    }
+
+    #[test]
+    fn test_truncated() {
+        assert!(details(decode(b"<a><b>1</b>")).contains("Missing end of element a"));
+        assert!(details(decode(b"<a x=\"1")).contains("Missing \""));
+        assert!(details(decode(b"<!-- <a/>")).contains("Missing -->"));
+    }
+
+    #[test]
+    fn test_depth() {
+        let nested = |depth| format!("{}{}", "<a>".repeat(depth), "</a>".repeat(depth));
+        assert!(details(decode(nested(MAX_DEPTH + 2).as_bytes())).contains("Nested too deep"));
+        assert!(decode(nested(MAX_DEPTH).as_bytes()).is_ok());
+    }
+
+    #[test]
+    fn test_malformed() {
+        assert!(details(decode(b"<a></b>")).contains("Expected end of a, found b"));
+        assert!(details(decode(b"<a/><b/>")).contains("Content after the root element"));
+        assert!(details(decode(b"<a x=1/>")).contains("Unquoted attribute x"));
+        assert!(details(decode(b"<a>&nope;</a>")).contains("Unknown entity"));
+        assert!(details(decode(b"text")).contains("Expected an element"));
+    }
+}